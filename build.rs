@@ -12,6 +12,8 @@ fn main() {
     source!("src/linker.ld");
     add_x86_64_asm("boot.asm");
     add_x86_64_asm("multiboot_header.asm");
+    add_user_program("user_hello");
+    add_build_info();
 }
 
 fn add_x86_64_asm(source: &str) {
@@ -25,3 +27,84 @@ fn add_x86_64_asm(source: &str) {
         println!("cargo:rustc-link-arg={}", object.to_str().unwrap());
     }
 }
+
+/// Assembles a test user program into `OUT_DIR`, as a flat binary that
+/// carries its own ELF header.
+fn add_user_program(name: &str) {
+    let source = source!("tests/{0}/{0}.asm", name);
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let output = format!("{}/{}.elf", out_dir, name);
+
+    let status = std::process::Command::new("nasm")
+        .args(["-f", "bin", "-o", &output, &source])
+        .status()
+        .expect("failed to run nasm");
+    assert!(status.success(), "nasm failed on {}", source);
+}
+
+/// Records which build this is for `src/version.rs`, as `HELLO_OS_*`
+/// environment variables.
+fn add_build_info() {
+    // Any source change may make the tree dirty, and a commit moves HEAD
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let git = command_output("git", &["describe", "--always", "--dirty", "--abbrev=12"]);
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let rustc = command_output(&rustc, &["--version"]);
+    let profile = std::env::var("PROFILE").unwrap_or_default();
+
+    // SOURCE_DATE_EPOCH gives reproducible builds a fixed time
+    let seconds = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch.parse().expect("SOURCE_DATE_EPOCH is not a number"),
+        Err(_) => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    };
+
+    println!("cargo:rustc-env=HELLO_OS_GIT={}", git.as_deref().unwrap_or("unknown"));
+    println!("cargo:rustc-env=HELLO_OS_BUILD_TIME={}", utc_timestamp(seconds));
+    println!("cargo:rustc-env=HELLO_OS_PROFILE={}", profile);
+    println!("cargo:rustc-env=HELLO_OS_RUSTC={}", rustc.as_deref().unwrap_or("unknown"));
+}
+
+/// Runs a command and returns the first line it printed, or `None` if it
+/// couldn't be run or failed.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let line = stdout.lines().next()?.trim();
+    (!line.is_empty()).then(|| line.to_string())
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+fn utc_timestamp(seconds: u64) -> String {
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Howard Hinnant's days_from_civil, backwards
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
@@ -0,0 +1,74 @@
+#!/usr/bin/env bash
+# Fills the .ksyms section of a kernel with its function symbols.
+#
+# The kernel reserves a fixed-size .ksyms section (see src/debug/ksyms.rs)
+# so the table can be written into the linked ELF in place. Nothing moves,
+# so the addresses in the table are the ones the kernel runs at and no
+# second link is needed.
+#
+# Layout, little endian:
+#   header   "KSYM", u32 count, u32 strings offset, u32 strings length
+#   entries  count * (u64 address, u32 size, u32 name offset, u32 name length, u32 0)
+#   strings  the mangled names, back to back
+set -euo pipefail
+
+kernel="$1"
+
+section="$(readelf -SW "${kernel}" | sed 's/^ *\[ *[0-9]*\] *//' | awk '$1 == ".ksyms" { print $4, $5 }')"
+if [[ -z "${section}" ]]; then
+	echo "ERROR: ${kernel} has no .ksyms section" >&2
+	exit 1
+fi
+read -r offset capacity <<<"${section}"
+offset=$((16#${offset}))
+capacity=$((16#${capacity}))
+
+workdir="$(mktemp -d)"
+trap 'rm -rf "${workdir}"' EXIT
+
+# Appends `value` to `out` as `bytes` little-endian bytes
+le() {
+	local value=$1 bytes=$2 byte i
+	for ((i = 0; i < bytes; i++)); do
+		printf -v byte '\\x%02x' $(((value >> (8 * i)) & 0xff))
+		out+="${byte}"
+	done
+}
+
+count=0
+strings=0
+out=""
+: >"${workdir}/strings"
+while read -r addr size name; do
+	le $((16#${addr})) 8
+	le $((16#${size})) 4
+	le "${strings}" 4
+	le "${#name}" 4
+	le 0 4
+	printf '%s' "${name}" >>"${workdir}/strings"
+	strings=$((strings + ${#name}))
+	count=$((count + 1))
+	# Flush now and then, the string gets slow to append to
+	if ((count % 256 == 0)); then
+		printf "${out}" >>"${workdir}/entries"
+		out=""
+	fi
+done < <(nm -n -S --defined-only "${kernel}" |
+	awk 'NF == 4 && $3 ~ /^[tT]$/ { print $1, $2, $4 }
+	     NF == 3 && $2 ~ /^[tT]$/ { print $1, 0, $3 }')
+printf "${out}" >>"${workdir}/entries"
+
+out="KSYM"
+le "${count}" 4
+le $((16 + count * 24)) 4
+le "${strings}" 4
+printf "${out}" >"${workdir}/table"
+cat "${workdir}/entries" "${workdir}/strings" >>"${workdir}/table"
+
+size="$(stat -c %s "${workdir}/table")"
+if ((size > capacity)); then
+	echo "ERROR: ${count} symbols need ${size} bytes, .ksyms has ${capacity}; raise KSYMS_SIZE" >&2
+	exit 1
+fi
+
+dd if="${workdir}/table" of="${kernel}" bs=4096 seek="${offset}" oflag=seek_bytes conv=notrunc status=none
@@ -0,0 +1,76 @@
+//! Just enough AML to find the S5 sleep type.
+//!
+//! The `SLP_TYP` values that turn the machine off are in the DSDT, which
+//! declares them as
+//!
+//! ```text
+//! Name (\_S5, Package () { SLP_TYPa, SLP_TYPb, Reserved, Reserved })
+//! ```
+//!
+//! Rather than interpret the AML, we look for the bytes of that: a
+//! `NameOp`, maybe the root prefix, `_S5_`, and a `PackageOp` whose first
+//! two elements are integer constants. The first such package wins. An
+//! `_S5_` returned by a method isn't found.
+
+const NAME_OP: u8 = 0x08;
+const ROOT_PREFIX: u8 = b'\\';
+const PACKAGE_OP: u8 = 0x12;
+const ZERO_OP: u8 = 0x00;
+const ONE_OP: u8 = 0x01;
+const BYTE_PREFIX: u8 = 0x0a;
+const WORD_PREFIX: u8 = 0x0b;
+const DWORD_PREFIX: u8 = 0x0c;
+
+/// `SLP_TYP` is 3 bits, so anything larger was misread
+const SLP_TYP_MAX: u64 = 0b111;
+
+/// The values to write to the `SLP_TYP` fields of the PM1 control
+/// registers to enter a sleep state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SleepType {
+    pub a: u8,
+    pub b: u8,
+}
+
+/// Finds the S5 sleep type in `aml`, the DSDT after its header.
+pub fn find_s5(aml: &[u8]) -> Option<SleepType> {
+    aml.windows(4)
+        .enumerate()
+        .filter(|&(at, name)| name == b"_S5_" && matches!(aml[..at], [.., NAME_OP] | [.., NAME_OP, ROOT_PREFIX]))
+        .find_map(|(at, _)| package(&aml[at + 4..]))
+}
+
+/// Reads the first two elements of the package at the start of `aml`.
+fn package(aml: &[u8]) -> Option<SleepType> {
+    let (&op, rest) = aml.split_first()?;
+    if op != PACKAGE_OP {
+        return None;
+    }
+    // Bits 6 and 7 of the PkgLength's first byte count the bytes after it
+    let extra = (*rest.first()? >> 6) as usize;
+    let (&elements, rest) = rest.get(1 + extra..)?.split_first()?;
+    if elements < 2 {
+        return None;
+    }
+
+    let (a, rest) = integer(rest)?;
+    let (b, _) = integer(rest)?;
+    Some(SleepType { a, b })
+}
+
+/// Reads an integer constant that fits in `SLP_TYP`, returning it and the
+/// bytes after it.
+fn integer(aml: &[u8]) -> Option<(u8, &[u8])> {
+    let (&op, rest) = aml.split_first()?;
+    let size = match op {
+        ZERO_OP => return Some((0, rest)),
+        ONE_OP => return Some((1, rest)),
+        BYTE_PREFIX => 1,
+        WORD_PREFIX => 2,
+        DWORD_PREFIX => 4,
+        _ => return None,
+    };
+    let bytes = rest.get(..size)?;
+    let value = bytes.iter().rev().fold(0u64, |value, &byte| value << 8 | byte as u64);
+    (value <= SLP_TYP_MAX).then(|| (value as u8, &rest[size..]))
+}
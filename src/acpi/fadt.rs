@@ -0,0 +1,113 @@
+//! The Fixed ACPI Description Table.
+//!
+//! It has the I/O ports of the power management registers and the reset
+//! register. Only the fields up to the reset value are declared; they are
+//! all there since ACPI 2.0, and older tables are shorter.
+
+use core::mem::{offset_of, size_of};
+
+use super::{SdtHeader, Table};
+use crate::error::AcpiError;
+
+/// Generic address in system memory
+pub const ADDRESS_SPACE_MEMORY: u8 = 0;
+/// Generic address in I/O space
+pub const ADDRESS_SPACE_IO: u8 = 1;
+
+/// Flag: the reset register is supported
+const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+
+/// Where a register is, in the address space it's in
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct GenericAddress {
+    pub address_space: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+/// The FADT, up to the reset value
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    _reserved: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_cnt: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm2_cnt_blk: u32,
+    pub pm_tmr_blk: u32,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub pm1_evt_len: u8,
+    pub pm1_cnt_len: u8,
+    pub pm2_cnt_len: u8,
+    pub pm_tmr_len: u8,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub cst_cnt: u8,
+    pub p_lvl2_lat: u16,
+    pub p_lvl3_lat: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alrm: u8,
+    pub mon_alrm: u8,
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    _reserved2: u8,
+    pub flags: u32,
+    /// Only valid if the table is long enough, see `reset_register`
+    pub reset_reg: GenericAddress,
+    pub reset_value: u8,
+}
+
+/// Size of an ACPI 1.0 FADT, which ends before the reset register
+const FADT_V1_SIZE: usize = offset_of!(Fadt, reset_reg);
+
+unsafe impl Table for Fadt {
+    const SIGNATURE: [u8; 4] = *b"FACP";
+    const MIN_LENGTH: usize = FADT_V1_SIZE;
+}
+
+/// Finds the FADT.
+///
+/// The table is checksummed, and is at least as long as an ACPI 1.0 one.
+pub fn read() -> Result<&'static Fadt, AcpiError> {
+    super::find::<Fadt>().ok_or(AcpiError::MissingTable(Fadt::SIGNATURE))
+}
+
+/// Returns the DSDT address from a FADT that hasn't been checked for
+/// length yet.
+pub(super) fn dsdt_address(header: &'static SdtHeader) -> Option<usize> {
+    if (header.length as usize) < offset_of!(Fadt, dsdt) + size_of::<u32>() {
+        return None;
+    }
+    let fadt = unsafe { &*(header as *const SdtHeader as *const Fadt) };
+    Some(fadt.dsdt as usize).filter(|&addr| addr != 0)
+}
+
+impl Fadt {
+    /// Returns the reset register and the value to write to it, if the
+    /// table has them.
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        let length = self.header.length as usize;
+        let flags = self.flags;
+        (length >= size_of::<Fadt>() && flags & FLAG_RESET_REG_SUP != 0)
+            .then_some((self.reset_reg, self.reset_value))
+    }
+}
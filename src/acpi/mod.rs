@@ -0,0 +1,482 @@
+//! ACPI tables.
+//!
+//! The bootloader passes a copy of the RSDP in the boot information. If
+//! it doesn't, we find the RSDP the way BIOS systems expect: in the first
+//! KB of the EBDA, or on a 16-byte boundary between `0xe0000` and
+//! `0xfffff`. [`init`] then walks the XSDT, or the RSDT on ACPI 1.0
+//! systems, once, and keeps the tables it lists, plus the DSDT from the
+//! FADT. A table with a bad checksum or length is skipped with a warning.
+//! The pages of every table kept are reserved in the page allocator.
+//!
+//! The tables are below 4GB, in identity-mapped memory. They're looked up
+//! by signature with [`find_table`], or by type with [`find`].
+//!
+//! There's no AML interpreter, so only the fixed tables can be used. The
+//! one thing needed from the DSDT's AML, the sleep type that powers the
+//! machine off, is found by a scan for its bytes.
+
+mod aml;
+pub mod fadt;
+pub mod tables;
+#[cfg(test)]
+mod test;
+
+use core::mem::size_of;
+use core::{ptr, str};
+
+use x86::io::{inw, outb, outw};
+
+use crate::boot::stage::BootArgs;
+use crate::error::{AcpiError, Error, Result, ResultExt};
+use crate::memory::multiboot2::BootInfo;
+use crate::memory::page_allocator::PageSize;
+use crate::memory::PhysAddr;
+use crate::sync::Once;
+use fadt::{ADDRESS_SPACE_IO, ADDRESS_SPACE_MEMORY};
+
+/// Segment of the EBDA, in the BIOS data area
+const EBDA_SEGMENT_PTR: usize = 0x40e;
+/// Bytes of the EBDA that are searched
+const EBDA_SEARCH_SIZE: usize = 1024;
+const BIOS_BASE: usize = 0xe0000;
+const BIOS_END: usize = 0x100000;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// The EBDA address, read once since the BIOS data area is unmapped later
+static EBDA: Once<usize> = Once::new();
+
+/// Size of the ACPI 1.0 part of the RSDP, covered by its checksum
+const RSDP_V1_SIZE: usize = 20;
+/// Offset of the revision in the RSDP
+const RSDP_REVISION: usize = 15;
+
+/// Most tables kept from the root table
+const MAX_TABLES: usize = 32;
+/// Most page allocator ranges the tables are reserved in. The allocator
+/// has room for 16, shared with the console and oops buffers.
+const MAX_RESERVED: usize = 8;
+/// Longest table believed. The DSDT is the largest, at tens of KB.
+const MAX_TABLE_LENGTH: usize = 1 << 20;
+
+/// The tables found by `init`
+static TABLES: Once<Tables> = Once::new();
+
+/// PM1 control register: the sleep state to enter
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP: u16 = 0b111 << PM1_SLP_TYP_SHIFT;
+/// PM1 control register: enter the sleep state in `SLP_TYP`
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// Where QEMU has the PM1a control register: 0x604 on q35, and 0xb004 on
+/// older `pc` machines. S5 is sleep type 0 on both.
+const QEMU_PM1A_CNT: [u16; 2] = [0x604, 0xb004];
+
+/// Root System Description Pointer
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    // ACPI 2.0 and later
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+/// The header every table starts with
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+impl SdtHeader {
+    /// Returns the signature as text, or `"????"` if it isn't ASCII.
+    pub fn name(&self) -> &str {
+        text(&self.signature).unwrap_or("????")
+    }
+
+    /// Returns the OEM ID as text, without the padding.
+    pub fn oem_id(&self) -> &str {
+        text(&self.oem_id).unwrap_or("?")
+    }
+
+    /// Returns the OEM table ID as text, without the padding.
+    pub fn oem_table_id(&self) -> &str {
+        text(&self.oem_table_id).unwrap_or("?")
+    }
+
+    /// Returns the physical address of the table.
+    pub fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+}
+
+/// Returns the ASCII in `bytes`, without trailing spaces and NULs.
+fn text(bytes: &[u8]) -> Option<&str> {
+    str::from_utf8(bytes)
+        .ok()
+        .filter(|text| text.is_ascii())
+        .map(|text| text.trim_end_matches([' ', '\0']))
+}
+
+/// A table with a fixed layout, starting with its header.
+///
+/// # Safety
+/// The type must be `repr(C, packed)`, start with an [`SdtHeader`], and be
+/// valid for any bytes.
+pub unsafe trait Table: Sized {
+    const SIGNATURE: [u8; 4];
+    /// Length of the shortest table that can be used. Fields past the
+    /// table's length must be checked before they're read.
+    const MIN_LENGTH: usize = size_of::<Self>();
+}
+
+/// Returns whether the bytes add up to zero.
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+/// Returns the `len` bytes at `addr`.
+///
+/// # Safety
+/// They must be identity-mapped and readable.
+unsafe fn bytes_at(addr: usize, len: usize) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(addr as *const u8, len) }
+}
+
+/// Returns the RSDP at the start of `bytes` if it's valid.
+///
+/// Both checksums are checked. The ACPI 1.0 RSDP is shorter, and its
+/// ACPI 2.0 fields read as zero.
+fn parse_rsdp(bytes: &[u8]) -> Option<Rsdp> {
+    let v1 = bytes.get(..RSDP_V1_SIZE)?;
+    if !v1.starts_with(RSDP_SIGNATURE) || !checksum_ok(v1) {
+        return None;
+    }
+
+    let mut copy = [0u8; size_of::<Rsdp>()];
+    let bytes = if v1[RSDP_REVISION] < 2 {
+        v1
+    } else {
+        let length = u32::from_le_bytes(bytes.get(RSDP_V1_SIZE..RSDP_V1_SIZE + 4)?.try_into().unwrap());
+        let bytes = bytes.get(..length as usize).filter(|bytes| bytes.len() >= copy.len())?;
+        if !checksum_ok(bytes) {
+            return None;
+        }
+        &bytes[..copy.len()]
+    };
+    copy[..bytes.len()].copy_from_slice(bytes);
+    Some(unsafe { ptr::read_unaligned(copy.as_ptr() as *const Rsdp) })
+}
+
+/// Looks for a valid RSDP on a 16-byte boundary.
+unsafe fn find_rsdp_in(start: usize, end: usize) -> Option<Rsdp> {
+    (start..end.saturating_sub(RSDP_V1_SIZE))
+        .step_by(16)
+        .find_map(|addr| parse_rsdp(unsafe { bytes_at(addr, end - addr) }))
+}
+
+/// Finds the tables and reserves their pages.
+///
+/// This must be called before `memory::init`, which sets up the page
+/// allocator, and before `memory::guard_null_pages` unmaps the BIOS data
+/// area.
+pub fn init(boot_info: Option<&BootInfo>) -> Result<()> {
+    ebda();
+
+    let rsdp = find_rsdp(boot_info)?;
+    let (root_addr, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address as usize, 8)
+    } else {
+        (rsdp.rsdt_address as usize, 4)
+    };
+    let tables = unsafe { Tables::walk(root_addr, entry_size)? };
+    // Without the tables there's no poweroff, so keep them even if the
+    // allocator may hand out their pages
+    if let Err(e) = reserve(&tables).context("reserving the ACPI tables") {
+        log::warn!("acpi: {}", e);
+    }
+
+    log::info!(
+        "acpi: {} tables in the {} at {:#x}, {} skipped",
+        tables.len,
+        tables.root.name(),
+        root_addr,
+        tables.skipped
+    );
+    TABLES.call_once(|| tables);
+    Ok(())
+}
+
+/// The `ACPI` boot stage.
+pub fn init_stage(_args: &BootArgs) -> Result<()> {
+    init(crate::boot::stage::boot_info())
+}
+
+/// Returns the EBDA address, or 0 if the BIOS didn't set it.
+fn ebda() -> usize {
+    *EBDA.call_once(|| unsafe { ptr::read_volatile(EBDA_SEGMENT_PTR as *const u16) as usize * 16 })
+}
+
+/// Finds the RSDP, in the boot information or else in the BIOS areas.
+fn find_rsdp(boot_info: Option<&BootInfo>) -> core::result::Result<Rsdp, AcpiError> {
+    if let Some(rsdp) = boot_info.and_then(|boot_info| boot_info.rsdp()).and_then(parse_rsdp) {
+        return Ok(rsdp);
+    }
+
+    unsafe {
+        let ebda = ebda();
+        let in_ebda = if ebda != 0 {
+            find_rsdp_in(ebda, ebda + EBDA_SEARCH_SIZE)
+        } else {
+            None
+        };
+        in_ebda
+            .or_else(|| find_rsdp_in(BIOS_BASE, BIOS_END))
+            .ok_or(AcpiError::NoRsdp)
+    }
+}
+
+/// Returns the table at `addr` if its length and checksum are right.
+///
+/// # Safety
+/// `addr` must point to a table header in identity-mapped memory.
+unsafe fn table_at(addr: usize) -> core::result::Result<&'static SdtHeader, AcpiError> {
+    let header = unsafe { &*(addr as *const SdtHeader) };
+    let length = header.length as usize;
+    if !(size_of::<SdtHeader>()..=MAX_TABLE_LENGTH).contains(&length) {
+        return Err(AcpiError::BadLength(header.signature, header.length));
+    }
+    if !checksum_ok(unsafe { bytes_at(addr, length) }) {
+        return Err(AcpiError::BadChecksum(header.signature));
+    }
+    Ok(header)
+}
+
+/// The tables listed by the root table.
+pub struct Tables {
+    /// The XSDT or RSDT
+    pub root: &'static SdtHeader,
+    tables: [Option<&'static SdtHeader>; MAX_TABLES],
+    len: usize,
+    /// Tables dropped for a bad checksum or length, or for being too many
+    pub skipped: usize,
+}
+
+impl Tables {
+    /// Reads the root table at `root_addr`, whose entries are
+    /// `entry_size` bytes, and the tables it lists.
+    ///
+    /// Only a bad root table is an error.
+    ///
+    /// # Safety
+    /// `root_addr` and every entry must point to a table header in
+    /// identity-mapped memory.
+    unsafe fn walk(root_addr: usize, entry_size: usize) -> core::result::Result<Self, AcpiError> {
+        let root = unsafe { table_at(root_addr)? };
+        let mut tables = Self {
+            root,
+            tables: [None; MAX_TABLES],
+            len: 0,
+            skipped: 0,
+        };
+
+        let entries = (root.length as usize - size_of::<SdtHeader>()) / entry_size;
+        for i in 0..entries {
+            let entry = root_addr + size_of::<SdtHeader>() + i * entry_size;
+            let addr = unsafe {
+                match entry_size {
+                    8 => ptr::read_unaligned(entry as *const u64) as usize,
+                    _ => ptr::read_unaligned(entry as *const u32) as usize,
+                }
+            };
+            unsafe { tables.add(addr) };
+        }
+
+        // The DSDT is only listed in the FADT
+        if let Some(dsdt) = tables.find(b"FACP").and_then(fadt::dsdt_address) {
+            unsafe { tables.add(dsdt) };
+        }
+        Ok(tables)
+    }
+
+    /// Keeps the table at `addr` if it's valid and there's room.
+    unsafe fn add(&mut self, addr: usize) {
+        match unsafe { table_at(addr) } {
+            Ok(table) if self.len < MAX_TABLES => {
+                self.tables[self.len] = Some(table);
+                self.len += 1;
+                return;
+            }
+            Ok(table) => log::warn!("acpi: Too many tables, skipping {}", table.name()),
+            Err(e) => log::warn!("acpi: Skipping the table at {:#x}: {}", addr, e),
+        }
+        self.skipped += 1;
+    }
+
+    /// Returns the tables in the order the root table lists them.
+    pub fn iter(&self) -> impl Iterator<Item = &'static SdtHeader> + '_ {
+        self.tables[..self.len].iter().flatten().copied()
+    }
+
+    /// Finds a table by its signature.
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+        self.iter().find(|table| table.signature == *signature)
+    }
+}
+
+/// Merges overlapping and adjacent ranges, then the closest ones until
+/// at most `max` are left, returning how many are left at the start of
+/// `ranges`.
+fn merge_ranges(ranges: &mut [(usize, usize)], max: usize) -> usize {
+    ranges.sort_unstable();
+    let mut len = 0;
+    for i in 0..ranges.len() {
+        let (start, end) = ranges[i];
+        if len > 0 && start <= ranges[len - 1].1 {
+            ranges[len - 1].1 = ranges[len - 1].1.max(end);
+        } else {
+            ranges[len] = (start, end);
+            len += 1;
+        }
+    }
+
+    // Reserving the pages between two tables is better than running out
+    while len > max.max(1) {
+        let i = (0..len - 1).min_by_key(|&i| ranges[i + 1].0 - ranges[i].1).unwrap();
+        ranges[i].1 = ranges[i + 1].1;
+        ranges.copy_within(i + 2..len, i + 1);
+        len -= 1;
+    }
+    len
+}
+
+/// Keeps the page allocator off the pages of the tables.
+///
+/// Tables are small and packed together, so their pages are merged into
+/// as few reserved ranges as possible, and the pages between the closest
+/// ones are reserved too if there are more than `MAX_RESERVED`.
+fn reserve(tables: &Tables) -> Result<()> {
+    let mut ranges = [(0, 0); MAX_TABLES + 1];
+    let mut len = 0;
+    for table in core::iter::once(tables.root).chain(tables.iter()) {
+        let start = PhysAddr(table.address());
+        let end = start.add(table.length as usize);
+        ranges[len] = (PageSize::Size4KB.align_down(start).0, PageSize::Size4KB.align_up(end).0);
+        len += 1;
+    }
+
+    let len = merge_ranges(&mut ranges[..len], MAX_RESERVED);
+    for &(start, end) in &ranges[..len] {
+        crate::memory::get_allocator().reserve_range(PhysAddr(start), end - start)?;
+    }
+    Ok(())
+}
+
+/// Returns the tables, if `init` found them.
+pub fn tables() -> Option<&'static Tables> {
+    TABLES.get()
+}
+
+/// Finds a table by its signature.
+///
+/// The table's length and checksum have been checked.
+pub fn find_table(signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+    tables()?.find(signature)
+}
+
+/// Finds a table by its type.
+///
+/// The table is at least `T::MIN_LENGTH` long.
+pub fn find<T: Table>() -> Option<&'static T> {
+    let header = find_table(&T::SIGNATURE)?;
+    ((header.length as usize) >= T::MIN_LENGTH).then(|| unsafe { &*(header as *const SdtHeader as *const T) })
+}
+
+/// Turns the machine off.
+///
+/// The machine enters the S5 sleep state through the PM1 control
+/// registers in the FADT, with the sleep type from the DSDT. If that
+/// can't be done, we try where QEMU has the registers, and halt if the
+/// machine is still on after that.
+pub fn poweroff() -> ! {
+    if let Err(e) = enter_s5() {
+        log::warn!("acpi: Can't power off: {}", e);
+    }
+
+    log::info!("acpi: Trying QEMU's power off ports");
+    for port in QEMU_PM1A_CNT {
+        unsafe {
+            outw(port, PM1_SLP_EN);
+        }
+    }
+
+    log::error!("acpi: The machine is still on, halting");
+    crate::debug::panic_guard::halt()
+}
+
+/// Enters the S5 sleep state.
+///
+/// Only returns if that didn't work.
+fn enter_s5() -> Result<()> {
+    let fadt = fadt::read()?;
+    let pm1a = io_port(fadt.pm1a_cnt_blk).ok_or(AcpiError::NoPm1aControl)?;
+    let pm1b = io_port(fadt.pm1b_cnt_blk);
+    let sleep_type = s5_sleep_type()?;
+
+    log::info!("acpi: Powering off through port {:#x}", pm1a);
+    // Both sleep types are set before either register is told to sleep
+    let registers = [(pm1a, sleep_type.a)].into_iter().chain(pm1b.map(|port| (port, sleep_type.b)));
+    for enable in [0, PM1_SLP_EN] {
+        for (port, typ) in registers.clone() {
+            unsafe {
+                let value = inw(port) & !(PM1_SLP_TYP | PM1_SLP_EN);
+                outw(port, value | (typ as u16) << PM1_SLP_TYP_SHIFT | enable);
+            }
+        }
+    }
+    Err(Error::Other("the machine didn't power off"))
+}
+
+/// Returns the I/O port of a PM1 block from the FADT, if there is one.
+fn io_port(block: u32) -> Option<u16> {
+    u16::try_from(block).ok().filter(|&port| port != 0)
+}
+
+/// Finds the `SLP_TYP` values of S5 in the DSDT.
+fn s5_sleep_type() -> Result<aml::SleepType> {
+    let dsdt = find_table(b"DSDT").ok_or(AcpiError::MissingTable(*b"DSDT"))?;
+    let header_size = size_of::<SdtHeader>();
+    let body = unsafe { bytes_at(dsdt.address() + header_size, dsdt.length as usize - header_size) };
+    aml::find_s5(body).ok_or_else(|| AcpiError::NoS5.into())
+}
+
+/// Resets the machine through the FADT reset register.
+///
+/// Only returns if there's no reset register or it didn't work.
+pub fn reset() -> Result<()> {
+    let fadt = fadt::read()?;
+    let (reg, value) = fadt.reset_register().ok_or(AcpiError::NoResetRegister)?;
+    let address = reg.address;
+
+    match reg.address_space {
+        ADDRESS_SPACE_MEMORY => unsafe { ptr::write_volatile(address as usize as *mut u8, value) },
+        ADDRESS_SPACE_IO if address <= 0xffff => unsafe { outb(address as u16, value) },
+        _ => return Err(AcpiError::NoResetRegister.into()),
+    }
+    Err(Error::Other("the machine didn't reset"))
+}
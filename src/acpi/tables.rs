@@ -0,0 +1,72 @@
+//! The fixed parts of the tables other subsystems will need.
+//!
+//! Only the fields before the variable-length entries are declared. The
+//! MADT's interrupt controller entries aren't parsed yet; the IOAPIC still
+//! comes from the MP tables.
+
+use core::mem::size_of;
+use core::ptr;
+
+use super::fadt::GenericAddress;
+use super::{SdtHeader, Table};
+
+/// The Multiple APIC Description Table
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Madt {
+    pub header: SdtHeader,
+    pub local_apic_address: u32,
+    pub flags: u32,
+}
+
+unsafe impl Table for Madt {
+    const SIGNATURE: [u8; 4] = *b"APIC";
+}
+
+/// The HPET Description Table
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub base_address: GenericAddress,
+    pub hpet_number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8,
+}
+
+unsafe impl Table for Hpet {
+    const SIGNATURE: [u8; 4] = *b"HPET";
+}
+
+/// The PCI Express memory-mapped configuration table
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Mcfg {
+    pub header: SdtHeader,
+    _reserved: u64,
+}
+
+unsafe impl Table for Mcfg {
+    const SIGNATURE: [u8; 4] = *b"MCFG";
+}
+
+/// The configuration space of a range of PCI buses
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct McfgEntry {
+    pub base_address: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+    _reserved: u32,
+}
+
+impl Mcfg {
+    /// Returns the entries after the fixed part.
+    pub fn entries(&self) -> impl Iterator<Item = McfgEntry> + '_ {
+        let start = self as *const Self as usize + size_of::<Self>();
+        let count = (self.header.length as usize).saturating_sub(size_of::<Self>()) / size_of::<McfgEntry>();
+        (0..count).map(move |i| unsafe { ptr::read_unaligned((start + i * size_of::<McfgEntry>()) as *const McfgEntry) })
+    }
+}
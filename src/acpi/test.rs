@@ -0,0 +1,234 @@
+//! ACPI table tests.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem::{offset_of, size_of};
+
+use super::fadt::{self, Fadt, GenericAddress};
+use super::tables::{Hpet, Madt, Mcfg, McfgEntry};
+use super::aml::{find_s5, SleepType};
+use super::{checksum_ok, find, find_table, merge_ranges, parse_rsdp, s5_sleep_type, tables, SdtHeader, Tables};
+use crate::error::AcpiError;
+
+/// Builds a table around `body`.
+///
+/// The checksum is made right, then `corrupt` is added to it. The table
+/// must be kept until the test is done with its address, and dropped
+/// before the test ends for the leak check.
+fn table(signature: &[u8; 4], body: &[u8], corrupt: u8) -> Box<[u8]> {
+    let mut bytes = vec![0u8; size_of::<SdtHeader>() + body.len()];
+    let length = bytes.len() as u32;
+    bytes[..4].copy_from_slice(signature);
+    bytes[4..8].copy_from_slice(&length.to_le_bytes());
+    bytes[8] = 1;
+    bytes[10..16].copy_from_slice(b"HELLO ");
+    bytes[16..24].copy_from_slice(b"TESTTABL");
+    bytes[size_of::<SdtHeader>()..].copy_from_slice(body);
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes[9] = 0u8.wrapping_sub(sum).wrapping_add(corrupt);
+    bytes.into_boxed_slice()
+}
+
+/// Returns the address of a table built by `table`.
+fn addr(table: &[u8]) -> usize {
+    table.as_ptr() as usize
+}
+
+/// Builds an XSDT listing `entries`.
+fn xsdt(entries: &[&[u8]]) -> Box<[u8]> {
+    let body: alloc::vec::Vec<u8> = entries.iter().flat_map(|entry| (addr(entry) as u64).to_le_bytes()).collect();
+    table(b"XSDT", &body, 0)
+}
+
+fn signatures(tables: &Tables) -> alloc::vec::Vec<[u8; 4]> {
+    tables.iter().map(|table| table.signature).collect()
+}
+
+#[test_case]
+fn table_layouts_match_the_spec() {
+    assert_eq!(size_of::<SdtHeader>(), 36);
+    assert_eq!(size_of::<GenericAddress>(), 12);
+    assert_eq!(offset_of!(Fadt, pm1a_evt_blk), 56);
+    assert_eq!(offset_of!(Fadt, pm1a_cnt_blk), 64);
+    assert_eq!(offset_of!(Fadt, pm_tmr_blk), 76);
+    assert_eq!(offset_of!(Fadt, gpe0_blk), 80);
+    assert_eq!(offset_of!(Fadt, flags), 112);
+    assert_eq!(offset_of!(Fadt, reset_reg), 116);
+    assert_eq!(offset_of!(Fadt, reset_value), 128);
+}
+
+#[test_case]
+fn checksum() {
+    assert!(checksum_ok(&[]));
+    assert!(checksum_ok(&[0x01, 0xff]));
+    assert!(!checksum_ok(&[0x01, 0xfe]));
+}
+
+#[test_case]
+fn qemu_has_a_fadt() {
+    // QEMU's PM1a blocks are in I/O space
+    let fadt = fadt::read().expect("no FADT");
+    let (pm1a_evt_blk, pm1a_cnt_blk) = (fadt.pm1a_evt_blk, fadt.pm1a_cnt_blk);
+    assert_ne!(pm1a_cnt_blk, 0);
+    assert_eq!(pm1a_cnt_blk, pm1a_evt_blk + 4);
+    // S5 is sleep type 0 on both of QEMU's chipsets
+    assert_eq!(s5_sleep_type(), Ok(SleepType { a: 0, b: 0 }));
+}
+
+#[test_case]
+fn missing_table() {
+    assert!(find_table(b"NONE").is_none());
+}
+
+#[test_case]
+fn fixed_table_layouts() {
+    assert_eq!(size_of::<Madt>(), 44);
+    assert_eq!(size_of::<Hpet>(), 56);
+    assert_eq!(size_of::<Mcfg>(), 44);
+    assert_eq!(size_of::<McfgEntry>(), 16);
+}
+
+#[test_case]
+fn walk_keeps_good_tables() {
+    let apic = table(b"APIC", &[0, 0, 0xe0, 0xfe, 1, 0, 0, 0], 0);
+    let hpet = table(b"HPET", &[0; 20], 0);
+    let root = xsdt(&[&apic, &hpet]);
+
+    let tables = unsafe { Tables::walk(addr(&root), 8) }.unwrap();
+    assert_eq!(tables.root.address(), addr(&root));
+    assert_eq!(signatures(&tables), [*b"APIC", *b"HPET"]);
+    assert_eq!(tables.skipped, 0);
+
+    let madt = tables.find(b"APIC").unwrap();
+    assert_eq!(madt.address(), addr(&apic));
+    assert_eq!((madt.name(), madt.oem_id(), madt.oem_table_id()), ("APIC", "HELLO", "TESTTABL"));
+    assert!(tables.find(b"MCFG").is_none());
+}
+
+#[test_case]
+fn walk_skips_bad_tables() {
+    let good = table(b"APIC", &[0; 8], 0);
+    let bad_checksum = table(b"HPET", &[0; 20], 1);
+    let mut bad_length = table(b"SSDT", &[], 0);
+    // Too short to hold its own header
+    bad_length[4..8].copy_from_slice(&8u32.to_le_bytes());
+    let root = xsdt(&[&bad_checksum, &good, &bad_length]);
+
+    let tables = unsafe { Tables::walk(addr(&root), 8) }.unwrap();
+    assert_eq!(signatures(&tables), [*b"APIC"]);
+    assert_eq!(tables.skipped, 2);
+}
+
+#[test_case]
+fn walk_fails_on_a_bad_root() {
+    let apic = table(b"APIC", &[0; 8], 0);
+    let root = table(b"XSDT", &(addr(&apic) as u64).to_le_bytes(), 0x10);
+    assert_eq!(unsafe { Tables::walk(addr(&root), 8) }.err(), Some(AcpiError::BadChecksum(*b"XSDT")));
+}
+
+#[test_case]
+fn walk_adds_the_dsdt() {
+    let dsdt = table(b"DSDT", &[0x10, 0x20], 0);
+    // The FADT only has room for a 32-bit address
+    if addr(&dsdt) > u32::MAX as usize {
+        return;
+    }
+    let mut body = [0u8; 80];
+    let dsdt_offset = offset_of!(Fadt, dsdt) - size_of::<SdtHeader>();
+    body[dsdt_offset..dsdt_offset + 4].copy_from_slice(&(addr(&dsdt) as u32).to_le_bytes());
+    let facp = table(b"FACP", &body, 0);
+    let root = xsdt(&[&facp]);
+
+    let tables = unsafe { Tables::walk(addr(&root), 8) }.unwrap();
+    assert_eq!(signatures(&tables), [*b"FACP", *b"DSDT"]);
+}
+
+#[test_case]
+fn rsdp_checksums() {
+    let mut rsdp = [0u8; 36];
+    rsdp[..8].copy_from_slice(b"RSD PTR ");
+    rsdp[16..20].copy_from_slice(&0x1234u32.to_le_bytes());
+    let sum = rsdp[..20].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    rsdp[8] = 0u8.wrapping_sub(sum);
+    assert!(parse_rsdp(&rsdp[..20]).is_some());
+
+    // An ACPI 2.0 RSDP needs its extended checksum too
+    rsdp[15] = 2;
+    rsdp[8] = rsdp[8].wrapping_sub(2);
+    rsdp[20..24].copy_from_slice(&36u32.to_le_bytes());
+    assert!(parse_rsdp(&rsdp).is_none());
+    let sum = rsdp.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    rsdp[32] = 0u8.wrapping_sub(sum);
+    let parsed = parse_rsdp(&rsdp).unwrap();
+    assert_eq!({ parsed.rsdt_address }, 0x1234);
+    assert!(parse_rsdp(&rsdp[..30]).is_none());
+}
+
+#[test_case]
+fn ranges_are_merged() {
+    let mut ranges = [(0x3000, 0x5000), (0x1000, 0x2000), (0x2000, 0x3000), (0x8000, 0x9000), (0x4000, 0x4800)];
+    let len = merge_ranges(&mut ranges, 8);
+    assert_eq!(ranges[..len], [(0x1000, 0x5000), (0x8000, 0x9000)]);
+    assert_eq!(merge_ranges(&mut [], 8), 0);
+}
+
+#[test_case]
+fn closest_ranges_are_merged_to_fit() {
+    let mut ranges = [(0x1000, 0x2000), (0x10000, 0x11000), (0x4000, 0x5000), (0x20000, 0x21000), (0x3000, 0x3800)];
+    let len = merge_ranges(&mut ranges, 2);
+    assert_eq!(ranges[..len], [(0x1000, 0x11000), (0x20000, 0x21000)]);
+
+    let mut ranges = [(0x1000, 0x2000), (0x8000, 0x9000)];
+    let len = merge_ranges(&mut ranges, 1);
+    assert_eq!(ranges[..len], [(0x1000, 0x9000)]);
+}
+
+#[test_case]
+fn qemu_tables_are_found() {
+    let tables = tables().expect("ACPI tables weren't found");
+    assert!(tables.find(b"FACP").is_some());
+    assert!(tables.find(b"DSDT").is_some());
+    let madt = find::<Madt>().expect("no MADT");
+    assert_eq!({ madt.local_apic_address }, 0xfee0_0000);
+}
+
+#[test_case]
+fn s5_is_found() {
+    // Name (_S5, Package (0x04) { Zero, Zero, Zero, Zero }), from QEMU
+    let qemu = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(find_s5(&qemu), Some(SleepType { a: 0, b: 0 }));
+
+    // Name (\_S5, Package () { 0x07, One }) after some other AML, with a
+    // two-byte PkgLength
+    let mut aml = vec![0x5b, 0x80, b'P', b'M', b'I', b'O', 0x01];
+    aml.extend([0x08, b'\\', b'_', b'S', b'5', b'_', 0x12, 0x45, 0x00, 0x02, 0x0a, 0x07, 0x01]);
+    assert_eq!(find_s5(&aml), Some(SleepType { a: 7, b: 1 }));
+
+    // Word and dword constants
+    let wide = [0x08, b'_', b'S', b'5', b'_', 0x12, 0x0a, 0x02, 0x0b, 0x05, 0x00, 0x0c, 0x06, 0, 0, 0];
+    assert_eq!(find_s5(&wide), Some(SleepType { a: 5, b: 6 }));
+}
+
+#[test_case]
+fn s5_scan_skips_lookalikes() {
+    // A reference to _S5_ and a method named _S5_ aren't the package
+    let mut aml = vec![0x70, b'_', b'S', b'5', b'_', 0x60];
+    aml.extend([0x14, 0x08, b'_', b'S', b'5', b'_', 0x00, 0xa4, 0x00]);
+    assert_eq!(find_s5(&aml), None);
+
+    aml.extend([0x08, b'_', b'S', b'5', b'_', 0x12, 0x05, 0x02, 0x0a, 0x05, 0x0a, 0x05]);
+    assert_eq!(find_s5(&aml), Some(SleepType { a: 5, b: 5 }));
+}
+
+#[test_case]
+fn s5_scan_rejects_bad_packages() {
+    // Not a package
+    assert_eq!(find_s5(&[0x08, b'_', b'S', b'5', b'_', 0x0a, 0x05]), None);
+    // One element
+    assert_eq!(find_s5(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x03, 0x01, 0x00]), None);
+    // Too large for SLP_TYP
+    assert_eq!(find_s5(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x05, 0x02, 0x0a, 0x08, 0x00]), None);
+    // Cut off
+    assert_eq!(find_s5(&[0x08, b'_', b'S', b'5', b'_', 0x12, 0x06, 0x04, 0x0a]), None);
+    assert_eq!(find_s5(b"_S5_"), None);
+}
@@ -1,13 +1,7 @@
 global start
 global long_mode_start
-global _bootinfo
 extern rust_main
 
-section .data
-; Storage for multiboot info pointer
-_bootinfo:
-    dq 0
-
 section .text
 
 bits 64
@@ -24,16 +18,24 @@ long_mode_start:
     ; setup stack
     mov rsp, stack_top
 
+    ; terminate the frame pointer chain for backtraces
+    xor ebp, ebp
+
+    ; rust_main(boot_info, magic). The upper halves of the registers are
+    ; undefined after the switch to long mode, and 32-bit moves clear them
+    mov edi, ebx
+    mov esi, esi
+
     call rust_main
 
     hlt
 
 bits 32    ; By default, GRUB sets us to 32-bit mode.
 start:
-    ; Save multiboot info pointer from EBX
-    ; EAX contains the magic number, EBX contains the multiboot info pointer
-    call check_multiboot
-    
+    ; EAX contains the magic number, EBX the multiboot info pointer.
+    ; rust_main checks the magic. Nothing below touches EBX or ESI
+    mov esi, eax
+
     ; Move stack pointer to our stack
     mov esp, stack_top
 
@@ -50,22 +52,6 @@ start:
     ; jump to long mode 
     jmp gdt64.code:long_mode_start
 
-check_multiboot:
-    ; Check if EAX contains the multiboot2 magic number
-    cmp eax, 0x36d76289
-    jne .no_multiboot
-    
-    ; Save the multiboot info pointer from EBX
-    ; We need to save it for later use in Rust
-    mov dword [_bootinfo], ebx
-    mov dword [_bootinfo + 4], 0
-    
-    ret
-
-.no_multiboot:
-    mov al, 'M'
-    jmp error
-
 set_up_page_tables:
     ; Clear the page tables
     mov edi, p4_table
@@ -114,15 +100,6 @@ enable_paging:
 
     ret
 
-; Prints `ERR: ` and the given error code to screen and hangs.
-; parameter: error code (in ascii) in al
-error:
-    mov dword [0xb8000], 0x4f524f45
-    mov dword [0xb8004], 0x4f3a4f52
-    mov dword [0xb8008], 0x4f204f20
-    mov byte  [0xb800a], al
-    hlt
-
 section .rodata
 gdt64:
     dq 0 ; zero entry
@@ -0,0 +1,47 @@
+//! Entry point of the application processors.
+
+use core::sync::atomic::Ordering;
+
+use crate::{cpu, gdt, interrupt, user};
+
+/// Initializes an application processor and leaves it idle.
+///
+/// The startup code jumps here in long mode, on the stack that was passed
+/// to `lapic::boot_ap`.
+#[unsafe(no_mangle)]
+pub extern "C" fn ap_rust_main() -> ! {
+    unsafe {
+        // Selects this CPU's entry in `CPUS` by its LAPIC ID
+        cpu::set_up_per_cpu_ptr();
+        if cpu::get_cpu_id() as usize >= crate::config::get().max_cpus as usize {
+            // Limited on the command line
+            loop {
+                core::arch::asm!("cli; hlt");
+            }
+        }
+        gdt::init_cpu();
+        cpu::enable_protections();
+        cpu::fpu::init();
+        user::syscall::init_cpu();
+
+        // Every LAPIC is at the same physical address, so `lapic::init`,
+        // called from here, just sees this CPU's own
+        if let Err(e) = interrupt::init_cpu() {
+            log::error!("AP {}: {}", cpu::get_cpu_id(), e);
+            loop {
+                core::arch::asm!("cli; hlt");
+            }
+        }
+    }
+
+    super::ONLINE_CPU_COUNT.fetch_add(1, Ordering::AcqRel);
+    super::wait_at_barrier();
+
+    crate::serial_println!("AP {} online", cpu::get_cpu_id());
+
+    loop {
+        unsafe {
+            core::arch::asm!("hlt");
+        }
+    }
+}
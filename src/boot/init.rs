@@ -0,0 +1,32 @@
+//! The initialization stages of the bootstrap processor.
+//!
+//! Each stage function lives in the subsystem it sets up. This table only
+//! says in what order they run and what each one needs, see
+//! [`stage`](super::stage) for how.
+
+use super::stage::InitStage;
+use crate::{acpi, config, console, cpu, debug, drivers, interrupt, memory, serial};
+
+/// The stages, in the order they run.
+pub static STAGES: [InitStage; 15] = [
+    InitStage::new("config", true, &[], config::init_stage),
+    InitStage::new("console", true, &["config"], console::init_stage),
+    InitStage::new("oops record", false, &["console"], debug::oops::init_stage),
+    InitStage::new("modules", true, &["console"], memory::modules_stage),
+    InitStage::new("per-CPU setup", true, &[], cpu::init_stage),
+    // Before memory, so the tables' pages are reserved before the page
+    // allocator starts handing pages out
+    InitStage::new("ACPI", false, &["console"], acpi::init_stage),
+    // Before interrupts, since interrupt handlers might allocate, and
+    // after `modules`, since it may hand out module memory
+    InitStage::new("memory", true, &["per-CPU setup", "modules"], memory::init_stage),
+    InitStage::new("framebuffer", false, &["console", "memory"], |_| console::init_late()),
+    InitStage::new("serial buffer", false, &["memory"], |_| serial::init_buffer()),
+    InitStage::new("interrupts", true, &["memory"], interrupt::init_stage),
+    InitStage::new("local interrupts", true, &["interrupts", "per-CPU setup"], interrupt::init_cpu_stage),
+    InitStage::new("keyboard", false, &["local interrupts"], |_| drivers::ps2_keyboard::init()),
+    InitStage::new("mouse", false, &["keyboard"], |_| drivers::ps2_mouse::init()),
+    InitStage::new("null guard", false, &["memory"], memory::null_guard_stage),
+    InitStage::new("AP barrier", false, &["local interrupts"], super::ap_barrier_stage),
+];
+
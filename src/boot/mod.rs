@@ -0,0 +1,75 @@
+//! Bringing up the CPUs.
+//!
+//! The bootstrap processor enters Rust at `rust_main`. Application
+//! processors enter at [`ap_main::ap_rust_main`] once `lapic::boot_ap` has
+//! started them. Each one counts itself online and then waits at the boot
+//! barrier, which the bootstrap processor joins once it has finished
+//! initializing the kernel. If some APs don't get there in time, the
+//! bootstrap processor breaks the barrier, which lets the APs waiting at
+//! it go, and any that come later go straight through.
+
+pub mod ap_main;
+pub mod init;
+pub mod phase;
+pub mod stage;
+#[cfg(test)]
+mod test;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::error::Result;
+use crate::sync::{Barrier, Once};
+use stage::BootArgs;
+
+/// How long the bootstrap processor waits for the others at the barrier.
+const BOOT_BARRIER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Number of CPUs that have finished their own initialization, starting
+/// with the bootstrap processor.
+static ONLINE_CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// Where the CPUs that are online meet before the APs go idle.
+///
+/// It's created once we know how many CPUs came up.
+static BOOT_BARRIER: Once<Barrier> = Once::new();
+
+/// Returns the number of CPUs that are online.
+pub fn online_cpu_count() -> usize {
+    ONLINE_CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Lets the application processors past the boot barrier.
+///
+/// This should be called by the bootstrap processor once the kernel is
+/// initialized and every AP is online. APs that don't show up are
+/// reported rather than waited for, and pass the broken barrier whenever
+/// they do.
+pub fn release_aps() {
+    let barrier = BOOT_BARRIER.call_once(|| Barrier::new(online_cpu_count()));
+    match barrier.wait_timeout(BOOT_BARRIER_TIMEOUT) {
+        Ok(result) if result.is_leader() => log::debug!("All CPUs reached the boot barrier"),
+        Ok(_) => {}
+        Err(timeout) => log::warn!("CPUs {:#x} did not reach the boot barrier", timeout.missing),
+    }
+}
+
+/// The `AP barrier` boot stage.
+pub fn ap_barrier_stage(_args: &BootArgs) -> Result<()> {
+    release_aps();
+    Ok(())
+}
+
+/// Waits until the bootstrap processor calls `release_aps`.
+fn wait_at_barrier() {
+    let barrier = loop {
+        match BOOT_BARRIER.get() {
+            Some(barrier) => break barrier,
+            None => core::hint::spin_loop(),
+        }
+    };
+    // Broken if some AP was late, which the bootstrap processor reported
+    if barrier.wait().is_ok_and(|result| result.is_leader()) {
+        log::debug!("All CPUs reached the boot barrier");
+    }
+}
@@ -0,0 +1,326 @@
+//! Boot phases and their outcomes.
+//!
+//! `rust_main` runs each fallible part of initialization as a phase and
+//! records how it went. A failed optional phase is only reported, so the
+//! kernel carries on without it. A failed required phase prints the table
+//! of everything so far before panicking, so it's clear what did work.
+//! A phase whose prerequisite failed is recorded as skipped.
+//!
+//! Phases are also timed, to see where boot time goes. [`time`] records
+//! the TSC at the start and end of a stretch of initialization into a
+//! global table, and [`BootPhases::run`] times every phase it runs. Timed
+//! phases can nest. Once booted, [`boot_complete`] marks the time to idle
+//! and [`times`] returns the table, which prints with the share of each
+//! phase.
+
+use core::fmt;
+
+use crate::cpu::tsc;
+use crate::error::{Error, Result};
+use crate::sync::Mutex;
+
+/// Maximum number of phases that are recorded.
+const MAX_PHASES: usize = 24;
+
+/// Maximum number of timed phases.
+const MAX_TIMED: usize = 32;
+
+/// The timed phases so far.
+static TIMES: Mutex<PhaseTimes> = Mutex::new(PhaseTimes::new());
+
+/// A phase that has run.
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub name: &'static str,
+    pub required: bool,
+    pub result: Result<()>,
+}
+
+/// The phases that have run, in order.
+pub struct BootPhases {
+    phases: [Option<Phase>; MAX_PHASES],
+    len: usize,
+}
+
+impl BootPhases {
+    pub const fn new() -> Self {
+        Self {
+            phases: [const { None }; MAX_PHASES],
+            len: 0,
+        }
+    }
+
+    /// Runs a phase and records its result.
+    ///
+    /// Returns whether it succeeded. If a required phase fails, the table
+    /// is printed and we panic.
+    pub fn run(&mut self, name: &'static str, required: bool, f: impl FnOnce() -> Result<()>) -> bool {
+        let timer = time(name);
+        let result = f();
+        drop(timer);
+        match &result {
+            Ok(()) => log::debug!("Boot phase {} done", name),
+            Err(e) => log::warn!("Boot phase {} failed: {}", name, e),
+        }
+        self.record(name, required, result)
+    }
+
+    /// Records a phase that didn't run because `prerequisite` failed.
+    ///
+    /// A required phase can't be skipped, so we panic as if it failed.
+    pub fn skip(&mut self, name: &'static str, required: bool, prerequisite: &'static str) {
+        log::warn!("Boot phase {} skipped: {} failed", name, prerequisite);
+        self.record(name, required, Err(Error::Skipped(prerequisite)));
+    }
+
+    /// Returns whether the phase `name` has run and succeeded.
+    pub fn succeeded(&self, name: &str) -> bool {
+        self.iter().any(|phase| phase.name == name && phase.result.is_ok())
+    }
+
+    fn record(&mut self, name: &'static str, required: bool, result: Result<()>) -> bool {
+        let ok = result.is_ok();
+        if self.len < MAX_PHASES {
+            self.phases[self.len] = Some(Phase {
+                name,
+                required,
+                result: result.clone(),
+            });
+            self.len += 1;
+        }
+
+        if let (true, Err(e)) = (required, result) {
+            crate::println!("{}", self);
+            panic!("Required boot phase {} failed: {}", name, e);
+        }
+        ok
+    }
+
+    /// Returns the phases that have run.
+    pub fn iter(&self) -> impl Iterator<Item = &Phase> {
+        self.phases[..self.len].iter().flatten()
+    }
+
+    /// Returns the error of the first phase that failed.
+    #[cfg(test)]
+    pub fn first_failure(&self) -> Option<(&'static str, &Error)> {
+        self.iter()
+            .find_map(|phase| phase.result.as_ref().err().map(|e| (phase.name, e)))
+    }
+}
+
+/// Prints a table with a line per phase
+impl fmt::Display for BootPhases {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Boot phases:")?;
+        for phase in self.iter() {
+            let kind = if phase.required { "" } else { " (optional)" };
+            match &phase.result {
+                Ok(()) => write!(f, "\n  {:<20} ok{}", phase.name, kind)?,
+                Err(Error::Skipped(prerequisite)) => {
+                    write!(f, "\n  {:<20} skipped{}: {} failed", phase.name, kind, prerequisite)?
+                }
+                Err(e) => write!(f, "\n  {:<20} failed{}: {}", phase.name, kind, e)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A phase with its TSC timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedPhase {
+    pub name: &'static str,
+    /// Number of phases it's nested in
+    pub depth: usize,
+    pub start: u64,
+    /// `None` while it's still running
+    pub end: Option<u64>,
+}
+
+/// The table of timed phases.
+#[derive(Debug, Clone)]
+pub struct PhaseTimes {
+    phases: [Option<TimedPhase>; MAX_TIMED],
+    len: usize,
+    /// Phases that are running, including ones that didn't fit
+    open: usize,
+    /// Phases that are running but didn't fit
+    open_dropped: usize,
+    /// TSC value the total counts from
+    boot_tsc: u64,
+    /// TSC value when the kernel went idle
+    idle_tsc: Option<u64>,
+    /// TSC frequency, zero if it isn't known
+    tsc_hz: u64,
+}
+
+impl PhaseTimes {
+    pub const fn new() -> Self {
+        Self {
+            phases: [None; MAX_TIMED],
+            len: 0,
+            open: 0,
+            open_dropped: 0,
+            boot_tsc: 0,
+            idle_tsc: None,
+            tsc_hz: 0,
+        }
+    }
+
+    /// Starts a phase at the TSC value `tsc`.
+    pub fn begin_at(&mut self, name: &'static str, tsc: u64) {
+        if self.len < MAX_TIMED && self.open_dropped == 0 {
+            self.phases[self.len] = Some(TimedPhase {
+                name,
+                depth: self.open,
+                start: tsc,
+                end: None,
+            });
+            self.len += 1;
+        } else {
+            self.open_dropped += 1;
+        }
+        self.open += 1;
+    }
+
+    /// Ends the innermost running phase at the TSC value `tsc`.
+    pub fn end_at(&mut self, tsc: u64) {
+        if self.open == 0 {
+            return;
+        }
+        self.open -= 1;
+        if self.open_dropped > 0 {
+            self.open_dropped -= 1;
+            return;
+        }
+        let running = self.phases[..self.len]
+            .iter_mut()
+            .flatten()
+            .rev()
+            .find(|phase| phase.end.is_none());
+        if let Some(phase) = running {
+            phase.end = Some(tsc);
+        }
+    }
+
+    /// Marks the end of boot at the TSC value `tsc`.
+    pub fn idle_at(&mut self, tsc: u64) {
+        self.idle_tsc = Some(tsc);
+    }
+
+    /// Sets where the total starts and how to convert cycles to time.
+    pub fn set_clock(&mut self, boot_tsc: u64, tsc_hz: u64) {
+        self.boot_tsc = boot_tsc;
+        self.tsc_hz = tsc_hz;
+    }
+
+    /// Returns the phases, in the order they started.
+    pub fn iter(&self) -> impl Iterator<Item = &TimedPhase> {
+        self.phases[..self.len].iter().flatten()
+    }
+
+    /// Returns the cycles a phase took, or `None` if it's still running.
+    pub fn cycles(&self, phase: &TimedPhase) -> Option<u64> {
+        Some(phase.end?.saturating_sub(phase.start))
+    }
+
+    /// Returns the cycles from boot to idle, or `None` before idle.
+    pub fn total_cycles(&self) -> Option<u64> {
+        Some(self.idle_tsc?.saturating_sub(self.boot_tsc))
+    }
+
+    /// Returns `cycles` in microseconds, or `None` without a TSC frequency.
+    pub fn micros(&self, cycles: u64) -> Option<u64> {
+        if self.tsc_hz == 0 {
+            return None;
+        }
+        Some((cycles as u128 * 1_000_000 / self.tsc_hz as u128) as u64)
+    }
+
+    /// Returns `cycles` as tenths of a percent of the total.
+    pub fn permille(&self, cycles: u64) -> Option<u64> {
+        let total = self.total_cycles().filter(|&total| total > 0)?;
+        Some((cycles as u128 * 1000 / total as u128) as u64)
+    }
+
+    /// Writes a duration in ms, or in cycles without a TSC frequency.
+    fn write_duration(&self, f: &mut fmt::Formatter<'_>, cycles: u64) -> fmt::Result {
+        match self.micros(cycles) {
+            Some(us) => write!(f, "{:>7}.{:03} ms", us / 1000, us % 1000),
+            None => write!(f, "{:>11} cy", cycles),
+        }
+    }
+
+    fn write_share(&self, f: &mut fmt::Formatter<'_>, cycles: u64) -> fmt::Result {
+        match self.permille(cycles) {
+            Some(permille) => write!(f, " {:>4}.{}%", permille / 10, permille % 10),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Prints a line per phase, indented by nesting, and the time to idle
+impl fmt::Display for PhaseTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Boot time:")?;
+        if self.tsc_hz == 0 {
+            write!(f, " (TSC not calibrated, in cycles)")?;
+        }
+        for phase in self.iter() {
+            let indent = 2 + 2 * phase.depth;
+            write!(f, "\n{:indent$}{:<width$}", "", phase.name, width = 26 - indent.min(24))?;
+            match self.cycles(phase) {
+                Some(cycles) => {
+                    self.write_duration(f, cycles)?;
+                    self.write_share(f, cycles)?;
+                }
+                None => write!(f, "    running")?,
+            }
+        }
+        if let Some(total) = self.total_cycles() {
+            write!(f, "\n  {:<24}", "total to idle")?;
+            self.write_duration(f, total)?;
+        }
+        Ok(())
+    }
+}
+
+/// Ends a timed phase when dropped.
+#[must_use = "the phase ends when this is dropped"]
+pub struct PhaseTimer(());
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        phase_end();
+    }
+}
+
+/// Starts a timed phase.
+pub fn phase_begin(name: &'static str) {
+    TIMES.lock().begin_at(name, tsc::rdtsc());
+}
+
+/// Ends the innermost running timed phase.
+pub fn phase_end() {
+    TIMES.lock().end_at(tsc::rdtsc());
+}
+
+/// Times a phase until the returned guard is dropped.
+pub fn time(name: &'static str) -> PhaseTimer {
+    phase_begin(name);
+    PhaseTimer(())
+}
+
+/// Marks the end of boot.
+pub fn boot_complete() {
+    TIMES.lock().idle_at(tsc::rdtsc());
+}
+
+/// Returns the timed phases, ready to print.
+pub fn times() -> PhaseTimes {
+    let clock = crate::time::clock();
+    let mut times = TIMES.lock().clone();
+    times.set_clock(clock.boot_tsc, clock.tsc_hz);
+    times
+}
@@ -0,0 +1,128 @@
+//! Running initialization as stages with prerequisites.
+//!
+//! `rust_main` used to call every subsystem's init function in a row, and
+//! what had to come before what was only in comments. Now each stage
+//! says which stages it needs in [`STAGES`](super::init::STAGES), and
+//! [`run`] goes through them in order as boot phases. A stage whose
+//! prerequisite failed or was skipped is skipped in turn, and a required
+//! stage that fails or is skipped stops the boot with the table of phases.
+//!
+//! [`check_order`] checks that every stage comes after the ones it needs,
+//! so moving a stage up is caught by the tests instead of at boot.
+
+use super::phase::BootPhases;
+use crate::cmdline::CommandLine;
+use crate::error::Result;
+use crate::memory::multiboot2::{self, BootInfo};
+use crate::sync::Once;
+
+/// What the bootloader passed to the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct BootArgs {
+    /// Physical address of the multiboot2 information
+    pub boot_info_addr: usize,
+    /// What the bootloader left in EAX
+    pub magic: u32,
+}
+
+impl BootArgs {
+    /// Returns whether a multiboot2 bootloader started the kernel, so
+    /// `boot_info_addr` means something.
+    pub fn is_from_multiboot2(&self) -> bool {
+        self.magic == multiboot2::BOOTLOADER_MAGIC
+    }
+}
+
+/// A step of initialization.
+#[derive(Debug, Clone, Copy)]
+pub struct InitStage {
+    pub name: &'static str,
+    /// Whether the kernel can't run without it
+    pub required: bool,
+    /// Stages that have to succeed before this one runs
+    pub after: &'static [&'static str],
+    pub run: fn(&BootArgs) -> Result<()>,
+}
+
+impl InitStage {
+    pub const fn new(
+        name: &'static str,
+        required: bool,
+        after: &'static [&'static str],
+        run: fn(&BootArgs) -> Result<()>,
+    ) -> Self {
+        Self {
+            name,
+            required,
+            after,
+            run,
+        }
+    }
+}
+
+/// The boot information, parsed by the `config` stage.
+static BOOT_INFO: Once<Option<&'static BootInfo>> = Once::new();
+
+/// Returns the boot information, if the bootloader passed a valid one.
+pub fn boot_info() -> Option<&'static BootInfo> {
+    BOOT_INFO.get().copied().flatten()
+}
+
+/// Returns the kernel command line.
+pub fn cmdline() -> CommandLine<'static> {
+    let cmdline = boot_info()
+        .and_then(|boot_info| boot_info.command_line().ok())
+        .unwrap_or("");
+    CommandLine::new(cmdline)
+}
+
+/// Runs `stages` in order and returns how each went.
+///
+/// Panics if a required stage fails or is skipped, or if the stages are
+/// out of order.
+pub fn run(stages: &[InitStage], args: &BootArgs) -> BootPhases {
+    if let Err((stage, prerequisite)) = check_order(stages) {
+        panic!("Bad init order: {} needs {}", stage, prerequisite);
+    }
+
+    let mut phases = BootPhases::new();
+    for stage in stages {
+        match stage.after.iter().find(|&&name| !phases.succeeded(name)) {
+            Some(&prerequisite) => phases.skip(stage.name, stage.required, prerequisite),
+            None => {
+                phases.run(stage.name, stage.required, || (stage.run)(args));
+            }
+        }
+    }
+    phases
+}
+
+/// Checks that every stage comes after the stages it needs, and that a
+/// required stage only needs required stages.
+///
+/// Returns the first stage that doesn't, with the stage it needs.
+pub fn check_order(stages: &[InitStage]) -> core::result::Result<(), (&'static str, &'static str)> {
+    for (i, stage) in stages.iter().enumerate() {
+        for &name in stage.after {
+            let ok = stages[..i]
+                .iter()
+                .find(|earlier| earlier.name == name)
+                .is_some_and(|earlier| earlier.required || !stage.required);
+            if !ok {
+                return Err((stage.name, name));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses the boot information, if a multiboot2 bootloader passed it.
+///
+/// Without the magic, the address is whatever was in EBX, so it isn't
+/// read at all.
+pub fn read_boot_info(args: &BootArgs) {
+    BOOT_INFO.call_once(|| {
+        let addr = if args.is_from_multiboot2() { args.boot_info_addr } else { 0 };
+        unsafe { BootInfo::parse(addr as *const u8).ok() }
+    });
+}
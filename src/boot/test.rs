@@ -0,0 +1,176 @@
+//! Boot phase tests.
+
+use core::fmt::Write;
+
+use super::init::STAGES;
+use super::stage::{self, BootArgs, InitStage};
+use super::phase::{BootPhases, PhaseTimes};
+use crate::error::{Error, MultibootError};
+use crate::memory;
+use crate::platform::MemorySource;
+use crate::testing::Buffer;
+
+/// Boot information with nothing but the end tag.
+#[repr(C, align(8))]
+struct EmptyBootInfo([u32; 4]);
+
+static EMPTY_BOOT_INFO: EmptyBootInfo = EmptyBootInfo([16, 0, 0, 8]);
+
+#[test_case]
+fn missing_memory_map_is_reported_with_phase() {
+    let mut phases = BootPhases::new();
+    let addr = &EMPTY_BOOT_INFO as *const EmptyBootInfo as usize;
+
+    let ok = phases.run("memory", false, || unsafe { memory::init(MemorySource::Multiboot2(addr)) });
+    assert!(!ok);
+    let (name, error) = phases.first_failure().unwrap();
+    assert_eq!(name, "memory");
+    assert_eq!(error.root_cause(), Error::Multiboot(MultibootError::MissingTag(6)));
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", phases).unwrap();
+    assert!(buffer
+        .as_str()
+        .contains(
+            "memory               failed (optional): initializing memory: \
+             reading the memory map: multiboot: no tag of type 6"
+        ));
+
+    // The real boot information is still in use
+    assert!(memory::boot_info().unwrap().memory_map_tag().is_ok());
+}
+
+#[test_case]
+fn phases_are_listed_in_order() {
+    let mut phases = BootPhases::new();
+    assert!(phases.run("first", true, || Ok(())));
+    assert!(!phases.run("second", false, || Err(Error::Other("broken"))));
+    assert!(phases.run("third", true, || Ok(())));
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", phases).unwrap();
+    let mut lines = buffer.as_str().lines();
+    assert_eq!(lines.next(), Some("Boot phases:"));
+    assert_eq!(lines.next(), Some("  first                ok"));
+    assert_eq!(lines.next(), Some("  second               failed (optional): broken"));
+    assert_eq!(lines.next(), Some("  third                ok"));
+    assert_eq!(lines.next(), None);
+    assert_eq!(phases.first_failure().map(|(name, _)| name), Some("second"));
+}
+
+#[test_case]
+fn init_stages_are_in_order() {
+    assert_eq!(stage::check_order(&STAGES), Ok(()));
+}
+
+#[test_case]
+fn out_of_order_stages_are_caught() {
+    let ok = |_: &BootArgs| Ok(());
+    let stages = [
+        InitStage { name: "interrupts", required: true, after: &["memory"], run: ok },
+        InitStage { name: "memory", required: true, after: &[], run: ok },
+    ];
+    assert_eq!(stage::check_order(&stages), Err(("interrupts", "memory")));
+
+    // A required stage can't need an optional one
+    let stages = [
+        InitStage { name: "memory", required: false, after: &[], run: ok },
+        InitStage { name: "interrupts", required: true, after: &["memory"], run: ok },
+    ];
+    assert_eq!(stage::check_order(&stages), Err(("interrupts", "memory")));
+}
+
+#[test_case]
+fn stages_after_a_failure_are_skipped() {
+    let stages = [
+        InitStage { name: "first", required: false, after: &[], run: |_| Err(Error::Other("broken")) },
+        InitStage { name: "second", required: false, after: &["first"], run: |_| Ok(()) },
+        InitStage { name: "third", required: false, after: &["second"], run: |_| Ok(()) },
+        InitStage { name: "fourth", required: true, after: &[], run: |_| Ok(()) },
+    ];
+    let phases = stage::run(&stages, &BootArgs { boot_info_addr: 0, magic: 0 });
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", phases).unwrap();
+    let mut lines = buffer.as_str().lines().skip(1);
+    assert_eq!(lines.next(), Some("  first                failed (optional): broken"));
+    assert_eq!(lines.next(), Some("  second               skipped (optional): first failed"));
+    assert_eq!(lines.next(), Some("  third                skipped (optional): second failed"));
+    assert_eq!(lines.next(), Some("  fourth               ok"));
+    assert!(phases.succeeded("fourth") && !phases.succeeded("second"));
+}
+
+/// Phases at a 1 MHz TSC, so a cycle is a microsecond.
+fn synthetic_times() -> PhaseTimes {
+    let mut times = PhaseTimes::new();
+    times.set_clock(1000, 1_000_000);
+    times.begin_at("gdt", 1000);
+    times.end_at(1500);
+    times.begin_at("memory", 2000);
+    times.begin_at("bitmap", 2100);
+    times.end_at(4100);
+    times.end_at(6000);
+    times.begin_at("interrupts", 6000);
+    times.idle_at(11000);
+    times
+}
+
+#[test_case]
+fn phase_time_arithmetic() {
+    let times = synthetic_times();
+    let phases: [_; 4] = core::array::from_fn(|i| *times.iter().nth(i).unwrap());
+
+    assert_eq!(phases.map(|phase| phase.depth), [0, 0, 1, 0]);
+    assert_eq!(phases.map(|phase| times.cycles(&phase)), [Some(500), Some(4000), Some(2000), None]);
+    assert_eq!(times.total_cycles(), Some(10000));
+    assert_eq!(times.micros(4000), Some(4000));
+    assert_eq!(times.permille(500), Some(50));
+    assert_eq!(times.permille(4000), Some(400));
+}
+
+#[test_case]
+fn phase_time_table() {
+    let times = synthetic_times();
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", times).unwrap();
+    let mut lines = buffer.as_str().lines();
+    assert_eq!(lines.next(), Some("Boot time:"));
+    assert_eq!(lines.next(), Some("  gdt                           0.500 ms    5.0%"));
+    assert_eq!(lines.next(), Some("  memory                        4.000 ms   40.0%"));
+    assert_eq!(lines.next(), Some("    bitmap                      2.000 ms   20.0%"));
+    assert_eq!(lines.next(), Some("  interrupts                  running"));
+    assert_eq!(lines.next(), Some("  total to idle                10.000 ms"));
+    assert_eq!(lines.next(), None);
+}
+
+#[test_case]
+fn phase_times_without_calibration() {
+    let mut times = PhaseTimes::new();
+    times.begin_at("gdt", 1000);
+    times.end_at(1500);
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", times).unwrap();
+    let mut lines = buffer.as_str().lines();
+    assert_eq!(lines.next(), Some("Boot time: (TSC not calibrated, in cycles)"));
+    assert_eq!(lines.next(), Some("  gdt                             500 cy"));
+    assert_eq!(lines.next(), None);
+}
+
+#[test_case]
+fn phase_times_overflow_keeps_nesting() {
+    let mut times = PhaseTimes::new();
+    for tsc in 0..40 {
+        times.begin_at("nested", tsc);
+    }
+    for tsc in 100..140 {
+        times.end_at(tsc);
+    }
+
+    assert_eq!(times.iter().count(), 32);
+    // The innermost phases that didn't fit took the first 8 ends
+    let outermost = times.iter().next().unwrap();
+    assert_eq!(times.cycles(outermost), Some(139));
+    assert!(times.iter().all(|phase| phase.end.is_some()));
+}
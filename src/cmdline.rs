@@ -0,0 +1,73 @@
+//! Kernel command line.
+//!
+//! The bootloader passes the command line in the multiboot2 command line
+//! tag. It consists of whitespace-separated options, each of which is
+//! either a bare flag (`selftest`) or a `key=value` pair (`console=ttyS0`).
+//! A value in double quotes can contain whitespace, as in
+//! `console="ttyS0, debugcon"`. The quotes aren't part of the value.
+
+/// A kernel command line.
+#[derive(Clone, Copy, Debug)]
+pub struct CommandLine<'a>(&'a str);
+
+impl<'a> CommandLine<'a> {
+    /// Wraps a raw command line string.
+    pub const fn new(cmdline: &'a str) -> Self {
+        Self(cmdline)
+    }
+
+    /// Returns an iterator over all options as `(key, value)` pairs.
+    ///
+    /// Bare flags have no value.
+    pub fn options(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        let mut rest = self.0;
+        core::iter::from_fn(move || {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                return None;
+            }
+
+            // An unterminated quote runs to the end of the line
+            let mut quoted = false;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    if c == '"' {
+                        quoted = !quoted;
+                    }
+                    !quoted && c.is_whitespace()
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            let (option, tail) = rest.split_at(end);
+            rest = tail;
+
+            Some(match option.split_once('=') {
+                Some((key, value)) => (key, Some(unquote(value))),
+                None => (option, None),
+            })
+        })
+    }
+
+    /// Returns the value of an option.
+    ///
+    /// If the option is given multiple times, the last one wins.
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.options()
+            .filter(|(k, _)| *k == key)
+            .last()
+            .and_then(|(_, value)| value)
+    }
+
+    /// Returns whether an option is present, with or without a value.
+    pub fn has(&self, key: &str) -> bool {
+        self.options().any(|(k, _)| k == key)
+    }
+}
+
+/// Removes the double quotes around a value, if there are any.
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
@@ -0,0 +1,7 @@
+//! Collections that don't need the heap.
+
+pub mod ring_buffer;
+#[cfg(test)]
+mod test;
+
+pub use ring_buffer::RingBuffer;
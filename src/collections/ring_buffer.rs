@@ -0,0 +1,115 @@
+//! A fixed-size FIFO that overwrites its oldest items when full.
+
+use core::mem::MaybeUninit;
+use core::{ptr, slice};
+
+/// A ring buffer of up to `N` items
+///
+/// Pushing to a full buffer drops the oldest item, so it always holds the
+/// most recent ones. The storage is inline, so it can live in a static
+/// before the heap is up.
+pub struct RingBuffer<T, const N: usize> {
+    items: [MaybeUninit<T>; N],
+    /// Index of the oldest item
+    head: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            items: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Number of items in the buffer
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of items the buffer can hold
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Adds an item, returning the oldest one if the buffer was full
+    pub fn push(&mut self, value: T) -> Option<T> {
+        if N == 0 {
+            return Some(value);
+        }
+
+        let tail = (self.head + self.len) % N;
+        if self.len < N {
+            self.items[tail].write(value);
+            self.len += 1;
+            return None;
+        }
+
+        // Full, so the tail is the head
+        let oldest = unsafe { self.items[tail].assume_init_read() };
+        self.items[tail].write(value);
+        self.head = (self.head + 1) % N;
+        Some(oldest)
+    }
+
+    /// Removes the oldest item
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let value = unsafe { self.items[self.head].assume_init_read() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Returns all items without removing them, oldest first
+    ///
+    /// The items may wrap around the end of the storage, so they're
+    /// returned as two slices: the older ones, then the newer ones.
+    pub fn peek_slice(&self) -> (&[T], &[T]) {
+        let first_len = self.len.min(N - self.head);
+        let base = self.items.as_ptr() as *const T;
+        // The first `len` items from `head`, wrapping around, are initialized
+        unsafe {
+            (
+                slice::from_raw_parts(base.add(self.head), first_len),
+                slice::from_raw_parts(base, self.len - first_len),
+            )
+        }
+    }
+
+    /// Returns an iterator over all items without removing them, oldest
+    /// first
+    pub fn peek_all_iter(&self) -> impl Iterator<Item = &T> {
+        let (first, second) = self.peek_slice();
+        first.iter().chain(second)
+    }
+
+    /// Removes all items
+    pub fn clear(&mut self) {
+        let (first, second) = self.peek_slice();
+        let (first, second) = (first as *const [T], second as *const [T]);
+        self.head = 0;
+        self.len = 0;
+        unsafe {
+            ptr::drop_in_place(first as *mut [T]);
+            ptr::drop_in_place(second as *mut [T]);
+        }
+    }
+}
+
+impl<T, const N: usize> Drop for RingBuffer<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
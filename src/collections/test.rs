@@ -0,0 +1,66 @@
+//! Collection tests.
+
+use super::RingBuffer;
+
+#[test_case]
+fn ring_buffer_keeps_newest() {
+    let mut ring: RingBuffer<u32, 4> = RingBuffer::new();
+    assert!(ring.is_empty());
+    assert_eq!(ring.capacity(), 4);
+
+    for i in 0..4 {
+        assert_eq!(ring.push(i), None);
+    }
+    assert_eq!(ring.push(4), Some(0));
+    assert_eq!(ring.push(5), Some(1));
+    assert_eq!(ring.len(), 4);
+
+    assert_eq!(ring.pop(), Some(2));
+    assert_eq!(ring.pop(), Some(3));
+    assert_eq!(ring.pop(), Some(4));
+    assert_eq!(ring.pop(), Some(5));
+    assert_eq!(ring.pop(), None);
+}
+
+#[test_case]
+fn ring_buffer_peek_wraps_around() {
+    let mut ring: RingBuffer<u32, 4> = RingBuffer::new();
+    assert_eq!(ring.peek_slice(), (&[][..], &[][..]));
+
+    ring.push(1);
+    ring.push(2);
+    assert_eq!(ring.peek_slice(), (&[1, 2][..], &[][..]));
+
+    for i in 3..=6 {
+        ring.push(i);
+    }
+    assert_eq!(ring.peek_slice(), (&[3, 4][..], &[5, 6][..]));
+
+    // Peeking doesn't consume anything
+    let mut items = [0; 4];
+    for (item, &value) in items.iter_mut().zip(ring.peek_all_iter()) {
+        *item = value;
+    }
+    assert_eq!(items, [3, 4, 5, 6]);
+    assert_eq!(ring.len(), 4);
+}
+
+#[test_case]
+fn ring_buffer_drops_items() {
+    use alloc::rc::Rc;
+
+    let value = Rc::new(());
+    let mut ring: RingBuffer<Rc<()>, 2> = RingBuffer::new();
+    for _ in 0..3 {
+        ring.push(value.clone());
+    }
+    assert_eq!(Rc::strong_count(&value), 3);
+
+    ring.clear();
+    assert_eq!(Rc::strong_count(&value), 1);
+    assert!(ring.is_empty());
+
+    ring.push(value.clone());
+    drop(ring);
+    assert_eq!(Rc::strong_count(&value), 1);
+}
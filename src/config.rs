@@ -0,0 +1,431 @@
+//! Boot-time configuration.
+//!
+//! Parameters that used to be hardcoded in the subsystems that use them
+//! are collected in [`KernelConfig`], and most can be changed on the
+//! kernel command line:
+//!
+//! | Option      | Field               | Default       |
+//! |-------------|---------------------|---------------|
+//! | `timer_us=` | `timer_period_us`   | 200           |
+//! | `maxcpus=`  | `max_cpus`          | 4             |
+//! | `baud=`     | `serial_baud`       | 38400         |
+//! | `loglevel=` | `log_level`         | info          |
+//! | `smap`      | `enable_smap`       | off           |
+//! | `smep`      | `enable_smep`       | off           |
+//! | `panic=`    | `panic_action`      | halt          |
+//! | `console=`  | `consoles`          | all available |
+//! | `timer=`    | `timer_source`      | lapic         |
+//! | `selftest`  | `self_test`         | off           |
+//! | `irqchip=`  | `irqchip`           | mps           |
+//!
+//! `panic=` is `halt`, `exit` to exit QEMU with the panic status, or
+//! `reboot` to reset the machine after a countdown. The countdown is
+//! 5 seconds unless given, as in `panic=reboot:10`.
+//!
+//! Flags are on when given bare, and take `on`, `off`, `1` or `0`.
+//!
+//! The interrupt stacks and the per-CPU array are sized when the kernel
+//! is built, from [`KernelConfig::DEFAULT`], so `ist_stack_size_kb` can't
+//! be changed at boot and `maxcpus=` can only lower the CPU limit.
+//!
+//! If an option is given more than once, the last valid one wins. Invalid
+//! values and unknown options are ignored, and [`report_warnings`] logs
+//! them once there's a log.
+
+#[cfg(test)]
+mod test;
+
+use core::fmt;
+
+use log::LevelFilter;
+
+use crate::boot::{self, stage::BootArgs};
+use crate::cmdline::CommandLine;
+use crate::error::Result;
+use crate::sync::Once;
+
+/// Rate of the LAPIC timer in kHz.
+///
+/// The timer isn't calibrated. This is QEMU's 1 GHz APIC bus with the
+/// divider left at its reset value of 2.
+pub const LAPIC_TIMER_KHZ: u64 = 500_000;
+
+/// Clock of the 16550 UART divisor, in baud.
+const UART_CLOCK: u32 = 115_200;
+
+/// Countdown before `panic=reboot` resets the machine.
+const PANIC_REBOOT_SECONDS: u32 = 5;
+
+/// The options [`KernelConfig`] reads, in the order `config` lists them.
+const OPTIONS: [&str; 11] = [
+    "timer_us", "maxcpus", "baud", "loglevel", "smap", "smep", "panic", "console", "timer", "selftest",
+    "irqchip",
+];
+
+/// Options that the modules using them read themselves.
+const OTHER_OPTIONS: &[&str] = &["test", "qemu.exit", "serial.crlf", "logsrc"];
+
+/// Maximum number of warnings kept. Later ones are only counted.
+const MAX_WARNINGS: usize = 8;
+
+static KERNEL_CONFIG: Once<KernelConfig> = Once::new();
+
+/// The boot-time parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelConfig {
+    /// Size of each interrupt stack
+    pub ist_stack_size_kb: u32,
+    /// Time between timer interrupts
+    pub timer_period_us: u32,
+    /// CPUs beyond this many stay offline
+    pub max_cpus: u8,
+    /// Speed of the serial console
+    pub serial_baud: u32,
+    /// Log level of console sinks that don't have their own
+    pub log_level: LevelFilter,
+    /// Whether to stop the kernel from accessing user pages
+    pub enable_smap: bool,
+    /// Whether to stop the kernel from executing user pages
+    pub enable_smep: bool,
+    /// What the panic handler does after printing its report
+    pub panic_action: PanicAction,
+    /// Console sinks to enable, and their log levels
+    pub consoles: ConsoleList,
+    /// What drives the timer interrupt
+    pub timer_source: TimerSource,
+    /// Whether to run the self-tests after boot
+    pub self_test: bool,
+    /// How to find the interrupt controller
+    pub irqchip: IrqChip,
+    /// Bit `i` is set if `OPTIONS[i]` came from the command line
+    from_cmdline: u16,
+    warnings: [Option<Warning>; MAX_WARNINGS],
+    warning_count: usize,
+}
+
+/// Where the value of an option came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    Cmdline,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Default => "default",
+            Self::Cmdline => "cmdline",
+        })
+    }
+}
+
+/// A problem with an option on the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Warning {
+    /// No module reads the option
+    UnknownOption(&'static str),
+    /// The option has a value it can't have, or is missing one
+    InvalidValue(&'static str, Option<&'static str>),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownOption(key) => write!(f, "Unknown option {:?}", key),
+            Self::InvalidValue(key, Some(value)) => write!(f, "Invalid value {:?} for {}", value, key),
+            Self::InvalidValue(key, None) => write!(f, "Missing value for {}", key),
+        }
+    }
+}
+
+/// The `console=` list.
+///
+/// It's kept as given and applied by `console::init`, which knows the
+/// sink names. Without one, every available sink is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleList(Option<&'static str>);
+
+impl ConsoleList {
+    /// Returns the list, if one was given.
+    pub fn as_str(&self) -> Option<&'static str> {
+        self.0
+    }
+}
+
+impl fmt::Display for ConsoleList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0.unwrap_or("all available"))
+    }
+}
+
+/// What drives the timer interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimerSource {
+    /// The LAPIC timer in periodic mode
+    Lapic,
+    /// The LAPIC timer in one-shot mode, re-armed on every tick
+    LapicOneShot,
+}
+
+impl TimerSource {
+    /// Parses the value of `timer=`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "lapic" => Some(Self::Lapic),
+            "lapic-oneshot" => Some(Self::LapicOneShot),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TimerSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lapic => "lapic",
+            Self::LapicOneShot => "lapic-oneshot",
+        })
+    }
+}
+
+/// How to find the IOAPIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqChip {
+    /// Look it up in the MP tables, falling back to the standard address
+    Mps,
+    /// Assume it's at the standard address
+    Standard,
+}
+
+impl IrqChip {
+    /// Parses the value of `irqchip=`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "mps" => Some(Self::Mps),
+            "standard" => Some(Self::Standard),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IrqChip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mps => "mps",
+            Self::Standard => "standard",
+        })
+    }
+}
+
+/// What the panic handler does after printing its report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Stop the CPU
+    Halt,
+    /// Count down this many seconds, then reset the machine
+    Reboot { seconds: u32 },
+    /// Exit QEMU with the panic status
+    Exit,
+}
+
+impl PanicAction {
+    /// Parses the value of `panic=`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.split_once(':') {
+            Some(("reboot", seconds)) => Some(Self::Reboot {
+                seconds: seconds.parse().ok()?,
+            }),
+            Some(_) => None,
+            None => match value {
+                "halt" => Some(Self::Halt),
+                "reboot" => Some(Self::Reboot {
+                    seconds: PANIC_REBOOT_SECONDS,
+                }),
+                "exit" => Some(Self::Exit),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for PanicAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Halt => f.write_str("halt"),
+            Self::Reboot { seconds } => write!(f, "reboot:{}", seconds),
+            Self::Exit => f.write_str("exit"),
+        }
+    }
+}
+
+/// Parses the value of a flag.
+fn parse_flag(value: Option<&str>) -> Option<bool> {
+    match value {
+        None | Some("on" | "1") => Some(true),
+        Some("off" | "0") => Some(false),
+        Some(_) => None,
+    }
+}
+
+impl KernelConfig {
+    /// The built-in parameters.
+    pub const DEFAULT: Self = Self {
+        ist_stack_size_kb: 1024,
+        timer_period_us: 200,
+        max_cpus: 4,
+        serial_baud: 38400,
+        log_level: LevelFilter::Info,
+        enable_smap: false,
+        enable_smep: false,
+        panic_action: PanicAction::Halt,
+        consoles: ConsoleList(None),
+        timer_source: TimerSource::Lapic,
+        self_test: false,
+        irqchip: IrqChip::Mps,
+        from_cmdline: 0,
+        warnings: [None; MAX_WARNINGS],
+        warning_count: 0,
+    };
+
+    /// Returns the built-in parameters with the ones on the command line
+    /// applied.
+    pub fn from_cmdline(cmdline: &CommandLine<'static>) -> Self {
+        let mut config = Self::DEFAULT;
+
+        for (key, value) in cmdline.options() {
+            match OPTIONS.iter().position(|&option| option == key) {
+                Some(i) => match config.set(key, value) {
+                    Some(()) => config.from_cmdline |= 1 << i,
+                    None => config.warn(Warning::InvalidValue(key, value)),
+                },
+                None if OTHER_OPTIONS.contains(&key) => {}
+                None => config.warn(Warning::UnknownOption(key)),
+            }
+        }
+
+        config
+    }
+
+    /// Applies one option, or returns `None` if the value is invalid.
+    fn set(&mut self, key: &str, value: Option<&'static str>) -> Option<()> {
+        match key {
+            "timer_us" => self.timer_period_us = value?.parse().ok().filter(|&us| us > 0)?,
+            "maxcpus" => {
+                let cpus: u8 = value?.parse().ok().filter(|&cpus| cpus > 0)?;
+                self.max_cpus = cpus.min(Self::DEFAULT.max_cpus);
+            }
+            "baud" => {
+                self.serial_baud = value?
+                    .parse()
+                    .ok()
+                    .filter(|&baud| baud > 0 && UART_CLOCK % baud == 0)?
+            }
+            "loglevel" => self.log_level = value?.parse().ok()?,
+            "smap" => self.enable_smap = parse_flag(value)?,
+            "smep" => self.enable_smep = parse_flag(value)?,
+            "panic" => self.panic_action = PanicAction::parse(value?)?,
+            "console" => self.consoles = ConsoleList(Some(value?)),
+            "timer" => self.timer_source = TimerSource::parse(value?)?,
+            "selftest" => self.self_test = parse_flag(value)?,
+            "irqchip" => self.irqchip = IrqChip::parse(value?)?,
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn warn(&mut self, warning: Warning) {
+        if let Some(slot) = self.warnings.get_mut(self.warning_count) {
+            *slot = Some(warning);
+        }
+        self.warning_count += 1;
+    }
+
+    /// Returns the problems found on the command line, up to a limit.
+    pub fn warnings(&self) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter().flatten()
+    }
+
+    /// Returns how many problems were found, including the ones that
+    /// weren't kept.
+    pub fn warning_count(&self) -> usize {
+        self.warning_count
+    }
+
+    /// Returns where the value of `option` came from.
+    pub fn source(&self, option: &str) -> Source {
+        match OPTIONS.iter().position(|&o| o == option) {
+            Some(i) if self.from_cmdline & (1 << i) != 0 => Source::Cmdline,
+            _ => Source::Default,
+        }
+    }
+
+    /// Returns every option with its value and where it came from.
+    pub fn options(&self) -> [(&'static str, &dyn fmt::Display, Source); OPTIONS.len()] {
+        OPTIONS.map(|option| {
+            let value: &dyn fmt::Display = match option {
+                "timer_us" => &self.timer_period_us,
+                "maxcpus" => &self.max_cpus,
+                "baud" => &self.serial_baud,
+                "loglevel" => &self.log_level,
+                "smap" => &self.enable_smap,
+                "smep" => &self.enable_smep,
+                "panic" => &self.panic_action,
+                "console" => &self.consoles,
+                "timer" => &self.timer_source,
+                "selftest" => &self.self_test,
+                _ => &self.irqchip,
+            };
+            (option, value, self.source(option))
+        })
+    }
+
+    /// Returns the LAPIC timer count for one timer period.
+    pub fn timer_cycles(&self) -> usize {
+        (self.timer_period_us as u64 * LAPIC_TIMER_KHZ / 1000) as usize
+    }
+
+    /// Returns the UART divisor for the serial speed.
+    pub fn serial_divisor(&self) -> u16 {
+        (UART_CLOCK / self.serial_baud) as u16
+    }
+}
+
+impl Default for KernelConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Reads the configuration from the command line.
+///
+/// This should be called once, before any subsystem is initialized.
+pub fn init(cmdline: &CommandLine<'static>) {
+    KERNEL_CONFIG.call_once(|| KernelConfig::from_cmdline(cmdline));
+}
+
+/// The `config` boot stage: reads the boot parameters.
+///
+/// There's no log yet, so bad boot information is reported by `memory`,
+/// and a bad magic by `console`.
+pub fn init_stage(args: &BootArgs) -> Result<()> {
+    boot::stage::read_boot_info(args);
+    init(&boot::stage::cmdline());
+    Ok(())
+}
+
+/// Logs the problems `init` found on the command line.
+///
+/// This should be called once the logger is up.
+pub fn report_warnings() {
+    let config = get();
+    for warning in config.warnings() {
+        log::warn!("config: {}, ignoring it", warning);
+    }
+    if config.warning_count() > MAX_WARNINGS {
+        log::warn!("config: {} more problems", config.warning_count() - MAX_WARNINGS);
+    }
+}
+
+/// Returns the configuration, or the built-in one before `init`.
+pub fn get() -> &'static KernelConfig {
+    KERNEL_CONFIG.get().unwrap_or(&KernelConfig::DEFAULT)
+}
@@ -0,0 +1,149 @@
+//! Configuration parsing tests.
+
+use log::LevelFilter;
+
+use super::{IrqChip, KernelConfig, PanicAction, Source, TimerSource, Warning};
+use crate::cmdline::CommandLine;
+
+#[test_case]
+fn defaults_match_old_constants() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new(""));
+    assert_eq!(config.timer_cycles(), 100_000);
+    assert_eq!(config.serial_divisor(), 3);
+    assert_eq!(config.ist_stack_size_kb, 1024);
+    assert_eq!(config.log_level, LevelFilter::Info);
+    assert!(!config.enable_smap && !config.enable_smep);
+    assert_eq!(config.panic_action, PanicAction::Halt);
+    // Production boots run no self-tests
+    assert!(!config.self_test);
+}
+
+#[test_case]
+fn cmdline_overrides() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new(
+        "timer_us=1000 maxcpus=2 baud=115200 loglevel=debug smep",
+    ));
+    assert_eq!(config.timer_period_us, 1000);
+    assert_eq!(config.max_cpus, 2);
+    assert_eq!(config.serial_divisor(), 1);
+    assert_eq!(config.log_level, LevelFilter::Debug);
+    assert!(config.enable_smep);
+    assert!(!config.enable_smap);
+}
+
+#[test_case]
+fn invalid_values_are_ignored() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new(
+        "timer_us=0 maxcpus=200 baud=12345 loglevel=loud",
+    ));
+    assert_eq!(config.timer_period_us, KernelConfig::DEFAULT.timer_period_us);
+    // Can't go above the size of the per-CPU array
+    assert_eq!(config.max_cpus, KernelConfig::DEFAULT.max_cpus);
+    assert_eq!(config.serial_baud, KernelConfig::DEFAULT.serial_baud);
+    assert_eq!(config.log_level, KernelConfig::DEFAULT.log_level);
+}
+
+#[test_case]
+fn panic_actions() {
+    let action = |cmdline| KernelConfig::from_cmdline(&CommandLine::new(cmdline)).panic_action;
+
+    assert_eq!(action("panic=halt"), PanicAction::Halt);
+    assert_eq!(action("panic=exit"), PanicAction::Exit);
+    assert_eq!(action("panic=reboot"), PanicAction::Reboot { seconds: 5 });
+    assert_eq!(action("panic=reboot:0"), PanicAction::Reboot { seconds: 0 });
+    assert_eq!(action("panic=reboot:30 panic=exit"), PanicAction::Exit);
+
+    for invalid in ["panic", "panic=", "panic=restart", "panic=reboot:soon", "panic=exit:1"] {
+        assert_eq!(action(invalid), PanicAction::Halt, "{}", invalid);
+    }
+}
+
+#[test_case]
+fn new_options() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new(
+        "console=debugcon timer=lapic-oneshot selftest irqchip=standard smap=on smep=0",
+    ));
+    assert_eq!(config.consoles.as_str(), Some("debugcon"));
+    assert_eq!(config.timer_source, TimerSource::LapicOneShot);
+    assert!(config.self_test);
+    assert_eq!(config.irqchip, IrqChip::Standard);
+    assert!(config.enable_smap && !config.enable_smep);
+    assert_eq!(config.warning_count(), 0);
+}
+
+#[test_case]
+fn quoted_values() {
+    let cmdline = CommandLine::new("console=\"ttyS0:info, debugcon\" test  loglevel=\"warn\"");
+    assert_eq!(cmdline.get("console"), Some("ttyS0:info, debugcon"));
+    assert!(cmdline.has("test"));
+    assert_eq!(cmdline.get("loglevel"), Some("warn"));
+    assert_eq!(cmdline.options().count(), 3);
+
+    let config = KernelConfig::from_cmdline(&cmdline);
+    assert_eq!(config.log_level, LevelFilter::Warn);
+
+    // An unterminated quote runs to the end
+    let cmdline = CommandLine::new("console=\"ttyS0 smap");
+    assert_eq!(cmdline.get("console"), Some("\"ttyS0 smap"));
+    assert!(!cmdline.has("smap"));
+}
+
+#[test_case]
+fn duplicate_options_last_wins() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new(
+        "timer_us=500 loglevel=error timer_us=1000 loglevel=trace selftest=off selftest",
+    ));
+    assert_eq!(config.timer_period_us, 1000);
+    assert_eq!(config.log_level, LevelFilter::Trace);
+    assert!(config.self_test);
+
+    // An invalid one doesn't undo an earlier valid one
+    let config = KernelConfig::from_cmdline(&CommandLine::new("timer_us=1000 timer_us=0"));
+    assert_eq!(config.timer_period_us, 1000);
+    assert_eq!(config.source("timer_us"), Source::Cmdline);
+}
+
+#[test_case]
+fn unknown_and_invalid_options_warn() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new(
+        "test logsrc=on serial.crlf=off frobnicate=1 quiet timer=pit panic",
+    ));
+    let warnings: [Option<&Warning>; 4] = {
+        let mut warnings = config.warnings();
+        core::array::from_fn(|_| warnings.next())
+    };
+    assert_eq!(
+        warnings,
+        [
+            Some(&Warning::UnknownOption("frobnicate")),
+            Some(&Warning::UnknownOption("quiet")),
+            Some(&Warning::InvalidValue("timer", Some("pit"))),
+            Some(&Warning::InvalidValue("panic", None)),
+        ]
+    );
+    assert_eq!(config.warning_count(), 4);
+    assert_eq!(config.timer_source, TimerSource::Lapic);
+
+    // Only the first few are kept, but all are counted
+    let config = KernelConfig::from_cmdline(&CommandLine::new("a b c d e f g h i j"));
+    assert_eq!(config.warnings().count(), 8);
+    assert_eq!(config.warning_count(), 10);
+}
+
+#[test_case]
+fn sources() {
+    let config = KernelConfig::from_cmdline(&CommandLine::new("maxcpus=2 irqchip=mps baud=1"));
+    assert_eq!(config.source("maxcpus"), Source::Cmdline);
+    // Given, even though it's the default
+    assert_eq!(config.source("irqchip"), Source::Cmdline);
+    // Invalid, so still the default
+    assert_eq!(config.source("baud"), Source::Default);
+    assert_eq!(config.source("loglevel"), Source::Default);
+
+    let options = config.options();
+    assert!(options.iter().any(|(option, _, source)| *option == "maxcpus" && *source == Source::Cmdline));
+    assert_eq!(
+        options.iter().filter(|(_, _, source)| *source == Source::Cmdline).count(),
+        2
+    );
+}
@@ -0,0 +1,479 @@
+//! Console output.
+//!
+//! `println!` and the panic handler go through here, and the output is
+//! fanned out to every enabled [`ConsoleSink`]. The built-in sinks are:
+//!
+//! - `ttyS0`: The first serial port (COM1).
+//! - `debugcon`: The QEMU/Bochs port 0xE9 debug console.
+//! - `vga`: The VGA text-mode buffer.
+//! - `fb`: A linear framebuffer set up by the bootloader.
+//!
+//! The sinks are selected with a comma-separated list on the kernel
+//! command line, e.g. `console=ttyS0,debugcon`. By default the serial port
+//! and whichever of VGA or the framebuffer the bootloader left us with are
+//! used.
+//!
+//! Each sink can have its own log level with a suffix, e.g.
+//! `console=ttyS0:info,debugcon:debug`. Sinks without one get the level
+//! from `loglevel=`, or info (see [`crate::config`]). The level only
+//! applies to the logger;
+//! `println!` output goes to every enabled sink.
+//!
+//! Sinks live in static storage so they can be registered before the
+//! allocator is up. Each message is written to all sinks while holding the
+//! registry lock, so every sink sees messages in the same order.
+//!
+//! Printing never fails and never panics, since the panic handler prints
+//! too. Errors from a sink are counted, and a sink that fails
+//! [`MAX_SINK_FAILURES`] times in a row is disabled so it can't break the
+//! others. A `Display` impl that returns an error only cuts its own
+//! message short, and one that panics is caught by the panic guard.
+//!
+//! The registry lock is a [`ReentrantMutex`], so a sink can log, or the
+//! kernel can panic, while a message is being written without
+//! deadlocking. The nested message is written out before the rest of the
+//! outer one.
+
+pub mod debugcon;
+#[cfg(test)]
+mod test;
+pub mod fb;
+mod font;
+mod serial;
+pub mod vga;
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use log::{Level, LevelFilter};
+
+use crate::sync::{Mutex, ReentrantMutex, ReentrantMutexGuard};
+
+use crate::boot::{self, stage::BootArgs};
+use crate::cmdline::CommandLine;
+use crate::error::{Error, MultibootError, Result};
+use crate::memory::multiboot2::{BootInfo, FramebufferType};
+use crate::memory::PhysAddr;
+use debugcon::{DebugCon, DEBUGCON_PORT};
+use fb::FramebufferConsole;
+use serial::SerialSink;
+use vga::{VgaWriter, VGA_BUFFER_ADDR, VGA_BUFFER_SIZE};
+
+/// Maximum number of registered sinks.
+const MAX_SINKS: usize = 8;
+
+/// Failed writes in a row after which a sink is disabled.
+pub const MAX_SINK_FAILURES: u32 = 3;
+
+/// An output device for the console.
+///
+/// Sinks are shared, so they have to handle their own locking.
+pub trait ConsoleSink: Sync {
+    /// Returns the name used to select the sink with `console=`.
+    fn name(&self) -> &'static str;
+
+    /// Writes a string.
+    fn write_str(&self, s: &str) -> fmt::Result;
+
+    /// Flushes buffered output.
+    fn flush(&self) {}
+
+    /// Returns whether the sink understands ANSI escape sequences.
+    #[allow(dead_code)] // Nothing emits escape sequences yet
+    fn supports_ansi(&self) -> bool {
+        false
+    }
+}
+
+/// A registered sink.
+#[derive(Clone, Copy)]
+struct SinkEntry {
+    sink: &'static dyn ConsoleSink,
+    enabled: bool,
+    max_level: LevelFilter,
+    /// Failed writes since boot
+    errors: u32,
+    /// Failed writes since the last one that worked
+    failures: u32,
+}
+
+/// A slot for a sink.
+type SinkSlot = Cell<Option<SinkEntry>>;
+
+/// The registered sinks.
+///
+/// It's used through a shared reference, since writing to a sink can lead
+/// back here.
+struct Registry {
+    sinks: [SinkSlot; MAX_SINKS],
+}
+
+impl Registry {
+    const fn new() -> Self {
+        Self {
+            sinks: [const { Cell::new(None) }; MAX_SINKS],
+        }
+    }
+
+    /// Returns copies of the registered entries.
+    fn entries(&self) -> impl Iterator<Item = SinkEntry> + '_ {
+        self.sinks.iter().filter_map(Cell::get)
+    }
+
+    /// Changes the registered entries with `f`.
+    fn update(&self, mut f: impl FnMut(&mut SinkEntry)) {
+        for slot in &self.sinks {
+            if let Some(mut entry) = slot.get() {
+                f(&mut entry);
+                slot.set(Some(entry));
+            }
+        }
+    }
+
+    /// Registers a sink.
+    fn register(&self, sink: &'static dyn ConsoleSink, enabled: bool) -> Result<()> {
+        let slot = self
+            .sinks
+            .iter()
+            .find(|slot| slot.get().is_none())
+            .ok_or(Error::TooMany("console sinks"))?;
+
+        slot.set(Some(SinkEntry {
+            sink,
+            enabled,
+            max_level: crate::config::get().log_level,
+            errors: 0,
+            failures: 0,
+        }));
+        Ok(())
+    }
+
+    /// Enables or disables a sink by name.
+    ///
+    /// Returns whether a sink with the name is registered.
+    fn set_enabled(&self, name: &str, enabled: bool) -> bool {
+        let mut found = false;
+        self.update(|entry| {
+            if entry.sink.name() == name {
+                entry.enabled = enabled;
+                found = true;
+            }
+        });
+        found
+    }
+
+    /// Enables exactly the sinks in a `console=` list and sets their levels.
+    ///
+    /// Returns whether any sink is enabled.
+    fn select(&self, list: &str) -> bool {
+        let mut any_enabled = false;
+        self.update(|entry| {
+            let spec = list
+                .split(',')
+                .map(parse_sink)
+                .find(|(name, _)| *name == entry.sink.name());
+
+            entry.enabled = spec.is_some();
+            entry.max_level = spec.and_then(|(_, level)| level).unwrap_or(crate::config::get().log_level);
+            any_enabled |= entry.enabled;
+        });
+        any_enabled
+    }
+
+    /// Returns the most verbose level of any enabled sink.
+    fn max_level(&self) -> LevelFilter {
+        self.entries()
+            .filter(|entry| entry.enabled)
+            .map(|entry| entry.max_level)
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    /// Writes to all enabled sinks, disabling those that keep failing.
+    fn write_fmt(&self, args: fmt::Arguments) {
+        self.write_filtered(args, |_| true);
+    }
+
+    /// Writes a log message to the enabled sinks that want its level.
+    fn write_log(&self, level: Level, args: fmt::Arguments) {
+        self.write_filtered(args, |entry| level <= entry.max_level);
+    }
+
+    fn write_filtered(&self, args: fmt::Arguments, filter: impl Fn(&SinkEntry) -> bool) {
+        for entry in self.entries().filter(|entry| entry.enabled && filter(entry)) {
+            let mut writer = SinkWriter::new(entry.sink);
+            // An error from a `Display` impl isn't the sink's fault
+            let _ = writer.write_fmt(args);
+            if !writer.failed {
+                entry.sink.flush();
+                if entry.failures == 0 {
+                    continue;
+                }
+            }
+
+            // The slot may have changed while we were writing
+            let mut disabled = false;
+            self.update(|current| {
+                if !core::ptr::addr_eq(current.sink, entry.sink) {
+                    return;
+                }
+                if writer.failed {
+                    current.errors = current.errors.saturating_add(1);
+                    current.failures += 1;
+                    if current.enabled && current.failures >= MAX_SINK_FAILURES {
+                        current.enabled = false;
+                        disabled = true;
+                    }
+                } else {
+                    current.failures = 0;
+                }
+            });
+
+            if disabled {
+                self.write_fmt(format_args!(
+                    "console: Disabled {} after {} failed writes in a row\n",
+                    entry.sink.name(),
+                    MAX_SINK_FAILURES
+                ));
+            }
+        }
+    }
+}
+
+/// Adapts a sink to `core::fmt::Write`, noting whether it failed.
+struct SinkWriter<'a> {
+    sink: &'a dyn ConsoleSink,
+    failed: bool,
+}
+
+impl<'a> SinkWriter<'a> {
+    fn new(sink: &'a dyn ConsoleSink) -> Self {
+        Self { sink, failed: false }
+    }
+}
+
+impl Write for SinkWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let result = self.sink.write_str(s);
+        self.failed |= result.is_err();
+        result
+    }
+}
+
+static REGISTRY: ReentrantMutex<Registry> = ReentrantMutex::new(Registry::new());
+
+/// Whether the debug console responded to the probe.
+static DEBUGCON_PRESENT: AtomicBool = AtomicBool::new(false);
+
+static SERIAL: SerialSink = SerialSink;
+static DEBUGCON: DebugCon = DebugCon::new(DEBUGCON_PORT);
+
+/// The VGA text-mode writer.
+static VGA: Mutex<VgaWriter> = Mutex::new(VgaWriter::new());
+
+/// The framebuffer console, if there is a usable framebuffer.
+static FB: Mutex<Option<FramebufferConsole>> = Mutex::new(None);
+
+/// Locks the registry.
+fn registry() -> ReentrantMutexGuard<'static, Registry> {
+    REGISTRY.lock()
+}
+
+/// Registers a sink.
+pub fn register(sink: &'static dyn ConsoleSink, enabled: bool) -> Result<()> {
+    registry().register(sink, enabled)
+}
+
+/// Enables or disables a sink by name.
+///
+/// Returns whether a sink with the name is registered.
+pub fn set_enabled(name: &str, enabled: bool) -> bool {
+    registry().set_enabled(name, enabled)
+}
+
+/// Initializes the console from the kernel command line.
+///
+/// This must be called before `memory::init` since it may reserve
+/// device memory.
+pub fn init(cmdline: &CommandLine, boot_info: Option<&BootInfo>) {
+    if let Some(value) = cmdline.get("serial.crlf") {
+        crate::serial::set_crlf(!matches!(value, "0" | "off"));
+    }
+    let _ = register(&SERIAL, true);
+
+    let debugcon_present = DEBUGCON.probe();
+    DEBUGCON_PRESENT.store(debugcon_present, Ordering::Relaxed);
+    if debugcon_present {
+        let _ = register(&DEBUGCON, false);
+    }
+
+    if vga_available(boot_info) {
+        // Never hand out the text buffer as RAM
+        let _ = crate::memory::get_allocator()
+            .reserve_range(PhysAddr(VGA_BUFFER_ADDR), VGA_BUFFER_SIZE);
+        VGA.lock().clear();
+        let _ = register(&VGA, true);
+    }
+
+    let fb_console = boot_info
+        .and_then(|boot_info| boot_info.framebuffer_tag().ok())
+        .and_then(FramebufferConsole::new);
+    if let Some(mut fb_console) = fb_console {
+        let _ = crate::memory::get_allocator()
+            .reserve_range(PhysAddr(fb_console.addr()), fb_console.size());
+        fb_console.clear();
+        *FB.lock() = Some(fb_console);
+        let _ = register(&FB, true);
+    }
+
+    if let Some(list) = crate::config::get().consoles.as_str() {
+        select(list);
+    }
+}
+
+/// The `console` boot stage: selects the console backends and starts the
+/// log.
+///
+/// Then fails if the kernel wasn't started by a multiboot2 bootloader,
+/// which is the first point the error can be seen.
+pub fn init_stage(args: &BootArgs) -> Result<()> {
+    let cmdline = boot::stage::cmdline();
+    init(&cmdline, boot::stage::boot_info());
+    crate::logger::init(&cmdline);
+    crate::config::report_warnings();
+    // The sinks are chosen before the logger is up, so this is told now
+    let fb = boot::stage::boot_info().and_then(|boot_info| boot_info.framebuffer_tag().ok());
+    if let Some(FramebufferType::Unknown(typ)) = fb.map(|fb| fb.framebuffer_type()) {
+        log::warn!("Unknown framebuffer type {}, not using it", typ);
+    }
+    crate::qemu::init(&cmdline);
+    crate::println!("{}", crate::version::BuildInfo::CURRENT);
+    if !args.is_from_multiboot2() {
+        return Err(MultibootError::BadMagic(args.magic).into());
+    }
+    Ok(())
+}
+
+/// Enables exactly the sinks in a `console=` list.
+fn select(list: &str) {
+    let any_enabled = registry().select(list);
+
+    // Don't end up with no output at all
+    if !any_enabled {
+        set_enabled(SERIAL.name(), true);
+    }
+
+    for spec in list.split(',') {
+        let (name, level) = parse_sink(spec);
+        let registered = registry()
+            .entries()
+            .any(|entry| entry.sink.name() == name);
+        if !registered {
+            crate::println!("console: Unknown or unavailable console {:?}", name);
+        } else if level.is_none() && spec.contains(':') {
+            crate::println!("console: Unknown log level in {:?}", spec);
+        }
+    }
+}
+
+/// Splits a `console=` item into the sink name and log level.
+///
+/// The level is `None` if there is none or it's not a valid level.
+fn parse_sink(spec: &str) -> (&str, Option<LevelFilter>) {
+    match spec.split_once(':') {
+        Some((name, level)) => (name, level.parse().ok()),
+        None => (spec, None),
+    }
+}
+
+/// A registered sink, as shown by the `consoles` shell command.
+#[derive(Debug, Clone, Copy)]
+pub struct SinkStatus {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub max_level: LevelFilter,
+    /// Failed writes since boot
+    pub errors: u32,
+}
+
+/// Returns the registered sinks.
+pub fn sinks() -> impl Iterator<Item = SinkStatus> {
+    let registry = registry();
+    let entries: [Option<SinkEntry>; MAX_SINKS] = core::array::from_fn(|i| registry.sinks[i].get());
+    entries.into_iter().flatten().map(|entry| SinkStatus {
+        name: entry.sink.name(),
+        enabled: entry.enabled,
+        max_level: entry.max_level,
+        errors: entry.errors,
+    })
+}
+
+/// Returns the most verbose log level of any enabled sink.
+pub fn max_log_level() -> LevelFilter {
+    registry().max_level()
+}
+
+/// Finishes console initialization once the heap is available.
+pub fn init_late() -> Result<()> {
+    match FB.lock().as_mut() {
+        Some(fb_console) => fb_console.enable_back_buffer(),
+        None => Ok(()),
+    }
+}
+
+/// Checks whether the machine is in VGA text mode.
+///
+/// Without a framebuffer tag we assume the legacy text mode that BIOS
+/// boots leave us in.
+fn vga_available(boot_info: Option<&BootInfo>) -> bool {
+    match boot_info.and_then(|boot_info| boot_info.framebuffer_tag().ok()) {
+        Some(fb) => {
+            matches!(fb.framebuffer_type(), FramebufferType::EgaText)
+                && fb.addr == VGA_BUFFER_ADDR as u64
+        }
+        None => true,
+    }
+}
+
+/// Writes formatted output to all enabled sinks.
+pub fn write_fmt(args: fmt::Arguments) {
+    registry().write_fmt(args);
+}
+
+/// Writes a log message to the enabled sinks that want its level.
+pub fn write_log(level: Level, args: fmt::Arguments) {
+    registry().write_log(level, args);
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    write_fmt(args);
+}
+
+/// Prints from the panic path.
+///
+/// The panic may have happened while another CPU held the registry lock,
+/// in which case we break the lock. If this CPU holds it, we just take it
+/// again. The output is always mirrored to the debug
+/// console when it's present, since writing to it can never block.
+#[doc(hidden)]
+pub fn _print_panic(args: fmt::Arguments) {
+    let registry = match REGISTRY.try_lock() {
+        Some(registry) => registry,
+        None => unsafe {
+            REGISTRY.force_unlock();
+            REGISTRY.lock()
+        },
+    };
+
+    let debugcon_enabled = registry
+        .entries()
+        .any(|entry| entry.enabled && entry.sink.name() == DEBUGCON.name());
+
+    registry.write_fmt(args);
+
+    if DEBUGCON_PRESENT.load(Ordering::Relaxed) && !debugcon_enabled {
+        let _ = SinkWriter::new(&DEBUGCON).write_fmt(args);
+    }
+}
@@ -0,0 +1,71 @@
+//! Port 0xE9 debug console.
+//!
+//! QEMU (`-debugcon stdio`) and Bochs expose a write-only "debug console"
+//! on I/O port 0xE9. Unlike the UART there is no line status to poll, so
+//! writes never block, and it keeps working even if the serial port is
+//! misconfigured.
+
+use core::fmt;
+
+use super::ConsoleSink;
+use crate::port_io::{Pio, PortIo};
+
+/// The conventional debug console port.
+pub const DEBUGCON_PORT: u16 = 0xE9;
+
+/// A writer for the 0xE9 debug console.
+pub struct DebugCon<P: PortIo = Pio> {
+    io: P,
+    port: u16,
+}
+
+impl DebugCon {
+    /// Creates a writer for the debug console at `port`.
+    pub const fn new(port: u16) -> Self {
+        Self::with_io(Pio, port)
+    }
+}
+
+impl<P: PortIo> DebugCon<P> {
+    /// Creates a writer for the debug console at `port` that goes through
+    /// `io`.
+    pub const fn with_io(io: P, port: u16) -> Self {
+        Self { io, port }
+    }
+
+    /// Checks whether the debug console is present.
+    ///
+    /// When the device is enabled, QEMU and Bochs return the port number
+    /// itself when the port is read back. Without it the read floats to 0xff.
+    pub fn probe(&self) -> bool {
+        self.io.read(self.port) == self.port as u8
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        self.io.write(self.port, byte);
+    }
+
+    pub fn write_str(&self, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+    }
+}
+
+impl<P: PortIo> fmt::Write for DebugCon<P> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        DebugCon::write_str(self, s);
+        Ok(())
+    }
+}
+
+impl<P: PortIo + Sync> ConsoleSink for DebugCon<P> {
+    fn name(&self) -> &'static str {
+        "debugcon"
+    }
+
+    fn write_str(&self, s: &str) -> fmt::Result {
+        DebugCon::write_str(self, s);
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+//! Linear framebuffer console.
+//!
+//! When GRUB hands us an RGB framebuffer instead of VGA text mode, we
+//! render text ourselves with the bitmap font in [`super::font`].
+//!
+//! The framebuffer is usually mapped write-combining, so reading it back
+//! (as scrolling does) is very slow. Once the heap is up, a back buffer
+//! can be enabled with [`FramebufferConsole::enable_back_buffer`] so that
+//! scrolling only ever writes to the framebuffer.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr;
+
+use super::ConsoleSink;
+use super::font::{FIRST_CHAR, FONT, FONT_HEIGHT, FONT_WIDTH, LAST_CHAR};
+use crate::error::{Error, Result};
+use crate::memory::fallible::try_vec;
+use crate::memory::multiboot2::{FramebufferTag, FramebufferType, RgbFields};
+use crate::sync::Mutex;
+
+/// The largest back buffer we try to allocate.
+const MAX_BACK_BUFFER_SIZE: usize = 2 * 1024 * 1024;
+
+/// The framebuffer must be covered by the boot identity map.
+const MAX_FRAMEBUFFER_END: u64 = 4 * 1024 * 1024 * 1024;
+
+/// A text console on a linear RGB framebuffer.
+pub struct FramebufferConsole {
+    base: *mut u8,
+    pitch: usize,
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+    fields: RgbFields,
+
+    /// Size of the screen in characters.
+    columns: usize,
+    rows: usize,
+
+    /// Cursor position in characters.
+    column: usize,
+    row: usize,
+
+    foreground: u32,
+    background: u32,
+
+    back_buffer: Option<Vec<u8>>,
+}
+
+unsafe impl Send for FramebufferConsole {}
+
+impl FramebufferConsole {
+    /// Creates a console from the multiboot2 framebuffer tag.
+    ///
+    /// Returns `None` if the framebuffer is not a supported RGB format, is
+    /// smaller than one character or is outside the identity-mapped region.
+    pub fn new(tag: &FramebufferTag) -> Option<Self> {
+        let FramebufferType::Rgb(fields) = tag.framebuffer_type() else {
+            return None;
+        };
+
+        if !matches!(tag.bpp, 16 | 24 | 32) {
+            return None;
+        }
+
+        // Scrolling needs at least one row of text
+        if (tag.width as usize) < FONT_WIDTH || (tag.height as usize) < FONT_HEIGHT {
+            return None;
+        }
+
+        let size = tag.pitch as u64 * tag.height as u64;
+        if tag.addr.checked_add(size)? > MAX_FRAMEBUFFER_END {
+            return None;
+        }
+
+        let mut console = Self {
+            base: tag.addr as usize as *mut u8,
+            pitch: tag.pitch as usize,
+            width: tag.width as usize,
+            height: tag.height as usize,
+            bytes_per_pixel: tag.bpp as usize / 8,
+            fields,
+            columns: tag.width as usize / FONT_WIDTH,
+            rows: tag.height as usize / FONT_HEIGHT,
+            column: 0,
+            row: 0,
+            foreground: 0,
+            background: 0,
+            back_buffer: None,
+        };
+        console.foreground = console.pixel(0xaa, 0xaa, 0xaa);
+        console.background = console.pixel(0, 0, 0);
+
+        Some(console)
+    }
+
+    /// Returns the physical address of the framebuffer.
+    pub fn addr(&self) -> usize {
+        self.base as usize
+    }
+
+    /// Returns the size of the framebuffer in bytes.
+    pub fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+
+    /// Sets the text color.
+    #[allow(dead_code)] // Nothing prints in color yet
+    pub fn set_color(&mut self, foreground: (u8, u8, u8), background: (u8, u8, u8)) {
+        self.foreground = self.pixel(foreground.0, foreground.1, foreground.2);
+        self.background = self.pixel(background.0, background.1, background.2);
+    }
+
+    /// Allocates a back buffer to speed up scrolling.
+    ///
+    /// This requires the heap. The current screen contents are lost.
+    pub fn enable_back_buffer(&mut self) -> Result<()> {
+        if self.size() > MAX_BACK_BUFFER_SIZE {
+            return Err(Error::Other("framebuffer is too large for a back buffer"));
+        }
+
+        // Drawing straight to the framebuffer works too, just slower
+        let back_buffer = try_vec(0u8, self.size())?;
+        self.back_buffer = Some(back_buffer);
+        self.clear();
+        Ok(())
+    }
+
+    /// Clears the screen and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                self.put_pixel(x, y, self.background);
+            }
+        }
+        self.row = 0;
+        self.column = 0;
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            // Backspace only moves the cursor
+            0x08 => self.column = self.column.saturating_sub(1),
+            byte => {
+                if self.column >= self.columns {
+                    self.new_line();
+                }
+                self.draw_glyph(self.column, self.row, byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves all text rows up by one and clears the last row.
+    fn scroll(&mut self) {
+        let row_bytes = self.pitch * FONT_HEIGHT;
+        let moved = self.pitch * (self.rows - 1) * FONT_HEIGHT;
+
+        match &mut self.back_buffer {
+            Some(back) => {
+                back.copy_within(row_bytes..row_bytes + moved, 0);
+                unsafe {
+                    ptr::copy_nonoverlapping(back.as_ptr(), self.base, moved);
+                }
+            }
+            None => unsafe {
+                ptr::copy(self.base.add(row_bytes), self.base, moved);
+            },
+        }
+
+        let y_start = (self.rows - 1) * FONT_HEIGHT;
+        for y in y_start..y_start + FONT_HEIGHT {
+            for x in 0..self.width {
+                self.put_pixel(x, y, self.background);
+            }
+        }
+    }
+
+    fn draw_glyph(&mut self, column: usize, row: usize, byte: u8) {
+        let index = match byte {
+            FIRST_CHAR..=LAST_CHAR => byte - FIRST_CHAR,
+            _ => b'?' - FIRST_CHAR,
+        };
+        let glyph = &FONT[index as usize];
+
+        let x0 = column * FONT_WIDTH;
+        let y0 = row * FONT_HEIGHT;
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..FONT_WIDTH {
+                let color = if bits & (0x80 >> dx) != 0 {
+                    self.foreground
+                } else {
+                    self.background
+                };
+                self.put_pixel(x0 + dx, y0 + dy, color);
+            }
+        }
+    }
+
+    /// Packs an RGB color into the framebuffer's pixel format.
+    fn pixel(&self, r: u8, g: u8, b: u8) -> u32 {
+        let channel = |value: u8, position: u8, size: u8| -> u32 {
+            ((value as u32) >> (8 - size.min(8))) << position
+        };
+
+        channel(r, self.fields.red_position, self.fields.red_size)
+            | channel(g, self.fields.green_position, self.fields.green_size)
+            | channel(b, self.fields.blue_position, self.fields.blue_size)
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, color: u32) {
+        let offset = y * self.pitch + x * self.bytes_per_pixel;
+        let bytes = color.to_le_bytes();
+
+        for (i, &byte) in bytes[..self.bytes_per_pixel].iter().enumerate() {
+            unsafe {
+                ptr::write_volatile(self.base.add(offset + i), byte);
+            }
+        }
+
+        if let Some(back) = &mut self.back_buffer {
+            back[offset..offset + self.bytes_per_pixel]
+                .copy_from_slice(&bytes[..self.bytes_per_pixel]);
+        }
+    }
+}
+
+impl fmt::Write for FramebufferConsole {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_str(s);
+        Ok(())
+    }
+}
+
+impl ConsoleSink for Mutex<Option<FramebufferConsole>> {
+    fn name(&self) -> &'static str {
+        "fb"
+    }
+
+    fn write_str(&self, s: &str) -> fmt::Result {
+        let mut console = self.lock();
+        let console = console.as_mut().ok_or(fmt::Error)?;
+        console.write_str(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+//! Serial port console sink.
+
+use core::fmt;
+
+use super::ConsoleSink;
+use crate::serial::SERIAL1;
+
+/// Console sink for COM1.
+pub struct SerialSink;
+
+impl ConsoleSink for SerialSink {
+    fn name(&self) -> &'static str {
+        "ttyS0"
+    }
+
+    fn write_str(&self, s: &str) -> fmt::Result {
+        SERIAL1.lock().write_str(s);
+        Ok(())
+    }
+
+    fn flush(&self) {
+        SERIAL1.lock().flush();
+    }
+
+    fn supports_ansi(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,266 @@
+//! Console routing tests.
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use log::{Level, LevelFilter};
+
+use super::debugcon::{DebugCon, DEBUGCON_PORT};
+use super::{parse_sink, ConsoleSink, Registry, MAX_SINK_FAILURES, REGISTRY};
+use crate::port_io::PortIo;
+
+/// A sink that counts writes.
+struct CountingSink {
+    name: &'static str,
+    writes: AtomicUsize,
+}
+
+impl CountingSink {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            writes: AtomicUsize::new(0),
+        }
+    }
+
+    fn writes(&self) -> usize {
+        self.writes.swap(0, Ordering::Relaxed)
+    }
+}
+
+impl ConsoleSink for CountingSink {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn write_str(&self, _s: &str) -> fmt::Result {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn parse_sink_levels() {
+    assert_eq!(parse_sink("ttyS0"), ("ttyS0", None));
+    assert_eq!(parse_sink("ttyS0:info"), ("ttyS0", Some(LevelFilter::Info)));
+    assert_eq!(parse_sink("debugcon:DEBUG"), ("debugcon", Some(LevelFilter::Debug)));
+    assert_eq!(parse_sink("fb:off"), ("fb", Some(LevelFilter::Off)));
+    assert_eq!(parse_sink("fb:loud"), ("fb", None));
+}
+
+#[test_case]
+fn select_applies_levels() {
+    static A: CountingSink = CountingSink::new("a");
+    static B: CountingSink = CountingSink::new("b");
+    static C: CountingSink = CountingSink::new("c");
+
+    let registry = Registry::new();
+    for sink in [&A, &B, &C] {
+        registry.register(sink, true).unwrap();
+    }
+
+    assert!(registry.select("a:warn,b:debug,unknown"));
+    assert_eq!(registry.max_level(), LevelFilter::Debug);
+
+    registry.write_log(Level::Info, format_args!("info"));
+    assert_eq!((A.writes(), B.writes(), C.writes()), (0, 1, 0));
+
+    registry.write_log(Level::Error, format_args!("error"));
+    assert_eq!((A.writes(), B.writes(), C.writes()), (1, 1, 0));
+
+    // Plain output ignores the levels
+    registry.write_fmt(format_args!("text"));
+    assert_eq!((A.writes(), B.writes(), C.writes()), (1, 1, 0));
+
+    assert!(registry.select("c"));
+    assert_eq!(registry.max_level(), crate::config::get().log_level);
+    assert!(!registry.select("unknown"));
+    assert_eq!(registry.max_level(), LevelFilter::Off);
+}
+
+/// Returns how many writes to a sink failed.
+fn errors(registry: &Registry, name: &str) -> Option<u32> {
+    registry
+        .entries()
+        .find(|entry| entry.sink.name() == name)
+        .map(|entry| entry.errors)
+}
+
+/// A sink whose writes fail while `failing` is set.
+struct FailingSink {
+    failing: AtomicBool,
+    writes: AtomicUsize,
+}
+
+impl ConsoleSink for FailingSink {
+    fn name(&self) -> &'static str {
+        "failing"
+    }
+
+    fn write_str(&self, _s: &str) -> fmt::Result {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        match self.failing.load(Ordering::Relaxed) {
+            true => Err(fmt::Error),
+            false => Ok(()),
+        }
+    }
+}
+
+#[test_case]
+fn failing_sink_is_disabled_after_repeated_errors() {
+    static GOOD: CountingSink = CountingSink::new("good");
+    static BAD: FailingSink = FailingSink {
+        failing: AtomicBool::new(true),
+        writes: AtomicUsize::new(0),
+    };
+
+    let registry = Registry::new();
+    registry.register(&BAD, true).unwrap();
+    registry.register(&GOOD, true).unwrap();
+
+    // One success in between starts the count over
+    registry.write_fmt(format_args!("one"));
+    BAD.failing.store(false, Ordering::Relaxed);
+    registry.write_fmt(format_args!("two"));
+    BAD.failing.store(true, Ordering::Relaxed);
+    for _ in 1..MAX_SINK_FAILURES {
+        registry.write_fmt(format_args!("three"));
+    }
+    assert!(registry.entries().all(|entry| entry.enabled));
+    assert_eq!(errors(&registry, "failing"), Some(MAX_SINK_FAILURES));
+    assert_eq!(GOOD.writes(), 1 + MAX_SINK_FAILURES as usize);
+
+    // The good sink gets the message and the notice about the bad one
+    registry.write_fmt(format_args!("four"));
+    assert!(GOOD.writes() > 1);
+    assert_eq!(errors(&registry, "failing"), Some(MAX_SINK_FAILURES + 1));
+
+    BAD.writes.store(0, Ordering::Relaxed);
+    registry.write_fmt(format_args!("five"));
+    assert_eq!(BAD.writes.load(Ordering::Relaxed), 0);
+    assert_eq!(GOOD.writes(), 1);
+    assert_eq!(errors(&registry, "good"), Some(0));
+}
+
+/// Fails to format after writing part of its output.
+struct BadDisplay;
+
+impl fmt::Display for BadDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("partial")?;
+        Err(fmt::Error)
+    }
+}
+
+#[test_case]
+fn formatting_errors_are_not_sink_errors() {
+    static SINK: CountingSink = CountingSink::new("counting");
+
+    let registry = Registry::new();
+    registry.register(&SINK, true).unwrap();
+    for _ in 0..MAX_SINK_FAILURES + 1 {
+        registry.write_fmt(format_args!("{} and more", BadDisplay));
+    }
+    assert!(registry.entries().all(|entry| entry.enabled));
+    assert_eq!(errors(&registry, "counting"), Some(0));
+    assert_eq!(SINK.writes(), MAX_SINK_FAILURES as usize + 1);
+
+    // Neither path panics
+    crate::print!("{}\n", BadDisplay);
+    crate::serial_print!("{}\n", BadDisplay);
+}
+
+/// A sink that logs a warning the first time it's written to.
+struct WarningSink {
+    writes: AtomicUsize,
+    warned: AtomicBool,
+}
+
+impl ConsoleSink for WarningSink {
+    fn name(&self) -> &'static str {
+        "warning"
+    }
+
+    fn write_str(&self, _s: &str) -> fmt::Result {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            log::warn!("Warning from a console sink");
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn sink_can_log_while_written_to() {
+    static SINK: WarningSink = WarningSink {
+        writes: AtomicUsize::new(0),
+        warned: AtomicBool::new(false),
+    };
+
+    super::register(&SINK, true).unwrap();
+    crate::println!("Writing to a sink that logs");
+    super::set_enabled(SINK.name(), false);
+
+    assert!(SINK.warned.load(Ordering::Relaxed));
+    // Both the message and the warning got through
+    assert!(SINK.writes.load(Ordering::Relaxed) > 1);
+    assert_eq!(REGISTRY.depth(), 0);
+}
+
+/// An I/O port that reads back as the debug console does when `present`,
+/// and keeps what is written to it.
+struct FakeDebugConPort {
+    present: bool,
+    written: RefCell<Vec<(u16, u8)>>,
+}
+
+impl FakeDebugConPort {
+    fn new(present: bool) -> Self {
+        Self {
+            present,
+            written: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl PortIo for FakeDebugConPort {
+    fn read(&self, port: u16) -> u8 {
+        // The device reads back as 0xE9, a missing one floats high
+        if self.present && port == DEBUGCON_PORT {
+            0xE9
+        } else {
+            0xff
+        }
+    }
+
+    fn write(&self, port: u16, value: u8) {
+        self.written.borrow_mut().push((port, value));
+    }
+}
+
+#[test_case]
+fn debugcon_probe_finds_the_device() {
+    assert!(DebugCon::with_io(FakeDebugConPort::new(true), DEBUGCON_PORT).probe());
+}
+
+#[test_case]
+fn debugcon_probe_without_the_device() {
+    assert!(!DebugCon::with_io(FakeDebugConPort::new(false), DEBUGCON_PORT).probe());
+    // Nothing answers on another port
+    assert!(!DebugCon::with_io(FakeDebugConPort::new(true), 0x402).probe());
+}
+
+#[test_case]
+fn debugcon_writes_bytes_unchanged() {
+    let port = FakeDebugConPort::new(true);
+    let debugcon = DebugCon::with_io(&port, DEBUGCON_PORT);
+    debugcon.write_str("a\nb");
+    debugcon.write_byte(0);
+
+    let written = port.written.borrow();
+    assert!(written.iter().all(|&(port, _)| port == DEBUGCON_PORT));
+    let bytes: Vec<u8> = written.iter().map(|&(_, byte)| byte).collect();
+    assert_eq!(bytes, b"a\nb\0");
+}
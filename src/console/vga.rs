@@ -0,0 +1,204 @@
+//! VGA text-mode console.
+//!
+//! This drives the legacy 80x25 text buffer at 0xB8000. Each cell is a
+//! 16-bit value with the character in the low byte and the color attribute
+//! in the high byte.
+//!
+//! References:
+//! - <https://wiki.osdev.org/Text_UI>
+//! - <https://wiki.osdev.org/Text_Mode_Cursor>
+
+use core::fmt;
+use core::ptr;
+
+use x86::io::outb;
+
+use super::ConsoleSink;
+use crate::sync::Mutex;
+
+/// Physical address of the text buffer.
+pub const VGA_BUFFER_ADDR: usize = 0xB8000;
+
+/// Number of columns.
+pub const VGA_WIDTH: usize = 80;
+
+/// Number of rows.
+pub const VGA_HEIGHT: usize = 25;
+
+/// Size of the text buffer in bytes.
+pub const VGA_BUFFER_SIZE: usize = VGA_WIDTH * VGA_HEIGHT * 2;
+
+/// CRT controller index register.
+const CRTC_INDEX: u16 = 0x3D4;
+
+/// CRT controller data register.
+const CRTC_DATA: u16 = 0x3D5;
+
+/// Cursor location high byte.
+const CRTC_CURSOR_HIGH: u8 = 0x0E;
+
+/// Cursor location low byte.
+const CRTC_CURSOR_LOW: u8 = 0x0F;
+
+/// A VGA text-mode color.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+/// A color attribute (foreground and background).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ColorCode(u8);
+
+impl ColorCode {
+    pub const fn new(foreground: Color, background: Color) -> Self {
+        Self((background as u8) << 4 | (foreground as u8))
+    }
+}
+
+/// A writer for the VGA text buffer.
+pub struct VgaWriter {
+    buffer: *mut u16,
+    row: usize,
+    column: usize,
+    color: ColorCode,
+}
+
+unsafe impl Send for VgaWriter {}
+
+impl VgaWriter {
+    /// Creates a writer for the text buffer at the default location.
+    pub const fn new() -> Self {
+        Self {
+            buffer: VGA_BUFFER_ADDR as *mut u16,
+            row: 0,
+            column: 0,
+            color: ColorCode::new(Color::LightGray, Color::Black),
+        }
+    }
+
+    /// Sets the color used for subsequent output.
+    #[allow(dead_code)] // Nothing prints in color yet
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.color = ColorCode::new(foreground, background);
+    }
+
+    /// Clears the screen and moves the cursor to the top left.
+    pub fn clear(&mut self) {
+        for row in 0..VGA_HEIGHT {
+            self.clear_row(row);
+        }
+        self.row = 0;
+        self.column = 0;
+        self.update_cursor();
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column = 0,
+            // Backspace only moves the cursor
+            0x08 => self.column = self.column.saturating_sub(1),
+            byte => {
+                if self.column >= VGA_WIDTH {
+                    self.new_line();
+                }
+                self.write_cell(self.row, self.column, byte);
+                self.column += 1;
+            }
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            match byte {
+                // Printable ASCII or cursor movement
+                0x20..=0x7e | b'\n' | b'\r' | 0x08 => self.write_byte(byte),
+                // Not part of the code page
+                _ => self.write_byte(0xfe),
+            }
+        }
+        self.update_cursor();
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < VGA_HEIGHT {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    /// Moves all rows up by one and clears the last row.
+    fn scroll(&mut self) {
+        unsafe {
+            ptr::copy(
+                self.buffer.add(VGA_WIDTH),
+                self.buffer,
+                VGA_WIDTH * (VGA_HEIGHT - 1),
+            );
+        }
+        self.clear_row(VGA_HEIGHT - 1);
+    }
+
+    fn clear_row(&mut self, row: usize) {
+        for column in 0..VGA_WIDTH {
+            self.write_cell(row, column, b' ');
+        }
+    }
+
+    fn write_cell(&mut self, row: usize, column: usize, byte: u8) {
+        let cell = (self.color.0 as u16) << 8 | byte as u16;
+        unsafe {
+            ptr::write_volatile(self.buffer.add(row * VGA_WIDTH + column), cell);
+        }
+    }
+
+    /// Moves the hardware cursor to the current position.
+    fn update_cursor(&self) {
+        let pos = (self.row * VGA_WIDTH + self.column.min(VGA_WIDTH - 1)) as u16;
+        unsafe {
+            outb(CRTC_INDEX, CRTC_CURSOR_LOW);
+            outb(CRTC_DATA, pos as u8);
+            outb(CRTC_INDEX, CRTC_CURSOR_HIGH);
+            outb(CRTC_DATA, (pos >> 8) as u8);
+        }
+    }
+}
+
+impl fmt::Write for VgaWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_str(s);
+        Ok(())
+    }
+}
+
+impl ConsoleSink for Mutex<VgaWriter> {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn write_str(&self, s: &str) -> fmt::Result {
+        self.lock().write_str(s);
+        Ok(())
+    }
+}
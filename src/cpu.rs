@@ -1,33 +1,76 @@
 //! The per-CPU data structure.
 //!
-//! The [`Cpu`] data structure is set as the `GS` base on the CPU.
-//! It currently consists of the following:
+//! Each CPU has a [`Cpu`] data structure in [`CPUS`], indexed by its LAPIC
+//! ID, which is set as the `GS` base on the CPU. It currently consists of
+//! the following:
 //!
+//! - A pointer to itself, so `mov rax, gs:[0]` yields the `Cpu`
 //! - GDT
 //! - TSS
 //! - IST stack spaces
 
+pub mod features;
+pub mod fpu;
+#[cfg(test)]
+mod test;
+pub mod tsc;
+
+pub use features::CpuFeatures;
+
 use core::arch::asm;
-use core::mem::MaybeUninit;
+use core::mem::{self, MaybeUninit};
 use core::ptr;
 
+use x86::cpuid::native_cpuid::cpuid_count;
 use x86::msr;
 
+use crate::boot::stage::BootArgs;
+use crate::config::KernelConfig;
+use crate::error::Result;
 use crate::gdt::{GlobalDescriptorTable, TaskStateSegment};
 use crate::interrupt::x86_xapic::XAPIC;
 
-static mut NEW_CPU: Cpu = Cpu::new();
+/// Maximum number of CPUs.
+///
+/// Each `Cpu` has 7 interrupt stacks, so this is kept small.
+pub const MAX_CPUS: usize = KernelConfig::DEFAULT.max_cpus as usize;
+
+/// The per-CPU data structures, indexed by LAPIC ID.
+static mut CPUS: [Cpu; MAX_CPUS] = [const { Cpu::new() }; MAX_CPUS];
+
+/// `IA32_APIC_BASE` bit set on the bootstrap processor.
+const APIC_BASE_BSP: u64 = 1 << 8;
 
 /// Size of an IST stack.
-const IST_STACK_SIZE: usize = 1 * 1024 * 1024; // 1 MiB
+const IST_STACK_SIZE: usize = KernelConfig::DEFAULT.ist_stack_size_kb as usize * 1024;
+
+/// Offset of [`Cpu::self_ptr`], for reading it relative to `GS` in assembly.
+pub const CURRENT_CPU_PTR_OFFSET: usize = mem::offset_of!(Cpu, self_ptr);
+
+/// Offset of [`Cpu::kernel_rsp`], for the system call entry.
+pub const KERNEL_RSP_OFFSET: usize = mem::offset_of!(Cpu, kernel_rsp);
+
+/// Offset of [`Cpu::user_rsp`], for the system call entry.
+pub const USER_RSP_OFFSET: usize = mem::offset_of!(Cpu, user_rsp);
+
+/// Offset of [`Cpu::interrupt_depth`], for the interrupt trampolines.
+pub const INTERRUPT_DEPTH_OFFSET: usize = mem::offset_of!(Cpu, interrupt_depth);
 
 #[repr(C, align(4096))]
 pub struct Cpu {
+    /// Pointer to this structure.
+    ///
+    /// This must stay the first field.
+    pub self_ptr: *mut Cpu,
+
     /// The CPU ID.
     ///
     /// Currently it's the logical APIC ID.
     pub id: usize,
 
+    /// Whether this is the bootstrap processor.
+    pub bsp: bool,
+
     /// State for the xAPIC driver.
     pub xapic: MaybeUninit<XAPIC>,
 
@@ -39,15 +82,46 @@ pub struct Cpu {
 
     /// The Interrupt Stacks.
     pub ist: [IstStack; 7],
+
+    /// Number of live `IrqGuard`s.
+    pub irq_depth: usize,
+
+    /// Whether interrupts were enabled before the outermost `IrqGuard`.
+    pub irqs_were_enabled: bool,
+
+    /// Number of interrupt handlers running, kept by the trampolines.
+    pub interrupt_depth: usize,
+
+    /// The kernel stack while a user program runs.
+    ///
+    /// System calls run on it, and it holds the kernel state to return to
+    /// when the program exits.
+    pub kernel_rsp: u64,
+
+    /// The user stack pointer while in a system call.
+    pub user_rsp: u64,
+
+    /// Number of panics this CPU is in, see `debug::panic_guard`.
+    pub panic_depth: usize,
+
+    /// Lock classes held by this CPU.
+    #[cfg(feature = "lockdep")]
+    pub held_locks: crate::sync::lockdep::HeldLocks,
 }
 
 /// A stack.
-#[repr(transparent)]
+///
+/// Aligned for the ABI, which also lets the canary be read as a `u64`.
+#[repr(C, align(16))]
 pub struct Stack<const SZ: usize>([u8; SZ]);
 
 /// An IST stack.
 pub type IstStack = Stack<IST_STACK_SIZE>;
 
+/// Written at the lowest address of a stack, so overflows can be noticed
+/// without guard pages.
+pub const STACK_CANARY: u64 = 0xDEAD_CAFE_BEEF_C0DE;
+
 impl<const SZ: usize> Stack<SZ> {
     pub const fn new() -> Self {
         Self([0u8; SZ])
@@ -56,6 +130,18 @@ impl<const SZ: usize> Stack<SZ> {
     pub fn bottom(&self) -> *const u8 {
         unsafe { (self.0.as_ptr() as *const u8).add(SZ) }
     }
+
+    /// Writes the canary at the lowest address.
+    pub fn write_canary(&mut self) {
+        unsafe { ptr::write_volatile(self.0.as_mut_ptr() as *mut u64, STACK_CANARY) }
+    }
+
+    /// Returns whether the canary is intact.
+    ///
+    /// A stack that grew down past its end overwrote it.
+    pub fn check_canary(&self) -> bool {
+        unsafe { ptr::read_volatile(self.0.as_ptr() as *const u64) == STACK_CANARY }
+    }
 }
 
 unsafe impl Send for Cpu {}
@@ -64,8 +150,11 @@ unsafe impl Sync for Cpu {}
 impl Cpu {
     pub const fn new() -> Self {
         Self {
-            // Implement this
+            // Set in `set_up_per_cpu_ptr`
+            self_ptr: ptr::null_mut(),
+            // Set in `set_up_per_cpu_ptr`, and from the LAPIC in `lapic::init`
             id: 0,
+            bsp: false,
             xapic: MaybeUninit::uninit(),
             gdt: GlobalDescriptorTable::empty(),
             tss: TaskStateSegment::new(),
@@ -78,20 +167,156 @@ impl Cpu {
                 IstStack::new(),
                 IstStack::new(),
             ],
+            irq_depth: 0,
+            irqs_were_enabled: false,
+            interrupt_depth: 0,
+            kernel_rsp: 0,
+            user_rsp: 0,
+            panic_depth: 0,
+            #[cfg(feature = "lockdep")]
+            held_locks: crate::sync::lockdep::HeldLocks::new(),
         }
     }
 }
 
 /// Returns a handle to the current CPU's data structure.
-/// We plan to implement support for per-CPU data structures via thread local
-/// variables for now just make sure you have one global CPU data structure and
-/// return it from this method
+///
+/// This reads the pointer at `gs:[0]`, so `set_up_per_cpu_ptr` must have
+/// been called on this CPU.
 pub fn get_current() -> &'static mut Cpu {
-    // Implement this
-    unsafe { &mut NEW_CPU }
+    let cpu: *mut Cpu;
+    unsafe {
+        asm!("mov {}, gs:[{}]", out(reg) cpu, const CURRENT_CPU_PTR_OFFSET, options(nostack, preserves_flags, readonly));
+        &mut *cpu
+    }
+}
+
+/// Returns a raw pointer to a field of the current CPU's data structure.
+macro_rules! get_current_cpu_field_ptr {
+    ($field:ident, $type:ty) => {
+        &mut (*$crate::cpu::get_current()).$field as *mut $type
+    };
+}
+pub(crate) use get_current_cpu_field_ptr;
+
+/// Points the `GS` base at the current CPU's data structure.
+///
+/// This must be the first thing each CPU does, since locks and anything
+/// else using `get_current` depend on it. Loading `GS` clears the base, so
+/// the GDT code leaves it alone.
+pub unsafe fn set_up_per_cpu_ptr() {
+    let id = initial_apic_id();
+    crate::kassert!(id < MAX_CPUS, "APIC ID {} is beyond MAX_CPUS", id);
+
+    let cpu = unsafe { &mut *ptr::addr_of_mut!(CPUS[id]) };
+    cpu.self_ptr = cpu as *mut Cpu;
+    cpu.id = id;
+
+    unsafe {
+        cpu.bsp = msr::rdmsr(msr::IA32_APIC_BASE) & APIC_BASE_BSP != 0;
+        msr::wrmsr(msr::IA32_GS_BASE, cpu.self_ptr as u64);
+        crate::kassert_eq!(msr::rdmsr(msr::IA32_GS_BASE), cpu.self_ptr as u64, "GS base did not stick");
+
+        let self_ptr: *mut Cpu;
+        asm!("mov {}, gs:[{}]", out(reg) self_ptr, const CURRENT_CPU_PTR_OFFSET);
+        crate::kassert_eq!(self_ptr, cpu.self_ptr, "gs:[{}] is not the Cpu", CURRENT_CPU_PTR_OFFSET);
+    }
+}
+
+/// Sets the ID of the current CPU.
+pub fn set_current_id(id: usize) {
+    get_current().id = id;
 }
 
+/// Returns the ID of the current CPU.
 pub fn get_cpu_id() -> i32 {
-    // Implement this
-    0
-}
\ No newline at end of file
+    get_current().id as i32
+}
+
+/// Returns whether the CPU with the given APIC ID has set up its
+/// per-CPU data.
+pub fn is_present(apic_id: u32) -> bool {
+    let id = apic_id as usize;
+    id < MAX_CPUS && unsafe { !(*ptr::addr_of!(CPUS[id])).self_ptr.is_null() }
+}
+
+/// Returns whether the current CPU is the bootstrap processor.
+pub fn is_bsp() -> bool {
+    get_current().bsp
+}
+
+/// Turns on the protections selected in the configuration, if the CPU
+/// has them.
+pub unsafe fn enable_protections() {
+    use x86::controlregs::{cr4, cr4_write, Cr4};
+
+    let config = crate::config::get();
+    let features = CpuFeatures::detect();
+    unsafe {
+        let mut flags = cr4();
+        if config.enable_smep && features.has("SMEP") {
+            flags |= Cr4::CR4_ENABLE_SMEP;
+        }
+        if config.enable_smap && features.has("SMAP") {
+            flags |= Cr4::CR4_ENABLE_SMAP;
+        }
+        cr4_write(flags);
+    }
+}
+
+/// The `per-CPU setup` boot stage: sets up the descriptor tables and CPU
+/// features of this CPU.
+pub fn init_stage(_args: &BootArgs) -> Result<()> {
+    unsafe {
+        {
+            let _gdt = crate::boot::phase::time("GDT");
+            crate::gdt::init_cpu();
+            enable_protections();
+        }
+        fpu::init();
+        crate::user::syscall::init_cpu();
+    }
+    Ok(())
+}
+
+/// Returns the LAPIC ID the CPU had at reset, from CPUID.
+fn initial_apic_id() -> usize {
+    (cpuid_count(1, 0).ebx >> 24) as usize
+}
+
+/// Resets the machine.
+///
+/// This uses the ACPI reset register if there is one, and otherwise pulses
+/// the CPU reset line through the keyboard controller. If that doesn't
+/// work we load an empty IDT and trigger a triple fault.
+pub fn reboot() -> ! {
+    use x86::dtables::{lidt, DescriptorTablePointer};
+    use x86::io::{inb, outb};
+
+    if let Err(e) = crate::acpi::reset() {
+        log::warn!("ACPI reset failed: {}", e);
+    }
+
+    unsafe {
+        // Wait for the input buffer to be empty, but not forever: without
+        // a keyboard controller the status reads 0xff
+        let deadline = tsc::deadline(core::time::Duration::from_millis(20));
+        while inb(0x64) & 0x02 != 0 && tsc::rdtsc() < deadline {
+            core::hint::spin_loop();
+        }
+        outb(0x64, 0xfe);
+
+        let idt: DescriptorTablePointer<u64> = DescriptorTablePointer {
+            limit: 0,
+            base: ptr::null(),
+        };
+        lidt(&idt);
+        asm!("int3");
+    }
+
+    loop {
+        unsafe {
+            asm!("cli; hlt");
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! CPU feature detection.
+//!
+//! [`CpuFeatures`] keeps the raw feature registers from the CPUID leaves we
+//! care about, and the table below gives the bits their names.
+//!
+//! References:
+//! - Intel SDM Vol. 2A, CPUID
+//! - AMD APM Vol. 3, Appendix E
+
+use core::fmt::{self, Write};
+
+use x86::cpuid::native_cpuid::cpuid_count;
+
+/// Maximum length of the compact feature string.
+const COMPACT_LEN: usize = 512;
+
+/// A CPUID output register holding feature bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    Leaf1Ecx,
+    Leaf1Edx,
+    Leaf7Ebx,
+    Leaf7Ecx,
+    Leaf7Edx,
+    ExtEcx,
+    ExtEdx,
+}
+
+/// A group of feature flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Basic,
+    Extended,
+    AmdExtended,
+}
+
+impl Category {
+    const ALL: [Category; 3] = [Category::Basic, Category::Extended, Category::AmdExtended];
+
+    fn title(self) -> &'static str {
+        match self {
+            Category::Basic => "Basic (leaf 1)",
+            Category::Extended => "Extended (leaf 7)",
+            Category::AmdExtended => "AMD Extended (leaf 0x80000001)",
+        }
+    }
+}
+
+/// A named feature bit.
+struct Flag {
+    name: &'static str,
+    register: Register,
+    bit: u32,
+}
+
+impl Flag {
+    const fn new(name: &'static str, register: Register, bit: u32) -> Self {
+        Self {
+            name,
+            register,
+            bit,
+        }
+    }
+
+    fn category(&self) -> Category {
+        match self.register {
+            Register::Leaf1Ecx | Register::Leaf1Edx => Category::Basic,
+            Register::Leaf7Ebx | Register::Leaf7Ecx | Register::Leaf7Edx => Category::Extended,
+            Register::ExtEcx | Register::ExtEdx => Category::AmdExtended,
+        }
+    }
+}
+
+use Register::*;
+
+/// All feature flags we know about, in display order.
+static FLAGS: &[Flag] = &[
+    // Leaf 1, EDX
+    Flag::new("FPU", Leaf1Edx, 0),
+    Flag::new("VME", Leaf1Edx, 1),
+    Flag::new("DE", Leaf1Edx, 2),
+    Flag::new("PSE", Leaf1Edx, 3),
+    Flag::new("TSC", Leaf1Edx, 4),
+    Flag::new("MSR", Leaf1Edx, 5),
+    Flag::new("PAE", Leaf1Edx, 6),
+    Flag::new("MCE", Leaf1Edx, 7),
+    Flag::new("CX8", Leaf1Edx, 8),
+    Flag::new("APIC", Leaf1Edx, 9),
+    Flag::new("SEP", Leaf1Edx, 11),
+    Flag::new("MTRR", Leaf1Edx, 12),
+    Flag::new("PGE", Leaf1Edx, 13),
+    Flag::new("MCA", Leaf1Edx, 14),
+    Flag::new("CMOV", Leaf1Edx, 15),
+    Flag::new("PAT", Leaf1Edx, 16),
+    Flag::new("PSE36", Leaf1Edx, 17),
+    Flag::new("CLFSH", Leaf1Edx, 19),
+    Flag::new("MMX", Leaf1Edx, 23),
+    Flag::new("FXSR", Leaf1Edx, 24),
+    Flag::new("SSE", Leaf1Edx, 25),
+    Flag::new("SSE2", Leaf1Edx, 26),
+    Flag::new("HTT", Leaf1Edx, 28),
+    // Leaf 1, ECX
+    Flag::new("SSE3", Leaf1Ecx, 0),
+    Flag::new("PCLMULQDQ", Leaf1Ecx, 1),
+    Flag::new("MONITOR", Leaf1Ecx, 3),
+    Flag::new("VMX", Leaf1Ecx, 5),
+    Flag::new("SSSE3", Leaf1Ecx, 9),
+    Flag::new("FMA", Leaf1Ecx, 12),
+    Flag::new("CX16", Leaf1Ecx, 13),
+    Flag::new("PCID", Leaf1Ecx, 17),
+    Flag::new("SSE4.1", Leaf1Ecx, 19),
+    Flag::new("SSE4.2", Leaf1Ecx, 20),
+    Flag::new("X2APIC", Leaf1Ecx, 21),
+    Flag::new("MOVBE", Leaf1Ecx, 22),
+    Flag::new("POPCNT", Leaf1Ecx, 23),
+    Flag::new("TSC-DEADLINE", Leaf1Ecx, 24),
+    Flag::new("AES", Leaf1Ecx, 25),
+    Flag::new("XSAVE", Leaf1Ecx, 26),
+    Flag::new("OSXSAVE", Leaf1Ecx, 27),
+    Flag::new("AVX", Leaf1Ecx, 28),
+    Flag::new("F16C", Leaf1Ecx, 29),
+    Flag::new("RDRAND", Leaf1Ecx, 30),
+    Flag::new("HYPERVISOR", Leaf1Ecx, 31),
+    // Leaf 7, EBX
+    Flag::new("FSGSBASE", Leaf7Ebx, 0),
+    Flag::new("BMI1", Leaf7Ebx, 3),
+    Flag::new("HLE", Leaf7Ebx, 4),
+    Flag::new("AVX2", Leaf7Ebx, 5),
+    Flag::new("SMEP", Leaf7Ebx, 7),
+    Flag::new("BMI2", Leaf7Ebx, 8),
+    Flag::new("ERMS", Leaf7Ebx, 9),
+    Flag::new("INVPCID", Leaf7Ebx, 10),
+    Flag::new("RTM", Leaf7Ebx, 11),
+    Flag::new("AVX512F", Leaf7Ebx, 16),
+    Flag::new("AVX512DQ", Leaf7Ebx, 17),
+    Flag::new("RDSEED", Leaf7Ebx, 18),
+    Flag::new("ADX", Leaf7Ebx, 19),
+    Flag::new("SMAP", Leaf7Ebx, 20),
+    Flag::new("CLFLUSHOPT", Leaf7Ebx, 23),
+    Flag::new("CLWB", Leaf7Ebx, 24),
+    Flag::new("AVX512CD", Leaf7Ebx, 28),
+    Flag::new("SHA", Leaf7Ebx, 29),
+    Flag::new("AVX512BW", Leaf7Ebx, 30),
+    Flag::new("AVX512VL", Leaf7Ebx, 31),
+    // Leaf 7, ECX
+    Flag::new("UMIP", Leaf7Ecx, 2),
+    Flag::new("PKU", Leaf7Ecx, 3),
+    Flag::new("OSPKE", Leaf7Ecx, 4),
+    Flag::new("LA57", Leaf7Ecx, 16),
+    Flag::new("RDPID", Leaf7Ecx, 22),
+    // Leaf 7, EDX
+    Flag::new("MD_CLEAR", Leaf7Edx, 10),
+    Flag::new("SPEC_CTRL", Leaf7Edx, 26),
+    Flag::new("STIBP", Leaf7Edx, 27),
+    Flag::new("ARCH_CAPABILITIES", Leaf7Edx, 29),
+    Flag::new("SSBD", Leaf7Edx, 31),
+    // Leaf 0x80000001, EDX
+    Flag::new("SYSCALL", ExtEdx, 11),
+    Flag::new("NX", ExtEdx, 20),
+    Flag::new("PDPE1GB", ExtEdx, 26),
+    Flag::new("RDTSCP", ExtEdx, 27),
+    Flag::new("LM", ExtEdx, 29),
+    // Leaf 0x80000001, ECX
+    Flag::new("LAHF_LM", ExtEcx, 0),
+    Flag::new("SVM", ExtEcx, 2),
+    Flag::new("LZCNT", ExtEcx, 5),
+    Flag::new("SSE4A", ExtEcx, 6),
+    Flag::new("PREFETCHW", ExtEcx, 8),
+];
+
+/// The feature bits reported by CPUID.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuFeatures {
+    leaf1_ecx: u32,
+    leaf1_edx: u32,
+    leaf7_ebx: u32,
+    leaf7_ecx: u32,
+    leaf7_edx: u32,
+    ext_ecx: u32,
+    ext_edx: u32,
+}
+
+impl CpuFeatures {
+    /// Queries the features of the current CPU.
+    pub fn detect() -> Self {
+        let mut features = Self::default();
+
+        let max_leaf = cpuid_count(0, 0).eax;
+        if max_leaf >= 1 {
+            let leaf1 = cpuid_count(1, 0);
+            features.leaf1_ecx = leaf1.ecx;
+            features.leaf1_edx = leaf1.edx;
+        }
+        if max_leaf >= 7 {
+            let leaf7 = cpuid_count(7, 0);
+            features.leaf7_ebx = leaf7.ebx;
+            features.leaf7_ecx = leaf7.ecx;
+            features.leaf7_edx = leaf7.edx;
+        }
+
+        let max_ext_leaf = cpuid_count(0x8000_0000, 0).eax;
+        if max_ext_leaf >= 0x8000_0001 {
+            let ext = cpuid_count(0x8000_0001, 0);
+            features.ext_ecx = ext.ecx;
+            features.ext_edx = ext.edx;
+        }
+
+        features
+    }
+
+    /// Checks whether a feature is supported, by its display name.
+    pub fn has(&self, name: &str) -> bool {
+        FLAGS
+            .iter()
+            .any(|flag| flag.name == name && self.is_set(flag))
+    }
+
+    /// Prints every known feature flag, grouped by CPUID leaf.
+    ///
+    /// This doesn't allocate, so it can be used before the heap is up.
+    pub fn print_summary(&self) {
+        for category in Category::ALL {
+            crate::println!("{}:", category.title());
+            for flag in FLAGS.iter().filter(|flag| flag.category() == category) {
+                let value = if self.is_set(flag) { "yes" } else { "no" };
+                crate::println!("  {}: {}", flag.name, value);
+            }
+        }
+    }
+
+    /// Returns the enabled features as a comma-separated list.
+    ///
+    /// The list is truncated if it doesn't fit.
+    pub fn compact(&self) -> CompactFeatures {
+        let mut out = CompactFeatures::new();
+        for flag in FLAGS.iter().filter(|flag| self.is_set(flag)) {
+            let separator = if out.len == 0 { "" } else { "," };
+            if out.len + separator.len() + flag.name.len() > COMPACT_LEN {
+                break;
+            }
+            let _ = out.write_str(separator);
+            let _ = out.write_str(flag.name);
+        }
+        out
+    }
+
+    fn is_set(&self, flag: &Flag) -> bool {
+        let value = match flag.register {
+            Leaf1Ecx => self.leaf1_ecx,
+            Leaf1Edx => self.leaf1_edx,
+            Leaf7Ebx => self.leaf7_ebx,
+            Leaf7Ecx => self.leaf7_ecx,
+            Leaf7Edx => self.leaf7_edx,
+            ExtEcx => self.ext_ecx,
+            ExtEdx => self.ext_edx,
+        };
+        value & (1 << flag.bit) != 0
+    }
+}
+
+/// A fixed-size buffer holding the compact feature list.
+pub struct CompactFeatures {
+    buf: [u8; COMPACT_LEN],
+    len: usize,
+}
+
+impl CompactFeatures {
+    const fn new() -> Self {
+        Self {
+            buf: [0; COMPACT_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole `str`s are ever appended
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl Write for CompactFeatures {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > COMPACT_LEN {
+            return Err(fmt::Error);
+        }
+        self.buf[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+impl fmt::Display for CompactFeatures {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
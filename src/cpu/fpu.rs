@@ -0,0 +1,161 @@
+//! x87, SSE and AVX register state.
+//!
+//! The kernel itself is built without SSE, so these registers only hold
+//! state that belongs to someone else. [`FpuState`] is an area to save
+//! that state in. Its size depends on which state components are enabled
+//! in XCR0, so it's only known at run time.
+//!
+//! References:
+//! - Intel SDM Vol. 1, Chapter 13, Managing State Using the XSAVE Feature Set
+
+use core::alloc::Layout;
+use core::arch::asm;
+use core::ptr::NonNull;
+
+use x86::controlregs::{cr0, cr0_write, cr4, cr4_write, xcr0, xcr0_write, Cr0, Cr4, Xcr0};
+use x86::cpuid::native_cpuid::cpuid_count;
+
+use super::CpuFeatures;
+use crate::memory::fallible::{try_alloc, AllocError};
+
+/// Size of the area used by FXSAVE.
+pub const FXSAVE_AREA_SIZE: usize = 512;
+
+/// XSAVE and FXSAVE need their area aligned to 64 and 16 bytes.
+const AREA_ALIGN: usize = 64;
+
+/// The state components we enable, if the CPU has them.
+const WANTED_XCR0: Xcr0 = Xcr0::XCR0_FPU_MMX_STATE
+    .union(Xcr0::XCR0_SSE_STATE)
+    .union(Xcr0::XCR0_AVX_STATE);
+
+/// Enables SSE, and XSAVE if the CPU has it, on the current CPU.
+///
+/// This should be called once per CPU.
+pub unsafe fn init() {
+    let features = CpuFeatures::detect();
+    unsafe {
+        let mut flags = cr0();
+        flags.remove(Cr0::CR0_EMULATE_COPROCESSOR);
+        flags.insert(Cr0::CR0_MONITOR_COPROCESSOR);
+        cr0_write(flags);
+
+        let mut flags = cr4() | Cr4::CR4_ENABLE_SSE | Cr4::CR4_UNMASKED_SSE;
+        if features.has("XSAVE") {
+            flags |= Cr4::CR4_ENABLE_OS_XSAVE;
+        }
+        cr4_write(flags);
+
+        if features.has("XSAVE") {
+            // Components the CPU supports, from CPUID leaf 0xD
+            let supported = Xcr0::from_bits_truncate(cpuid_count(0xd, 0).eax as u64);
+            xcr0_write(supported & WANTED_XCR0);
+        }
+    }
+}
+
+/// Returns whether XSAVE has been enabled on the current CPU.
+fn xsave_enabled() -> bool {
+    CpuFeatures::detect().has("OSXSAVE")
+}
+
+/// Returns whether XSAVEOPT can be used on the current CPU.
+fn xsaveopt_supported() -> bool {
+    xsave_enabled() && cpuid_count(0xd, 1).eax & 1 != 0
+}
+
+/// Returns the size of the save area for the components enabled in XCR0.
+///
+/// Without XSAVE, this is the size of the FXSAVE area.
+pub fn xsave_area_size() -> usize {
+    if !xsave_enabled() {
+        return FXSAVE_AREA_SIZE;
+    }
+    cpuid_count(0xd, 0).ebx as usize
+}
+
+/// An area to save the x87, SSE and AVX registers in.
+#[allow(dead_code)] // Nothing switches FPU state yet
+pub struct FpuState {
+    area: NonNull<u8>,
+    layout: Layout,
+}
+
+#[allow(dead_code)] // Nothing switches FPU state yet
+impl FpuState {
+    /// Allocates a zeroed area big enough for the current CPU.
+    ///
+    /// XRSTOR faults on an area with a bad header, and zeroes are a valid
+    /// one, so an area can be restored before it has been saved to.
+    pub fn allocate() -> Result<Self, AllocError> {
+        let layout = Layout::from_size_align(xsave_area_size(), AREA_ALIGN).unwrap();
+        let area = try_alloc(layout)?;
+        unsafe { area.as_ptr().write_bytes(0, layout.size()) };
+        Ok(Self { area, layout })
+    }
+
+    /// Returns the saved state.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.area.as_ptr(), self.layout.size()) }
+    }
+
+    /// Saves the registers of the current CPU.
+    ///
+    /// XSAVEOPT is used if there is one, which skips components that
+    /// haven't changed since they were restored from this area.
+    pub fn save(&mut self) {
+        let area = self.area.as_ptr();
+        unsafe {
+            if xsaveopt_supported() {
+                let mask = xcr0().bits();
+                asm!(
+                    "xsaveopt64 [{}]",
+                    in(reg) area,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack, preserves_flags),
+                );
+            } else if xsave_enabled() {
+                let mask = xcr0().bits();
+                asm!(
+                    "xsave64 [{}]",
+                    in(reg) area,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!("fxsave64 [{}]", in(reg) area, options(nostack, preserves_flags));
+            }
+        }
+    }
+
+    /// Loads the registers of the current CPU from the area.
+    ///
+    /// # Safety
+    /// The area must hold state saved on a CPU with the same XCR0, or be
+    /// freshly allocated.
+    pub unsafe fn restore(&self) {
+        let area = self.area.as_ptr();
+        unsafe {
+            if xsave_enabled() {
+                let mask = xcr0().bits();
+                asm!(
+                    "xrstor64 [{}]",
+                    in(reg) area,
+                    in("eax") mask as u32,
+                    in("edx") (mask >> 32) as u32,
+                    options(nostack, preserves_flags),
+                );
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) area, options(nostack, preserves_flags));
+            }
+        }
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { alloc::alloc::dealloc(self.area.as_ptr(), self.layout) };
+    }
+}
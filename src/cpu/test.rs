@@ -0,0 +1,48 @@
+//! CPU tests.
+
+use core::arch::asm;
+use core::time::Duration;
+
+use super::fpu::{xsave_area_size, FpuState, FXSAVE_AREA_SIZE};
+use super::tsc;
+
+fn read_xmm0() -> u64 {
+    let value;
+    unsafe { asm!("movq {}, xmm0", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+fn write_xmm0(value: u64) {
+    unsafe { asm!("movq xmm0, {}", in(reg) value, options(nomem, nostack)) };
+}
+
+#[test_case]
+fn fpu_area_size_and_alignment() {
+    let size = xsave_area_size();
+    assert!(size >= FXSAVE_AREA_SIZE, "area of {} bytes", size);
+
+    let state = FpuState::allocate().unwrap();
+    assert_eq!(state.as_bytes().len(), size);
+    assert_eq!(state.as_bytes().as_ptr() as usize % 64, 0);
+}
+
+#[test_case]
+fn fpu_state_is_restored() {
+    let mut state = FpuState::allocate().unwrap();
+
+    write_xmm0(0x1122_3344_5566_7788);
+    state.save();
+    write_xmm0(0xdead_beef);
+    assert_eq!(read_xmm0(), 0xdead_beef);
+
+    unsafe { state.restore() };
+    assert_eq!(read_xmm0(), 0x1122_3344_5566_7788);
+}
+
+#[test_case]
+fn busy_wait_waits_long_enough() {
+    let duration = Duration::from_micros(100);
+    let start = tsc::rdtsc();
+    tsc::busy_wait(duration);
+    assert!(tsc::rdtsc() - start >= tsc::cycles(duration));
+}
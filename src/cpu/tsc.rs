@@ -0,0 +1,76 @@
+//! The time stamp counter.
+//!
+//! The TSC isn't calibrated against a timer yet. Its frequency comes from
+//! CPUID if the CPU reports it, and is a guess otherwise, so durations
+//! converted to cycles are best-effort.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use x86::cpuid::native_cpuid::cpuid_count;
+
+/// TSC frequency to assume if CPUID doesn't tell.
+const FALLBACK_KHZ: u64 = 3_000_000;
+
+/// The TSC frequency, zero until first needed.
+static KHZ: AtomicU64 = AtomicU64::new(0);
+
+/// Reads the TSC.
+pub fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Returns the TSC frequency in kHz.
+pub fn khz() -> u64 {
+    let khz = KHZ.load(Ordering::Relaxed);
+    if khz != 0 {
+        return khz;
+    }
+
+    let khz = cpuid_khz().unwrap_or(FALLBACK_KHZ);
+    KHZ.store(khz, Ordering::Relaxed);
+    khz
+}
+
+/// Converts a duration to TSC cycles.
+pub fn cycles(duration: Duration) -> u64 {
+    let cycles = duration.as_micros().saturating_mul(khz() as u128) / 1000;
+    cycles.try_into().unwrap_or(u64::MAX)
+}
+
+/// Returns the TSC value `duration` from now.
+pub fn deadline(duration: Duration) -> u64 {
+    rdtsc().saturating_add(cycles(duration))
+}
+
+/// Spins for `duration`.
+///
+/// Works with interrupts off, like in the panic handler.
+pub fn busy_wait(duration: Duration) {
+    let deadline = deadline(duration);
+    while rdtsc() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Reads the TSC frequency from CPUID leaf 0x15, or 0x16 as a fallback.
+fn cpuid_khz() -> Option<u64> {
+    let max_leaf = cpuid_count(0, 0).eax;
+
+    if max_leaf >= 0x15 {
+        let leaf = cpuid_count(0x15, 0);
+        if leaf.eax != 0 && leaf.ebx != 0 && leaf.ecx != 0 {
+            return Some(leaf.ecx as u64 * leaf.ebx as u64 / leaf.eax as u64 / 1000);
+        }
+    }
+
+    if max_leaf >= 0x16 {
+        // Base frequency in MHz
+        let mhz = cpuid_count(0x16, 0).eax & 0xFFFF;
+        if mhz != 0 {
+            return Some(mhz as u64 * 1000);
+        }
+    }
+
+    None
+}
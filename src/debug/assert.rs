@@ -0,0 +1,258 @@
+//! Kernel assertions.
+//!
+//! [`kassert!`] and friends work like their `core` counterparts, but on
+//! failure they log a report with the state of the machine before
+//! panicking:
+//!
+//! ```text
+//! assertion failed: self.is_initialized(): PageAllocator::init was not called
+//!   at src/memory/page_allocator.rs:370:9
+//!   cpu 0, interrupt depth 1, tick 5028
+//!   rsp=0x... rbp=0x... rflags=0x... cr0=0x... cr2=0x... cr3=0x... cr4=0x...
+//! ```
+//!
+//! [`kdebug_assert!`] and its variants are only checked in debug builds.
+//!
+//! [`kassert!`]: crate::kassert
+//! [`kdebug_assert!`]: crate::kdebug_assert
+
+use core::arch::asm;
+use core::fmt;
+use core::panic::Location;
+
+/// Asserts that a condition holds, with a report on failure.
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            $crate::debug::assert::fail(
+                concat!("assertion failed: ", stringify!($cond)),
+                None,
+            );
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            $crate::debug::assert::fail(
+                concat!("assertion failed: ", stringify!($cond)),
+                Some(format_args!($($arg)+)),
+            );
+        }
+    };
+}
+
+/// Asserts that two values are equal, with a report on failure.
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    $crate::debug::assert::fail_cmp("==", left, right, None);
+                }
+            }
+        }
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    $crate::debug::assert::fail_cmp(
+                        "==",
+                        left,
+                        right,
+                        Some(format_args!($($arg)+)),
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Asserts that two values are not equal, with a report on failure.
+#[macro_export]
+macro_rules! kassert_ne {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left == *right {
+                    $crate::debug::assert::fail_cmp("!=", left, right, None);
+                }
+            }
+        }
+    };
+    ($left:expr, $right:expr, $($arg:tt)+) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if *left == *right {
+                    $crate::debug::assert::fail_cmp(
+                        "!=",
+                        left,
+                        right,
+                        Some(format_args!($($arg)+)),
+                    );
+                }
+            }
+        }
+    };
+}
+
+/// Like [`kassert!`](crate::kassert), but only checked in debug builds.
+#[macro_export]
+macro_rules! kdebug_assert {
+    ($($arg:tt)*) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert!($($arg)*);
+        }
+    };
+}
+
+/// Like [`kassert_eq!`](crate::kassert_eq), but only checked in debug builds.
+#[macro_export]
+macro_rules! kdebug_assert_eq {
+    ($($arg:tt)*) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert_eq!($($arg)*);
+        }
+    };
+}
+
+/// Like [`kassert_ne!`](crate::kassert_ne), but only checked in debug builds.
+#[macro_export]
+macro_rules! kdebug_assert_ne {
+    ($($arg:tt)*) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert_ne!($($arg)*);
+        }
+    };
+}
+
+/// Control and general-purpose registers at the time of a failure.
+#[derive(Debug, Clone, Copy)]
+pub struct Registers {
+    pub rsp: u64,
+    pub rbp: u64,
+    pub rflags: u64,
+    pub cr0: u64,
+    pub cr2: u64,
+    pub cr3: u64,
+    pub cr4: u64,
+}
+
+impl Registers {
+    /// Reads the registers of the current CPU.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (rsp, rbp, rflags, cr0, cr2, cr3, cr4): (u64, u64, u64, u64, u64, u64, u64);
+        unsafe {
+            asm!("mov {}, rsp", out(reg) rsp);
+            asm!("mov {}, rbp", out(reg) rbp);
+            asm!("pushfq; pop {}", out(reg) rflags);
+            asm!("mov {}, cr0", out(reg) cr0);
+            asm!("mov {}, cr2", out(reg) cr2);
+            asm!("mov {}, cr3", out(reg) cr3);
+            asm!("mov {}, cr4", out(reg) cr4);
+        }
+
+        Self {
+            rsp,
+            rbp,
+            rflags,
+            cr0,
+            cr2,
+            cr3,
+            cr4,
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "rsp={:#x} rbp={:#x} rflags={:#x} cr0={:#x} cr2={:#x} cr3={:#x} cr4={:#x}",
+            self.rsp, self.rbp, self.rflags, self.cr0, self.cr2, self.cr3, self.cr4,
+        )
+    }
+}
+
+/// The report logged for a failed assertion.
+pub struct Report<'a> {
+    pub summary: fmt::Arguments<'a>,
+    pub message: Option<fmt::Arguments<'a>>,
+    pub location: &'a Location<'a>,
+    pub cpu: i32,
+    pub depth: usize,
+    pub ticks: u64,
+    pub registers: Registers,
+}
+
+impl<'a> Report<'a> {
+    /// Collects the state of the current CPU.
+    #[inline(always)]
+    fn new(
+        summary: fmt::Arguments<'a>,
+        message: Option<fmt::Arguments<'a>>,
+        location: &'a Location<'a>,
+    ) -> Self {
+        Self {
+            summary,
+            message,
+            location,
+            cpu: crate::cpu::get_cpu_id(),
+            depth: crate::interrupt::depth(),
+            ticks: crate::time::ticks(),
+            registers: Registers::capture(),
+        }
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.summary)?;
+        if let Some(message) = self.message {
+            write!(f, ": {}", message)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  at {}", self.location)?;
+        writeln!(
+            f,
+            "  cpu {}, interrupt depth {}, tick {}",
+            self.cpu, self.depth, self.ticks
+        )?;
+        write!(f, "  {}", self.registers)
+    }
+}
+
+/// Reports a failed `kassert!` and panics.
+#[doc(hidden)]
+#[cold]
+#[track_caller]
+pub fn fail(expr: &'static str, message: Option<fmt::Arguments>) -> ! {
+    report(Report::new(format_args!("{}", expr), message, Location::caller()))
+}
+
+/// Reports a failed `kassert_eq!` or `kassert_ne!` and panics.
+#[doc(hidden)]
+#[cold]
+#[track_caller]
+pub fn fail_cmp(
+    op: &'static str,
+    left: &dyn fmt::Debug,
+    right: &dyn fmt::Debug,
+    message: Option<fmt::Arguments>,
+) -> ! {
+    report(Report::new(
+        format_args!("assertion `left {} right` failed: left={:?} right={:?}", op, left, right),
+        message,
+        Location::caller(),
+    ))
+}
+
+fn report(report: Report) -> ! {
+    log::error!(target: "kassert", "{}", report);
+    match report.message {
+        Some(message) => panic!("{}: {}", report.summary, message),
+        None => panic!("{}", report.summary),
+    }
+}
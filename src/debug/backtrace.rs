@@ -0,0 +1,116 @@
+//! Backtraces by following frame pointers.
+//!
+//! The target forces frame pointers on, so every function starts by
+//! pushing RBP and pointing RBP at the saved copy. Each frame then holds
+//! the caller's RBP with the return address right above it:
+//!
+//! ```text
+//! [rbp + 8]  return address into the caller
+//! [rbp]      the caller's rbp
+//! ```
+//!
+//! `boot.asm` clears RBP before calling `rust_main`, which ends the chain.
+//! Every read is checked with [`crate::memory::probe`], so a corrupted
+//! stack ends the backtrace early instead of faulting.
+
+#[cfg(any(test, feature = "heap-debug"))]
+use core::arch::asm;
+use core::fmt;
+
+use super::ksyms::{self, Symbolized};
+use crate::memory::probe;
+
+/// Maximum number of frames that are recorded.
+pub const MAX_FRAMES: usize = 32;
+
+/// Largest plausible distance between two frames.
+const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+/// Return addresses on the stack, innermost first.
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    frames: [u64; MAX_FRAMES],
+    len: usize,
+}
+
+impl Backtrace {
+    /// Walks the stack of the caller.
+    ///
+    /// The first frame is the return address into the caller.
+    #[cfg(test)]
+    #[inline(never)]
+    pub fn capture() -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        Self::walk(rbp, MAX_FRAMES)
+    }
+
+    /// Like `capture`, but stops after `max` frames.
+    #[cfg(feature = "heap-debug")]
+    #[inline(never)]
+    pub fn capture_up_to(max: usize) -> Self {
+        let rbp: u64;
+        unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+        Self::walk(rbp, max.min(MAX_FRAMES))
+    }
+
+    /// Walks the stack starting at the frame `rbp` points to.
+    pub fn from_rbp(rbp: u64) -> Self {
+        Self::walk(rbp, MAX_FRAMES)
+    }
+
+    fn walk(mut rbp: u64, max: usize) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+        };
+
+        while backtrace.len < max && rbp != 0 && rbp % 8 == 0 {
+            let (Some(next), Some(ret)) = (read_u64(rbp), read_u64(rbp + 8)) else {
+                break;
+            };
+            if ret == 0 {
+                break;
+            }
+            backtrace.frames[backtrace.len] = ret;
+            backtrace.len += 1;
+
+            // The stack grows down, so callers are always further up
+            if next != 0 && (next <= rbp || next - rbp > MAX_FRAME_SIZE) {
+                break;
+            }
+            rbp = next;
+        }
+        backtrace
+    }
+
+    /// Returns the return addresses, innermost first.
+    pub fn frames(&self) -> &[u64] {
+        &self.frames[..self.len]
+    }
+}
+
+/// Reads an aligned `u64`, which can't straddle two pages.
+fn read_u64(addr: u64) -> Option<u64> {
+    if !probe::is_mapped(addr as usize) {
+        return None;
+    }
+    Some(unsafe { core::ptr::read_volatile(addr as *const u64) })
+}
+
+/// Prints a line per frame, with its function if the symbols are there.
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !ksyms::is_loaded() {
+            writeln!(f, "  (no kernel symbols, run ksyms.sh on the kernel)")?;
+        }
+        for (i, &addr) in self.frames().iter().enumerate() {
+            let symbolized = Symbolized::return_address(addr);
+            match symbolized.symbol() {
+                Some(_) => writeln!(f, "  #{:<2} {:#018x} {}", i, addr, symbolized)?,
+                None => writeln!(f, "  #{:<2} {:#018x}", i, addr)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Rust symbol demangling.
+//!
+//! Covers what shows up in backtraces: legacy `_ZN` paths with their hash
+//! dropped, and v0 `_R` paths made of crate roots, nested names and
+//! closures. Anything else, like v0 generic arguments, prints as the
+//! mangled name, which is still enough to find the function.
+//!
+//! ```text
+//! _ZN8hello_os9rust_main17h0123456789abcdefE  ->  hello_os::rust_main
+//! _RNvCs1234_8hello_os9rust_main              ->  hello_os::rust_main
+//! ```
+
+use core::fmt::{self, Write};
+
+/// Demangles `name` when printed.
+pub fn demangle(name: &str) -> Demangle<'_> {
+    Demangle(name)
+}
+
+/// A symbol name that demangles itself when printed.
+#[derive(Debug, Clone, Copy)]
+pub struct Demangle<'a>(&'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Parse once without output, so a name that turns out to be
+        // unsupported halfway through isn't printed half demangled
+        if legacy(&mut Discard, self.0).is_ok() {
+            legacy(f, self.0)
+        } else if V0::new(self.0).and_then(|mut v0| v0.symbol(&mut Discard).ok()).is_some() {
+            V0::new(self.0).ok_or(fmt::Error)?.symbol(f)
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+/// Compares the demangled name without formatting it somewhere first.
+impl PartialEq<&str> for Demangle<'_> {
+    fn eq(&self, other: &&str) -> bool {
+        let mut compare = Compare(other);
+        write!(compare, "{}", self).is_ok() && compare.0.is_empty()
+    }
+}
+
+/// A writer that throws everything away.
+struct Discard;
+
+impl Write for Discard {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// A writer that fails as soon as the output stops matching.
+struct Compare<'a>(&'a str);
+
+impl Write for Compare<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 = self.0.strip_prefix(s).ok_or(fmt::Error)?;
+        Ok(())
+    }
+}
+
+/// Splits a decimal length off the front of `s`.
+fn parse_len(s: &str) -> Option<(usize, &str)> {
+    let digits = s.bytes().take_while(u8::is_ascii_digit).count();
+    Some((s[..digits].parse().ok()?, &s[digits..]))
+}
+
+/// Returns whether a legacy path component is the trailing `h<16 hex>`.
+fn is_hash(ident: &str) -> bool {
+    ident.len() == 17
+        && ident.starts_with('h')
+        && ident[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Writes a legacy `_ZN...E` path.
+fn legacy(out: &mut impl Write, name: &str) -> fmt::Result {
+    let mut rest = name
+        .strip_prefix("_ZN")
+        .or_else(|| name.strip_prefix("__ZN"))
+        .ok_or(fmt::Error)?;
+
+    let mut first = true;
+    while !rest.starts_with('E') {
+        let (len, tail) = parse_len(rest).ok_or(fmt::Error)?;
+        let ident = tail.get(..len).ok_or(fmt::Error)?;
+        rest = &tail[len..];
+        if rest.starts_with('E') && is_hash(ident) {
+            break;
+        }
+
+        if !first {
+            out.write_str("::")?;
+        }
+        first = false;
+        legacy_ident(out, ident)?;
+    }
+    if first {
+        return Err(fmt::Error);
+    }
+    Ok(())
+}
+
+/// Writes a legacy path component, decoding its `$..$` escapes.
+fn legacy_ident(out: &mut impl Write, ident: &str) -> fmt::Result {
+    // A leading `_` keeps an escape from starting the component
+    let mut rest = match ident.strip_prefix('_') {
+        Some(tail) if tail.starts_with('$') => tail,
+        _ => ident,
+    };
+
+    while !rest.is_empty() {
+        if let Some(tail) = rest.strip_prefix("..") {
+            out.write_str("::")?;
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix('$') {
+            let end = tail.find('$').ok_or(fmt::Error)?;
+            let escape = &tail[..end];
+            let c = match escape {
+                "SP" => '@',
+                "BP" => '*',
+                "RF" => '&',
+                "LT" => '<',
+                "GT" => '>',
+                "LP" => '(',
+                "RP" => ')',
+                "C" => ',',
+                _ => {
+                    let hex = escape.strip_prefix('u').ok_or(fmt::Error)?;
+                    let code = u32::from_str_radix(hex, 16).map_err(|_| fmt::Error)?;
+                    char::from_u32(code).ok_or(fmt::Error)?
+                }
+            };
+            out.write_char(c)?;
+            rest = &tail[end + 1..];
+        } else {
+            let end = rest.find(['$', '.']).unwrap_or(rest.len()).max(1);
+            out.write_str(&rest[..end])?;
+            rest = &rest[end..];
+        }
+    }
+    Ok(())
+}
+
+/// A parser for the supported subset of v0 mangling.
+struct V0<'a> {
+    rest: &'a str,
+}
+
+impl<'a> V0<'a> {
+    fn new(name: &'a str) -> Option<Self> {
+        let rest = name.strip_prefix("_R").or_else(|| name.strip_prefix("__R"))?;
+        // An optional encoding version, which is only ever absent so far
+        let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit());
+        Some(Self { rest })
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = *self.rest.as_bytes().first()?;
+        self.rest = &self.rest[1..];
+        Some(byte)
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.rest.as_bytes().first() == Some(&byte);
+        if found {
+            self.rest = &self.rest[1..];
+        }
+        found
+    }
+
+    /// Skips a base-62 number, which ends in `_`.
+    fn skip_base62(&mut self) -> Option<()> {
+        let end = self.rest.find('_')?;
+        if !self.rest[..end].bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return None;
+        }
+        self.rest = &self.rest[end + 1..];
+        Some(())
+    }
+
+    /// Skips an optional `s<base62>` disambiguator.
+    fn disambiguator(&mut self) -> Option<()> {
+        if self.eat(b's') {
+            self.skip_base62()?;
+        }
+        Some(())
+    }
+
+    /// Returns an identifier. Punycode ones aren't supported.
+    fn ident(&mut self) -> Option<&'a str> {
+        let (len, rest) = parse_len(self.rest)?;
+        let rest = rest.strip_prefix('_').unwrap_or(rest);
+        let ident = rest.get(..len)?;
+        self.rest = &rest[len..];
+        Some(ident)
+    }
+
+    /// Writes the path of the symbol; what follows it is ignored.
+    fn symbol(&mut self, out: &mut impl Write) -> fmt::Result {
+        self.path(out).ok_or(fmt::Error)?
+    }
+
+    /// Writes a path, returning `None` if it can't be parsed.
+    fn path(&mut self, out: &mut impl Write) -> Option<fmt::Result> {
+        match self.next()? {
+            b'C' => {
+                self.disambiguator()?;
+                let ident = self.ident()?;
+                Some(out.write_str(ident))
+            }
+            b'N' => {
+                let namespace = self.next()?;
+                if let Err(e) = self.path(out)? {
+                    return Some(Err(e));
+                }
+                self.disambiguator()?;
+                let ident = self.ident()?;
+                Some(match namespace {
+                    b'C' => out.write_str("::{closure}"),
+                    b'S' => out.write_str("::{shim}"),
+                    b'a'..=b'z' => write!(out, "::{}", ident),
+                    _ if ident.is_empty() => Ok(()),
+                    _ => write!(out, "::{{{}}}", ident),
+                })
+            }
+            _ => None,
+        }
+    }
+}
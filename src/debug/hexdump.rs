@@ -0,0 +1,175 @@
+//! Memory dumps for debugging.
+//!
+//! Each line shows 16 bytes with their offset from the start of the dump,
+//! the bytes in hex, and the printable ASCII characters:
+//!
+//! ```text
+//! 0000: 48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|
+//! ```
+//!
+//! Runs of identical lines are collapsed into a single `*` like `hexdump`
+//! does. All reads go through [`crate::memory::probe`], so dumping a bogus
+//! pointer prints `<unmapped>` for the affected lines instead of faulting.
+//!
+//! [`write_hexdump`] writes the same thing to any [`fmt::Write`].
+
+use core::fmt::{self, Write};
+
+use crate::memory::probe;
+
+/// Number of bytes per line.
+const BYTES_PER_LINE: usize = 16;
+
+/// Longest possible line: a 64-bit offset, the hex bytes, and the ASCII.
+const MAX_LINE_LEN: usize = 16 + 2 + BYTES_PER_LINE * 3 + 1 + 2 + BYTES_PER_LINE + 1;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Dumps a byte slice, or `len` bytes starting at a pointer.
+#[macro_export]
+macro_rules! hexdump {
+    ($slice:expr) => {{
+        let slice: &[u8] = $slice.as_ref();
+        $crate::debug::hexdump(slice.as_ptr(), slice.len())
+    }};
+    ($ptr:expr, $len:expr) => {
+        $crate::debug::hexdump($ptr as *const u8, $len)
+    };
+}
+
+/// Dumps `len` bytes starting at `addr` to the console.
+pub fn hexdump(addr: *const u8, len: usize) {
+    // Printing can't fail
+    let _ = write_hexdump(&mut Console, addr, len);
+}
+
+/// Dumps `len` bytes starting at `addr` to `out`, a line at a time.
+pub fn write_hexdump(out: &mut impl Write, addr: *const u8, len: usize) -> fmt::Result {
+    let addr = addr as usize;
+    let mut offset = 0;
+    let mut previous: Option<[u8; BYTES_PER_LINE]> = None;
+    let mut squeezing = false;
+
+    while offset < len {
+        let line_offset = offset;
+        let count = (len - offset).min(BYTES_PER_LINE);
+        offset += count;
+
+        let mut bytes = [0u8; BYTES_PER_LINE];
+        let mut mapped = true;
+        for (i, byte) in bytes[..count].iter_mut().enumerate() {
+            match probe::read_u8(addr.wrapping_add(line_offset + i)) {
+                Some(value) => *byte = value,
+                None => {
+                    mapped = false;
+                    break;
+                }
+            }
+        }
+
+        if !mapped {
+            writeln!(out, "{:04x}: <unmapped>", line_offset)?;
+            previous = None;
+            squeezing = false;
+            continue;
+        }
+
+        if count == BYTES_PER_LINE && previous == Some(bytes) {
+            if !squeezing {
+                out.write_str("*\n")?;
+                squeezing = true;
+            }
+            continue;
+        }
+
+        squeezing = false;
+        previous = if count == BYTES_PER_LINE {
+            Some(bytes)
+        } else {
+            None
+        };
+        Line::new(line_offset, &bytes[..count]).render(out)?;
+        out.write_char('\n')?;
+    }
+
+    // Show where a collapsed run ends
+    if squeezing {
+        writeln!(out, "{:04x}", len)?;
+    }
+    Ok(())
+}
+
+/// Writes to the console.
+struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::print!("{}", s);
+        Ok(())
+    }
+}
+
+/// A single line of output.
+struct Line<'a> {
+    offset: usize,
+    bytes: &'a [u8],
+}
+
+impl<'a> Line<'a> {
+    fn new(offset: usize, bytes: &'a [u8]) -> Self {
+        Self { offset, bytes }
+    }
+
+    /// Writes the line to `out`, without a newline.
+    ///
+    /// Dumps can be large, so this fills in the bytes of the line directly
+    /// and writes them all at once, instead of going through `core::fmt`
+    /// for every byte.
+    fn render(&self, out: &mut impl Write) -> fmt::Result {
+        let mut buf = [0u8; MAX_LINE_LEN];
+        let mut len = 0;
+        let mut push = |byte: u8| {
+            buf[len] = byte;
+            len += 1;
+        };
+
+        let digits = (usize::BITS - self.offset.leading_zeros()).div_ceil(4).max(4);
+        for shift in (0..digits).rev() {
+            push(HEX_DIGITS[(self.offset >> (shift * 4)) & 0xf]);
+        }
+        push(b':');
+        push(b' ');
+
+        for i in 0..BYTES_PER_LINE {
+            match self.bytes.get(i) {
+                Some(&byte) => {
+                    push(HEX_DIGITS[(byte >> 4) as usize]);
+                    push(HEX_DIGITS[(byte & 0xf) as usize]);
+                    push(b' ');
+                }
+                None => {
+                    push(b' ');
+                    push(b' ');
+                    push(b' ');
+                }
+            }
+            if i == BYTES_PER_LINE / 2 - 1 {
+                push(b' ');
+            }
+        }
+
+        push(b' ');
+        push(b'|');
+        for &byte in self.bytes {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                push(byte);
+            } else {
+                push(b'.');
+            }
+        }
+        push(b'|');
+
+        // Only ASCII was written
+        out.write_str(core::str::from_utf8(&buf[..len]).unwrap())
+    }
+}
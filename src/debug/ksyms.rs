@@ -0,0 +1,183 @@
+//! Kernel symbol table.
+//!
+//! The kernel reserves [`KSYMS_SIZE`] bytes in the `.ksyms` section, and
+//! `ksyms.sh` writes the function symbols of the linked ELF into it. The
+//! table is patched in place, so no address moves and it always matches
+//! the code it describes. A kernel that skipped that step (say, a plain
+//! `cargo build`) still boots, it just can't name addresses.
+//!
+//! The format is described in `ksyms.sh`. Entries are sorted by address.
+
+use core::fmt;
+
+use super::demangle::demangle;
+
+/// Room reserved for the table.
+pub const KSYMS_SIZE: usize = 1024 * 1024;
+
+const MAGIC: &[u8; 4] = b"KSYM";
+const HEADER_LEN: usize = 16;
+const ENTRY_LEN: usize = 24;
+
+#[repr(C, align(4096))]
+struct Table([u8; KSYMS_SIZE]);
+
+/// Only the magic is set here; the rest is written after linking.
+#[used]
+#[unsafe(link_section = ".ksyms")]
+static KSYMS: Table = Table({
+    let mut bytes = [0; KSYMS_SIZE];
+    bytes[0] = MAGIC[0];
+    bytes[1] = MAGIC[1];
+    bytes[2] = MAGIC[2];
+    bytes[3] = MAGIC[3];
+    bytes
+});
+
+/// A function symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub addr: u64,
+    /// Zero if unknown, as for symbols defined in assembly
+    pub size: u64,
+    /// The mangled name
+    pub name: &'static str,
+}
+
+impl Symbol {
+    /// Returns whether `addr` is inside the function.
+    ///
+    /// Without a size, anything up to the next symbol counts.
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.addr && (self.size == 0 || addr - self.addr < self.size)
+    }
+}
+
+/// Returns the table bytes.
+fn table() -> &'static [u8] {
+    // The compiler would otherwise fold reads to the zeros it put there
+    let table = core::hint::black_box(&KSYMS as *const Table);
+    unsafe { &(*table).0 }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// A validated view of the table.
+struct Symbols {
+    entries: &'static [u8],
+    strings: &'static [u8],
+    len: usize,
+}
+
+impl Symbols {
+    fn get() -> Option<Self> {
+        let table = table();
+        if &table[..4] != MAGIC {
+            return None;
+        }
+        let len = read_u32(table, 4)? as usize;
+        let strings_offset = read_u32(table, 8)? as usize;
+        let strings_len = read_u32(table, 12)? as usize;
+
+        let entries = table.get(HEADER_LEN..HEADER_LEN.checked_add(len.checked_mul(ENTRY_LEN)?)?)?;
+        let strings = table.get(strings_offset..strings_offset.checked_add(strings_len)?)?;
+        (len > 0).then_some(Self { entries, strings, len })
+    }
+
+    fn addr(&self, index: usize) -> u64 {
+        read_u64(self.entries, index * ENTRY_LEN).unwrap_or(0)
+    }
+
+    fn symbol(&self, index: usize) -> Option<Symbol> {
+        let entry = &self.entries[index * ENTRY_LEN..(index + 1) * ENTRY_LEN];
+        let name_offset = read_u32(entry, 12)? as usize;
+        let name_len = read_u32(entry, 16)? as usize;
+        let name = self.strings.get(name_offset..name_offset.checked_add(name_len)?)?;
+        Some(Symbol {
+            addr: read_u64(entry, 0)?,
+            size: read_u32(entry, 8)? as u64,
+            name: core::str::from_utf8(name).ok()?,
+        })
+    }
+}
+
+/// Returns whether the symbol table was filled in.
+pub fn is_loaded() -> bool {
+    Symbols::get().is_some()
+}
+
+/// Finds the function containing `addr`.
+pub fn lookup(addr: u64) -> Option<Symbol> {
+    let symbols = Symbols::get()?;
+
+    // Binary search for the last symbol at or below `addr`
+    let (mut low, mut high) = (0, symbols.len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if symbols.addr(mid) <= addr {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    let symbol = symbols.symbol(low.checked_sub(1)?)?;
+    symbol.contains(addr).then_some(symbol)
+}
+
+/// Finds a function by its demangled name, like `hello_os::rust_main`.
+#[cfg(test)]
+pub fn find(name: &str) -> Option<Symbol> {
+    let symbols = Symbols::get()?;
+    (0..symbols.len)
+        .filter_map(|index| symbols.symbol(index))
+        .find(|symbol| demangle(symbol.name) == name)
+}
+
+/// An address that prints as `function+0xoffset`.
+///
+/// Return addresses point after the call, which may already be past the
+/// end of a function that doesn't return, so [`Symbolized::return_address`]
+/// looks up the byte before.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbolized {
+    addr: u64,
+    symbol: Option<Symbol>,
+}
+
+impl Symbolized {
+    pub fn new(addr: u64) -> Self {
+        Self {
+            addr,
+            symbol: lookup(addr),
+        }
+    }
+
+    pub fn return_address(addr: u64) -> Self {
+        Self {
+            addr,
+            symbol: lookup(addr.wrapping_sub(1)),
+        }
+    }
+
+    pub fn symbol(&self) -> Option<Symbol> {
+        self.symbol
+    }
+}
+
+impl fmt::Display for Symbolized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.symbol {
+            Some(symbol) => write!(f, "{}+{:#x}", demangle(symbol.name), self.addr - symbol.addr),
+            None => write!(f, "{:#x}", self.addr),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Debugging helpers.
+
+pub mod assert;
+pub mod backtrace;
+pub mod demangle;
+pub mod hexdump;
+pub mod ksyms;
+pub mod oops;
+pub mod panic_guard;
+pub mod snapshot;
+#[cfg(test)]
+mod test;
+pub mod timed_log;
+
+pub use backtrace::Backtrace;
+pub use hexdump::hexdump;
+pub use panic_guard::PanicPath;
+pub use snapshot::{capture_registers, MachineState};
+pub use timed_log::TimedLog;
@@ -0,0 +1,331 @@
+//! Panic reports that survive a warm reboot.
+//!
+//! A panic with `panic=reboot` on real hardware loses its report unless
+//! someone was watching the serial port. So the panic handler also writes
+//! a summary to [`OOPS_SIZE`] bytes of RAM at the fixed physical address
+//! [`OOPS_ADDR`], which a reset doesn't clear. The next boot prints it and
+//! clears it:
+//!
+//! ```text
+//! previous kernel oops found
+//! cpu 0, 5123 ms after boot
+//! panicked at src/shell.rs:290:5:
+//! ...
+//! ```
+//!
+//! After a cold boot the page holds whatever the RAM came up with, so a
+//! record only counts if its magic, version, lengths and checksum all
+//! match. The page is reserved before the page allocator starts, and the
+//! panic handler only writes it if the memory map says it's RAM and
+//! early boot memory stayed below it.
+
+use core::fmt;
+use core::mem::{offset_of, size_of};
+#[cfg(not(test))]
+use core::panic::PanicInfo;
+use core::ptr::{addr_of_mut, write_volatile};
+use core::sync::atomic::{compiler_fence, AtomicU8, Ordering};
+
+use crate::boot::stage::BootArgs;
+use crate::error::Result;
+use crate::interrupt::InterruptStackFrame;
+use crate::memory::multiboot2::{BootInfo, MEMORY_AREA_TYPE_AVAILABLE};
+use crate::memory::PhysAddr;
+
+/// Where the record is kept.
+pub const OOPS_ADDR: usize = 0x0300_0000;
+
+/// Room reserved for the record.
+pub const OOPS_SIZE: usize = 2 * 4096;
+
+/// Longer panic messages are truncated.
+pub const MESSAGE_LEN: usize = 512;
+
+/// How much of the end of the log is kept.
+pub const LOG_LEN: usize = 4096;
+
+const MAGIC: u64 = u64::from_le_bytes(*b"KOOPSREC");
+
+/// Bumped whenever [`OopsRecord`] changes.
+const VERSION: u32 = 1;
+
+/// The record isn't reserved, so the page may be anything.
+const STATE_OFF: u8 = 0;
+/// The record is reserved and can be read.
+const STATE_RESERVED: u8 = 1;
+/// The panic handler may write the record.
+const STATE_ARMED: u8 = 2;
+
+static STATE: AtomicU8 = AtomicU8::new(STATE_OFF);
+
+const _: () = assert!(size_of::<OopsRecord>() <= OOPS_SIZE);
+
+/// What a panic leaves for the next boot.
+#[repr(C)]
+pub struct OopsRecord {
+    magic: u64,
+    version: u32,
+    cpu: i32,
+    uptime_ms: u64,
+    message_len: u32,
+    log_len: u32,
+    registers: InterruptStackFrame,
+    message: [u8; MESSAGE_LEN],
+    log: [u8; LOG_LEN],
+    /// FNV-1a of everything before it
+    checksum: u64,
+}
+
+impl OopsRecord {
+    /// Fills in the record, leaving it valid.
+    ///
+    /// The magic is written last, so a panic while formatting the message
+    /// leaves an invalid record rather than a half-written one. Nothing
+    /// reads the record again in this boot, so the stores are volatile to
+    /// keep the compiler from dropping or reordering them.
+    pub fn fill(
+        &mut self,
+        message: fmt::Arguments,
+        registers: &InterruptStackFrame,
+        cpu: i32,
+        uptime_ms: u64,
+        for_each_log_line: impl FnOnce(&mut dyn FnMut(&str)),
+    ) {
+        unsafe {
+            write_volatile(addr_of_mut!(self.magic), 0);
+            write_volatile(addr_of_mut!(self.version), VERSION);
+            write_volatile(addr_of_mut!(self.cpu), cpu);
+            write_volatile(addr_of_mut!(self.uptime_ms), uptime_ms);
+            write_volatile(addr_of_mut!(self.registers), *registers);
+        }
+
+        let mut text = Text {
+            bytes: &mut self.message,
+            len: 0,
+        };
+        let _ = fmt::write(&mut text, message);
+        let message_len = text.len as u32;
+        unsafe { write_volatile(addr_of_mut!(self.message_len), message_len) };
+
+        let mut log = Text {
+            bytes: &mut self.log,
+            len: 0,
+        };
+        for_each_log_line(&mut |line| {
+            log.push_tail(line);
+            log.push_tail("\n");
+        });
+        let log_len = log.len as u32;
+        unsafe { write_volatile(addr_of_mut!(self.log_len), log_len) };
+
+        let checksum = self.compute_checksum();
+        unsafe { write_volatile(addr_of_mut!(self.checksum), checksum) };
+        compiler_fence(Ordering::Release);
+        unsafe { write_volatile(addr_of_mut!(self.magic), MAGIC) };
+    }
+
+    /// Returns whether the record was written by [`fill`](Self::fill) and
+    /// hasn't changed since.
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC
+            && self.version == VERSION
+            && self.message_len as usize <= MESSAGE_LEN
+            && self.log_len as usize <= LOG_LEN
+            && self.checksum == self.compute_checksum()
+    }
+
+    /// Invalidates the record.
+    pub fn clear(&mut self) {
+        unsafe {
+            write_volatile(addr_of_mut!(self.magic), 0);
+            write_volatile(addr_of_mut!(self.checksum), 0);
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        text_str(&self.message[..self.message_len as usize])
+    }
+
+    pub fn log(&self) -> &str {
+        text_str(&self.log[..self.log_len as usize])
+    }
+
+    fn compute_checksum(&self) -> u64 {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, offset_of!(Self, checksum))
+        };
+        fnv1a(bytes)
+    }
+}
+
+impl fmt::Display for OopsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "cpu {}, {} ms after boot", self.cpu, self.uptime_ms)?;
+        writeln!(f, "{}", self.message())?;
+        writeln!(f, "Registers:\n{}", self.registers)?;
+        write!(f, "Recent log messages:\n{}", self.log())
+    }
+}
+
+/// Returns the part of `bytes` that is valid UTF-8.
+///
+/// Only whole characters are written, so this only trims if the record
+/// was damaged in a way the checksum didn't catch.
+fn text_str(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Text in a fixed buffer.
+struct Text<'a> {
+    bytes: &'a mut [u8],
+    len: usize,
+}
+
+impl Text<'_> {
+    /// Appends `s`, dropping the oldest text to make room.
+    fn push_tail(&mut self, s: &str) {
+        let capacity = self.bytes.len();
+        let mut s = s;
+        if s.len() > capacity {
+            let mut start = s.len() - capacity;
+            while !s.is_char_boundary(start) {
+                start += 1;
+            }
+            s = &s[start..];
+        }
+
+        let overflow = (self.len + s.len()).saturating_sub(capacity);
+        if overflow > 0 {
+            // Keep whole characters
+            let mut drop = overflow;
+            while drop < self.len && (self.bytes[drop] & 0xc0) == 0x80 {
+                drop += 1;
+            }
+            self.bytes.copy_within(drop..self.len, 0);
+            self.len -= drop;
+        }
+
+        self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+    }
+}
+
+/// Appends to the text, dropping whatever doesn't fit.
+impl fmt::Write for Text<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(self.bytes.len() - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.bytes[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// Returns the record in its page.
+///
+/// # Safety
+/// The record must be reserved, and nothing else may be using it.
+unsafe fn record() -> &'static mut OopsRecord {
+    unsafe { &mut *(OOPS_ADDR as *mut OopsRecord) }
+}
+
+/// Reserves the record's page if the memory map says it's RAM.
+///
+/// This must be called before `memory::init`.
+pub fn init(boot_info: Option<&BootInfo>) {
+    let start = OOPS_ADDR as u64;
+    let end = start + OOPS_SIZE as u64;
+    let is_ram = boot_info
+        .and_then(|boot_info| boot_info.memory_map_tag().ok())
+        .is_some_and(|mmap| {
+            mmap.memory_areas().any(|area| {
+                area.typ == MEMORY_AREA_TYPE_AVAILABLE
+                    && area.base_addr <= start
+                    && end <= area.base_addr + area.length
+            })
+        });
+    if !is_ram {
+        return;
+    }
+
+    if crate::memory::get_allocator()
+        .reserve_range(PhysAddr(OOPS_ADDR), OOPS_SIZE)
+        .is_ok()
+    {
+        STATE.store(STATE_RESERVED, Ordering::Release);
+    }
+}
+
+/// Lets the panic handler write the record.
+///
+/// This must be called after `memory::init`, which may have put early boot
+/// memory over the record on a machine with a lot of RAM.
+pub fn arm() {
+    if STATE.load(Ordering::Acquire) != STATE_RESERVED {
+        log::warn!("No RAM at {:#x}, panics won't be kept across reboots", OOPS_ADDR);
+        return;
+    }
+    if crate::memory::early_memory_end().0 > OOPS_ADDR {
+        log::warn!("Early boot memory covers {:#x}, panics won't be kept across reboots", OOPS_ADDR);
+        STATE.store(STATE_OFF, Ordering::Release);
+        return;
+    }
+    STATE.store(STATE_ARMED, Ordering::Release);
+}
+
+/// Returns the record from before the last reboot, if there is one.
+pub fn previous() -> Option<&'static OopsRecord> {
+    if STATE.load(Ordering::Acquire) == STATE_OFF {
+        return None;
+    }
+    let record = unsafe { record() };
+    record.is_valid().then_some(record)
+}
+
+/// Forgets the record from before the last reboot.
+pub fn clear() {
+    if STATE.load(Ordering::Acquire) != STATE_OFF {
+        unsafe { record() }.clear();
+    }
+}
+
+/// The `oops record` boot stage: prints the panic of the previous boot,
+/// if there was one.
+pub fn init_stage(_args: &BootArgs) -> Result<()> {
+    init(crate::boot::stage::boot_info());
+    if let Some(oops) = previous() {
+        crate::println!("previous kernel oops found");
+        crate::println!("{}", oops);
+        clear();
+    }
+    Ok(())
+}
+
+/// Writes the record for a panic.
+///
+/// Called once by the panic handler, after the report is printed.
+#[cfg(not(test))]
+pub fn save(info: &PanicInfo, registers: &InterruptStackFrame) {
+    if STATE.load(Ordering::Acquire) != STATE_ARMED {
+        return;
+    }
+    let record = unsafe { record() };
+    record.fill(
+        format_args!("{}", info),
+        registers,
+        crate::cpu::get_cpu_id(),
+        crate::time::uptime_ms(),
+        |push| crate::logger::for_each_recent(push),
+    );
+}
@@ -0,0 +1,84 @@
+//! Panics in the panic handler.
+//!
+//! The panic handler formats arbitrary `Display` impls, takes console
+//! locks and reads allocator state, any of which can panic again. Without
+//! a guard that recursed until the stack ran out and the machine triple
+//! faulted, losing the original report. Each CPU counts the panics it is
+//! in, so one CPU's panic doesn't cut short another's report:
+//!
+//! - The first panic gets the full report.
+//! - A panic inside it writes [`DOUBLE_PANIC_MESSAGE`] byte by byte to
+//!   COM1 and the 0xE9 debug console, without locks or formatting.
+//! - Anything deeper halts right away.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::console::debugcon::{DebugCon, DEBUGCON_PORT};
+
+/// Written when the panic handler panics.
+pub const DOUBLE_PANIC_MESSAGE: &[u8] = b"\r\n!!! DOUBLE PANIC, halting !!!\r\n";
+
+/// Depth for panics before the per-CPU data is set up.
+static EARLY_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// How to handle a panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPath {
+    /// Print the full report.
+    Full,
+    /// The report panicked, say so and halt.
+    Minimal,
+    /// Saying so panicked too, just halt.
+    Halt,
+}
+
+impl PanicPath {
+    pub(super) fn for_depth(depth: usize) -> Self {
+        match depth {
+            0 | 1 => Self::Full,
+            2 => Self::Minimal,
+            _ => Self::Halt,
+        }
+    }
+}
+
+/// Counts a panic on this CPU and returns how to handle it.
+///
+/// The count never goes down, since the panic handler doesn't return.
+pub fn enter() -> PanicPath {
+    // Until `set_up_per_cpu_ptr` the GS base is still zero
+    let depth = if unsafe { x86::msr::rdmsr(x86::msr::IA32_GS_BASE) } == 0 {
+        EARLY_DEPTH.fetch_add(1, Ordering::Relaxed) + 1
+    } else {
+        let depth = &mut crate::cpu::get_current().panic_depth;
+        *depth += 1;
+        *depth
+    };
+    PanicPath::for_depth(depth)
+}
+
+/// Writes [`DOUBLE_PANIC_MESSAGE`] without anything that could panic.
+pub fn report_double_panic() {
+    crate::serial::write_raw(DOUBLE_PANIC_MESSAGE);
+
+    let debugcon = DebugCon::new(DEBUGCON_PORT);
+    for &byte in DOUBLE_PANIC_MESSAGE {
+        debugcon.write_byte(byte);
+    }
+}
+
+/// Panics when printed, to panic again inside the panic handler.
+pub struct PanicsWhenPrinted;
+
+impl core::fmt::Display for PanicsWhenPrinted {
+    fn fmt(&self, _f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        panic!("Panic while printing a panic message");
+    }
+}
+
+/// Stops this CPU for good.
+pub fn halt() -> ! {
+    loop {
+        unsafe { core::arch::asm!("cli; hlt") };
+    }
+}
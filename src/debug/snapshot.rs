@@ -0,0 +1,116 @@
+//! The state of the machine, for the panic handler.
+//!
+//! A panic from Rust code doesn't come with registers like an exception
+//! does, so the panic handler takes them itself as the first thing it
+//! does. They're saved in an [`InterruptStackFrame`] so they're printed
+//! like the registers of an exception.
+
+use core::arch::naked_asm;
+use core::fmt;
+use core::mem::offset_of;
+
+use super::assert::Registers;
+use crate::interrupt::InterruptStackFrame;
+
+/// Saves the general-purpose registers, RFLAGS, CS and SS into `frame`.
+///
+/// RIP is the return address and RSP is the stack pointer of the caller,
+/// as it was before the call. Every register is stored before any is
+/// used as scratch, and RAX is put back before returning.
+///
+/// # Safety
+/// `frame` must be valid for writes.
+#[unsafe(naked)]
+pub unsafe extern "C" fn capture_registers(frame: *mut InterruptStackFrame) {
+    naked_asm!(
+        "mov [rdi + {r15}], r15",
+        "mov [rdi + {r14}], r14",
+        "mov [rdi + {r13}], r13",
+        "mov [rdi + {r12}], r12",
+        "mov [rdi + {rbp}], rbp",
+        "mov [rdi + {rbx}], rbx",
+        "mov [rdi + {r11}], r11",
+        "mov [rdi + {r10}], r10",
+        "mov [rdi + {r9}], r9",
+        "mov [rdi + {r8}], r8",
+        "mov [rdi + {rcx}], rcx",
+        "mov [rdi + {rdx}], rdx",
+        "mov [rdi + {rsi}], rsi",
+        "mov [rdi + {rdi}], rdi",
+        "mov [rdi + {rax}], rax",
+        "pushfq",
+        "pop qword ptr [rdi + {rflags}]",
+
+        // Only now can RAX be used
+        "mov qword ptr [rdi + {error_code}], 0",
+        "mov rax, [rsp]",
+        "mov [rdi + {rip}], rax",
+        "lea rax, [rsp + 8]",
+        "mov [rdi + {rsp}], rax",
+        "xor eax, eax",
+        "mov ax, cs",
+        "mov [rdi + {cs}], rax",
+        "mov ax, ss",
+        "mov [rdi + {ss}], rax",
+
+        "mov rax, [rdi + {rax}]",
+        "ret",
+
+        r15 = const offset_of!(InterruptStackFrame, r15),
+        r14 = const offset_of!(InterruptStackFrame, r14),
+        r13 = const offset_of!(InterruptStackFrame, r13),
+        r12 = const offset_of!(InterruptStackFrame, r12),
+        rbp = const offset_of!(InterruptStackFrame, rbp),
+        rbx = const offset_of!(InterruptStackFrame, rbx),
+        r11 = const offset_of!(InterruptStackFrame, r11),
+        r10 = const offset_of!(InterruptStackFrame, r10),
+        r9 = const offset_of!(InterruptStackFrame, r9),
+        r8 = const offset_of!(InterruptStackFrame, r8),
+        rcx = const offset_of!(InterruptStackFrame, rcx),
+        rdx = const offset_of!(InterruptStackFrame, rdx),
+        rsi = const offset_of!(InterruptStackFrame, rsi),
+        rdi = const offset_of!(InterruptStackFrame, rdi),
+        rax = const offset_of!(InterruptStackFrame, rax),
+        error_code = const offset_of!(InterruptStackFrame, error_code),
+        rip = const offset_of!(InterruptStackFrame, rip),
+        cs = const offset_of!(InterruptStackFrame, cs),
+        rflags = const offset_of!(InterruptStackFrame, rflags),
+        rsp = const offset_of!(InterruptStackFrame, rsp),
+        ss = const offset_of!(InterruptStackFrame, ss),
+    );
+}
+
+/// Everything but the general-purpose registers.
+pub struct MachineState {
+    pub cpu: i32,
+    pub depth: usize,
+    pub ticks: u64,
+    pub gs_base: u64,
+    pub registers: Registers,
+}
+
+impl MachineState {
+    /// Reads the state of the current CPU.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        Self {
+            cpu: crate::cpu::get_cpu_id(),
+            depth: crate::interrupt::depth(),
+            ticks: crate::time::ticks(),
+            gs_base: unsafe { x86::msr::rdmsr(x86::msr::IA32_GS_BASE) },
+            registers: Registers::capture(),
+        }
+    }
+}
+
+impl fmt::Display for MachineState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "cpu {}, interrupt depth {}, tick {}, GS base {:#x}",
+            self.cpu, self.depth, self.ticks, self.gs_base
+        )?;
+        let r = &self.registers;
+        write!(f, "CR0 {:#x} CR2 {:#x} CR3 {:#x} CR4 {:#x}", r.cr0, r.cr2, r.cr3, r.cr4)
+    }
+}
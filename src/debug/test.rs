@@ -0,0 +1,457 @@
+//! Debugging helper tests.
+
+use alloc::boxed::Box;
+use alloc::format;
+use core::alloc::Layout;
+use core::arch::asm;
+use core::fmt::Write;
+use core::panic::Location;
+
+use super::assert::{Registers, Report};
+use super::demangle::demangle;
+use super::hexdump::write_hexdump;
+use super::ksyms::{self, Symbolized};
+use super::oops::{OopsRecord, LOG_LEN, MESSAGE_LEN};
+use super::panic_guard::{self, PanicPath};
+use super::{capture_registers, Backtrace, MachineState, TimedLog};
+use crate::interrupt::InterruptStackFrame;
+use crate::testing::Buffer;
+
+#[test_case]
+fn kassert_passes() {
+    crate::kassert!(1 + 1 == 2);
+    crate::kassert!(true, "with a message {}", 42);
+    crate::kassert_eq!(2 + 2, 4);
+    crate::kassert_ne!(2 + 2, 5, "with a message");
+}
+
+#[test_case]
+fn kdebug_assert_is_compiled_out_in_release() {
+    let mut evaluated = false;
+    crate::kdebug_assert!({
+        evaluated = true;
+        true
+    });
+    assert_eq!(evaluated, cfg!(debug_assertions));
+}
+
+#[test_case]
+fn report_contents() {
+    let registers = Registers {
+        rsp: 0x1000,
+        rbp: 0x2000,
+        rflags: 0x202,
+        cr0: 0x80000011,
+        cr2: 0,
+        cr3: 0x3000,
+        cr4: 0x20,
+    };
+    let report = Report {
+        summary: format_args!("assertion failed: x < 4"),
+        message: Some(format_args!("x is {}", 7)),
+        location: Location::caller(),
+        cpu: 2,
+        depth: 1,
+        ticks: 99,
+        registers,
+    };
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", report).unwrap();
+    let mut lines = buffer.as_str().lines();
+
+    assert_eq!(lines.next(), Some("assertion failed: x < 4: x is 7"));
+    assert!(lines.next().unwrap().starts_with("  at src/debug/test.rs:"));
+    assert_eq!(lines.next(), Some("  cpu 2, interrupt depth 1, tick 99"));
+    assert_eq!(
+        lines.next(),
+        Some("  rsp=0x1000 rbp=0x2000 rflags=0x202 cr0=0x80000011 cr2=0x0 cr3=0x3000 cr4=0x20")
+    );
+    assert_eq!(lines.next(), None);
+}
+
+#[test_case]
+fn timed_log_counts_up() {
+    let timer = TimedLog::new();
+    let first = timer.elapsed();
+    core::hint::spin_loop();
+    assert!(timer.elapsed() > first);
+}
+
+#[test_case]
+fn capture_registers_sees_the_caller() {
+    let local = 0u64;
+    let mut frame = InterruptStackFrame::default();
+    unsafe { capture_registers(&mut frame) };
+
+    let here = &local as *const u64 as u64;
+    assert!(frame.rsp.abs_diff(here) < 4096, "rsp {:#x}, local at {:#x}", frame.rsp, here);
+    assert_eq!(frame.rsp % 8, 0);
+    assert_ne!(frame.rip, 0);
+    assert_eq!(frame.cs, x86::segmentation::cs().bits() as u64);
+    assert_eq!(frame.error_code, 0);
+}
+
+#[test_case]
+fn capture_registers_keeps_values() {
+    let mut frame = InterruptStackFrame::default();
+    let ptr = &mut frame as *mut InterruptStackFrame;
+    unsafe {
+        asm!(
+            "mov r12, {value}",
+            "mov rax, {value}",
+            "not rax",
+            "call {capture}",
+            capture = sym capture_registers,
+            value = const 0x1234_5678_9abc_def0u64,
+            in("rdi") ptr,
+            out("r12") _,
+            clobber_abi("C"),
+        );
+    }
+
+    assert_eq!(frame.r12, 0x1234_5678_9abc_def0);
+    assert_eq!(frame.rax, !0x1234_5678_9abc_def0);
+    assert_eq!(frame.rdi, ptr as u64);
+}
+
+#[test_case]
+fn machine_state_shows_the_current_cpu() {
+    let state = MachineState::capture();
+    assert_eq!(state.depth, 0);
+
+    let text = format!("{}", state);
+    let prefix = format!("cpu {}, interrupt depth 0, tick ", crate::cpu::get_cpu_id());
+    assert!(text.starts_with(&prefix), "{}", text);
+    assert!(text.contains(" CR3 "), "{}", text);
+}
+
+#[test_case]
+fn stack_frame_layout() {
+    let frame = InterruptStackFrame {
+        rip: 0xffff_8000_0010_2030,
+        rsp: 0x7fff_f000,
+        rflags: 0x202,
+        rax: 1,
+        r15: 15,
+        error_code: 0xe,
+        cs: 0x8,
+        ss: 0x10,
+        ..Default::default()
+    };
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", frame).unwrap();
+    let mut lines = buffer.as_str().lines();
+
+    assert_eq!(
+        lines.next(),
+        Some("RIP 0xffff800000102030 RSP 0x000000007ffff000 RFLAGS 0x0000000000000202")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("RAX 0x0000000000000001 RBX 0x0000000000000000 RCX    0x0000000000000000")
+    );
+    assert_eq!(
+        lines.nth(3),
+        Some("R13 0x0000000000000000 R14 0x0000000000000000 R15    0x000000000000000f")
+    );
+    assert_eq!(lines.next(), Some("CS 0x8 SS 0x10 error code 0xe"));
+    assert_eq!(lines.next(), None);
+}
+
+#[test_case]
+fn demangle_legacy() {
+    let cases = [
+        ("_ZN8hello_os9rust_main17h0123456789abcdefE", "hello_os::rust_main"),
+        (
+            "_ZN4core9panicking9panic_fmt17h9f2a5a7c3e1d0b4aE",
+            "core::panicking::panic_fmt",
+        ),
+        (
+            "_ZN72_$LT$hello_os..memory..Allocator$u20$as$u20$core..alloc..GlobalAlloc$GT$5alloc17h00000000000000ffE",
+            "<hello_os::memory::Allocator as core::alloc::GlobalAlloc>::alloc",
+        ),
+        ("_ZN8hello_os4user7syscall13syscall_entryE", "hello_os::user::syscall::syscall_entry"),
+        ("_ZN3foo28_$u7b$$u7b$closure$u7d$$u7d$17h0000000000000000E", "foo::{{closure}}"),
+    ];
+
+    for (mangled, name) in cases {
+        assert!(demangle(mangled) == name, "{} is not {}", demangle(mangled), name);
+    }
+}
+
+#[test_case]
+fn demangle_v0() {
+    let cases = [
+        ("_RNvCs1234_8hello_os9rust_main", "hello_os::rust_main"),
+        ("_RNvNtCs1234_8hello_os6memory5alloc", "hello_os::memory::alloc"),
+        ("_RNCNvCsabc_3foo3bar0B4_", "foo::bar::{closure}"),
+    ];
+
+    for (mangled, name) in cases {
+        assert!(demangle(mangled) == name, "{} is not {}", demangle(mangled), name);
+    }
+}
+
+#[test_case]
+fn demangle_passes_other_names_through() {
+    for name in ["rust_main", "long_mode_start", "_ZNE", "_ZN3foo", "_RINvC3foo3barpE"] {
+        assert!(demangle(name) == name, "{} changed to {}", name, demangle(name));
+    }
+}
+
+#[test_case]
+fn ksyms_resolves_functions() {
+    assert!(ksyms::is_loaded(), "the test runner didn't fill in the symbol table");
+
+    let addr = crate::rust_main as *const () as u64;
+    let symbol = ksyms::lookup(addr).unwrap();
+    assert_eq!(symbol.addr, addr);
+    assert!(demangle(symbol.name) == "rust_main");
+    assert_eq!(ksyms::lookup(addr + 1), Some(symbol));
+    assert_eq!(ksyms::find("rust_main"), Some(symbol));
+
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", Symbolized::new(addr + 0x10)).unwrap();
+    assert_eq!(buffer.as_str(), "rust_main+0x10");
+
+    // Below the kernel there's nothing
+    assert_eq!(ksyms::lookup(0x1000), None);
+}
+
+#[inline(never)]
+fn backtrace_inner() -> Backtrace {
+    let backtrace = Backtrace::capture();
+    // Keeps the calls from becoming tail calls, which leave no frame
+    core::hint::black_box(&backtrace);
+    backtrace
+}
+
+#[inline(never)]
+fn backtrace_middle() -> Backtrace {
+    let backtrace = backtrace_inner();
+    core::hint::black_box(&backtrace);
+    backtrace
+}
+
+#[inline(never)]
+fn backtrace_outer() -> Backtrace {
+    let backtrace = backtrace_middle();
+    core::hint::black_box(&backtrace);
+    backtrace
+}
+
+/// A deliberate panic would end the test run, so this checks a backtrace
+/// taken the same way from a known chain of calls instead.
+#[test_case]
+fn backtrace_names_the_callers() {
+    let backtrace = backtrace_outer();
+
+    let mut expected = [
+        "::backtrace_inner",
+        "::backtrace_middle",
+        "::backtrace_outer",
+        "rust_main",
+    ]
+    .into_iter()
+    .peekable();
+    for &addr in backtrace.frames() {
+        let Some(symbol) = Symbolized::return_address(addr).symbol() else {
+            continue;
+        };
+        let mut name = Buffer::new();
+        write!(name, "{}", demangle(symbol.name)).unwrap();
+        if expected.peek().is_some_and(|suffix| name.as_str().ends_with(suffix)) {
+            expected.next();
+        }
+    }
+
+    assert_eq!(expected.next(), None, "missing from the backtrace:\n{}", backtrace);
+}
+
+#[test_case]
+fn backtrace_stops_at_bad_frames() {
+    assert!(Backtrace::from_rbp(0).frames().is_empty());
+    assert!(Backtrace::from_rbp(0x1001).frames().is_empty());
+    assert!(Backtrace::from_rbp(0xdead_0000_0000).frames().is_empty());
+}
+
+#[test_case]
+fn panic_paths_by_depth() {
+    assert_eq!(PanicPath::for_depth(1), PanicPath::Full);
+    assert_eq!(PanicPath::for_depth(2), PanicPath::Minimal);
+    assert_eq!(PanicPath::for_depth(3), PanicPath::Halt);
+    assert_eq!(PanicPath::for_depth(10), PanicPath::Halt);
+}
+
+/// A real double panic ends the test run, so this only checks the
+/// counting. `panic double` in the shell shows the message.
+#[test_case]
+fn panic_depth_counts_on_this_cpu() {
+    let saved = crate::cpu::get_current().panic_depth;
+    crate::cpu::get_current().panic_depth = 0;
+
+    assert_eq!(panic_guard::enter(), PanicPath::Full);
+    assert_eq!(panic_guard::enter(), PanicPath::Minimal);
+    assert_eq!(panic_guard::enter(), PanicPath::Halt);
+    assert_eq!(crate::cpu::get_current().panic_depth, 3);
+
+    crate::cpu::get_current().panic_depth = saved;
+}
+
+/// Returns a record on the heap, since it's too big for the stack.
+fn oops_record(fill_byte: u8) -> Box<OopsRecord> {
+    let layout = Layout::new::<OopsRecord>();
+    unsafe {
+        let ptr = alloc::alloc::alloc(layout);
+        assert!(!ptr.is_null());
+        ptr.write_bytes(fill_byte, layout.size());
+        Box::from_raw(ptr as *mut OopsRecord)
+    }
+}
+
+fn fill_oops(record: &mut OopsRecord, lines: &[&str]) {
+    let registers = InterruptStackFrame {
+        rip: 0xffff_8000_0010_1234,
+        ..Default::default()
+    };
+    record.fill(format_args!("panicked at {}", "src/shell.rs"), &registers, 1, 5123, |push| {
+        for line in lines {
+            push(line);
+        }
+    });
+}
+
+#[test_case]
+fn oops_record_round_trip() {
+    let mut record = oops_record(0);
+    assert!(!record.is_valid());
+
+    fill_oops(&mut record, &["first line", "second line"]);
+    assert!(record.is_valid());
+    assert_eq!(record.message(), "panicked at src/shell.rs");
+    assert_eq!(record.log(), "first line\nsecond line\n");
+
+    let text = format!("{}", record);
+    let mut lines = text.lines();
+    assert_eq!(lines.next(), Some("cpu 1, 5123 ms after boot"));
+    assert_eq!(lines.next(), Some("panicked at src/shell.rs"));
+    assert_eq!(lines.next(), Some("Registers:"));
+    assert!(lines.next().unwrap().starts_with("RIP 0xffff800000101234"));
+    assert!(text.ends_with("Recent log messages:\nfirst line\nsecond line\n"));
+
+    record.clear();
+    assert!(!record.is_valid());
+}
+
+#[test_case]
+fn oops_record_rejects_garbage() {
+    // What RAM might hold after a cold boot
+    for fill_byte in [0x00, 0xff, 0x5a] {
+        assert!(!oops_record(fill_byte).is_valid());
+    }
+
+    // A single flipped byte
+    let mut record = oops_record(0);
+    fill_oops(&mut record, &["a line"]);
+    let bytes = &mut *record as *mut OopsRecord as *mut u8;
+    unsafe {
+        let byte = bytes.add(100);
+        byte.write(!byte.read());
+        assert!(!record.is_valid());
+        byte.write(!byte.read());
+    }
+    assert!(record.is_valid());
+}
+
+#[test_case]
+fn oops_record_truncates() {
+    let mut record = oops_record(0xff);
+    let long_line = "x".repeat(1000);
+    let registers = InterruptStackFrame::default();
+    record.fill(format_args!("{}", long_line), &registers, 0, 0, |push| {
+        for i in 0..100 {
+            push(&format!("line {:03} {}", i, "y".repeat(60)));
+        }
+    });
+
+    assert!(record.is_valid());
+    assert_eq!(record.message().len(), MESSAGE_LEN);
+    // The end of the log is kept
+    assert_eq!(record.log().len(), LOG_LEN);
+    assert!(record.log().ends_with(&format!("line 099 {}\n", "y".repeat(60))));
+    assert!(!record.log().contains("line 000"));
+}
+
+/// Dumps `bytes` into a buffer.
+fn dump(bytes: &[u8]) -> Buffer {
+    let mut out = Buffer::new();
+    write_hexdump(&mut out, bytes.as_ptr(), bytes.len()).unwrap();
+    out
+}
+
+#[test_case]
+fn hexdump_line() {
+    assert_eq!(
+        dump(b"Hello, world!\n\0\0").as_str(),
+        "0000: 48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 00  |Hello, world!...|\n"
+    );
+}
+
+#[test_case]
+fn hexdump_partial_last_line() {
+    assert_eq!(
+        dump(b"0123456789abcdefXYZ\x7f").as_str(),
+        concat!(
+            "0000: 30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66  |0123456789abcdef|\n",
+            "0010: 58 59 5a 7f                                       |XYZ.|\n",
+        )
+    );
+}
+
+#[test_case]
+fn hexdump_ascii_column() {
+    assert_eq!(
+        dump(&[0x1f, b' ', b'A', b'~', 0x7f, 0x80, 0xff]).as_str(),
+        "0000: 1f 20 41 7e 7f 80 ff                              |. A~...|\n"
+    );
+}
+
+#[test_case]
+fn hexdump_squeezes_repeated_lines() {
+    let zeros = "0000: 00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n";
+    assert_eq!(dump(&[0; 64]).as_str(), format!("{}*\n0040\n", zeros));
+
+    // A different line ends the run, and a short last line is never
+    // squeezed
+    let mut bytes = [0; 52];
+    bytes[32] = 1;
+    assert_eq!(
+        dump(&bytes).as_str(),
+        format!(
+            "{}*\n{}{}",
+            zeros,
+            "0020: 01 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n",
+            "0030: 00 00 00 00                                       |....|\n",
+        )
+    );
+}
+
+#[test_case]
+fn hexdump_widens_long_offsets() {
+    let mut bytes = alloc::vec![0; 0x10020];
+    bytes[0x1001f] = 0xff;
+
+    let out = dump(&bytes);
+    assert!(out.as_str().ends_with("*\n10010: 00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 ff  |................|\n"));
+}
+
+#[test_case]
+fn hexdump_unmapped() {
+    // Not canonical, so never mapped
+    let addr = 0x8000_0000_0000 as *const u8;
+    let mut out = Buffer::new();
+    write_hexdump(&mut out, addr, 20).unwrap();
+    assert_eq!(out.as_str(), "0000: <unmapped>\n0010: <unmapped>\n");
+}
@@ -0,0 +1,28 @@
+//! Timestamped progress messages.
+//!
+//! The TSC isn't calibrated, so the cycle counts only mean something
+//! relative to each other. That's enough to spot a slow init phase.
+
+use crate::cpu::tsc::rdtsc;
+
+/// Prints messages with the cycles elapsed since it was created.
+pub struct TimedLog {
+    tsc_start: u64,
+}
+
+impl TimedLog {
+    /// Starts counting from now.
+    pub fn new() -> Self {
+        Self { tsc_start: rdtsc() }
+    }
+
+    /// Returns the cycles elapsed since `new`.
+    pub fn elapsed(&self) -> u64 {
+        rdtsc().wrapping_sub(self.tsc_start)
+    }
+
+    /// Prints `msg` with the elapsed cycles.
+    pub fn checkpoint(&self, msg: &str) {
+        crate::println!("[+{} cycles] {}", self.elapsed(), msg);
+    }
+}
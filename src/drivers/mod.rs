@@ -0,0 +1,4 @@
+//! Device drivers.
+
+pub mod ps2_keyboard;
+pub mod ps2_mouse;
@@ -0,0 +1,327 @@
+//! The i8042 PS/2 controller.
+//!
+//! Every wait for the controller or a device gives up after a timeout, so
+//! a machine without a controller, or without a keyboard or mouse on it,
+//! boots anyway. A missing controller reads as all ones.
+//!
+//! The keyboard and the mouse share the output buffer, and the status
+//! says which of them each byte is from. Bytes that arrive while we wait
+//! for a device to answer aren't dropped: the mouse's go to the mouse
+//! driver, and the keyboard's are kept for [`try_read`].
+
+use core::time::Duration;
+
+use x86::io::{inb, outb};
+
+use crate::collections::ring_buffer::RingBuffer;
+use crate::cpu::tsc;
+use crate::error::{Ps2Error, Result};
+use crate::sync::Mutex;
+
+const DATA_PORT: u16 = 0x60;
+/// Status when read, commands when written.
+const COMMAND_PORT: u16 = 0x64;
+
+/// The output buffer has a byte for us.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// The controller hasn't taken the last byte we wrote yet.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The byte in the output buffer is from the mouse.
+const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xa7;
+const CMD_ENABLE_AUX: u8 = 0xa8;
+const CMD_TEST_AUX_PORT: u8 = 0xa9;
+const CMD_SELF_TEST: u8 = 0xaa;
+const CMD_TEST_KEYBOARD_PORT: u8 = 0xab;
+const CMD_DISABLE_KEYBOARD: u8 = 0xad;
+const CMD_ENABLE_KEYBOARD: u8 = 0xae;
+/// The next byte written goes to the mouse.
+const CMD_WRITE_AUX: u8 = 0xd4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+/// Configuration byte: interrupt on keyboard data.
+const CONFIG_KEYBOARD_IRQ: u8 = 1 << 0;
+/// Configuration byte: interrupt on mouse data.
+const CONFIG_AUX_IRQ: u8 = 1 << 1;
+/// Configuration byte: the mouse clock is off.
+const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+/// Configuration byte: translate scancodes to set 1.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const KBD_SET_LEDS: u8 = 0xed;
+const KBD_SCANCODE_SET: u8 = 0xf0;
+const KBD_ENABLE_SCANNING: u8 = 0xf4;
+const KBD_RESET: u8 = 0xff;
+
+const ACK: u8 = 0xfa;
+const RESEND: u8 = 0xfe;
+const KBD_SELF_TEST_PASSED: u8 = 0xaa;
+
+/// Times a command is sent when the device asks for it again.
+const TRIES: usize = 3;
+
+/// How long the controller or a device gets to answer.
+pub const TIMEOUT: Duration = Duration::from_millis(20);
+/// How long the keyboard gets to reset.
+const RESET_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A device port of the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    Keyboard,
+    /// The mouse
+    Aux,
+}
+
+impl Port {
+    fn of(status: u8) -> Self {
+        match status & STATUS_AUX_DATA {
+            0 => Self::Keyboard,
+            _ => Self::Aux,
+        }
+    }
+}
+
+/// The output buffer holds more than this only if something's broken.
+const MAX_FLUSH: usize = 16;
+
+/// Keyboard bytes kept while waiting for a response. A keyboard sends
+/// a few at most in the time it has to answer.
+const BACKLOG_CAPACITY: usize = 16;
+
+/// Keyboard bytes that arrived while we waited for a response, oldest
+/// first.
+static KEYBOARD_BACKLOG: Mutex<RingBuffer<u8, BACKLOG_CAPACITY>> = Mutex::new(RingBuffer::new());
+
+fn status() -> u8 {
+    unsafe { inb(COMMAND_PORT) }
+}
+
+/// Waits until `pred` holds for the status, returning the status.
+fn wait_status(pred: impl Fn(u8) -> bool, deadline: u64) -> Result<u8> {
+    loop {
+        let status = status();
+        if status == 0xff {
+            return Err(Ps2Error::NotPresent.into());
+        }
+        if pred(status) {
+            return Ok(status);
+        }
+        if tsc::rdtsc() >= deadline {
+            return Err(Ps2Error::Timeout.into());
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Reads a byte, waiting up to `timeout` for one.
+fn read(timeout: Duration) -> Result<u8> {
+    wait_status(|status| status & STATUS_OUTPUT_FULL != 0, tsc::deadline(timeout))?;
+    Ok(unsafe { inb(DATA_PORT) })
+}
+
+/// Reads a byte from `port`, waiting up to `timeout` for one.
+///
+/// Bytes from the other port are kept.
+pub fn read_from(port: Port, timeout: Duration) -> Result<u8> {
+    read_from_until(port, tsc::deadline(timeout))
+}
+
+fn read_from_until(port: Port, deadline: u64) -> Result<u8> {
+    loop {
+        let status = wait_status(|status| status & STATUS_OUTPUT_FULL != 0, deadline)?;
+        let byte = unsafe { inb(DATA_PORT) };
+        if Port::of(status) == port {
+            return Ok(byte);
+        }
+        keep(Port::of(status), byte);
+    }
+}
+
+/// Reads the answer to a byte sent to `port`, keeping anything else the
+/// device sends in the meantime.
+fn read_response(port: Port) -> Result<u8> {
+    let deadline = tsc::deadline(TIMEOUT);
+    loop {
+        match read_from_until(port, deadline)? {
+            byte @ (ACK | RESEND) => return Ok(byte),
+            byte => keep(port, byte),
+        }
+    }
+}
+
+/// Passes on a byte that isn't the one we're waiting for.
+fn keep(port: Port, byte: u8) {
+    match port {
+        Port::Keyboard => {
+            if KEYBOARD_BACKLOG.lock().push(byte).is_some() {
+                crate::log_ratelimited!(log::Level::Warn, "ps2", "Keyboard backlog full, dropping the oldest byte");
+            }
+        }
+        Port::Aux => crate::drivers::ps2_mouse::receive(byte),
+    }
+}
+
+fn write(port: u16, byte: u8) -> Result<()> {
+    wait_status(|status| status & STATUS_INPUT_FULL == 0, tsc::deadline(TIMEOUT))?;
+    unsafe { outb(port, byte) };
+    Ok(())
+}
+
+fn command(cmd: u8) -> Result<()> {
+    write(COMMAND_PORT, cmd)
+}
+
+fn command_with_response(cmd: u8) -> Result<u8> {
+    command(cmd)?;
+    read(TIMEOUT)
+}
+
+/// Drops whatever is in the output buffer.
+fn flush() {
+    for _ in 0..MAX_FLUSH {
+        let status = status();
+        if status == 0xff || status & STATUS_OUTPUT_FULL == 0 {
+            return;
+        }
+        unsafe { inb(DATA_PORT) };
+    }
+}
+
+/// Replaces the configuration byte with `f` of it.
+fn update_config(f: impl FnOnce(u8) -> u8) -> Result<()> {
+    let config = command_with_response(CMD_READ_CONFIG)?;
+    command(CMD_WRITE_CONFIG)?;
+    write(DATA_PORT, f(config))
+}
+
+/// Sends a byte to a device and waits for it to be acknowledged.
+pub fn send(port: Port, byte: u8) -> Result<()> {
+    let mut response = RESEND;
+    for _ in 0..TRIES {
+        if port == Port::Aux {
+            command(CMD_WRITE_AUX)?;
+        }
+        write(DATA_PORT, byte)?;
+        response = read_response(port)?;
+        if response != RESEND {
+            break;
+        }
+    }
+    match response {
+        ACK => Ok(()),
+        response => Err(Ps2Error::NoAck(byte, response).into()),
+    }
+}
+
+/// Sets up the controller and the keyboard for scancode set 2 with
+/// keyboard interrupts.
+///
+/// The mouse port is left disabled.
+pub fn init() -> Result<()> {
+    if status() == 0xff {
+        return Err(Ps2Error::NotPresent.into());
+    }
+
+    command(CMD_DISABLE_KEYBOARD)?;
+    command(CMD_DISABLE_AUX)?;
+    flush();
+
+    // No interrupts while we're polling, and no translation to set 1
+    let config = command_with_response(CMD_READ_CONFIG)? & !(CONFIG_KEYBOARD_IRQ | CONFIG_AUX_IRQ | CONFIG_TRANSLATION);
+    command(CMD_WRITE_CONFIG)?;
+    write(DATA_PORT, config)?;
+
+    let result = command_with_response(CMD_SELF_TEST)?;
+    if result != SELF_TEST_PASSED {
+        return Err(Ps2Error::SelfTestFailed(result).into());
+    }
+    // The self-test may have reset the controller
+    command(CMD_WRITE_CONFIG)?;
+    write(DATA_PORT, config)?;
+
+    let result = command_with_response(CMD_TEST_KEYBOARD_PORT)?;
+    if result != PORT_TEST_PASSED {
+        return Err(Ps2Error::PortTestFailed(result).into());
+    }
+    command(CMD_ENABLE_KEYBOARD)?;
+
+    send(Port::Keyboard, KBD_RESET)?;
+    let result = read_from(Port::Keyboard, RESET_TIMEOUT)?;
+    if result != KBD_SELF_TEST_PASSED {
+        return Err(Ps2Error::NoAck(KBD_RESET, result).into());
+    }
+
+    // Set 2 is what keyboards start in, so carry on if it's refused
+    if let Err(e) = send(Port::Keyboard, KBD_SCANCODE_SET).and_then(|()| send(Port::Keyboard, 2)) {
+        log::warn!("ps2: Can't select scancode set 2, assuming it: {}", e);
+    }
+    send(Port::Keyboard, KBD_ENABLE_SCANNING)?;
+
+    command(CMD_WRITE_CONFIG)?;
+    write(DATA_PORT, config | CONFIG_KEYBOARD_IRQ)
+}
+
+/// Tests and enables the mouse port, without its interrupt.
+///
+/// [`init`] must have succeeded.
+pub fn init_aux() -> Result<()> {
+    let result = command_with_response(CMD_TEST_AUX_PORT)?;
+    if result != PORT_TEST_PASSED {
+        return Err(Ps2Error::AuxPortTestFailed(result).into());
+    }
+    command(CMD_ENABLE_AUX)?;
+    update_config(|config| config & !CONFIG_AUX_CLOCK_DISABLED)
+}
+
+/// Turns the mouse interrupt on or off.
+pub fn set_aux_irq(enabled: bool) -> Result<()> {
+    update_config(|config| match enabled {
+        true => config | CONFIG_AUX_IRQ,
+        false => config & !CONFIG_AUX_IRQ,
+    })
+}
+
+/// Disables the mouse port.
+pub fn disable_aux() -> Result<()> {
+    command(CMD_DISABLE_AUX)
+}
+
+/// Reads a byte if there is one, with the port it came from.
+///
+/// Keyboard bytes kept while waiting for a response come first.
+pub fn try_read() -> Option<(Port, u8)> {
+    if let Some(byte) = KEYBOARD_BACKLOG.lock().pop() {
+        return Some((Port::Keyboard, byte));
+    }
+    let status = status();
+    if status == 0xff || status & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    Some((Port::of(status), unsafe { inb(DATA_PORT) }))
+}
+
+/// Reads a byte from the mouse if the next byte is one.
+///
+/// A byte from the keyboard is left for the keyboard to read.
+pub fn try_read_aux() -> Option<u8> {
+    let status = status();
+    if status == 0xff || status & (STATUS_OUTPUT_FULL | STATUS_AUX_DATA) != STATUS_OUTPUT_FULL | STATUS_AUX_DATA {
+        return None;
+    }
+    Some(unsafe { inb(DATA_PORT) })
+}
+
+/// Turns the keyboard LEDs on or off.
+///
+/// `leds` has Scroll Lock in bit 0, Num Lock in bit 1 and Caps Lock in
+/// bit 2.
+pub fn set_leds(leds: u8) -> Result<()> {
+    send(Port::Keyboard, KBD_SET_LEDS)?;
+    send(Port::Keyboard, leds)
+}
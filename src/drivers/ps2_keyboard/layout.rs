@@ -0,0 +1,141 @@
+//! Keyboard layouts.
+//!
+//! A [`Layout`] gives the character of each key without and with Shift.
+//! What the modifiers do with them is the same on every layout: Caps Lock
+//! swaps the two for letters, Ctrl turns letters into control characters,
+//! and the keypad digits only type with Num Lock on. Keys without a
+//! character, like the function keys, map to `None`.
+
+use super::scancode::{KeyCode, MAX_MAKE_CODE};
+use super::Modifiers;
+
+/// Number of keys a layout has characters for.
+const KEYS: usize = MAX_MAKE_CODE as usize + 1;
+
+/// The keypad keys that type digits or a decimal point with Num Lock on.
+const KEYPAD_NUMBERS: [u8; 11] = [0x70, 0x69, 0x72, 0x7a, 0x6b, 0x73, 0x74, 0x6c, 0x75, 0x7d, 0x71];
+
+/// The characters of a layout.
+pub struct Layout {
+    pub name: &'static str,
+    /// `[without Shift, with Shift]` of each key that isn't extended
+    keys: [[Option<char>; 2]; KEYS],
+}
+
+impl Layout {
+    /// Builds a layout from the make code and characters of each key
+    /// that types one.
+    pub const fn new(name: &'static str, keys: &[(u8, char, char)]) -> Self {
+        let mut table = [[None; 2]; KEYS];
+        let mut i = 0;
+        while i < keys.len() {
+            let (code, normal, shifted) = keys[i];
+            table[code as usize] = [Some(normal), Some(shifted)];
+            i += 1;
+        }
+        Self { name, keys: table }
+    }
+
+    /// Returns the character a key types with `modifiers`.
+    pub fn map(&self, keycode: KeyCode, modifiers: &Modifiers) -> Option<char> {
+        if keycode.is_extended() {
+            return match keycode {
+                KeyCode::KEYPAD_SLASH => Some('/'),
+                KeyCode::KEYPAD_ENTER => Some('\n'),
+                KeyCode::DELETE => Some('\x7f'),
+                _ => None,
+            };
+        }
+
+        let code = keycode.0 as u8;
+        if KEYPAD_NUMBERS.contains(&code) && !modifiers.num_lock {
+            return None;
+        }
+
+        let [normal, shifted] = *self.keys.get(code as usize)?;
+        let c = if modifiers.shift() { shifted? } else { normal? };
+        let c = match (modifiers.caps_lock && c.is_alphabetic(), modifiers.shift()) {
+            (true, true) => normal?,
+            (true, false) => shifted?,
+            (false, _) => c,
+        };
+
+        if modifiers.ctrl() && c.is_ascii_alphabetic() {
+            return Some((c.to_ascii_uppercase() as u8 - b'@') as char);
+        }
+        Some(c)
+    }
+}
+
+/// The US QWERTY layout.
+pub static US_QWERTY: Layout = Layout::new(
+    "us",
+    &[
+        (0x0e, '`', '~'),
+        (0x16, '1', '!'),
+        (0x1e, '2', '@'),
+        (0x26, '3', '#'),
+        (0x25, '4', '$'),
+        (0x2e, '5', '%'),
+        (0x36, '6', '^'),
+        (0x3d, '7', '&'),
+        (0x3e, '8', '*'),
+        (0x46, '9', '('),
+        (0x45, '0', ')'),
+        (0x4e, '-', '_'),
+        (0x55, '=', '+'),
+        (0x66, '\x08', '\x08'),
+        (0x0d, '\t', '\t'),
+        (0x15, 'q', 'Q'),
+        (0x1d, 'w', 'W'),
+        (0x24, 'e', 'E'),
+        (0x2d, 'r', 'R'),
+        (0x2c, 't', 'T'),
+        (0x35, 'y', 'Y'),
+        (0x3c, 'u', 'U'),
+        (0x43, 'i', 'I'),
+        (0x44, 'o', 'O'),
+        (0x4d, 'p', 'P'),
+        (0x54, '[', '{'),
+        (0x5b, ']', '}'),
+        (0x5d, '\\', '|'),
+        (0x1c, 'a', 'A'),
+        (0x1b, 's', 'S'),
+        (0x23, 'd', 'D'),
+        (0x2b, 'f', 'F'),
+        (0x34, 'g', 'G'),
+        (0x33, 'h', 'H'),
+        (0x3b, 'j', 'J'),
+        (0x42, 'k', 'K'),
+        (0x4b, 'l', 'L'),
+        (0x4c, ';', ':'),
+        (0x52, '\'', '"'),
+        (0x5a, '\n', '\n'),
+        (0x1a, 'z', 'Z'),
+        (0x22, 'x', 'X'),
+        (0x21, 'c', 'C'),
+        (0x2a, 'v', 'V'),
+        (0x32, 'b', 'B'),
+        (0x31, 'n', 'N'),
+        (0x3a, 'm', 'M'),
+        (0x41, ',', '<'),
+        (0x49, '.', '>'),
+        (0x4a, '/', '?'),
+        (0x29, ' ', ' '),
+        (0x76, '\x1b', '\x1b'),
+        (0x7c, '*', '*'),
+        (0x7b, '-', '-'),
+        (0x79, '+', '+'),
+        (0x70, '0', '0'),
+        (0x69, '1', '1'),
+        (0x72, '2', '2'),
+        (0x7a, '3', '3'),
+        (0x6b, '4', '4'),
+        (0x73, '5', '5'),
+        (0x74, '6', '6'),
+        (0x6c, '7', '7'),
+        (0x75, '8', '8'),
+        (0x7d, '9', '9'),
+        (0x71, '.', '.'),
+    ],
+);
@@ -0,0 +1,299 @@
+//! PS/2 keyboard.
+//!
+//! The keyboard speaks scancode set 2, untranslated, and interrupts on
+//! IRQ 1. If the IRQ can't be had, the controller is polled instead, like
+//! the serial port is. Either way, each key press or release becomes a
+//! [`KeyEvent`] in a queue, with the character it types on the current
+//! [`Layout`], and the lock keys' LEDs follow their state.
+//!
+//! Setting the LEDs means waiting for the keyboard to answer, which the
+//! IRQ handler shouldn't do. It leaves them for the next reader of
+//! events to send.
+//!
+//! A key held down sends its make code again every so often. Those come
+//! out as presses with `repeat` set.
+
+pub mod controller;
+pub mod layout;
+pub mod scancode;
+#[cfg(test)]
+mod test;
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::collections::ring_buffer::RingBuffer;
+use crate::error::Result;
+use crate::sync::{Mutex, WaitQueue};
+use controller::Port;
+use layout::Layout;
+use scancode::{Decoder, KeyCode, Scancode};
+
+/// The keyboard's IRQ.
+const KEYBOARD_IRQ: usize = 1;
+
+/// Events kept until someone reads them. Older ones are dropped.
+const EVENT_CAPACITY: usize = 64;
+
+/// [`init`] hasn't run or failed, so there's no keyboard to read.
+const MODE_NONE: u8 = 0;
+/// The controller is read whenever someone looks for an event.
+const MODE_POLLED: u8 = 1;
+/// The IRQ handler reads the controller.
+const MODE_IRQ: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(MODE_NONE);
+/// The LEDs to send, with [`LEDS_PENDING`], or 0 if they're up to date.
+static PENDING_LEDS: AtomicU8 = AtomicU8::new(0);
+/// Set in [`PENDING_LEDS`] above the LED bits.
+const LEDS_PENDING: u8 = 1 << 7;
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new(&layout::US_QWERTY));
+static EVENTS: Mutex<RingBuffer<KeyEvent, EVENT_CAPACITY>> = Mutex::new(RingBuffer::new());
+/// Woken when events are queued.
+static EVENT_QUEUE: WaitQueue = WaitQueue::new();
+
+/// The state of the modifier and lock keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub left_alt: bool,
+    pub right_alt: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    pub const fn new() -> Self {
+        Self {
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            left_alt: false,
+            right_alt: false,
+            caps_lock: false,
+            num_lock: false,
+            scroll_lock: false,
+        }
+    }
+
+    /// Either Shift key is down
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    /// Either Ctrl key is down
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    /// Returns the LEDs for the lock keys, in the order the keyboard
+    /// wants them.
+    pub fn leds(&self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+/// A key press or release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub keycode: KeyCode,
+    /// The character the key typed, for presses of keys that type one
+    pub unicode: Option<char>,
+    /// The modifiers after the key was handled
+    pub modifiers: Modifiers,
+    pub pressed: bool,
+    /// The key was already down, so this is the keyboard repeating it
+    pub repeat: bool,
+}
+
+/// Turns the bytes from the keyboard into key events.
+pub struct Keyboard {
+    decoder: Decoder,
+    layout: &'static Layout,
+    modifiers: Modifiers,
+    /// Bit `n` is set while the key with code `n` is down
+    down: [u64; KeyCode::COUNT.div_ceil(64)],
+}
+
+impl Keyboard {
+    pub const fn new(layout: &'static Layout) -> Self {
+        Self {
+            decoder: Decoder::new(),
+            layout,
+            modifiers: Modifiers::new(),
+            down: [0; KeyCode::COUNT.div_ceil(64)],
+        }
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn layout(&self) -> &'static Layout {
+        self.layout
+    }
+
+    /// Returns whether a key is down.
+    pub fn is_down(&self, keycode: KeyCode) -> bool {
+        let code = keycode.0 as usize;
+        self.down[code / 64] & (1 << (code % 64)) != 0
+    }
+
+    fn set_down(&mut self, keycode: KeyCode, down: bool) {
+        let code = keycode.0 as usize;
+        if down {
+            self.down[code / 64] |= 1 << (code % 64);
+        } else {
+            self.down[code / 64] &= !(1 << (code % 64));
+        }
+    }
+
+    /// Handles a byte from the keyboard, returning the event if it ends
+    /// a key.
+    pub fn handle(&mut self, byte: u8) -> Option<KeyEvent> {
+        let Scancode { keycode, pressed } = self.decoder.feed(byte)?;
+        let repeat = pressed && self.is_down(keycode);
+        // Pause has no release, so it's never down
+        if keycode != KeyCode::PAUSE {
+            self.set_down(keycode, pressed);
+        }
+
+        let modifiers = &mut self.modifiers;
+        match keycode {
+            KeyCode::LEFT_SHIFT => modifiers.left_shift = pressed,
+            KeyCode::RIGHT_SHIFT => modifiers.right_shift = pressed,
+            KeyCode::LEFT_CTRL => modifiers.left_ctrl = pressed,
+            KeyCode::RIGHT_CTRL => modifiers.right_ctrl = pressed,
+            KeyCode::LEFT_ALT => modifiers.left_alt = pressed,
+            KeyCode::RIGHT_ALT => modifiers.right_alt = pressed,
+            KeyCode::CAPS_LOCK if pressed && !repeat => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NUM_LOCK if pressed && !repeat => modifiers.num_lock = !modifiers.num_lock,
+            KeyCode::SCROLL_LOCK if pressed && !repeat => modifiers.scroll_lock = !modifiers.scroll_lock,
+            _ => {}
+        }
+
+        let unicode = if pressed { self.layout.map(keycode, &self.modifiers) } else { None };
+        Some(KeyEvent {
+            keycode,
+            unicode,
+            modifiers: self.modifiers,
+            pressed,
+            repeat,
+        })
+    }
+}
+
+/// Sets up the controller and the keyboard.
+///
+/// Fails if there's no controller or no keyboard, and then nothing reads
+/// the controller again.
+pub fn init() -> Result<()> {
+    controller::init()?;
+    match crate::interrupt::irq::register_irq(KEYBOARD_IRQ, handle_irq) {
+        Ok(()) => MODE.store(MODE_IRQ, Ordering::Release),
+        Err(e) => {
+            log::warn!("ps2: Can't register IRQ {}, polling instead: {}", KEYBOARD_IRQ, e);
+            MODE.store(MODE_POLLED, Ordering::Release);
+        }
+    }
+    log::info!("ps2: Keyboard ready, layout {}", KEYBOARD.lock().layout().name);
+    Ok(())
+}
+
+fn handle_irq(_irq: usize) {
+    receive();
+}
+
+/// Turns whatever the controller has into events.
+///
+/// Bytes from the mouse go to the mouse driver.
+fn receive() {
+    let mut keyboard = KEYBOARD.lock();
+    let leds = keyboard.modifiers().leds();
+    let mut received = false;
+    while let Some((port, byte)) = controller::try_read() {
+        if port == Port::Aux {
+            crate::drivers::ps2_mouse::receive(byte);
+            continue;
+        }
+        if let Some(event) = keyboard.handle(byte) {
+            EVENTS.lock().push(event);
+            received = true;
+        }
+    }
+
+    let new_leds = keyboard.modifiers().leds();
+    if new_leds != leds {
+        PENDING_LEDS.store(LEDS_PENDING | new_leds, Ordering::Release);
+    }
+    drop(keyboard);
+
+    if received {
+        EVENT_QUEUE.wake_all();
+    }
+}
+
+/// Sends the LEDs that [`receive`] left, then reads what the keyboard
+/// sent in the meantime.
+fn send_leds() {
+    if PENDING_LEDS.load(Ordering::Acquire) == 0 {
+        return;
+    }
+
+    // Holding the keyboard keeps the IRQ handler from taking the ACK
+    let keyboard = KEYBOARD.lock();
+    let leds = PENDING_LEDS.swap(0, Ordering::AcqRel);
+    if leds & LEDS_PENDING != 0 {
+        if let Err(e) = controller::set_leds(leds & !LEDS_PENDING) {
+            crate::log_ratelimited!(log::Level::Warn, "ps2", "Can't set the LEDs: {}", e);
+        }
+    }
+    drop(keyboard);
+
+    // Key presses that came before the ACK were kept by the controller
+    receive();
+}
+
+/// Takes the oldest event, if there is one.
+pub fn try_read_event() -> Option<KeyEvent> {
+    if MODE.load(Ordering::Acquire) == MODE_POLLED {
+        receive();
+    }
+    send_leds();
+    EVENTS.lock().pop()
+}
+
+/// Takes the oldest event, waiting for one if there isn't any.
+///
+/// Without a keyboard, this never returns. This must be called with
+/// interrupts enabled.
+#[allow(dead_code)] // Nothing reads the keyboard yet
+pub fn read_event() -> KeyEvent {
+    let mut event = None;
+    let pred = || {
+        event = try_read_event();
+        event.is_some()
+    };
+    if MODE.load(Ordering::Acquire) == MODE_IRQ {
+        EVENT_QUEUE.wait_until(pred);
+    } else {
+        crate::time::wait_until(pred);
+    }
+    event.unwrap()
+}
+
+/// Reads an ASCII character from the keyboard if a key has typed one.
+///
+/// Events before it that don't type one, like releases, are dropped.
+pub fn try_read_char() -> Option<char> {
+    loop {
+        let event = try_read_event()?;
+        if let Some(c) = event.unicode.filter(|c| event.pressed && c.is_ascii()) {
+            return Some(c);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Scancode set 2.
+//!
+//! A key press is the key's make code, one byte, or two for the keys
+//! added after the XT, which start with [`EXTENDED`]. A release is the
+//! make code with [`RELEASE`] before its last byte. Two keys don't follow
+//! the rules:
+//!
+//! - Print Screen is surrounded by a fake Left Shift press and release,
+//!   `E0 12 E0 7C` and `E0 F0 7C E0 F0 12`. Other extended keys send
+//!   fake Shifts too while Shift or Num Lock is on. They're dropped.
+//! - Pause is `E1 14 77 E1 F0 14 F0 77` when pressed, and nothing when
+//!   released.
+
+use core::fmt;
+
+/// Comes before the make code of an extended key.
+pub const EXTENDED: u8 = 0xe0;
+/// Comes before the last byte of a release.
+pub const RELEASE: u8 = 0xf0;
+/// Starts the Pause sequence.
+pub const PAUSE_PREFIX: u8 = 0xe1;
+/// Bytes of the Pause sequence after the first.
+const PAUSE_LEN: u8 = 7;
+
+/// Largest make code of a key that isn't extended.
+pub const MAX_MAKE_CODE: u8 = 0x83;
+
+/// A physical key, named by its set 2 make code.
+///
+/// The make codes of extended keys have [`KeyCode::EXTENDED_BIT`] set,
+/// and Pause, which has none, is [`KeyCode::PAUSE`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeyCode(pub u16);
+
+impl KeyCode {
+    pub const EXTENDED_BIT: u16 = 0x100;
+    /// One more than the largest key code.
+    pub const COUNT: usize = 0x201;
+
+    pub const LEFT_SHIFT: Self = Self(0x12);
+    pub const RIGHT_SHIFT: Self = Self(0x59);
+    pub const LEFT_CTRL: Self = Self(0x14);
+    pub const RIGHT_CTRL: Self = Self(0x114);
+    pub const LEFT_ALT: Self = Self(0x11);
+    /// AltGr on layouts that have it
+    pub const RIGHT_ALT: Self = Self(0x111);
+    pub const CAPS_LOCK: Self = Self(0x58);
+    pub const NUM_LOCK: Self = Self(0x77);
+    pub const SCROLL_LOCK: Self = Self(0x7e);
+    pub const DELETE: Self = Self(0x171);
+    pub const KEYPAD_SLASH: Self = Self(0x14a);
+    pub const KEYPAD_ENTER: Self = Self(0x15a);
+    pub const PRINT_SCREEN: Self = Self(0x17c);
+    pub const PAUSE: Self = Self(0x200);
+
+    /// The Shifts that some extended keys send around themselves.
+    const FAKE_SHIFTS: [Self; 2] = [Self(0x112), Self(0x159)];
+
+    /// Returns whether the key was added after the XT.
+    pub fn is_extended(&self) -> bool {
+        self.0 & Self::EXTENDED_BIT != 0
+    }
+}
+
+impl fmt::Display for KeyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::PAUSE => write!(f, "Pause"),
+            Self::PRINT_SCREEN => write!(f, "PrintScreen"),
+            code if code.is_extended() => write!(f, "E0 {:02X}", code.0 & 0xff),
+            code => write!(f, "{:02X}", code.0),
+        }
+    }
+}
+
+/// A key press or release, before the modifiers are applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scancode {
+    pub keycode: KeyCode,
+    pub pressed: bool,
+}
+
+/// Puts the bytes of set 2 sequences back together.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// The last byte was [`EXTENDED`]
+    extended: bool,
+    /// A [`RELEASE`] came since the last key
+    release: bool,
+    /// Bytes of the Pause sequence still to come
+    pause_left: u8,
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            release: false,
+            pause_left: 0,
+        }
+    }
+
+    /// Takes a byte from the keyboard, returning the key if it ends one.
+    ///
+    /// Bytes that aren't part of a key, like command acknowledgements,
+    /// are dropped along with any unfinished sequence.
+    pub fn feed(&mut self, byte: u8) -> Option<Scancode> {
+        if self.pause_left > 0 {
+            self.pause_left -= 1;
+            return (self.pause_left == 0).then_some(Scancode {
+                keycode: KeyCode::PAUSE,
+                pressed: true,
+            });
+        }
+
+        match byte {
+            EXTENDED => self.extended = true,
+            RELEASE => self.release = true,
+            PAUSE_PREFIX => self.pause_left = PAUSE_LEN,
+            0x01..=MAX_MAKE_CODE => {
+                let extended = if self.extended { KeyCode::EXTENDED_BIT } else { 0 };
+                let scancode = Scancode {
+                    keycode: KeyCode(byte as u16 | extended),
+                    pressed: !self.release,
+                };
+                *self = Self::new();
+                if KeyCode::FAKE_SHIFTS.contains(&scancode.keycode) {
+                    return None;
+                }
+                return Some(scancode);
+            }
+            _ => *self = Self::new(),
+        }
+        None
+    }
+}
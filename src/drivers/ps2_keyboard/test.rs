@@ -0,0 +1,203 @@
+//! PS/2 keyboard tests.
+
+use super::layout::US_QWERTY;
+use super::scancode::{Decoder, KeyCode, Scancode};
+use super::{KeyEvent, Keyboard, Modifiers};
+
+fn feed(decoder: &mut Decoder, bytes: &[u8]) -> Option<Scancode> {
+    let (last, rest) = bytes.split_last().unwrap();
+    for &byte in rest {
+        assert_eq!(decoder.feed(byte), None, "byte {:#x} of {:x?}", byte, bytes);
+    }
+    decoder.feed(*last)
+}
+
+fn key(keycode: u16, pressed: bool) -> Option<Scancode> {
+    Some(Scancode {
+        keycode: KeyCode(keycode),
+        pressed,
+    })
+}
+
+/// Feeds `bytes` to the keyboard, returning the last event.
+fn type_bytes(keyboard: &mut Keyboard, bytes: &[u8]) -> Option<KeyEvent> {
+    bytes.iter().fold(None, |_, &byte| keyboard.handle(byte))
+}
+
+fn typed(keyboard: &mut Keyboard, code: u8) -> Option<char> {
+    let c = keyboard.handle(code).unwrap().unicode;
+    keyboard.handle(0xf0);
+    keyboard.handle(code);
+    c
+}
+
+#[test_case]
+fn decode_press_and_release() {
+    let mut decoder = Decoder::new();
+    assert_eq!(feed(&mut decoder, &[0x1c]), key(0x1c, true));
+    assert_eq!(feed(&mut decoder, &[0xf0, 0x1c]), key(0x1c, false));
+}
+
+#[test_case]
+fn decode_extended() {
+    let mut decoder = Decoder::new();
+    // Up arrow
+    assert_eq!(feed(&mut decoder, &[0xe0, 0x75]), key(0x175, true));
+    assert_eq!(feed(&mut decoder, &[0xe0, 0xf0, 0x75]), key(0x175, false));
+    assert!(KeyCode(0x175).is_extended());
+    assert!(!KeyCode(0x75).is_extended());
+}
+
+#[test_case]
+fn decode_print_screen_drops_fake_shift() {
+    let mut decoder = Decoder::new();
+    assert_eq!(feed(&mut decoder, &[0xe0, 0x12, 0xe0, 0x7c]), key(KeyCode::PRINT_SCREEN.0, true));
+    assert_eq!(feed(&mut decoder, &[0xe0, 0xf0, 0x7c]), key(KeyCode::PRINT_SCREEN.0, false));
+    assert_eq!(feed(&mut decoder, &[0xe0, 0xf0, 0x12]), None);
+}
+
+#[test_case]
+fn decode_pause() {
+    let mut decoder = Decoder::new();
+    let pause = [0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77];
+    assert_eq!(feed(&mut decoder, &pause), key(KeyCode::PAUSE.0, true));
+    // The decoder is back to normal afterwards
+    assert_eq!(feed(&mut decoder, &[0x1c]), key(0x1c, true));
+}
+
+#[test_case]
+fn decode_drops_stray_bytes() {
+    let mut decoder = Decoder::new();
+    // An acknowledgement in the middle of a release
+    assert_eq!(feed(&mut decoder, &[0xf0, 0xfa]), None);
+    assert_eq!(feed(&mut decoder, &[0x1c]), key(0x1c, true));
+}
+
+#[test_case]
+fn keycode_display() {
+    use alloc::format;
+    assert_eq!(format!("{}", KeyCode(0x1c)), "1C");
+    assert_eq!(format!("{}", KeyCode(0x175)), "E0 75");
+    assert_eq!(format!("{}", KeyCode::PAUSE), "Pause");
+}
+
+#[test_case]
+fn layout_maps_keys() {
+    let none = Modifiers::new();
+    let shift = Modifiers {
+        left_shift: true,
+        ..Modifiers::new()
+    };
+    let cases = [
+        (0x16, none, Some('1')),
+        (0x16, shift, Some('!')),
+        (0x15, none, Some('q')),
+        (0x15, shift, Some('Q')),
+        (0x66, none, Some('\x08')),
+        (0x5a, shift, Some('\n')),
+        (0x5d, shift, Some('|')),
+        (0x29, none, Some(' ')),
+        (0x14, none, None),
+        (0x05, none, None),
+        (0x14a, none, Some('/')),
+        (0x171, none, Some('\x7f')),
+        (0x175, none, None),
+    ];
+
+    for (code, modifiers, c) in cases {
+        assert_eq!(US_QWERTY.map(KeyCode(code), &modifiers), c, "key {:#x}", code);
+    }
+}
+
+#[test_case]
+fn layout_applies_locks_and_ctrl() {
+    let caps = Modifiers {
+        caps_lock: true,
+        ..Modifiers::new()
+    };
+    let caps_shift = Modifiers {
+        right_shift: true,
+        ..caps
+    };
+    assert_eq!(US_QWERTY.map(KeyCode(0x1c), &caps), Some('A'));
+    assert_eq!(US_QWERTY.map(KeyCode(0x16), &caps), Some('1'));
+    assert_eq!(US_QWERTY.map(KeyCode(0x1c), &caps_shift), Some('a'));
+    assert_eq!(US_QWERTY.map(KeyCode(0x16), &caps_shift), Some('!'));
+
+    let ctrl = Modifiers {
+        left_ctrl: true,
+        ..Modifiers::new()
+    };
+    assert_eq!(US_QWERTY.map(KeyCode(0x21), &ctrl), Some('\x03'));
+
+    // Keypad 7 only types with Num Lock
+    let num = Modifiers {
+        num_lock: true,
+        ..Modifiers::new()
+    };
+    assert_eq!(US_QWERTY.map(KeyCode(0x6c), &Modifiers::new()), None);
+    assert_eq!(US_QWERTY.map(KeyCode(0x6c), &num), Some('7'));
+}
+
+#[test_case]
+fn modifiers_are_tracked() {
+    let mut keyboard = Keyboard::new(&US_QWERTY);
+    assert_eq!(typed(&mut keyboard, 0x1c), Some('a'));
+
+    keyboard.handle(0x59);
+    assert!(keyboard.modifiers().shift());
+    assert_eq!(typed(&mut keyboard, 0x1c), Some('A'));
+    type_bytes(&mut keyboard, &[0xf0, 0x59]);
+    assert!(!keyboard.modifiers().shift());
+
+    // Right Ctrl is extended
+    type_bytes(&mut keyboard, &[0xe0, 0x14]);
+    assert!(keyboard.modifiers().right_ctrl);
+    assert_eq!(typed(&mut keyboard, 0x21), Some('\x03'));
+    type_bytes(&mut keyboard, &[0xe0, 0xf0, 0x14]);
+    assert!(!keyboard.modifiers().ctrl());
+}
+
+#[test_case]
+fn locks_toggle_once_per_press() {
+    let mut keyboard = Keyboard::new(&US_QWERTY);
+    // Caps Lock held down, so the keyboard repeats it
+    let event = type_bytes(&mut keyboard, &[0x58, 0x58, 0x58]).unwrap();
+    assert!(event.repeat && event.modifiers.caps_lock);
+    type_bytes(&mut keyboard, &[0xf0, 0x58]);
+    assert!(keyboard.modifiers().caps_lock);
+    assert_eq!(typed(&mut keyboard, 0x1c), Some('A'));
+
+    type_bytes(&mut keyboard, &[0x77, 0xf0, 0x77]);
+    assert_eq!(keyboard.modifiers().leds(), 0b110);
+    type_bytes(&mut keyboard, &[0x58, 0xf0, 0x58, 0x7e, 0xf0, 0x7e]);
+    assert_eq!(keyboard.modifiers().leds(), 0b011);
+}
+
+#[test_case]
+fn repeats_are_flagged() {
+    let mut keyboard = Keyboard::new(&US_QWERTY);
+    let first = keyboard.handle(0x1c).unwrap();
+    assert!(first.pressed && !first.repeat);
+    assert!(keyboard.is_down(KeyCode(0x1c)));
+
+    let again = keyboard.handle(0x1c).unwrap();
+    assert!(again.pressed && again.repeat);
+    assert_eq!(again.unicode, Some('a'));
+
+    let release = type_bytes(&mut keyboard, &[0xf0, 0x1c]).unwrap();
+    assert!(!release.pressed && !release.repeat && release.unicode.is_none());
+    assert!(!keyboard.is_down(KeyCode(0x1c)));
+}
+
+#[test_case]
+fn pause_is_never_down() {
+    let mut keyboard = Keyboard::new(&US_QWERTY);
+    let pause = [0xe1, 0x14, 0x77, 0xe1, 0xf0, 0x14, 0xf0, 0x77];
+    for _ in 0..2 {
+        let event = type_bytes(&mut keyboard, &pause).unwrap();
+        assert!(event.pressed && !event.repeat);
+    }
+    assert!(!keyboard.is_down(KeyCode::PAUSE));
+    assert_eq!(keyboard.modifiers(), Modifiers::new());
+}
@@ -0,0 +1,209 @@
+//! PS/2 mouse.
+//!
+//! The mouse is on the controller's auxiliary port, set up after the
+//! keyboard, and interrupts on IRQ 12. Its packets become
+//! [`MouseEvent`]s in a queue that [`read_event`] takes them from. A
+//! mouse with a scroll wheel only reports it after the IntelliMouse
+//! knock, sample rates 200, 100 and 80 in a row, after which its ID
+//! changes from 0 to 3. Without a mouse, the commands time out and
+//! [`init`] fails, like the keyboard's.
+//!
+//! The `mouse` shell command shows where the mouse has moved to, for
+//! checking that it works.
+
+pub mod packet;
+#[cfg(test)]
+mod test;
+
+use core::fmt;
+use core::time::Duration;
+
+use crate::collections::ring_buffer::RingBuffer;
+use crate::cpu::tsc;
+use crate::drivers::ps2_keyboard::controller::{self, Port};
+use crate::error::Result;
+use crate::sync::{IrqGuard, Mutex};
+use crate::{print, println, shell, time};
+pub use packet::{Buttons, MouseEvent};
+use packet::PacketDecoder;
+
+/// The mouse's IRQ.
+const MOUSE_IRQ: usize = 12;
+
+/// Events kept until someone reads them. Older ones are dropped.
+const EVENT_CAPACITY: usize = 64;
+
+/// The bytes of a packet come together, so a gap this long between two
+/// of them means a byte was lost.
+const PACKET_GAP: Duration = Duration::from_millis(50);
+
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_ID: u8 = 0xf2;
+const ENABLE_REPORTING: u8 = 0xf4;
+const SET_DEFAULTS: u8 = 0xf6;
+
+/// The sample rates that turn on the scroll wheel.
+const WHEEL_KNOCK: [u8; 3] = [200, 100, 80];
+/// The ID of a mouse with its scroll wheel on.
+const ID_WHEEL: u8 = 3;
+/// Samples per second, the default.
+const SAMPLE_RATE: u8 = 100;
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+static EVENTS: Mutex<RingBuffer<MouseEvent, EVENT_CAPACITY>> = Mutex::new(RingBuffer::new());
+
+struct Mouse {
+    decoder: PacketDecoder,
+    /// A byte after this TSC value starts a new packet
+    stale_at: u64,
+}
+
+impl Mouse {
+    const fn new() -> Self {
+        Self {
+            decoder: PacketDecoder::new(false),
+            stale_at: 0,
+        }
+    }
+}
+
+/// Sets up the mouse.
+///
+/// Fails if there's no mouse port or no mouse, leaving the port disabled.
+/// The keyboard must have been set up.
+pub fn init() -> Result<()> {
+    // The keyboard's IRQ handler would take the answers
+    let irq = IrqGuard::new();
+    controller::init_aux()?;
+    let result = configure().and_then(|wheel| {
+        MOUSE.lock().decoder = PacketDecoder::new(wheel);
+        crate::interrupt::irq::register_irq(MOUSE_IRQ, handle_irq)?;
+        if let Err(e) = controller::set_aux_irq(true) {
+            let _ = crate::interrupt::irq::unregister_irq(MOUSE_IRQ);
+            return Err(e);
+        }
+        Ok(wheel)
+    });
+    let wheel = match result {
+        Ok(wheel) => wheel,
+        Err(e) => {
+            let _ = controller::disable_aux();
+            return Err(e.context("setting up the mouse"));
+        }
+    };
+    drop(irq);
+
+    if let Err(e) = shell::register("mouse", "Show the mouse position until a key is pressed", show_position) {
+        log::warn!("ps2: Can't add the mouse command: {}", e);
+    }
+    log::info!("ps2: Mouse ready, {}", if wheel { "with a scroll wheel" } else { "no scroll wheel" });
+    Ok(())
+}
+
+/// Sets up the mouse and starts reporting, returning whether the scroll
+/// wheel is on.
+fn configure() -> Result<bool> {
+    controller::send(Port::Aux, SET_DEFAULTS)?;
+
+    for rate in WHEEL_KNOCK {
+        set_sample_rate(rate)?;
+    }
+    controller::send(Port::Aux, GET_ID)?;
+    let wheel = controller::read_from(Port::Aux, controller::TIMEOUT)? == ID_WHEEL;
+    set_sample_rate(SAMPLE_RATE)?;
+
+    controller::send(Port::Aux, ENABLE_REPORTING)?;
+    Ok(wheel)
+}
+
+fn set_sample_rate(rate: u8) -> Result<()> {
+    controller::send(Port::Aux, SET_SAMPLE_RATE)?;
+    controller::send(Port::Aux, rate)
+}
+
+fn handle_irq(_irq: usize) {
+    while let Some(byte) = controller::try_read_aux() {
+        receive(byte);
+    }
+}
+
+/// Takes a byte from the mouse.
+///
+/// The keyboard driver hands over the mouse bytes it reads too.
+pub fn receive(byte: u8) {
+    let mut mouse = MOUSE.lock();
+    let now = tsc::rdtsc();
+    if now > mouse.stale_at {
+        mouse.decoder.reset();
+    }
+    mouse.stale_at = tsc::deadline(PACKET_GAP);
+
+    if let Some(event) = mouse.decoder.feed(byte) {
+        EVENTS.lock().push(event);
+    }
+}
+
+/// Takes the oldest event, if there is one.
+pub fn read_event() -> Option<MouseEvent> {
+    EVENTS.lock().pop()
+}
+
+/// Where the mouse has moved to, in mouse units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub x: i64,
+    /// Positive is down, like on the screen
+    pub y: i64,
+    pub wheel: i64,
+    pub buttons: Buttons,
+}
+
+impl Position {
+    pub fn update(&mut self, event: &MouseEvent) {
+        self.x += event.dx as i64;
+        self.y -= event.dy as i64;
+        self.wheel += event.wheel as i64;
+        self.buttons = event.buttons;
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let button = |down, name| if down { name } else { '-' };
+        write!(
+            f,
+            "x {:6} y {:6} wheel {:4} buttons {}{}{}",
+            self.x,
+            self.y,
+            self.wheel,
+            button(self.buttons.left, 'L'),
+            button(self.buttons.middle, 'M'),
+            button(self.buttons.right, 'R'),
+        )
+    }
+}
+
+/// Shows the position on one line, updated as the mouse moves, until a
+/// key is pressed.
+fn show_position(_args: &[&str]) -> Result<()> {
+    println!("Move the mouse, press a key to stop");
+    let mut position = Position::default();
+    loop {
+        print!("\r{}", position);
+
+        let mut key = false;
+        time::wait_until(|| {
+            key = crate::serial::try_read_byte().is_some()
+                || crate::drivers::ps2_keyboard::try_read_char().is_some()
+                || shell::take_break();
+            key || !EVENTS.lock().is_empty()
+        });
+        if key {
+            println!();
+            return Ok(());
+        }
+        while let Some(event) = read_event() {
+            position.update(&event);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Mouse movement packets.
+//!
+//! A mouse sends a packet of three bytes whenever it moves or a button
+//! changes:
+//!
+//! | Byte | Bits 7 to 0                                                      |
+//! |------|------------------------------------------------------------------|
+//! | 0    | Y overflow, X overflow, Y sign, X sign, 1, middle, right, left   |
+//! | 1    | X movement, low 8 bits                                           |
+//! | 2    | Y movement, low 8 bits                                           |
+//!
+//! The movement is 9-bit two's complement, with Y positive up. When it
+//! doesn't fit, the overflow bit is set and the movement is clamped.
+//! A mouse with its scroll wheel turned on adds a fourth byte, the wheel
+//! movement, which is negative up.
+//!
+//! Bit 3 of the first byte, always set, is all there is to find where a
+//! packet starts. A byte without it where a packet should start is
+//! dropped, so after a lost byte the decoder finds its way back within a
+//! few packets.
+
+/// Set in the first byte of every packet.
+pub const ALWAYS_ONE: u8 = 1 << 3;
+
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+/// The buttons that are down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+/// A movement packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    /// Positive is up
+    pub dy: i16,
+    /// Positive is down, towards the user
+    pub wheel: i8,
+    pub buttons: Buttons,
+}
+
+/// Puts packets back together from the bytes of the mouse.
+#[derive(Debug)]
+pub struct PacketDecoder {
+    bytes: [u8; 4],
+    /// Bytes of the current packet so far
+    len: usize,
+    /// Bytes in a packet, 4 with the wheel
+    size: usize,
+}
+
+impl PacketDecoder {
+    pub const fn new(wheel: bool) -> Self {
+        Self {
+            bytes: [0; 4],
+            len: 0,
+            size: if wheel { 4 } else { 3 },
+        }
+    }
+
+    /// Drops the packet so far.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Takes a byte from the mouse, returning the event if it ends a
+    /// packet.
+    pub fn feed(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.len == 0 && byte & ALWAYS_ONE == 0 {
+            return None;
+        }
+
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.size {
+            return None;
+        }
+        self.len = 0;
+
+        let [flags, x, y, wheel] = self.bytes;
+        Some(MouseEvent {
+            dx: movement(x, flags & X_SIGN != 0, flags & X_OVERFLOW != 0),
+            dy: movement(y, flags & Y_SIGN != 0, flags & Y_OVERFLOW != 0),
+            wheel: if self.size == 4 { wheel as i8 } else { 0 },
+            buttons: Buttons {
+                left: flags & LEFT_BUTTON != 0,
+                right: flags & RIGHT_BUTTON != 0,
+                middle: flags & MIDDLE_BUTTON != 0,
+            },
+        })
+    }
+}
+
+/// Returns the movement from its low 8 bits, sign and overflow bits.
+fn movement(low: u8, negative: bool, overflow: bool) -> i16 {
+    match (overflow, negative) {
+        (true, false) => 255,
+        (true, true) => -256,
+        (false, false) => low as i16,
+        (false, true) => low as i16 - 256,
+    }
+}
@@ -0,0 +1,90 @@
+//! PS/2 mouse tests, on packets recorded from QEMU and a real mouse.
+
+use super::packet::{Buttons, MouseEvent, PacketDecoder};
+use super::Position;
+
+/// Feeds `bytes` to the decoder, returning every event.
+fn decode(decoder: &mut PacketDecoder, bytes: &[u8]) -> alloc::vec::Vec<MouseEvent> {
+    bytes.iter().filter_map(|&byte| decoder.feed(byte)).collect()
+}
+
+fn event(dx: i16, dy: i16, wheel: i8, left: bool) -> MouseEvent {
+    MouseEvent {
+        dx,
+        dy,
+        wheel,
+        buttons: Buttons {
+            left,
+            ..Buttons::default()
+        },
+    }
+}
+
+#[test_case]
+fn decode_movement() {
+    let mut decoder = PacketDecoder::new(false);
+    // Right and up, then left and down
+    assert_eq!(decode(&mut decoder, &[0x08, 0x05, 0x02]), [event(5, 2, 0, false)]);
+    assert_eq!(decode(&mut decoder, &[0x38, 0xfd, 0xf0]), [event(-3, -16, 0, false)]);
+}
+
+#[test_case]
+fn decode_buttons() {
+    let mut decoder = PacketDecoder::new(false);
+    let events = decode(&mut decoder, &[0x09, 0x00, 0x00, 0x0e, 0x00, 0x00]);
+    assert_eq!(events[0], event(0, 0, 0, true));
+    assert_eq!(events[1].buttons, Buttons {
+        left: false,
+        right: true,
+        middle: true,
+    });
+}
+
+#[test_case]
+fn decode_overflow() {
+    let mut decoder = PacketDecoder::new(false);
+    // The low bits are meaningless once the movement overflows
+    assert_eq!(decode(&mut decoder, &[0x48, 0x12, 0x00]), [event(255, 0, 0, false)]);
+    assert_eq!(decode(&mut decoder, &[0x58, 0x12, 0x00]), [event(-256, 0, 0, false)]);
+    assert_eq!(decode(&mut decoder, &[0xa8, 0x00, 0x34]), [event(0, -256, 0, false)]);
+    assert_eq!(decode(&mut decoder, &[0x88, 0x00, 0x34]), [event(0, 255, 0, false)]);
+    // Largest movements that fit
+    assert_eq!(decode(&mut decoder, &[0x38, 0x00, 0xff]), [event(-256, -1, 0, false)]);
+}
+
+#[test_case]
+fn decode_wheel() {
+    let mut decoder = PacketDecoder::new(true);
+    assert_eq!(decode(&mut decoder, &[0x08, 0x00, 0x00, 0xff]), [event(0, 0, -1, false)]);
+    assert_eq!(decode(&mut decoder, &[0x09, 0x01, 0x00, 0x02]), [event(1, 0, 2, true)]);
+    // Three bytes aren't a packet
+    assert!(decode(&mut decoder, &[0x08, 0x00, 0x00]).is_empty());
+}
+
+#[test_case]
+fn decoder_resynchronizes() {
+    let mut decoder = PacketDecoder::new(false);
+    // The first byte of a packet was lost, so the rest is skipped until
+    // a byte that can start one
+    let events = decode(&mut decoder, &[0x05, 0x02, 0x08, 0x01, 0x01]);
+    assert_eq!(events, [event(1, 1, 0, false)]);
+
+    // Starting over after a gap drops the packet so far
+    decode(&mut decoder, &[0x08, 0x07]);
+    decoder.reset();
+    assert_eq!(decode(&mut decoder, &[0x09, 0x00, 0x00]), [event(0, 0, 0, true)]);
+}
+
+#[test_case]
+fn position_follows_events() {
+    use alloc::format;
+
+    let mut position = Position::default();
+    position.update(&event(10, 4, 0, true));
+    position.update(&event(-3, -1, 1, false));
+    assert_eq!((position.x, position.y, position.wheel), (7, -3, 1));
+    assert_eq!(
+        format!("{}", position),
+        "x      7 y     -3 wheel    1 buttons ---"
+    );
+}
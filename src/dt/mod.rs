@@ -0,0 +1,126 @@
+//! Flattened Device Tree parsing.
+//!
+//! Only x86-64 with multiboot2 is supported for now, so this is groundwork
+//! for booting on platforms that describe their hardware with a device tree
+//! instead. All values in the blob are big-endian.
+
+use core::mem::size_of;
+
+/// Magic number at the start of a device tree blob
+pub const FDT_MAGIC: u32 = 0xD00D_FEED;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+
+/// The start of the device tree header
+#[derive(Debug, Clone, Copy)]
+pub struct FdtHeader {
+    pub magic: u32,
+    pub totalsize: u32,
+    pub off_dt_struct: u32,
+    pub off_dt_strings: u32,
+}
+
+/// A device tree blob
+pub struct Fdt<'a> {
+    header: FdtHeader,
+    data: &'a [u8],
+}
+
+impl<'a> Fdt<'a> {
+    /// Parse the header of the blob at `addr`
+    ///
+    /// # Safety
+    /// `addr` must point to readable memory, at least as long as the
+    /// header says if the magic number matches.
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        let header = unsafe { core::slice::from_raw_parts(addr as *const u8, size_of::<[u32; 4]>()) };
+        let totalsize = read_be32(header, 4)? as usize;
+        Self::new(unsafe { core::slice::from_raw_parts(addr as *const u8, totalsize) })
+    }
+
+    /// Parse the header of a blob
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let header = FdtHeader {
+            magic: read_be32(data, 0)?,
+            totalsize: read_be32(data, 4)?,
+            off_dt_struct: read_be32(data, 8)?,
+            off_dt_strings: read_be32(data, 12)?,
+        };
+
+        if header.magic != FDT_MAGIC || header.totalsize as usize > data.len() {
+            return None;
+        }
+        Some(Self { header, data: &data[..header.totalsize as usize] })
+    }
+
+    pub fn header(&self) -> &FdtHeader {
+        &self.header
+    }
+
+    /// The root node
+    pub fn root(&self) -> Option<FdtNode<'_>> {
+        let offset = self.header.off_dt_struct as usize;
+        if read_be32(self.data, offset)? != FDT_BEGIN_NODE {
+            return None;
+        }
+        Some(FdtNode { fdt: self, offset })
+    }
+
+    /// Returns the NUL-terminated string at `offset` in the strings block
+    fn string(&self, offset: usize) -> Option<&'a str> {
+        let start = (self.header.off_dt_strings as usize).checked_add(offset)?;
+        let bytes = self.data.get(start..)?;
+        let len = bytes.iter().position(|&byte| byte == 0)?;
+        core::str::from_utf8(&bytes[..len]).ok()
+    }
+}
+
+/// A node in the structure block
+pub struct FdtNode<'a> {
+    fdt: &'a Fdt<'a>,
+    /// Offset of the node's `FDT_BEGIN_NODE` token
+    offset: usize,
+}
+
+impl<'a> FdtNode<'a> {
+    /// Find a property of this node, not of its children
+    pub fn find_property(&self, name: &str) -> Option<&'a [u8]> {
+        let data = self.fdt.data;
+
+        // Skip the token and the NUL-terminated, padded node name
+        let name_start = self.offset + 4;
+        let name_len = data.get(name_start..)?.iter().position(|&byte| byte == 0)?;
+        let mut offset = align4(name_start + name_len + 1);
+
+        loop {
+            match read_be32(data, offset)? {
+                FDT_PROP => {
+                    let len = read_be32(data, offset + 4)? as usize;
+                    let name_offset = read_be32(data, offset + 8)? as usize;
+                    let value_start = offset + 12;
+                    let value = data.get(value_start..value_start.checked_add(len)?)?;
+
+                    if self.fdt.string(name_offset)? == name {
+                        return Some(value);
+                    }
+                    offset = align4(value_start + len);
+                }
+                FDT_NOP => offset += 4,
+                // Properties come before child nodes, so a node token or
+                // anything else ends the search
+                _ => return None,
+            }
+        }
+    }
+}
+
+fn read_be32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn align4(offset: usize) -> usize {
+    offset.next_multiple_of(4)
+}
@@ -1,16 +1,815 @@
 //! Error handling.
+//!
+//! Every error has a stable numeric [`code`](Error::code), so it can be
+//! reported where there's no room for a message, like the QEMU exit
+//! status. Codes are never reused: new variants get new codes. Errors of
+//! a subsystem are grouped in their own enum, and their codes share a
+//! range:
+//!
+//! | Range           | Errors                 |
+//! |-----------------|------------------------|
+//! | `0x001..0x100`  | General, in [`Error`]  |
+//! | `0x100..0x200`  | [`MemError`]           |
+//! | `0x200..0x300`  | [`IntError`]           |
+//! | `0x300..0x400`  | [`AcpiError`]          |
+//! | `0x400..0x500`  | [`SerialError`]        |
+//! | `0x500..0x600`  | [`MultibootError`]     |
+//! | `0x600..0x700`  | [`ElfError`]           |
+//! | `0x700..0x800`  | [`Ps2Error`]           |
+//!
+//! An error can be wrapped with a message saying what was being done when
+//! it happened, with [`Error::context`] or [`ResultExt::context`]. Each
+//! layer on the way up adds its own, and they're printed outermost first:
+//! `initializing memory: parsing multiboot info: ...`. The wrapped error
+//! keeps its code, and is stored in the error itself, so adding context
+//! never allocates.
+//!
+//! Subsystem errors convert into [`Error`] with `From`, so `?` works across
+//! modules. [`bail!`](crate::bail) and [`ensure!`](crate::ensure) return
+//! early with an error.
+
+#[cfg(test)]
+mod test;
+
+use core::fmt;
+
+use crate::memory::fallible::AllocError;
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 /// An error.
 #[non_exhaustive]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Error {
-    /// No such script is defined.
-    NoSuchScript,
-
     /// Invalid descriptor type: {0}
     InvalidDescriptorType(u8),
 
+    /// TSS descriptor is not an available TSS: type {0:#06b}
+    UnexpectedTssType(u8),
+
+    /// Invalid number.
+    InvalidNumber,
+
+    /// Missing argument: {0}
+    MissingArgument(&'static str),
+
+    /// Too many {0}.
+    TooMany(&'static str),
+
+    /// Not attempted because {0} failed.
+    Skipped(&'static str),
+
+    /// Memory error.
+    Memory(MemError),
+
+    /// Interrupt error.
+    Interrupt(IntError),
+
+    /// ACPI error.
+    Acpi(AcpiError),
+
+    /// Serial port error.
+    Serial(SerialError),
+
+    /// Multiboot information error.
+    Multiboot(MultibootError),
+
+    /// ELF loading error.
+    Elf(ElfError),
+
+    /// PS/2 controller error.
+    Ps2(Ps2Error),
+
     /// Other error.
     Other(&'static str),
+
+    /// {chain}: {source}
+    WithContext {
+        chain: ContextChain,
+        source: Cause,
+    },
+}
+
+/// The error under a context chain: an [`Error`] other than
+/// `WithContext`, which can't hold itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cause {
+    InvalidDescriptorType(u8),
+    UnexpectedTssType(u8),
+    InvalidNumber,
+    MissingArgument(&'static str),
+    TooMany(&'static str),
+    Skipped(&'static str),
+    Memory(MemError),
+    Interrupt(IntError),
+    Acpi(AcpiError),
+    Serial(SerialError),
+    Multiboot(MultibootError),
+    Elf(ElfError),
+    Ps2(Ps2Error),
+    Other(&'static str),
+}
+
+/// Maximum number of messages in a `ContextChain`.
+const MAX_CONTEXT_DEPTH: usize = 4;
+
+/// Messages added to an error, outermost first.
+///
+/// Each layer adds a message here, up to [`MAX_CONTEXT_DEPTH`]. Messages
+/// past that are logged and dropped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContextChain {
+    msgs: [&'static str; MAX_CONTEXT_DEPTH],
+    len: usize,
+}
+
+impl ContextChain {
+    const fn new() -> Self {
+        Self {
+            msgs: [""; MAX_CONTEXT_DEPTH],
+            len: 0,
+        }
+    }
+
+    /// Adds a message outside the others.
+    fn push_outer(&mut self, msg: &'static str) {
+        if self.len == MAX_CONTEXT_DEPTH {
+            log::warn!("Dropped error context: {}", msg);
+            return;
+        }
+        self.msgs.copy_within(..self.len, 1);
+        self.msgs[0] = msg;
+        self.len += 1;
+    }
+
+    /// Returns the messages, outermost first.
+    pub fn iter(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.msgs[..self.len].iter().copied()
+    }
+}
+
+/// Adds context to the error of a `Result`.
+pub trait ResultExt<T> {
+    /// Wraps the error, if any, with a message.
+    fn context(self, msg: &'static str) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> ResultExt<T> for core::result::Result<T, E> {
+    fn context(self, msg: &'static str) -> Result<T> {
+        self.map_err(|e| e.into().context(msg))
+    }
+}
+
+/// An error in the memory subsystem.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MemError {
+    /// The multiboot information is invalid.
+    BadBootInfo,
+
+    /// The multiboot information has no memory map.
+    NoMemoryMap,
+
+    /// Memory map entries are too small: {0} bytes
+    MemoryMapEntryTooSmall(u32),
+
+    /// Memory map size doesn't match its entry size.
+    MemoryMapBadSize,
+
+    /// Memory map has no available memory.
+    NoAvailableMemory,
+
+    /// Memory map entry wraps around: base {0:#x}
+    MemoryMapOverflow(u64),
+
+    /// Not enough available memory: {0} bytes
+    InsufficientMemory(u64),
+
+    /// The end of the kernel is not in available memory.
+    KernelNotInAvailableMemory,
+
+    /// The page allocator is already initialized.
+    AlreadyInitialized,
+
+    /// Too many reserved ranges.
+    TooManyReservedRanges,
+
+    /// The page array is too large.
+    PageArrayTooLarge,
+
+    /// Not enough early memory for the page array.
+    NoEarlyMemory,
+
+    /// Out of memory.
+    OutOfMemory,
+
+    /// Failed to allocate {size} bytes aligned to {align}.
+    AllocFailed { size: usize, align: usize },
+
+    /// {0:#x} is already mapped by a large page.
+    LargePageInTheWay(u64),
+
+    /// No support for {0}.
+    Unsupported(&'static str),
+}
+
+/// An error in the interrupt subsystem.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IntError {
+    /// Not an exception vector: {0}
+    NotAnException(usize),
+
+    /// No free interrupt vectors.
+    NoFreeVectors,
+
+    /// The IDT is not initialized.
+    IdtNotInitialized,
+
+    /// No MP floating pointer was found.
+    MpsNotFound,
+
+    /// The MP floating pointer has no configuration table.
+    MpsNoConfigTable,
+
+    /// Invalid MP configuration table at {0:#x}
+    MpsBadTable(usize),
+
+    /// Invalid MP table entry at {0:#x}
+    MpsBadEntry(usize),
+
+    /// The MP tables list no usable IOAPIC.
+    MpsNoIoApic,
+
+    /// Vector {0:#x} is already in use.
+    VectorInUse(u8),
+
+    /// The IOAPIC has no GSI {0}.
+    GsiOutOfRange(u32),
+
+    /// No CPU has APIC ID {0}.
+    UnknownCpu(u32),
+
+    /// IRQ {0} already has a handler.
+    AlreadyRegistered(usize),
+
+    /// IRQ {0} has no handler.
+    NotRegistered(usize),
+
+    /// The IOAPIC is not initialized.
+    IoApicNotInitialized,
+}
+
+/// An error reading the ACPI tables.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AcpiError {
+    /// No RSDP was found.
+    NoRsdp,
+
+    /// A table has a bad checksum: {0}
+    BadChecksum([u8; 4]),
+
+    /// A table is missing: {0}
+    MissingTable([u8; 4]),
+
+    /// The FADT has no PM1a control block.
+    NoPm1aControl,
+
+    /// The FADT has no usable reset register.
+    NoResetRegister,
+
+    /// A table has a bad length: {0}, {1} bytes
+    BadLength([u8; 4], u32),
+
+    /// The DSDT has no `\_S5_` package to power off with.
+    NoS5,
+}
+
+/// An error setting up a serial port.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    /// No UART at port {0:#x}
+    NotPresent(u16),
+
+    /// The UART can't run at {0} baud.
+    UnsupportedBaud(u32),
+}
+
+/// An error in the multiboot information from the bootloader.
+///
+/// Offsets are from the start of the boot information, so they can be
+/// found in a hexdump of it.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MultibootError {
+    /// The bootloader passed no boot information.
+    NullPointer,
+
+    /// The total size is too small: {0} bytes
+    BadTotalSize(u32),
+
+    /// The tag at {offset:#x} is {size} bytes, which doesn't fit.
+    TagOverrun { offset: usize, size: u32 },
+
+    /// There is no tag of type {0}.
+    MissingTag(u32),
+
+    /// The boot information at {0:#x} is not 8-byte aligned.
+    BadAlignment(usize),
+
+    /// A string is not valid UTF-8.
+    StringNotUtf8,
+
+    /// The kernel was entered with magic {0:#x} instead of the multiboot2 one.
+    BadMagic(u32),
+}
+
+/// An error loading an ELF binary.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    /// Not an ELF file.
+    NotElf,
+
+    /// Not a 64-bit little-endian x86-64 executable.
+    Unsupported,
+
+    /// A header at {0:#x} is outside the file.
+    Truncated(usize),
+
+    /// A segment at {0:#x} is outside user memory.
+    BadAddress(u64),
+}
+
+/// An error talking to the PS/2 controller or a device on it.
+#[non_exhaustive]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Ps2Error {
+    /// No PS/2 controller.
+    NotPresent,
+
+    /// The controller or device didn't respond in time.
+    Timeout,
+
+    /// The controller self-test returned {0:#x}
+    SelfTestFailed(u8),
+
+    /// The keyboard port test returned {0:#x}
+    PortTestFailed(u8),
+
+    /// The device answered command {0:#x} with {1:#x}
+    NoAck(u8, u8),
+
+    /// The mouse port test returned {0:#x}
+    AuxPortTestFailed(u8),
+}
+
+impl Error {
+    /// Wraps the error with a message saying what was being done.
+    ///
+    /// A chain holds [`MAX_CONTEXT_DEPTH`] messages. Once it's full, the
+    /// message is only logged.
+    pub fn context(self, msg: &'static str) -> Error {
+        let (mut chain, source) = self.split();
+        chain.push_outer(msg);
+        Self::WithContext { chain, source }
+    }
+
+    /// Separates the context from the error under it.
+    fn split(self) -> (ContextChain, Cause) {
+        let cause = match self {
+            Self::WithContext { chain, source } => return (chain, source),
+            Self::InvalidDescriptorType(typ) => Cause::InvalidDescriptorType(typ),
+            Self::UnexpectedTssType(typ) => Cause::UnexpectedTssType(typ),
+            Self::InvalidNumber => Cause::InvalidNumber,
+            Self::MissingArgument(name) => Cause::MissingArgument(name),
+            Self::TooMany(what) => Cause::TooMany(what),
+            Self::Skipped(prerequisite) => Cause::Skipped(prerequisite),
+            Self::Memory(e) => Cause::Memory(e),
+            Self::Interrupt(e) => Cause::Interrupt(e),
+            Self::Acpi(e) => Cause::Acpi(e),
+            Self::Serial(e) => Cause::Serial(e),
+            Self::Multiboot(e) => Cause::Multiboot(e),
+            Self::Elf(e) => Cause::Elf(e),
+            Self::Ps2(e) => Cause::Ps2(e),
+            Self::Other(message) => Cause::Other(message),
+        };
+        (ContextChain::new(), cause)
+    }
+
+    /// Returns the innermost error, without any context.
+    pub fn root_cause(&self) -> Error {
+        self.clone().split().1.into()
+    }
+
+    /// Returns the stable code of the error.
+    ///
+    /// Context doesn't change what went wrong, so this is the code of the
+    /// root cause.
+    pub fn code(&self) -> u32 {
+        match self {
+            Self::InvalidDescriptorType(_) => 0x001,
+            Self::UnexpectedTssType(_) => 0x002,
+            Self::InvalidNumber => 0x003,
+            Self::MissingArgument(_) => 0x004,
+            Self::TooMany(_) => 0x005,
+            Self::Skipped(_) => 0x006,
+            Self::Other(_) => 0x0ff,
+            Self::Memory(e) => 0x100 + e.code(),
+            Self::Interrupt(e) => 0x200 + e.code(),
+            Self::Acpi(e) => 0x300 + e.code(),
+            Self::Serial(e) => 0x400 + e.code(),
+            Self::Multiboot(e) => 0x500 + e.code(),
+            Self::Elf(e) => 0x600 + e.code(),
+            Self::Ps2(e) => 0x700 + e.code(),
+            Self::WithContext { source, .. } => Error::from(source.clone()).code(),
+        }
+    }
+}
+
+impl MemError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::BadBootInfo => 0x01,
+            Self::NoMemoryMap => 0x02,
+            Self::MemoryMapEntryTooSmall(_) => 0x03,
+            Self::MemoryMapBadSize => 0x04,
+            Self::NoAvailableMemory => 0x05,
+            Self::MemoryMapOverflow(_) => 0x06,
+            Self::InsufficientMemory(_) => 0x07,
+            Self::KernelNotInAvailableMemory => 0x08,
+            Self::AlreadyInitialized => 0x09,
+            Self::TooManyReservedRanges => 0x0a,
+            Self::PageArrayTooLarge => 0x0b,
+            Self::NoEarlyMemory => 0x0c,
+            Self::OutOfMemory => 0x0d,
+            Self::AllocFailed { .. } => 0x0e,
+            Self::LargePageInTheWay(_) => 0x0f,
+            Self::Unsupported(_) => 0x10,
+        }
+    }
+}
+
+impl IntError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::NotAnException(_) => 0x01,
+            Self::NoFreeVectors => 0x02,
+            Self::IdtNotInitialized => 0x03,
+            Self::MpsNotFound => 0x04,
+            Self::MpsNoConfigTable => 0x05,
+            Self::MpsBadTable(_) => 0x06,
+            Self::MpsBadEntry(_) => 0x07,
+            Self::MpsNoIoApic => 0x08,
+            Self::VectorInUse(_) => 0x09,
+            Self::GsiOutOfRange(_) => 0x0a,
+            Self::UnknownCpu(_) => 0x0b,
+            Self::AlreadyRegistered(_) => 0x0c,
+            Self::NotRegistered(_) => 0x0d,
+            Self::IoApicNotInitialized => 0x0e,
+        }
+    }
+}
+
+impl AcpiError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::NoRsdp => 0x01,
+            Self::BadChecksum(_) => 0x02,
+            Self::MissingTable(_) => 0x03,
+            Self::NoPm1aControl => 0x04,
+            Self::NoResetRegister => 0x05,
+            Self::BadLength(..) => 0x06,
+            Self::NoS5 => 0x07,
+        }
+    }
+}
+
+impl SerialError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::NotPresent(_) => 0x01,
+            Self::UnsupportedBaud(_) => 0x02,
+        }
+    }
+}
+
+impl MultibootError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::NullPointer => 0x01,
+            Self::BadTotalSize(_) => 0x02,
+            Self::TagOverrun { .. } => 0x03,
+            Self::MissingTag(_) => 0x04,
+            Self::BadAlignment(_) => 0x05,
+            Self::StringNotUtf8 => 0x06,
+            Self::BadMagic(_) => 0x07,
+        }
+    }
+}
+
+impl ElfError {
+    fn code(&self) -> u32 {
+        match self {
+            Self::NotElf => 0x01,
+            Self::Unsupported => 0x02,
+            Self::Truncated(_) => 0x03,
+            Self::BadAddress(_) => 0x04,
+        }
+    }
+}
+
+impl Ps2Error {
+    fn code(&self) -> u32 {
+        match self {
+            Self::NotPresent => 0x01,
+            Self::Timeout => 0x02,
+            Self::SelfTestFailed(_) => 0x03,
+            Self::PortTestFailed(_) => 0x04,
+            Self::NoAck(..) => 0x05,
+            Self::AuxPortTestFailed(_) => 0x06,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidDescriptorType(typ) => write!(f, "invalid descriptor type {}", typ),
+            Self::UnexpectedTssType(typ) => {
+                write!(f, "TSS descriptor is not an available TSS: type {:#06b}", typ)
+            }
+            Self::InvalidNumber => write!(f, "invalid number"),
+            Self::MissingArgument(name) => write!(f, "missing argument: {}", name),
+            Self::TooMany(what) => write!(f, "too many {}", what),
+            Self::Skipped(prerequisite) => write!(f, "not attempted because {} failed", prerequisite),
+            Self::Memory(e) => write!(f, "memory: {}", e),
+            Self::Interrupt(e) => write!(f, "interrupts: {}", e),
+            Self::Acpi(e) => write!(f, "ACPI: {}", e),
+            Self::Serial(e) => write!(f, "serial: {}", e),
+            Self::Multiboot(e) => write!(f, "multiboot: {}", e),
+            Self::Elf(e) => write!(f, "ELF: {}", e),
+            Self::Ps2(e) => write!(f, "PS/2: {}", e),
+            Self::Other(message) => write!(f, "{}", message),
+            Self::WithContext { chain, source } => {
+                for msg in chain.iter() {
+                    write!(f, "{}: ", msg)?;
+                }
+                write!(f, "{}", Error::from(source.clone()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for MemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadBootInfo => write!(f, "invalid multiboot information"),
+            Self::NoMemoryMap => write!(f, "no memory map in the multiboot information"),
+            Self::MemoryMapEntryTooSmall(size) => {
+                write!(f, "memory map entries are too small: {} bytes", size)
+            }
+            Self::MemoryMapBadSize => write!(f, "memory map size doesn't match its entry size"),
+            Self::NoAvailableMemory => write!(f, "memory map has no available memory"),
+            Self::MemoryMapOverflow(base) => {
+                write!(f, "memory map entry wraps around: base {:#x}", base)
+            }
+            Self::InsufficientMemory(bytes) => {
+                write!(f, "not enough available memory: {} bytes", bytes)
+            }
+            Self::KernelNotInAvailableMemory => {
+                write!(f, "the end of the kernel is not in available memory")
+            }
+            Self::AlreadyInitialized => write!(f, "the page allocator is already initialized"),
+            Self::TooManyReservedRanges => write!(f, "too many reserved ranges"),
+            Self::PageArrayTooLarge => write!(f, "the page array is too large"),
+            Self::NoEarlyMemory => write!(f, "not enough early memory for the page array"),
+            Self::OutOfMemory => write!(f, "out of memory"),
+            Self::AllocFailed { size, align } => {
+                write!(f, "failed to allocate {} bytes aligned to {}", size, align)
+            }
+            Self::LargePageInTheWay(virt) => {
+                write!(f, "{:#x} is already mapped by a large page", virt)
+            }
+            Self::Unsupported(what) => write!(f, "no support for {}", what),
+        }
+    }
+}
+
+impl fmt::Display for IntError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotAnException(vector) => write!(f, "vector {} is not an exception", vector),
+            Self::NoFreeVectors => write!(f, "no free interrupt vectors"),
+            Self::IdtNotInitialized => write!(f, "the IDT is not initialized"),
+            Self::MpsNotFound => write!(f, "no MP floating pointer"),
+            Self::MpsNoConfigTable => write!(f, "no MP configuration table"),
+            Self::MpsBadTable(addr) => write!(f, "invalid MP configuration table at {:#x}", addr),
+            Self::MpsBadEntry(addr) => write!(f, "invalid MP table entry at {:#x}", addr),
+            Self::MpsNoIoApic => write!(f, "no usable IOAPIC in the MP tables"),
+            Self::VectorInUse(vector) => write!(f, "vector {:#x} is already in use", vector),
+            Self::GsiOutOfRange(gsi) => write!(f, "the IOAPIC has no GSI {}", gsi),
+            Self::UnknownCpu(apic_id) => write!(f, "no CPU has APIC ID {}", apic_id),
+            Self::AlreadyRegistered(irq) => write!(f, "IRQ {} already has a handler", irq),
+            Self::NotRegistered(irq) => write!(f, "IRQ {} has no handler", irq),
+            Self::IoApicNotInitialized => write!(f, "the IOAPIC is not initialized"),
+        }
+    }
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoRsdp => write!(f, "no RSDP found"),
+            Self::BadChecksum(signature) => {
+                write!(f, "bad checksum in {}", Signature(signature))
+            }
+            Self::MissingTable(signature) => write!(f, "no {} table", Signature(signature)),
+            Self::NoPm1aControl => write!(f, "no PM1a control block in the FADT"),
+            Self::NoResetRegister => write!(f, "no usable reset register in the FADT"),
+            Self::BadLength(signature, length) => {
+                write!(f, "{} table has a bad length of {} bytes", Signature(signature), length)
+            }
+            Self::NoS5 => write!(f, "no \\_S5_ package in the DSDT"),
+        }
+    }
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPresent(port) => write!(f, "no UART at port {:#x}", port),
+            Self::UnsupportedBaud(baud) => write!(f, "unsupported speed {} baud", baud),
+        }
+    }
+}
+
+impl fmt::Display for MultibootError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NullPointer => write!(f, "no boot information"),
+            Self::BadTotalSize(size) => write!(f, "total size of {} bytes is too small", size),
+            Self::TagOverrun { offset, size } => {
+                write!(f, "tag at offset {:#x} has a bad size of {} bytes", offset, size)
+            }
+            Self::MissingTag(typ) => write!(f, "no tag of type {}", typ),
+            Self::BadAlignment(addr) => {
+                write!(f, "boot information at {:#x} is not 8-byte aligned", addr)
+            }
+            Self::StringNotUtf8 => write!(f, "string is not valid UTF-8"),
+            Self::BadMagic(magic) => {
+                write!(f, "not loaded by a multiboot2 bootloader: magic {:#x}", magic)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotElf => write!(f, "not an ELF file"),
+            Self::Unsupported => write!(f, "not a 64-bit x86-64 executable"),
+            Self::Truncated(offset) => write!(f, "header at {:#x} is outside the file", offset),
+            Self::BadAddress(vaddr) => write!(f, "segment at {:#x} is outside user memory", vaddr),
+        }
+    }
+}
+
+impl fmt::Display for Ps2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPresent => write!(f, "no controller"),
+            Self::Timeout => write!(f, "no response in time"),
+            Self::SelfTestFailed(result) => write!(f, "controller self-test returned {:#x}", result),
+            Self::PortTestFailed(result) => write!(f, "keyboard port test returned {:#x}", result),
+            Self::NoAck(command, response) => {
+                write!(f, "device answered command {:#x} with {:#x}", command, response)
+            }
+            Self::AuxPortTestFailed(result) => write!(f, "mouse port test returned {:#x}", result),
+        }
+    }
+}
+
+/// Shows an ACPI table signature as text.
+struct Signature<'a>(&'a [u8; 4]);
+
+impl fmt::Display for Signature<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &byte in self.0 {
+            let c = if byte.is_ascii_graphic() { byte as char } else { '?' };
+            write!(f, "{}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Cause> for Error {
+    fn from(cause: Cause) -> Self {
+        match cause {
+            Cause::InvalidDescriptorType(typ) => Self::InvalidDescriptorType(typ),
+            Cause::UnexpectedTssType(typ) => Self::UnexpectedTssType(typ),
+            Cause::InvalidNumber => Self::InvalidNumber,
+            Cause::MissingArgument(name) => Self::MissingArgument(name),
+            Cause::TooMany(what) => Self::TooMany(what),
+            Cause::Skipped(prerequisite) => Self::Skipped(prerequisite),
+            Cause::Memory(e) => Self::Memory(e),
+            Cause::Interrupt(e) => Self::Interrupt(e),
+            Cause::Acpi(e) => Self::Acpi(e),
+            Cause::Serial(e) => Self::Serial(e),
+            Cause::Multiboot(e) => Self::Multiboot(e),
+            Cause::Elf(e) => Self::Elf(e),
+            Cause::Ps2(e) => Self::Ps2(e),
+            Cause::Other(message) => Self::Other(message),
+        }
+    }
+}
+
+impl From<MemError> for Error {
+    fn from(e: MemError) -> Self {
+        Self::Memory(e)
+    }
+}
+
+impl From<IntError> for Error {
+    fn from(e: IntError) -> Self {
+        Self::Interrupt(e)
+    }
+}
+
+impl From<AcpiError> for Error {
+    fn from(e: AcpiError) -> Self {
+        Self::Acpi(e)
+    }
+}
+
+impl From<SerialError> for Error {
+    fn from(e: SerialError) -> Self {
+        Self::Serial(e)
+    }
+}
+
+impl From<MultibootError> for Error {
+    fn from(e: MultibootError) -> Self {
+        Self::Multiboot(e)
+    }
+}
+
+impl From<ElfError> for Error {
+    fn from(e: ElfError) -> Self {
+        Self::Elf(e)
+    }
+}
+
+impl From<Ps2Error> for Error {
+    fn from(e: Ps2Error) -> Self {
+        Self::Ps2(e)
+    }
+}
+
+impl From<AllocError> for Error {
+    fn from(e: AllocError) -> Self {
+        Self::Memory(MemError::AllocFailed {
+            size: e.layout.size(),
+            align: e.layout.align(),
+        })
+    }
+}
+
+/// Returns early with an error.
+///
+/// Takes anything that converts into the error type of the function, or a
+/// message, which becomes [`Error::Other`].
+///
+/// ```ignore
+/// bail!(IntError::NoFreeVectors);
+/// bail!("no console");
+/// ```
+#[macro_export]
+macro_rules! bail {
+    ($msg:literal $(,)?) => {
+        return ::core::result::Result::Err(::core::convert::From::from(
+            $crate::error::Error::Other($msg),
+        ))
+    };
+    ($err:expr $(,)?) => {
+        return ::core::result::Result::Err(::core::convert::From::from($err))
+    };
+}
+
+/// Returns early with an error unless a condition holds.
+///
+/// The error is given like for [`bail!`](crate::bail).
+///
+/// ```ignore
+/// ensure!(len >= HEADER_SIZE, AcpiError::MissingTable(*b"APIC"));
+/// ```
+#[macro_export]
+macro_rules! ensure {
+    ($cond:expr, $($err:tt)+) => {
+        if !$cond {
+            $crate::bail!($($err)+);
+        }
+    };
 }
@@ -0,0 +1,225 @@
+//! Error tests.
+
+use core::fmt::Write;
+
+use super::{
+    AcpiError, ElfError, Error, IntError, MemError, MultibootError, Ps2Error, ResultExt, SerialError,
+    MAX_CONTEXT_DEPTH,
+};
+use crate::testing::Buffer;
+
+fn display(error: &Error) -> Buffer {
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", error).unwrap();
+    buffer
+}
+
+#[test_case]
+fn display_messages() {
+    let cases = [
+        (Error::InvalidNumber, "invalid number"),
+        (Error::MissingArgument("address"), "missing argument: address"),
+        (Error::TooMany("console sinks"), "too many console sinks"),
+        (Error::Skipped("memory"), "not attempted because memory failed"),
+        (Error::UnexpectedTssType(0b1011), "TSS descriptor is not an available TSS: type 0b1011"),
+        (
+            MemError::InsufficientMemory(4096).into(),
+            "memory: not enough available memory: 4096 bytes",
+        ),
+        (
+            MemError::MemoryMapOverflow(0xffff_f000).into(),
+            "memory: memory map entry wraps around: base 0xfffff000",
+        ),
+        (
+            MemError::Unsupported("device tree memory maps").into(),
+            "memory: no support for device tree memory maps",
+        ),
+        (IntError::NotAnException(40).into(), "interrupts: vector 40 is not an exception"),
+        (IntError::VectorInUse(0x30).into(), "interrupts: vector 0x30 is already in use"),
+        (IntError::GsiOutOfRange(24).into(), "interrupts: the IOAPIC has no GSI 24"),
+        (IntError::UnknownCpu(7).into(), "interrupts: no CPU has APIC ID 7"),
+        (IntError::AlreadyRegistered(4).into(), "interrupts: IRQ 4 already has a handler"),
+        (IntError::NotRegistered(4).into(), "interrupts: IRQ 4 has no handler"),
+        (AcpiError::MissingTable(*b"APIC").into(), "ACPI: no APIC table"),
+        (AcpiError::BadChecksum(*b"FAC\0").into(), "ACPI: bad checksum in FAC?"),
+        (AcpiError::BadLength(*b"XSDT", 12).into(), "ACPI: XSDT table has a bad length of 12 bytes"),
+        (AcpiError::NoS5.into(), "ACPI: no \\_S5_ package in the DSDT"),
+        (SerialError::NotPresent(0x3f8).into(), "serial: no UART at port 0x3f8"),
+        (Ps2Error::NoAck(0xf0, 0xfe).into(), "PS/2: device answered command 0xf0 with 0xfe"),
+        (Ps2Error::AuxPortTestFailed(0x01).into(), "PS/2: mouse port test returned 0x1"),
+        (
+            MultibootError::TagOverrun { offset: 0x48, size: 300 }.into(),
+            "multiboot: tag at offset 0x48 has a bad size of 300 bytes",
+        ),
+        (
+            MultibootError::BadMagic(0x2badb002).into(),
+            "multiboot: not loaded by a multiboot2 bootloader: magic 0x2badb002",
+        ),
+        (Error::Other("something broke"), "something broke"),
+    ];
+
+    for (error, message) in cases {
+        assert_eq!(display(&error).as_str(), message);
+    }
+}
+
+/// Codes end up in logs and exit statuses, so they must never change.
+#[test_case]
+fn codes_are_stable() {
+    let cases = [
+        (Error::InvalidDescriptorType(0), 0x001),
+        (Error::UnexpectedTssType(0), 0x002),
+        (Error::InvalidNumber, 0x003),
+        (Error::MissingArgument(""), 0x004),
+        (Error::TooMany(""), 0x005),
+        (Error::Skipped(""), 0x006),
+        (Error::Other(""), 0x0ff),
+        (MemError::BadBootInfo.into(), 0x101),
+        (MemError::NoEarlyMemory.into(), 0x10c),
+        (MemError::AllocFailed { size: 0, align: 0 }.into(), 0x10e),
+        (MemError::Unsupported("").into(), 0x110),
+        (IntError::NotAnException(0).into(), 0x201),
+        (IntError::NoFreeVectors.into(), 0x202),
+        (IntError::MpsNoIoApic.into(), 0x208),
+        (IntError::VectorInUse(0).into(), 0x209),
+        (IntError::GsiOutOfRange(0).into(), 0x20a),
+        (IntError::UnknownCpu(0).into(), 0x20b),
+        (IntError::AlreadyRegistered(0).into(), 0x20c),
+        (IntError::NotRegistered(0).into(), 0x20d),
+        (IntError::IoApicNotInitialized.into(), 0x20e),
+        (AcpiError::NoRsdp.into(), 0x301),
+        (AcpiError::MissingTable([0; 4]).into(), 0x303),
+        (AcpiError::BadLength([0; 4], 0).into(), 0x306),
+        (AcpiError::NoS5.into(), 0x307),
+        (SerialError::NotPresent(0).into(), 0x401),
+        (SerialError::UnsupportedBaud(0).into(), 0x402),
+        (MultibootError::NullPointer.into(), 0x501),
+        (MultibootError::StringNotUtf8.into(), 0x506),
+        (MultibootError::BadMagic(0).into(), 0x507),
+        (ElfError::NotElf.into(), 0x601),
+        (ElfError::BadAddress(0).into(), 0x604),
+        (Ps2Error::NotPresent.into(), 0x701),
+        (Ps2Error::NoAck(0, 0).into(), 0x705),
+        (Ps2Error::AuxPortTestFailed(0).into(), 0x706),
+    ];
+
+    for (error, code) in cases {
+        assert_eq!(error.code(), code, "code of {:?}", error);
+    }
+}
+
+#[test_case]
+fn subsystem_codes_stay_in_range() {
+    let errors: [Error; 7] = [
+        MemError::NoEarlyMemory.into(),
+        IntError::NoFreeVectors.into(),
+        AcpiError::MissingTable([0; 4]).into(),
+        SerialError::UnsupportedBaud(0).into(),
+        MultibootError::MissingTag(0).into(),
+        ElfError::Unsupported.into(),
+        Ps2Error::Timeout.into(),
+    ];
+
+    for (i, error) in errors.iter().enumerate() {
+        let base = 0x100 * (i as u32 + 1);
+        assert!((base..base + 0x100).contains(&error.code()));
+    }
+}
+
+#[test_case]
+fn context_wraps_the_cause() {
+    let error = Error::from(MemError::NoMemoryMap)
+        .context("reading the memory map")
+        .context("memory");
+
+    assert_eq!(
+        display(&error).as_str(),
+        "memory: reading the memory map: memory: no memory map in the multiboot information"
+    );
+    assert_eq!(error.root_cause(), Error::Memory(MemError::NoMemoryMap));
+    assert_eq!(error.code(), 0x102);
+}
+
+#[test_case]
+fn context_is_never_used_up() {
+    // Far more errors than any fixed pool of wrapped errors would hold
+    for _ in 0..1000 {
+        let error = Error::from(MultibootError::TagOverrun { offset: 0x58, size: 4 })
+            .context("finding the memory map")
+            .context("parsing multiboot info")
+            .context("initializing memory");
+
+        assert_eq!(
+            display(&error).as_str(),
+            "initializing memory: parsing multiboot info: finding the memory map: \
+             multiboot: tag at offset 0x58 has a bad size of 4 bytes"
+        );
+    }
+}
+
+#[test_case]
+fn deep_context_chains_keep_the_innermost() {
+    let mut error = Error::from(IntError::NoFreeVectors);
+    for msg in ["a", "b", "c", "d", "e", "f"] {
+        error = error.context(msg);
+    }
+
+    assert_eq!(MAX_CONTEXT_DEPTH, 4);
+    assert_eq!(display(&error).as_str(), "d: c: b: a: interrupts: no free interrupt vectors");
+    assert_eq!(error.root_cause(), Error::Interrupt(IntError::NoFreeVectors));
+    assert_eq!(error.code(), 0x202);
+}
+
+#[test_case]
+fn context_on_results() {
+    let ok: core::result::Result<u8, MemError> = Ok(1);
+    assert_eq!(ok.context("unused"), Ok(1));
+
+    let err: core::result::Result<u8, IntError> = Err(IntError::NoFreeVectors);
+    let error = err.context("allocating a vector").unwrap_err();
+    assert_eq!(display(&error).as_str(), "allocating a vector: interrupts: no free interrupt vectors");
+    assert_eq!(error.code(), 0x202);
+}
+
+/// Fails at a different step depending on `step`, each with its own error
+/// type.
+fn propagate(step: u32) -> super::Result<u32> {
+    use crate::memory::fallible::try_vec;
+    use crate::{bail, ensure};
+
+    ensure!(step != 0, IntError::NoFreeVectors);
+    ensure!(step != 1, "step 1 is not allowed");
+    if step == 2 {
+        let huge = try_vec(0u8, 64 * 1024 * 1024)?;
+        return Ok(huge.len() as u32);
+    }
+    if step == 3 {
+        Err(MultibootError::MissingTag(6))?;
+    }
+    if step == 4 {
+        bail!(SerialError::NotPresent(0x2f8));
+    }
+    Ok(step)
+}
+
+#[test_case]
+fn question_mark_converts_subsystem_errors() {
+    assert_eq!(propagate(0), Err(IntError::NoFreeVectors.into()));
+    assert_eq!(propagate(1), Err(Error::Other("step 1 is not allowed")));
+    assert_eq!(
+        propagate(2),
+        Err(MemError::AllocFailed { size: 64 * 1024 * 1024, align: 1 }.into())
+    );
+    assert_eq!(propagate(3), Err(MultibootError::MissingTag(6).into()));
+    assert_eq!(propagate(4).map_err(|e| e.code()), Err(0x401));
+    assert_eq!(propagate(5), Ok(5));
+}
+
+#[test_case]
+fn propagated_errors_keep_context() {
+    let error = propagate(3).context("reading the memory map").unwrap_err();
+    assert_eq!(
+        display(&error).as_str(),
+        "reading the memory map: multiboot: no tag of type 6"
+    );
+}
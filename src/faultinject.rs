@@ -0,0 +1,116 @@
+//! Failures on demand, for testing error paths.
+//!
+//! Running out of memory and getting bad boot information hardly ever
+//! happen under QEMU, so the code that handles them is rarely run. With
+//! the `faultinject` feature, tests can make them happen:
+//!
+//! - [`fail_nth_allocation`] makes one page allocation fail.
+//! - [`fail_allocations_matching`] makes every page allocation of some
+//!   sizes fail until [`reset`].
+//! - [`corrupt_next_multiboot_tag`] makes the next multiboot tag that's
+//!   read look like it has a size of 0.
+//!
+//! `PageAllocator::allocate_page`, `BootInfo::parse` and the multiboot tag
+//! iterator ask [`fail_allocation`] and [`corrupt_tag`] whether to fail.
+//! Without the feature neither this module nor the hooks are compiled in.
+//! With it, the `faultinject` shell command arms the same failures by hand.
+//!
+//! The settings are global, and anything else allocating in the meantime
+//! uses them up too, so tests should arm them right before the call they
+//! test and [`reset`] afterwards.
+
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::error::{Error, Result};
+use crate::{println, shell};
+
+/// Page allocations left until the one that fails, 0 if none will
+static FAIL_COUNTDOWN: AtomicUsize = AtomicUsize::new(0);
+
+/// Page sizes in bytes that fail, empty if none do
+static FAIL_SIZES_START: AtomicUsize = AtomicUsize::new(0);
+static FAIL_SIZES_END: AtomicUsize = AtomicUsize::new(0);
+
+static CORRUPT_TAG: AtomicBool = AtomicBool::new(false);
+
+/// Failures injected so far
+static INJECTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes the `n`th page allocation from now fail, 1 being the next one.
+///
+/// 0 cancels it.
+pub fn fail_nth_allocation(n: usize) {
+    FAIL_COUNTDOWN.store(n, Ordering::SeqCst);
+}
+
+/// Makes page allocations fail while the page size in bytes is in `sizes`.
+pub fn fail_allocations_matching(sizes: Range<usize>) {
+    // Empty while it's being changed
+    FAIL_SIZES_END.store(0, Ordering::SeqCst);
+    FAIL_SIZES_START.store(sizes.start, Ordering::SeqCst);
+    FAIL_SIZES_END.store(sizes.end, Ordering::SeqCst);
+}
+
+/// Makes the next multiboot tag that's read look corrupt.
+pub fn corrupt_next_multiboot_tag() {
+    CORRUPT_TAG.store(true, Ordering::SeqCst);
+}
+
+/// Cancels everything that hasn't happened yet.
+pub fn reset() {
+    FAIL_COUNTDOWN.store(0, Ordering::SeqCst);
+    FAIL_SIZES_END.store(0, Ordering::SeqCst);
+    FAIL_SIZES_START.store(0, Ordering::SeqCst);
+    CORRUPT_TAG.store(false, Ordering::SeqCst);
+}
+
+/// Returns how many failures have been injected since boot.
+pub fn injected() -> usize {
+    INJECTED.load(Ordering::SeqCst)
+}
+
+/// Adds the `faultinject` shell command.
+pub fn init() {
+    let help = "faultinject [nth <n> | sizes <start> <end> | tag | reset]: Make allocations or boot info fail";
+    if let Err(e) = shell::register("faultinject", help, command) {
+        log::warn!("faultinject: Can't add the shell command: {}", e);
+    }
+}
+
+/// Arms a failure, then shows how many have been injected.
+fn command(args: &[&str]) -> Result<()> {
+    match args {
+        [] => {}
+        ["nth", n] => fail_nth_allocation(shell::parse_number(n)?),
+        ["sizes", start, end] => fail_allocations_matching(shell::parse_number(start)?..shell::parse_number(end)?),
+        ["tag"] => corrupt_next_multiboot_tag(),
+        ["reset"] => reset(),
+        _ => return Err(Error::Other("usage: faultinject [nth <n> | sizes <start> <end> | tag | reset]")),
+    }
+    println!("Failures injected: {}", injected());
+    Ok(())
+}
+
+/// Returns whether a page allocation of `bytes` should fail.
+///
+/// Each call counts as an allocation for [`fail_nth_allocation`].
+pub fn fail_allocation(bytes: usize) -> bool {
+    let nth = FAIL_COUNTDOWN
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+        .is_ok_and(|left| left == 1);
+    let sizes = FAIL_SIZES_START.load(Ordering::SeqCst)..FAIL_SIZES_END.load(Ordering::SeqCst);
+    injected_if(nth || sizes.contains(&bytes))
+}
+
+/// Returns whether the multiboot tag being read should look corrupt.
+pub fn corrupt_tag() -> bool {
+    injected_if(CORRUPT_TAG.swap(false, Ordering::SeqCst))
+}
+
+fn injected_if(fail: bool) -> bool {
+    if fail {
+        INJECTED.fetch_add(1, Ordering::SeqCst);
+    }
+    fail
+}
@@ -10,12 +10,19 @@
 //! ## GDT Entries
 //!
 //! * 0 - Null
-//! * 1 - Kernel Data
-//! * 2 - Kernel Code
+//! * 1 - Kernel Code
+//! * 2 - Kernel Data
 //! * 3 - User Data
 //! * 4 - User Code
 //! * 5,6 - TSS
+//!
+//! `syscall` and `sysret` don't read the descriptors, they take the
+//! selectors from `IA32_STAR` and the ones after them, so this order is
+//! fixed: kernel data right after kernel code, and user code right after
+//! user data.
 
+#[cfg(test)]
+mod test;
 mod types;
 
 use core::cmp::min;
@@ -29,6 +36,7 @@ use x86::segmentation::{SegmentSelector, load_ds, load_es, load_ss};
 use x86::task::load_tr;
 
 use crate::cpu::IstStack;
+use crate::error::{Error, Result};
 use types::{AccessByte, SystemAccessByte, SystemDescriptorType};
 
 // GDT flags
@@ -46,6 +54,7 @@ pub unsafe fn init_cpu() {
     // Initialize TSS
     let tss_addr = {
         for i in 0..min(cpu.ist.len(), 7) {
+            cpu.ist[i].write_canary();
             let ist_addr = cpu.ist[i].bottom();
             cpu.tss.set_ist(i, ist_addr as u64);
         }
@@ -59,48 +68,14 @@ pub unsafe fn init_cpu() {
         &cpu.tss as *const TaskStateSegment
     };
 
-    // Initialize GDT
+    // The code and data descriptors are set up in `GlobalDescriptorTable::empty`
     let gdt = &mut cpu.gdt;
 
-    gdt.kernel_data = {
-        let mut access = AccessByte::new();
-        access.set_privilege(0);
-        access.set_executable(false);
-        access.set_read_write(true);
-        GdtEntry::new(0, 0, access, GDT_F_LONG_MODE)
-    };
-
-    // Just an example (kernel code, you need more)
-    gdt.kernel_code = {
-        let mut access = AccessByte::new();
-        access.set_privilege(0);
-        access.set_executable(true);
-        access.set_read_write(true);
-        GdtEntry::new(0, 0, access, GDT_F_LONG_MODE)
-    };
-
-    // User data segment
-    gdt.user_data = {
-        let mut access = AccessByte::new();
-        access.set_privilege(3);
-        access.set_executable(false);
-        access.set_read_write(true);
-        GdtEntry::new(0, 0, access, GDT_F_LONG_MODE)
-    };
-
-    // User code segment
-    gdt.user_code = {
-        let mut access = AccessByte::new();
-        access.set_privilege(3);
-        access.set_executable(true);
-        access.set_read_write(true);
-        GdtEntry::new(0, 0, access, GDT_F_LONG_MODE)
-    };
-
     // TSS segment
     gdt.tss = {
         let mut access = SystemAccessByte::new(SystemDescriptorType::AvailableTss);
         access.set_privilege(3);
+        // The base is a linear address, like every segment base
         BigGdtEntry::new(
             tss_addr as u64,
             mem::size_of::<TaskStateSegment>() as u32,
@@ -109,12 +84,9 @@ pub unsafe fn init_cpu() {
         )
     };
 
-    // You need to initialize other GDT entries, e.g., kernel data, user
-    // code and data and TSS
-    //
-    // For TSS use SystemAccessByte, set privilege to 3 and use BigGdtEntry type
-    // Use tss_addr as a pointer (offset)
-    // and mem::size_of::<TaskStateSegment>() as u32 as limit.
+    if let Err(e) = gdt.validate() {
+        panic!("Invalid GDT: {}", e);
+    }
 
     unsafe {
         // Load GDT
@@ -134,16 +106,17 @@ pub unsafe fn init_cpu() {
 /// A Global Descriptor Table.
 #[derive(Debug)]
 #[repr(packed)]
+#[allow(dead_code)] // "field is never read" - used by the CPU
 pub struct GlobalDescriptorTable {
     /// Null entry.
     _null: GdtEntry,
 
-    /// Kernel data.
-    pub kernel_data: GdtEntry,
-
     /// Kernel code.
     pub kernel_code: GdtEntry,
 
+    /// Kernel data.
+    pub kernel_data: GdtEntry,
+
     /// User data.
     pub user_data: GdtEntry,
 
@@ -157,8 +130,8 @@ pub struct GlobalDescriptorTable {
 }
 
 impl GlobalDescriptorTable {
-    pub const KERNEL_DATA_INDEX: u16 = 1;
-    pub const KERNEL_CODE_INDEX: u16 = 2;
+    pub const KERNEL_CODE_INDEX: u16 = 1;
+    pub const KERNEL_DATA_INDEX: u16 = 2;
     pub const USER_DATA_INDEX: u16 = 3;
     pub const USER_CODE_INDEX: u16 = 4;
     pub const TSS_INDEX: u16 = 5;
@@ -168,20 +141,64 @@ impl GlobalDescriptorTable {
     pub const KERNEL_CS: u16 = SegmentSelector::new(Self::KERNEL_CODE_INDEX, Ring::Ring0).bits();
     pub const KERNEL_SS: u16 = SegmentSelector::new(Self::KERNEL_DATA_INDEX, Ring::Ring0).bits();
 
-    /// Zero-initializes the GDT.
+    /// Returns a GDT with the code and data descriptors filled in.
     ///
-    /// It must be correctly initialized before being loaded.
+    /// The TSS descriptor depends on where the TSS lives, so it must be
+    /// set before the GDT is loaded.
     pub const fn empty() -> Self {
         Self {
             _null: GdtEntry::empty(),
-            kernel_code: GdtEntry::empty(),
-            kernel_data: GdtEntry::empty(),
-            user_code: GdtEntry::empty(),
-            user_data: GdtEntry::empty(),
+            kernel_code: GdtEntry::KERNEL_CODE_ENTRY,
+            kernel_data: GdtEntry::KERNEL_DATA_ENTRY,
+            user_code: GdtEntry::USER_CODE_ENTRY,
+            user_data: GdtEntry::USER_DATA_ENTRY,
             tss: BigGdtEntry::empty(),
         }
     }
 
+    /// Checks that the GDT is ready to be loaded.
+    ///
+    /// The TSS must be available, since `ltr` faults on a busy one. Once
+    /// the GDT is loaded the CPU marks it busy, so this only passes before.
+    pub fn validate(&self) -> Result<()> {
+        let tss = self.tss;
+        let descriptor_type = tss.descriptor_type()?;
+        if !descriptor_type.is_valid_in_long_mode() {
+            return Err(Error::InvalidDescriptorType(descriptor_type.into()));
+        }
+        if descriptor_type != SystemDescriptorType::AvailableTss {
+            return Err(Error::UnexpectedTssType(descriptor_type.into()));
+        }
+        Ok(())
+    }
+
+    /// Copies this GDT to `dest` and returns a pointer to the copy.
+    ///
+    /// Application processors start in real mode and can't reach the GDT
+    /// where it normally lives, so they load a copy below 1MB, e.g. in a
+    /// page from `memory::alloc_low_page`.
+    ///
+    /// # Safety
+    /// `dest` must be valid for writes of `size_of::<GlobalDescriptorTable>()`
+    /// bytes.
+    #[allow(dead_code)] // For the AP trampoline
+    pub unsafe fn copy_to_low_memory(&self, dest: *mut u8) -> DescriptorTablePointer<Self> {
+        let size = mem::size_of::<Self>();
+        assert!(
+            dest as usize + size <= crate::memory::low::LOW_MEMORY_END,
+            "GDT copy must be below 1MB"
+        );
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(self as *const Self as *const u8, dest, size);
+        }
+
+        DescriptorTablePointer {
+            limit: (size - 1) as u16,
+            base: dest as *const Self,
+        }
+    }
+
     /// Returns a pointer to this GDT.
     fn get_pointer(&self) -> DescriptorTablePointer<Self> {
         let limit = mem::size_of::<Self>().try_into().expect("GDT too big");
@@ -207,6 +224,22 @@ pub struct GdtEntry {
 }
 
 impl GdtEntry {
+    /// The kernel code segment.
+    pub const KERNEL_CODE_ENTRY: GdtEntry =
+        GdtEntry::new(0, 0, AccessByte::kernel_code(), GDT_F_LONG_MODE);
+
+    /// The kernel data segment.
+    pub const KERNEL_DATA_ENTRY: GdtEntry =
+        GdtEntry::new(0, 0, AccessByte::kernel_data(), GDT_F_LONG_MODE);
+
+    /// The user code segment.
+    pub const USER_CODE_ENTRY: GdtEntry =
+        GdtEntry::new(0, 0, AccessByte::user_code(), GDT_F_LONG_MODE);
+
+    /// The user data segment.
+    pub const USER_DATA_ENTRY: GdtEntry =
+        GdtEntry::new(0, 0, AccessByte::user_data(), GDT_F_LONG_MODE);
+
     /// Creates an empty (not present) entry.
     pub const fn empty() -> Self {
         Self::new(0, 0, AccessByte::not_present(), 0)
@@ -269,6 +302,11 @@ impl BigGdtEntry {
         }
     }
 
+    /// Returns the type of the descriptor.
+    pub fn descriptor_type(&self) -> Result<SystemDescriptorType> {
+        SystemDescriptorType::try_from(self.access_type & 0x0F)
+    }
+
     /// Returns the "Access Bytes" that VMX wants.
     #[allow(dead_code)] // TODO: Now dead because the VMX subsystem was removed
     pub fn access_bytes(&self) -> u32 {
@@ -0,0 +1,85 @@
+//! GDT tests.
+
+use super::types::SystemDescriptorType;
+use super::GlobalDescriptorTable;
+use crate::error::Error;
+
+#[test_case]
+fn descriptor_type_round_trip() {
+    for raw in 0..=0b1111u8 {
+        let descriptor_type = SystemDescriptorType::try_from(raw).unwrap();
+        assert_eq!(u8::from(descriptor_type), raw);
+    }
+}
+
+#[test_case]
+fn legacy_descriptor_types() {
+    // 16-bit TSS and 32-bit call gate
+    for raw in [0b0001, 0b0100] {
+        let descriptor_type = SystemDescriptorType::try_from(raw).unwrap();
+        assert_eq!(descriptor_type, SystemDescriptorType::Unknown(raw));
+        assert!(!descriptor_type.is_valid_in_long_mode());
+    }
+    assert!(SystemDescriptorType::CallGate64.is_valid_in_long_mode());
+    assert!(matches!(
+        SystemDescriptorType::try_from(0x10),
+        Err(Error::InvalidDescriptorType(0x10))
+    ));
+}
+
+#[test_case]
+fn loaded_tss_is_busy() {
+    // `gdt::init_cpu` has run, so `ltr` has marked the TSS busy
+    let gdt = &crate::cpu::get_current().gdt;
+    assert!(matches!(gdt.validate(), Err(Error::UnexpectedTssType(0b1011))));
+    assert!(GlobalDescriptorTable::empty().validate().is_err());
+}
+
+#[test_case]
+fn copy_to_low_memory() {
+    let page = crate::memory::alloc_low_page().expect("No free memory below 1MB");
+    assert!(page.0 < crate::memory::low::LOW_MEMORY_END);
+
+    let gdt = &crate::cpu::get_current().gdt;
+    let size = core::mem::size_of::<GlobalDescriptorTable>();
+    let pointer = unsafe { gdt.copy_to_low_memory(page.0 as *mut u8) };
+    assert_eq!(pointer.base as usize, page.0);
+    assert_eq!(pointer.limit as usize, size - 1);
+
+    let original = unsafe { core::slice::from_raw_parts(gdt as *const _ as *const u8, size) };
+    let copy = unsafe { core::slice::from_raw_parts(page.0 as *const u8, size) };
+    assert_eq!(original, copy);
+
+    crate::memory::free_low_page(page);
+}
+
+#[test_case]
+fn ist_canaries_are_intact() {
+    let cpu = crate::cpu::get_current();
+    assert!(cpu.ist.iter().all(|stack| stack.check_canary()));
+}
+
+#[test_case]
+fn corrupted_canary_is_detected() {
+    use crate::cpu::Stack;
+
+    let mut stack = Stack::<64>::new();
+    assert!(!stack.check_canary());
+    stack.write_canary();
+    assert!(stack.check_canary());
+
+    // An overflowing stack reaches its lowest bytes last, and that's where
+    // the canary is
+    unsafe { core::ptr::write_volatile(&mut stack as *mut Stack<64> as *mut u8, 0) };
+    assert!(!stack.check_canary());
+}
+
+/// `syscall` and `sysret` compute the selectors from `IA32_STAR`.
+#[test_case]
+fn selectors_follow_syscall_layout() {
+    use GlobalDescriptorTable as GDT;
+
+    assert_eq!(GDT::KERNEL_SS, GDT::KERNEL_CS + 8);
+    assert_eq!(GDT::USER_SS, GDT::KERNEL_SS + 8 + 3);
+    assert_eq!(GDT::USER_CS, GDT::KERNEL_SS + 16 + 3);
+}
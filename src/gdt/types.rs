@@ -69,16 +69,58 @@ bitfield! {
 }
 
 impl AccessByte {
-    pub fn new() -> Self {
-        let mut access = Self(0);
-        access.set_present(true);
-        access.set_code_data(true);
-        access
+    /// Returns a present Code/Data access byte.
+    pub const fn new() -> Self {
+        // Present and Code/Data
+        Self(1 << 7 | 1 << 4)
     }
 
     pub const fn not_present() -> Self {
         Self(0)
     }
+
+    /// Returns the access byte for the kernel code segment.
+    pub const fn kernel_code() -> Self {
+        Self::new().with_privilege(0).with_executable(true).with_read_write(true)
+    }
+
+    /// Returns the access byte for the kernel data segment.
+    pub const fn kernel_data() -> Self {
+        Self::new().with_privilege(0).with_executable(false).with_read_write(true)
+    }
+
+    /// Returns the access byte for the user code segment.
+    pub const fn user_code() -> Self {
+        Self::new().with_privilege(3).with_executable(true).with_read_write(true)
+    }
+
+    /// Returns the access byte for the user data segment.
+    pub const fn user_data() -> Self {
+        Self::new().with_privilege(3).with_executable(false).with_read_write(true)
+    }
+
+    /// Sets the descriptor privilege level.
+    pub const fn with_privilege(self, dpl: u8) -> Self {
+        Self(self.0 & !(0b11 << 5) | (dpl & 0b11) << 5)
+    }
+
+    /// Sets whether this segment is executable.
+    pub const fn with_executable(self, executable: bool) -> Self {
+        Self::with_bit(self, 3, executable)
+    }
+
+    /// Sets the Writable (Data) or Readable (Code) bit.
+    pub const fn with_read_write(self, read_write: bool) -> Self {
+        Self::with_bit(self, 1, read_write)
+    }
+
+    const fn with_bit(self, bit: u8, value: bool) -> Self {
+        if value {
+            Self(self.0 | 1 << bit)
+        } else {
+            Self(self.0 & !(1 << bit))
+        }
+    }
 }
 
 bitfield! {
@@ -125,20 +167,50 @@ impl SystemAccessByte {
 }
 
 /// The type of a System descriptor.
-#[derive(Debug, Copy, Clone)]
+///
+/// Long Mode reuses some of the legacy type encodings for 64-bit
+/// descriptors and leaves the rest undefined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SystemDescriptorType {
+    /// A Local Descriptor Table.
+    Ldt,
+
     /// An available TSS.
     AvailableTss,
 
     /// A busy TSS.
     BusyTss,
+
+    /// A 64-bit call gate.
+    CallGate64,
+
+    /// A 64-bit interrupt gate.
+    InterruptGate64,
+
+    /// A 64-bit trap gate.
+    TrapGate64,
+
+    /// A type that is reserved or only exists in legacy modes.
+    Unknown(u8),
+}
+
+impl SystemDescriptorType {
+    /// Returns whether the type is defined in Long Mode.
+    pub fn is_valid_in_long_mode(&self) -> bool {
+        !matches!(self, Self::Unknown(_))
+    }
 }
 
 impl From<SystemDescriptorType> for u8 {
     fn from(descriptor_type: SystemDescriptorType) -> u8 {
         match descriptor_type {
+            SystemDescriptorType::Ldt => 0b0010,
             SystemDescriptorType::AvailableTss => 0b1001,
             SystemDescriptorType::BusyTss => 0b1011,
+            SystemDescriptorType::CallGate64 => 0b1100,
+            SystemDescriptorType::InterruptGate64 => 0b1110,
+            SystemDescriptorType::TrapGate64 => 0b1111,
+            SystemDescriptorType::Unknown(descriptor_type) => descriptor_type,
         }
     }
 }
@@ -146,10 +218,16 @@ impl From<SystemDescriptorType> for u8 {
 impl TryFrom<u8> for SystemDescriptorType {
     type Error = Error;
 
+    /// Decodes a 4-bit descriptor type.
     fn try_from(descriptor_type: u8) -> Result<Self> {
         match descriptor_type {
+            0b0010 => Ok(Self::Ldt),
             0b1001 => Ok(Self::AvailableTss),
             0b1011 => Ok(Self::BusyTss),
+            0b1100 => Ok(Self::CallGate64),
+            0b1110 => Ok(Self::InterruptGate64),
+            0b1111 => Ok(Self::TrapGate64),
+            0..=0b1111 => Ok(Self::Unknown(descriptor_type)),
             _ => Err(Error::InvalidDescriptorType(descriptor_type)),
         }
     }
@@ -2,6 +2,8 @@
 
 use core::convert::TryFrom;
 
+use crate::error::{Error, IntError};
+
 pub const EXCEPTION_MAX: usize = 31;
 
 /// An exception.
@@ -108,13 +110,13 @@ impl From<Exception> for usize {
 }
 
 impl TryFrom<usize> for Exception {
-    type Error = &'static str;
+    type Error = Error;
 
     fn try_from(num: usize) -> Result<Self, Self::Error> {
         use Exception::*;
 
         if num >= EXCEPTION_MAX {
-            return Err("Not an exception");
+            return Err(IntError::NotAnException(num).into());
         }
 
         match num {
@@ -12,6 +12,7 @@
 // Licensed under the MIT license <http://opensource.org/licenses/MIT>.
 // See top-level LICENSE.
 
+use core::fmt;
 use core::marker::PhantomData;
 use core::mem;
 
@@ -142,6 +143,39 @@ impl Idt {
 
         unsafe { lidt(&ptr) };
     }
+
+    /// Returns the handler address for a vector, or `None` if the entry
+    /// is not present.
+    pub fn get_handler_addr(&self, vector: usize) -> Option<u64> {
+        match vector {
+            0..=31 => self.entries()[vector].handler_addr(),
+            32..=255 => self.interrupts[vector - 32].handler_addr(),
+            _ => None,
+        }
+    }
+
+    /// Prints all present entries with their handlers.
+    pub fn dump(&self) {
+        use crate::debug::ksyms::Symbolized;
+        use crate::println;
+
+        for (vector, entry) in self.entries().iter().enumerate() {
+            let Some(addr) = entry.handler_addr() else {
+                continue;
+            };
+
+            let attributes = entry.attributes;
+            println!("  {:#04x}: {}, handler={}", vector, attributes, Symbolized::new(addr));
+        }
+    }
+
+    /// Returns all 256 entries as an array.
+    ///
+    /// The handler type only matters when setting the handler, so it's
+    /// fine to view every entry as `Entry<HandlerFunc>` for reading.
+    fn entries(&self) -> &[Entry<HandlerFunc>; 256] {
+        unsafe { &*(self as *const Idt as *const [Entry<HandlerFunc>; 256]) }
+    }
 }
 
 /// An entry in an X86-64 Interrupt Descriptor Table.
@@ -219,6 +253,15 @@ impl<F> Entry<F> {
         self
     }
 
+    /// Returns the handler address, or `None` if the entry is not present.
+    pub fn handler_addr(&self) -> Option<u64> {
+        if !self.attributes.is_present() {
+            return None;
+        }
+
+        Some((self.entry_hi as u64) << 32 | (self.entry_mid as u64) << 16 | self.entry_low as u64)
+    }
+
     /// Sets the IST stack.
     pub fn set_ist(&mut self, ist: u8) -> &mut Self {
         self.ist = ist;
@@ -291,6 +334,44 @@ impl EntryAttributes {
         self.0.set_bits(0..4, gate_type.into());
         self
     }
+
+    /// Returns whether the Present bit is set.
+    pub fn is_present(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// Returns the Descriptor Privilege Level.
+    pub fn dpl(&self) -> Ring {
+        match self.0.get_bits(5..7) {
+            0 => Ring::Ring0,
+            1 => Ring::Ring1,
+            2 => Ring::Ring2,
+            _ => Ring::Ring3,
+        }
+    }
+
+    /// Returns the gate type, or `None` if the type bits are invalid.
+    pub fn gate_type(&self) -> Option<GateType> {
+        match self.0.get_bits(0..4) {
+            0b1110 => Some(GateType::Int32),
+            0b0110 => Some(GateType::Int16),
+            0b1111 => Some(GateType::Trap32),
+            0b0111 => Some(GateType::Trap16),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EntryAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "P={} DPL={:?} type={:?}",
+            self.is_present(),
+            self.dpl(),
+            self.gate_type()
+        )
+    }
 }
 
 /// Type of an IDT gate.
@@ -304,6 +385,7 @@ impl EntryAttributes {
 ///
 /// We mostly deal with `Int32` and `Trap32`. The GateType field is 4-bit wide.
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GateType {
     /// 32-bit interrupt gate (0b1110).
     ///
@@ -1,22 +1,103 @@
 //! IOAPIC.
 
-use core::mem::MaybeUninit;
-
 use x86::apic::{ApicControl, ioapic::IoApic};
 
-pub static mut IOAPIC: MaybeUninit<IoApic> = MaybeUninit::zeroed();
+use super::IRQ_OFFSET;
+use crate::ensure;
+use crate::error::{IntError, Result};
+use crate::sync::{Mutex, MutexGuard, Once};
 
-pub unsafe fn init(ioapic_base: usize) {
-    unsafe {
-        let mut ioapic = IoApic::new(ioapic_base);
-        IOAPIC.write(ioapic);
+/// Redirection table registers, two per pin.
+const REG_TABLE: u32 = 0x10;
+
+/// Redirection entry bit that masks the pin.
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+/// The IOAPIC, set up by `init`.
+static IOAPIC: Once<Mutex<IoApicHandle>> = Once::new();
+
+/// An `IoApic` that can be shared.
+struct IoApicHandle {
+    ioapic: IoApic,
+    base: usize,
+}
+
+// The registers are MMIO, and access is serialized by the mutex
+unsafe impl Send for IoApicHandle {}
+
+impl IoApicHandle {
+    /// Masks one pin, which `IoApic` can't do.
+    fn mask(&mut self, gsi: u8) {
+        let select = self.base as *mut u32;
+        let window = (self.base + 0x10) as *mut u32;
+        unsafe {
+            select.write_volatile(REG_TABLE + 2 * gsi as u32);
+            window.write_volatile(REDIRECTION_MASKED | (IRQ_OFFSET as u32 + gsi as u32));
+        }
     }
 }
 
-pub unsafe fn init_cpu() {
-    let mut cpu = crate::cpu::get_current();
+pub unsafe fn init(ioapic_base: usize) {
+    IOAPIC.call_once(|| {
+        Mutex::new(IoApicHandle {
+            ioapic: unsafe { IoApic::new(ioapic_base) },
+            base: ioapic_base,
+        })
+    });
+}
+
+/// Returns whether `init` has been called.
+#[allow(dead_code)] // Nothing checks before calling with_ioapic yet
+pub fn is_initialized() -> bool {
+    IOAPIC.is_completed()
+}
+
+fn lock() -> Result<MutexGuard<'static, IoApicHandle>> {
+    Ok(IOAPIC.get().ok_or(IntError::IoApicNotInitialized)?.lock())
+}
+
+/// Runs `f` with the IOAPIC locked.
+pub fn with_ioapic<R>(f: impl FnOnce(&mut IoApic) -> R) -> Result<R> {
+    Ok(f(&mut lock()?.ioapic))
+}
+
+/// Returns the base address of the IOAPIC registers.
+#[allow(dead_code)] // Only the test reads it so far
+pub fn ioapic_base() -> Result<usize> {
+    Ok(lock()?.base)
+}
+
+/// Returns the number of pins, which are GSIs 0 and up.
+pub fn gsi_count() -> Result<u32> {
+    with_ioapic(|ioapic| ioapic.supported_interrupts() as u32)
+}
+
+/// Unmasks `gsi` and sends it to the CPU with APIC ID `apic_id`, on
+/// vector `IRQ_OFFSET + gsi`.
+pub fn route(gsi: u32, apic_id: u32) -> Result<()> {
+    let mut handle = lock()?;
+    ensure!(
+        gsi < handle.ioapic.supported_interrupts() as u32,
+        IntError::GsiOutOfRange(gsi)
+    );
+    ensure!(crate::cpu::is_present(apic_id), IntError::UnknownCpu(apic_id));
+    handle.ioapic.enable(gsi as u8, apic_id as u8);
+    Ok(())
+}
+
+/// Masks `gsi`.
+pub fn mask(gsi: u32) -> Result<()> {
+    let mut handle = lock()?;
+    ensure!(
+        gsi < handle.ioapic.supported_interrupts() as u32,
+        IntError::GsiOutOfRange(gsi)
+    );
+    handle.mask(gsi as u8);
+    Ok(())
+}
 
-    let ioapic = unsafe { IOAPIC.assume_init_mut() };
-    ioapic.enable(0, crate::cpu::get_cpu_id() as u8);
-    ioapic.enable(1, crate::cpu::get_cpu_id() as u8);
+/// Routes the legacy IRQs to the CPU with the given APIC ID.
+pub fn init_cpu(apic_id: u32) -> Result<()> {
+    route(0, apic_id)?;
+    route(1, apic_id)
 }
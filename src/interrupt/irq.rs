@@ -0,0 +1,151 @@
+//! Vectors and handlers for device interrupts.
+//!
+//! IOAPIC pin `n`, or GSI `n`, is delivered on vector `IRQ_OFFSET + n`,
+//! which is IRQ `n`. [`register_irq`] takes that vector, installs the
+//! handler and routes the pin to the bootstrap processor. Vectors that no
+//! pin uses, for IPIs and the like, come from [`allocate_vector`].
+//!
+//! Every IRQ vector without a handler of its own enters through
+//! [`dispatch`], which looks up the vector in the LAPIC's in-service
+//! register and calls the handler registered for it.
+
+use super::{ioapic, lapic, InterruptStackFrame, IRQ_COUNTS, IRQ_OFFSET, IRQ_TIMER, LAPIC_SPURIOUS_VECTOR, NUM_IRQS};
+use crate::ensure;
+use crate::error::{IntError, Result};
+use crate::sync::Mutex;
+
+/// A device interrupt handler. It gets the IRQ number.
+///
+/// The end of interrupt is sent after it returns.
+pub type IrqHandler = fn(usize);
+
+/// The vectors that are taken, one bit each.
+struct VectorMap([u64; 4]);
+
+impl VectorMap {
+    /// Exceptions, the LAPIC timer and the spurious vector are taken.
+    const fn new() -> Self {
+        let mut map = Self([0; 4]);
+        let mut vector = 0;
+        while vector < IRQ_OFFSET {
+            map.set(vector as u8);
+            vector += 1;
+        }
+        map.set((IRQ_OFFSET + IRQ_TIMER) as u8);
+        map.set(LAPIC_SPURIOUS_VECTOR as u8);
+        map
+    }
+
+    const fn set(&mut self, vector: u8) {
+        self.0[vector as usize / 64] |= 1 << (vector % 64);
+    }
+
+    fn is_set(&self, vector: u8) -> bool {
+        self.0[vector as usize / 64] & (1 << (vector % 64)) != 0
+    }
+
+    fn clear(&mut self, vector: u8) {
+        self.0[vector as usize / 64] &= !(1 << (vector % 64));
+    }
+}
+
+static VECTORS: Mutex<VectorMap> = Mutex::new(VectorMap::new());
+
+/// The handler of each IRQ.
+static HANDLERS: Mutex<[Option<IrqHandler>; NUM_IRQS]> = Mutex::new([None; NUM_IRQS]);
+
+/// Takes `vector`, failing if it's already taken.
+pub fn reserve_vector(vector: u8) -> Result<()> {
+    let mut vectors = VECTORS.lock();
+    ensure!(!vectors.is_set(vector), IntError::VectorInUse(vector));
+    vectors.set(vector);
+    Ok(())
+}
+
+/// Takes a free vector.
+///
+/// They're handed out from the top, away from the vectors of the IOAPIC
+/// pins.
+#[allow(dead_code)] // No MSI device asks for a vector yet
+pub fn allocate_vector() -> Result<u8> {
+    let mut vectors = VECTORS.lock();
+    let vector = (IRQ_OFFSET as u8..=u8::MAX)
+        .rev()
+        .find(|&vector| !vectors.is_set(vector))
+        .ok_or(IntError::NoFreeVectors)?;
+    vectors.set(vector);
+    Ok(vector)
+}
+
+/// Gives back a vector from [`reserve_vector`] or [`allocate_vector`].
+pub fn free_vector(vector: u8) {
+    VECTORS.lock().clear(vector);
+}
+
+/// Sets up `handler` for GSI `irq` and routes it to the bootstrap
+/// processor.
+pub fn register_irq(irq: usize, handler: IrqHandler) -> Result<()> {
+    ensure!(
+        irq < NUM_IRQS && irq < ioapic::gsi_count()? as usize,
+        IntError::GsiOutOfRange(irq as u32)
+    );
+
+    let mut handlers = HANDLERS.lock();
+    ensure!(handlers[irq].is_none(), IntError::AlreadyRegistered(irq));
+    let vector = (IRQ_OFFSET + irq) as u8;
+    reserve_vector(vector)?;
+
+    // Set before the pin is unmasked, so the first interrupt finds it
+    handlers[irq] = Some(handler);
+    if let Err(e) = ioapic::route(irq as u32, crate::cpu::get_cpu_id() as u32) {
+        handlers[irq] = None;
+        free_vector(vector);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Masks GSI `irq` and removes its handler.
+pub fn unregister_irq(irq: usize) -> Result<()> {
+    let mut handlers = HANDLERS.lock();
+    ensure!(
+        handlers.get(irq).is_some_and(|handler| handler.is_some()),
+        IntError::NotRegistered(irq)
+    );
+    ioapic::mask(irq as u32)?;
+    handlers[irq] = None;
+    free_vector((IRQ_OFFSET + irq) as u8);
+    Ok(())
+}
+
+/// Routes GSI `irq` to the CPU with APIC ID `apic_id`.
+pub fn set_irq_affinity(irq: usize, apic_id: u32) -> Result<()> {
+    let handlers = HANDLERS.lock();
+    ensure!(
+        handlers.get(irq).is_some_and(|handler| handler.is_some()),
+        IntError::NotRegistered(irq)
+    );
+    ioapic::route(irq as u32, apic_id)
+}
+
+/// Returns whether IRQ `irq` has a handler.
+pub fn is_registered(irq: usize) -> bool {
+    HANDLERS.lock().get(irq).is_some_and(|handler| handler.is_some())
+}
+
+/// Entry point of the IRQ vectors without a handler of their own.
+pub(super) unsafe extern "C" fn dispatch(_regs: &mut InterruptStackFrame) {
+    if let Some(irq) = lapic::in_service_vector()
+        .map(|vector| vector as usize)
+        .filter(|&vector| vector >= IRQ_OFFSET)
+        .map(|vector| vector - IRQ_OFFSET)
+    {
+        IRQ_COUNTS[irq].fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        // Not held while the handler runs, so it can register others
+        let handler = HANDLERS.lock()[irq];
+        if let Some(handler) = handler {
+            handler(irq);
+        }
+    }
+    lapic::end_of_interrupt();
+}
@@ -11,6 +11,7 @@ use x86::apic::{ApicControl, ApicId};
 use x86::msr;
 
 use super::Cycles;
+use crate::config::TimerSource;
 // use crate::{boot, cpu};
 
 use crate::cpu::{self, get_cpu_id};
@@ -30,8 +31,14 @@ pub unsafe fn init() {
     let apic_region: &'static mut [u32] = unsafe { probe_apic() };
     let mut xapic = XAPIC::new(apic_region);
     xapic.attach();
+
+    // The APIC ID is in bits 31:24 of the ID register in xAPIC mode
+    cpu::set_current_id((xapic.id() >> 24) as usize);
+
     xapic.tsc_set_oneshot(0xfffffffe);
     xapic.tsc_enable(32);
+    // Re-armed on every tick either way
+    xapic.timer_set_periodic(crate::config::get().timer_source == TimerSource::Lapic);
 
     cpu.xapic.write(xapic);
 }
@@ -39,8 +46,7 @@ pub unsafe fn init() {
 /// Arms the timer interrupt.
 pub fn set_timer(cycles: Cycles) {
     let xapic = unsafe {
-        crate::cpu::get_current().xapic.assume_init_mut()
-        //(&mut *crate::cpu::get_current_cpu_field_ptr!(xapic, MaybeUninit<XAPIC>)).assume_init_mut()
+        (&mut *crate::cpu::get_current_cpu_field_ptr!(xapic, MaybeUninit<XAPIC>)).assume_init_mut()
     };
 
     // FIXME: Truncated
@@ -50,13 +56,24 @@ pub fn set_timer(cycles: Cycles) {
 /// Acknowledges an interrupt.
 pub fn end_of_interrupt() {
     let xapic = unsafe {
-        crate::cpu::get_current().xapic.assume_init_mut()
-        // (&mut *crate::cpu::get_current_cpu_field_ptr!(xapic, MaybeUninit<XAPIC>)).assume_init_mut()
+        (&mut *crate::cpu::get_current_cpu_field_ptr!(xapic, MaybeUninit<XAPIC>)).assume_init_mut()
     };
 
     xapic.eoi();
 }
 
+/// Returns the highest vector being handled on this CPU, if any.
+///
+/// That's the one whose handler is running, since only a higher vector
+/// can interrupt a handler.
+pub fn in_service_vector() -> Option<u8> {
+    let xapic = unsafe {
+        (&*crate::cpu::get_current_cpu_field_ptr!(xapic, MaybeUninit<XAPIC>)).assume_init_ref()
+    };
+
+    xapic.highest_in_service()
+}
+
 /// Boots an application processor.
 pub unsafe fn boot_ap(cpu_id: u32, stack: u64, code: u64) {
     // Will need to implement this to boot other CPUs, but not now
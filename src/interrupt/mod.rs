@@ -9,13 +9,24 @@
 mod exception;
 mod idt;
 mod ioapic;
+pub mod irq;
 mod lapic;
 mod mps;
+#[cfg(test)]
+mod test;
 pub mod x86_xapic;
 
 use core::arch::{asm, naked_asm};
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
 use idt::Idt;
 use x86::io::{inb, outb};
+use x86::Ring;
+
+use crate::boot::stage::BootArgs;
+use crate::config::IrqChip;
+use crate::error::{IntError, Result, ResultExt};
+use crate::sync::{Mutex, Once};
 
 //pub use lapic::{boot_ap, end_of_interrupt, set_timer};
 
@@ -23,8 +34,34 @@ use x86::io::{inb, outb};
 pub const IRQ_OFFSET: usize = 32;
 pub const IRQ_TIMER: usize = 0;
 
+/// The LAPIC spurious interrupt vector.
+pub const LAPIC_SPURIOUS_VECTOR: usize = 0xFF;
+
+/// Number of IRQ vectors.
+pub const NUM_IRQS: usize = 256 - IRQ_OFFSET;
+
 /// The global IDT.
-static mut GLOBAL_IDT: Idt = Idt::new();
+static GLOBAL_IDT: Once<Idt> = Once::new();
+
+/// Number of spurious LAPIC interrupts since boot.
+static SPURIOUS_IRQ_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// The registers at the last fatal exception.
+static LAST_FAULT: Mutex<Option<InterruptStackFrame>> = Mutex::new(None);
+
+/// Number of times each IRQ has fired.
+static IRQ_COUNTS: [AtomicU64; NUM_IRQS] = [const { AtomicU64::new(0) }; NUM_IRQS];
+
+/// MCi_STATUS and MCi_ADDR of bank 0. Each bank has 4 MSRs.
+const IA32_MC0_STATUS: u32 = 0x401;
+const IA32_MC0_ADDR: u32 = 0x402;
+
+/// MCi_STATUS bits.
+const MCI_STATUS_VAL: u64 = 1 << 63;
+const MCI_STATUS_ADDRV: u64 = 1 << 58;
+
+/// How often the timer checks the IST stack canaries.
+const CANARY_CHECK_PERIOD_US: u64 = 10_000;
 
 const PIC1_DATA: u16 = 0x21;
 const PIC2_DATA: u16 = 0xa1;
@@ -54,6 +91,7 @@ macro_rules! wrap_interrupt_with_error_code {
             naked_asm!(
 
                 "cld",
+                "inc qword ptr gs:[{depth}]",
                 "push rax",
                 "push rdi",
                 "push rsi",
@@ -94,10 +132,12 @@ macro_rules! wrap_interrupt_with_error_code {
                 "pop rax",
                 "add rsp, 8",  // pop error code
 
+                "dec qword ptr gs:[{depth}]",
                 "iretq",
 
                 //breakpoint = sym crate::debugger::breakpoint,
                 handler = sym $handler,
+                depth = const crate::cpu::INTERRUPT_DEPTH_OFFSET,
             );
         }
 
@@ -120,6 +160,7 @@ macro_rules! wrap_interrupt {
                 //"call {breakpoint}",
 
                 "cld",
+                "inc qword ptr gs:[{depth}]",
 
                 "push 0", // error_code
                 "push rax",
@@ -161,10 +202,12 @@ macro_rules! wrap_interrupt {
                 "pop rax",
                 "add rsp, 8", // error_code
 
+                "dec qword ptr gs:[{depth}]",
                 "iretq",
 
                 //breakpoint = sym crate::debugger::breakpoint,
                 handler = sym $handler,
+                depth = const crate::cpu::INTERRUPT_DEPTH_OFFSET,
             );
         }
 
@@ -184,36 +227,129 @@ unsafe extern "C" fn page_fault(regs: &mut InterruptStackFrame) {
     unsafe {
         asm!("mov {}, cr2", out(reg) cr2);
     }
-    panic!("Page fault at address {:#x}, RIP: {:#x}, error code: {:#x}",
-           cr2, regs.rip, regs.error_code);
+    record_fault(regs);
+    if cr2 < crate::memory::paging::NULL_GUARD_SIZE as u64 {
+        panic!("NULL pointer dereference (offset {:#x}), RIP: {:#x}, error code: {:#x}",
+               cr2, regs.rip, regs.error_code);
+    }
+    panic!("Page fault at address {:#x}, RIP: {:#x}, error code: {:#x}\n{}",
+           cr2, regs.rip, regs.error_code, crate::memory::paging::translate(cr2));
 }
 
 /// General Protection Fault handler.
 unsafe extern "C" fn general_protection_fault(regs: &mut InterruptStackFrame) {
+    record_fault(regs);
     panic!("General Protection Fault at RIP: {:#x}, error code: {:#x}",
            regs.rip, regs.error_code);
 }
 
 /// Double Fault handler.
 unsafe extern "C" fn double_fault(regs: &mut InterruptStackFrame) {
+    record_fault(regs);
     panic!("Double Fault at RIP: {:#x}", regs.rip);
 }
 
+/// Non-Maskable Interrupt handler.
+unsafe extern "C" fn non_maskable_interrupt(_regs: &mut InterruptStackFrame) {
+    // Nothing sends us NMIs yet
+}
+
+/// Machine Check handler.
+unsafe extern "C" fn machine_check(regs: &mut InterruptStackFrame) {
+    use x86::msr::{rdmsr, IA32_MCG_CAP, IA32_MCG_STATUS};
+
+    record_fault(regs);
+
+    let (mcg_status, banks) = unsafe { (rdmsr(IA32_MCG_STATUS), rdmsr(IA32_MCG_CAP) & 0xff) };
+    for bank in 0..banks as u32 {
+        let status = unsafe { rdmsr(IA32_MC0_STATUS + 4 * bank) };
+        if status & MCI_STATUS_VAL != 0 {
+            let addr = if status & MCI_STATUS_ADDRV != 0 {
+                unsafe { rdmsr(IA32_MC0_ADDR + 4 * bank) }
+            } else {
+                0
+            };
+            panic!("Machine Check at RIP: {:#x}, bank {}, status: {:#x}, address: {:#x}, MCG_STATUS: {:#x}",
+                   regs.rip, bank, status, addr, mcg_status);
+        }
+    }
+    panic!("Machine Check at RIP: {:#x}, no valid error bank, MCG_STATUS: {:#x}",
+           regs.rip, mcg_status);
+}
+
+/// Spurious LAPIC interrupt handler.
+///
+/// Spurious interrupts must not be acknowledged with an EOI.
+unsafe extern "C" fn spurious_irq_handler(_regs: &mut InterruptStackFrame) {
+    SPURIOUS_IRQ_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+crate::selftest!(breakpoint_returns, breakpoint_round_trip);
+
+/// An `int3` goes through the IDT and comes back.
+fn breakpoint_round_trip() -> Result<()> {
+    unsafe { asm!("int3") };
+    Ok(())
+}
+
 /// Breakpoint handler.
 unsafe extern "C" fn breakpoint(regs: &mut InterruptStackFrame) {
+    #[cfg(test)]
+    {
+        let hook = BREAKPOINT_HOOK.swap(0, Ordering::Relaxed);
+        if hook != 0 {
+            let hook: fn() = unsafe { core::mem::transmute(hook) };
+            hook();
+        }
+    }
+}
+
+/// What the next breakpoint runs, as a `fn()`, or 0.
+#[cfg(test)]
+static BREAKPOINT_HOOK: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(0);
+
+/// Makes the next `int3` on any CPU run `hook` in the handler, for tests
+/// that need to be in an exception handler.
+#[cfg(test)]
+pub fn on_next_breakpoint(hook: fn()) {
+    BREAKPOINT_HOOK.store(hook as usize, Ordering::Relaxed);
 }
 
 /// Timer interrupt handler.
 unsafe extern "C" fn timer(regs: &mut InterruptStackFrame) {
     use crate::interrupt::{lapic, Cycles};
-    lapic::set_timer(Cycles(100_000)); 
+    // Every CPU has a timer, but time only advances on one
+    if crate::cpu::is_bsp() {
+        crate::time::tick();
+    }
+    IRQ_COUNTS[IRQ_TIMER].fetch_add(1, Ordering::Relaxed);
+    let period_us = crate::config::get().timer_period_us as u64;
+    if crate::time::ticks() % (CANARY_CHECK_PERIOD_US / period_us).max(1) == 0 {
+        check_stack_canaries();
+    }
+    lapic::set_timer(Cycles(crate::config::get().timer_cycles()));
     // Acknowledge the interrupt
     lapic::end_of_interrupt();
 }
 
+/// Panics if an IST stack of this CPU has overflowed.
+///
+/// Only the timer calls this. It can't run while the double fault handler
+/// is on its stack, since interrupts are disabled there.
+fn check_stack_canaries() {
+    for (i, stack) in crate::cpu::get_current().ist.iter().enumerate() {
+        if !stack.check_canary() {
+            panic!("IST stack {} canary corrupted — stack overflow detected", i);
+        }
+    }
+}
+
 /// Registers passed to the interrupt handler
+///
+/// The panic handler fills one in with `debug::capture_registers`, so both
+/// are printed the same way.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct InterruptStackFrame {
     pub r15: u64,
     pub r14: u64,
@@ -239,11 +375,84 @@ pub struct InterruptStackFrame {
     pub ss: u64,
 }   
 
+/// Prints the registers three to a line, like this:
+///
+/// ```text
+/// RIP 0x... RSP 0x... RFLAGS 0x...
+/// RAX 0x... RBX 0x... RCX    0x...
+/// ...
+/// CS 0x8 SS 0x10 error code 0x0
+/// ```
+impl fmt::Display for InterruptStackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = [
+            [("RIP", self.rip), ("RSP", self.rsp), ("RFLAGS", self.rflags)],
+            [("RAX", self.rax), ("RBX", self.rbx), ("RCX", self.rcx)],
+            [("RDX", self.rdx), ("RSI", self.rsi), ("RDI", self.rdi)],
+            [("RBP", self.rbp), ("R8", self.r8), ("R9", self.r9)],
+            [("R10", self.r10), ("R11", self.r11), ("R12", self.r12)],
+            [("R13", self.r13), ("R14", self.r14), ("R15", self.r15)],
+        ];
+        for row in rows {
+            let [(a, a_value), (b, b_value), (c, c_value)] = row;
+            writeln!(f, "{:<3} {:#018x} {:<3} {:#018x} {:<6} {:#018x}", a, a_value, b, b_value, c, c_value)?;
+        }
+        write!(f, "CS {:#x} SS {:#x} error code {:#x}", self.cs, self.ss, self.error_code)
+    }
+}
+
+/// Saves the registers of a fatal exception for the panic handler.
+fn record_fault(regs: &InterruptStackFrame) {
+    *LAST_FAULT.lock() = Some(*regs);
+}
+
+/// Returns the registers at the last fatal exception, if any.
+#[cfg(not(test))]
+pub fn last_fault() -> Option<InterruptStackFrame> {
+    LAST_FAULT.try_lock().and_then(|fault| *fault)
+}
+
+/// Returns the handler address for `vector`, or `None` if it has none.
+pub fn handler_addr(vector: usize) -> Result<Option<u64>> {
+    Ok(GLOBAL_IDT.get().ok_or(IntError::IdtNotInitialized)?.get_handler_addr(vector))
+}
+
+/// Prints every vector that has a handler.
+pub fn dump_idt() -> Result<()> {
+    GLOBAL_IDT.get().ok_or(IntError::IdtNotInitialized)?.dump();
+    Ok(())
+}
+
+/// Returns the number of interrupt handlers running on this CPU.
+///
+/// This is nonzero when called from an interrupt or exception handler.
+pub fn depth() -> usize {
+    crate::cpu::get_current().interrupt_depth
+}
+
+/// Forgets the handlers running on this CPU.
+///
+/// A handler that panics never returns through its trampoline, so the
+/// panic handler calls this once it has reported the depth.
+#[cfg(not(test))]
+pub fn abandon_handlers() {
+    crate::cpu::get_current().interrupt_depth = 0;
+}
+
+/// Returns the number of spurious LAPIC interrupts since boot.
+pub fn spurious_irq_count() -> u64 {
+    SPURIOUS_IRQ_COUNT.load(Ordering::Relaxed)
+}
+
+/// Returns the number of times an IRQ has fired.
+pub fn irq_count(irq: usize) -> u64 {
+    IRQ_COUNTS[irq].load(Ordering::Relaxed)
+}
+
 /// Initializes global interrupt controllers.
 ///
 /// This should be called only once
-#[allow(static_mut_refs)]
-pub unsafe fn init() {
+pub unsafe fn init() -> Result<()> {
     unsafe {
         let pic1 = inb(PIC1_DATA);
         let pic2 = inb(PIC2_DATA);
@@ -251,41 +460,73 @@ pub unsafe fn init() {
         outb(PIC1_DATA, 0xff);
         outb(PIC2_DATA, 0xff);
 
-        let idt = &mut GLOBAL_IDT;
-
-        // Implement:
-        //
-        // You need to initialize idt with handlers similar to a couple of examples below
-        // of course you need handler implementations, check invalid_opcode above
-        // idt.breakpoint.set_handler_fn(wrap_interrupt!(breakpoint));
-        // idt.page_fault.set_handler_fn(wrap_interrupt_with_error_code!(page_fault));
-        // idt.interrupts[IRQ_TIMER].set_handler_fn(wrap_interrupt!(timer));
-        
-        // Set up exception handlers
-        idt.divide_by_zero.set_handler_fn(wrap_interrupt!(invalid_opcode));
-        idt.breakpoint.set_handler_fn(wrap_interrupt!(breakpoint));
-        idt.invalid_opcode.set_handler_fn(wrap_interrupt!(invalid_opcode));
-        idt.double_fault.set_handler_fn(wrap_interrupt_with_error_code!(double_fault));
-        idt.general_protection_fault.set_handler_fn(wrap_interrupt_with_error_code!(general_protection_fault));
-        idt.page_fault.set_handler_fn(wrap_interrupt_with_error_code!(page_fault));
-        
-        // Set up timer interrupt handler
-        idt.interrupts[IRQ_TIMER].set_handler_fn(wrap_interrupt!(timer));
-
-        let ioapic_base = mps::probe_ioapic();
+        GLOBAL_IDT.call_once(|| {
+            let mut idt = Idt::new();
+
+            // Set up exception handlers
+            idt.divide_by_zero.set_handler_fn(wrap_interrupt!(invalid_opcode));
+            idt.non_maskable_interrupt.set_handler_fn(wrap_interrupt!(non_maskable_interrupt));
+            idt.breakpoint.set_handler_fn(wrap_interrupt!(breakpoint));
+            idt.breakpoint.attributes.set_privilege_level(Ring::Ring3);
+            idt.invalid_opcode.set_handler_fn(wrap_interrupt!(invalid_opcode));
+            idt.double_fault.set_handler_fn(wrap_interrupt_with_error_code!(double_fault));
+            idt.general_protection_fault.set_handler_fn(wrap_interrupt_with_error_code!(general_protection_fault));
+            idt.page_fault.set_handler_fn(wrap_interrupt_with_error_code!(page_fault));
+            idt.machine_check.set_handler_fn(wrap_interrupt!(machine_check));
+
+            // Set up timer interrupt handler
+            idt.interrupts[IRQ_TIMER].set_handler_fn(wrap_interrupt!(timer));
+            idt.interrupts[LAPIC_SPURIOUS_VECTOR - IRQ_OFFSET].set_handler_fn(wrap_interrupt!(spurious_irq_handler));
+
+            // The rest go to whatever `register_irq` installed
+            for irq in 0..NUM_IRQS {
+                if !idt.interrupts[irq].attributes.is_present() {
+                    idt.interrupts[irq].set_handler_fn(wrap_interrupt!(irq::dispatch));
+                }
+            }
+
+            idt
+        });
+
+        let ioapic_base = match crate::config::get().irqchip {
+            IrqChip::Mps => mps::probe_ioapic().context("finding the IOAPIC")?,
+            IrqChip::Standard => mps::STANDARD_IOAPIC_BASE,
+        };
         ioapic::init(ioapic_base);
     }
+    Ok(())
+}
+
+/// The `interrupts` boot stage: sets up the IDT and the IOAPIC.
+pub fn init_stage(_args: &BootArgs) -> Result<()> {
+    unsafe { init() }
 }
 
 /// Initializes per-CPU interrupt controllers.
 ///
-/// This should be called only once per CPU.
-pub unsafe fn init_cpu() {
+/// This should be called only once per CPU. Legacy IRQs go to the
+/// bootstrap processor.
+pub unsafe fn init_cpu() -> Result<()> {
+    let idt = GLOBAL_IDT
+        .get()
+        .ok_or(IntError::IdtNotInitialized)
+        .context("loading the IDT")?;
     unsafe {
         lapic::init();
-        ioapic::init_cpu();
-        GLOBAL_IDT.load();
+
+        if crate::cpu::is_bsp() {
+            let cpu_id = crate::cpu::get_cpu_id();
+            ioapic::init_cpu(cpu_id as u32).context("routing legacy IRQs")?;
+        }
+        idt.load();
 
         asm!("sti");
     }
+    Ok(())
+}
+
+/// The `local interrupts` boot stage: sets up this CPU's LAPIC and enables
+/// interrupts.
+pub fn init_cpu_stage(_args: &BootArgs) -> Result<()> {
+    unsafe { init_cpu() }
 }
@@ -1,25 +1,47 @@
+//! Intel MultiProcessor Specification tables.
+//!
+//! We only use them to find the IOAPIC. Without them we fall back to the
+//! standard IOAPIC address.
+
+use core::mem::size_of;
 use core::ptr;
 
-const FALLBACK_IOAPIC_BASE: usize = 0xfec0_0000;
+use crate::ensure;
+use crate::error::{Error, IntError, Result};
+
+/// Where the IOAPIC is on most machines.
+pub const STANDARD_IOAPIC_BASE: usize = 0xfec0_0000;
 
 const EBDA_BASE: usize = 0x80000;
 const EBDA_MAX_SIZE: usize = 128 * 1024;
 const BIOS_BASE: usize = 0xf0000;
 const BIOS_MAX_SIZE: usize = 64 * 1024;
 
+/// Where the floating pointer can be, as base and size
+const SEARCH_AREAS: [(usize, usize); 2] = [(EBDA_BASE, EBDA_MAX_SIZE), (BIOS_BASE, BIOS_MAX_SIZE)];
+
 const FP_SIGNATURE: &[u8] = b"_MP_";
 const CONF_SIGNATURE: &[u8] = b"PCMP";
 
+const ENTRY_PROCESSOR: u8 = 0;
 const ENTRY_IOAPIC: u8 = 2;
 
-#[derive(Debug)]
+/// IOAPIC entry flag: the IOAPIC is usable.
+const IOAPIC_ENABLED: u8 = 1 << 0;
+
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 struct FloatingPointer {
     signature: [u8; 4],
     phys_addr: u32,
+    /// Length in 16-byte units
+    len: u8,
+    spec_rev: u8,
+    checksum: u8,
+    features: [u8; 5],
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 struct ConfigurationTable {
     signature: [u8; 4],
@@ -32,9 +54,12 @@ struct ConfigurationTable {
     oem_table_size: u16,
     entry_count: u16,
     lapic_base: u32,
+    ext_table_len: u16,
+    ext_table_checksum: u8,
+    reserved: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 struct IoApicEntry {
     entry_type: u8,
@@ -44,18 +69,36 @@ struct IoApicEntry {
     base: u32,
 }
 
+/// Returns whether the bytes at `addr` add up to zero.
+unsafe fn checksum_ok(addr: usize, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
 impl FloatingPointer {
-    fn get_config_table(&self) -> &'static ConfigurationTable {
-        let config: &ConfigurationTable = unsafe { &*(self.phys_addr as *const _) };
-        if config.signature != CONF_SIGNATURE {
-            panic!("Invalid configuration table");
-        }
-        config
+    /// Reads the configuration table, if there is a valid one.
+    ///
+    /// A zero address means the system uses one of the default
+    /// configurations, which have no table.
+    unsafe fn get_config_table(&self) -> Result<(usize, ConfigurationTable)> {
+        let addr = self.phys_addr as usize;
+        ensure!(addr != 0, IntError::MpsNoConfigTable);
+
+        let config = unsafe { ptr::read_unaligned(addr as *const ConfigurationTable) };
+        let len = config.len as usize;
+        ensure!(
+            config.signature == CONF_SIGNATURE
+                && len >= size_of::<ConfigurationTable>()
+                && unsafe { checksum_ok(addr, len) },
+            IntError::MpsBadTable(addr)
+        );
+
+        Ok((addr, config))
     }
 }
 
 impl ConfigurationTable {
-    const HEADER_SIZE: usize = 44;
+    const HEADER_SIZE: usize = size_of::<ConfigurationTable>();
 
     fn oem_id_str(&self) -> Option<&str> {
         core::str::from_utf8(&self.oem_id).ok()
@@ -65,58 +108,96 @@ impl ConfigurationTable {
         core::str::from_utf8(&self.product_id).ok()
     }
 
-    fn get_ioapic_entry(&self) -> Option<&'static IoApicEntry> {
-        let mut cur = (self as *const ConfigurationTable as usize) + Self::HEADER_SIZE;
-        let mut i = 0;
+    /// Finds the first usable IOAPIC entry in the table at `addr`.
+    ///
+    /// The walk stops at the end of the table, even if there are supposed
+    /// to be more entries.
+    unsafe fn get_ioapic_entry(&self, addr: usize) -> Result<IoApicEntry> {
+        let end = addr + self.len as usize;
+        let mut cur = addr + Self::HEADER_SIZE;
+
+        for _ in 0..self.entry_count {
+            if cur >= end {
+                break;
+            }
 
-        while i < self.entry_count {
             let entry_type = unsafe { ptr::read_volatile(cur as *const u8) };
             let entry_len = match entry_type {
-                0 => 20, // Processor
-                1 | 2 | 3 | 4 => 8,
-                _ => panic!("Invalid MPS entry type {}", entry_type),
+                ENTRY_PROCESSOR => 20,
+                1..=4 => 8,
+                // We can't tell how long it is, so we can't go on
+                _ => crate::bail!(IntError::MpsBadEntry(cur)),
             };
+            ensure!(cur + entry_len <= end, IntError::MpsBadEntry(cur));
 
             if entry_type == ENTRY_IOAPIC {
-                let entry = unsafe { &*(cur as *const IoApicEntry) };
-                return Some(entry);
+                let entry = unsafe { ptr::read_unaligned(cur as *const IoApicEntry) };
+                if entry.flags & IOAPIC_ENABLED != 0 {
+                    return Ok(entry);
+                }
             }
 
             cur += entry_len;
-            i += 1;
         }
 
-        None
+        Err(IntError::MpsNoIoApic.into())
     }
 }
 
-pub unsafe fn probe_ioapic() -> usize {
-    FALLBACK_IOAPIC_BASE
+/// Finds the IOAPIC base address.
+///
+/// Without MP tables, or with a floating pointer to one of the default
+/// configurations, the IOAPIC is at the standard address. Tables that are
+/// there but can't be used are an error.
+pub unsafe fn probe_ioapic() -> Result<usize> {
+    unsafe { probe_ioapic_in(&SEARCH_AREAS) }
 }
 
-/*pub unsafe fn probe_ioapic() -> usize {
-    let fp_p = find_fp(EBDA_BASE, EBDA_MAX_SIZE).or_else(|| find_fp(BIOS_BASE, BIOS_MAX_SIZE));
-
-    let fp = if let Some(fp_p) = fp_p {
-        log::info!("MPS Floating Pointer: {:#x?}", fp_p);
-        &*fp_p
-    } else {
-        log::warn!("MPS Floating Pointer not found, assuming {:#x}", FALLBACK_IOAPIC_BASE);
-        return FALLBACK_IOAPIC_BASE;
-    };
+/// Finds the IOAPIC base address with a floating pointer in `areas`.
+pub(super) unsafe fn probe_ioapic_in(areas: &[(usize, usize)]) -> Result<usize> {
+    match unsafe { find_ioapic(areas) } {
+        Ok(ioapic) => {
+            log::info!("mps: IOAPIC {} at {:#x}", ioapic.id, ioapic.base);
+            Ok(ioapic.base as usize)
+        }
+        Err(Error::Interrupt(e @ (IntError::MpsNotFound | IntError::MpsNoConfigTable))) => {
+            log::warn!("mps: {}, assuming IOAPIC at {:#x}", e, STANDARD_IOAPIC_BASE);
+            Ok(STANDARD_IOAPIC_BASE)
+        }
+        Err(e) => Err(e),
+    }
+}
 
-    let config = fp.get_config_table();
-    let ioapic = config.get_ioapic_entry().expect("No IOAPIC entry found");
-    return ioapic.base as usize;
-}*/
+/// Finds the first usable IOAPIC in the MP tables.
+unsafe fn find_ioapic(areas: &[(usize, usize)]) -> Result<IoApicEntry> {
+    let fp_addr = areas
+        .iter()
+        .find_map(|&(base, size)| unsafe { find_fp(base, size) })
+        .ok_or(IntError::MpsNotFound)?;
+    log::info!("mps: Floating pointer at {:#x}", fp_addr);
+
+    let fp = unsafe { ptr::read_unaligned(fp_addr as *const FloatingPointer) };
+    let (config_addr, config) = unsafe { fp.get_config_table() }?;
+    log::info!(
+        "mps: {} {}",
+        config.oem_id_str().unwrap_or("?").trim_end(),
+        config.product_id_str().unwrap_or("?").trim_end(),
+    );
+
+    unsafe { config.get_ioapic_entry(config_addr) }
+}
 
-unsafe fn find_fp(base: usize, size: usize) -> Option<*const FloatingPointer> {
+/// Looks for a valid floating pointer structure on a 16-byte boundary.
+unsafe fn find_fp(base: usize, size: usize) -> Option<usize> {
     let mut cur = base;
-    let search_end = cur + size - 16;
-    while cur < search_end {
+    let search_end = cur + size - size_of::<FloatingPointer>();
+    while cur <= search_end {
         let signature = unsafe { ptr::read_volatile(cur as *const [u8; FP_SIGNATURE.len()]) };
         if signature == FP_SIGNATURE {
-            return Some(cur as *const FloatingPointer);
+            let len = unsafe { ptr::read_volatile((cur + 8) as *const u8) } as usize * 16;
+            if len >= size_of::<FloatingPointer>() && unsafe { checksum_ok(cur, len) } {
+                return Some(cur);
+            }
         }
         cur += 16;
     }
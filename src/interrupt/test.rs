@@ -0,0 +1,224 @@
+//! IDT tests.
+
+use x86::Ring;
+
+use super::idt::GateType;
+use super::{GLOBAL_IDT, IRQ_OFFSET, LAPIC_SPURIOUS_VECTOR};
+
+#[test_case]
+fn breakpoint_is_callable_from_user_mode() {
+    let idt = GLOBAL_IDT.get().expect("IDT is not initialized");
+    assert_eq!(idt.breakpoint.attributes.dpl(), Ring::Ring3);
+}
+
+#[test_case]
+fn exceptions_are_kernel_only() {
+    let idt = GLOBAL_IDT.get().expect("IDT is not initialized");
+    for attributes in [
+        idt.page_fault.attributes,
+        idt.general_protection_fault.attributes,
+        idt.double_fault.attributes,
+        idt.non_maskable_interrupt.attributes,
+        idt.machine_check.attributes,
+    ] {
+        assert!(attributes.is_present());
+        assert_eq!(attributes.dpl(), Ring::Ring0);
+        assert_eq!(attributes.gate_type(), Some(GateType::Int32));
+    }
+}
+
+#[test_case]
+fn depth_counts_the_handlers_on_this_cpu() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static IN_HANDLER: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+    assert_eq!(super::depth(), 0);
+    super::on_next_breakpoint(|| IN_HANDLER.store(super::depth(), Ordering::Relaxed));
+    unsafe { core::arch::asm!("int3") };
+    assert_eq!(IN_HANDLER.load(Ordering::Relaxed), 1);
+    assert_eq!(super::depth(), 0);
+}
+
+#[test_case]
+fn init_installs_handlers() {
+    use super::handler_addr;
+
+    // Breakpoint, page fault, the timer and the spurious vector
+    for vector in [3, 14, IRQ_OFFSET + super::IRQ_TIMER, LAPIC_SPURIOUS_VECTOR] {
+        assert!(handler_addr(vector).unwrap().is_some(), "vector {:#x} has no handler", vector);
+    }
+    // Debug (#DB) is left out
+    assert_eq!(handler_addr(1), Ok(None));
+    assert_eq!(handler_addr(256), Ok(None));
+}
+
+#[test_case]
+fn spurious_vector_has_handler() {
+    let idt = GLOBAL_IDT.get().expect("IDT is not initialized");
+    assert!(idt.interrupts[LAPIC_SPURIOUS_VECTOR - IRQ_OFFSET].attributes.is_present());
+}
+
+/// An MP floating pointer with its configuration table right after it
+#[repr(C, align(16))]
+struct MpTables([u8; 96]);
+
+impl MpTables {
+    /// Offset of the configuration table
+    const CONFIG: usize = 16;
+
+    /// Fills in both, with a processor, a disabled IOAPIC and then the
+    /// IOAPIC at `ioapic_base`.
+    fn fill(&mut self, ioapic_base: u32) {
+        let config_addr = self.0.as_ptr() as usize + Self::CONFIG;
+        let fp = &mut self.0[..Self::CONFIG];
+        fp[..4].copy_from_slice(b"_MP_");
+        fp[4..8].copy_from_slice(&(config_addr as u32).to_le_bytes());
+        fp[8] = 1;
+        fp[9] = 4;
+
+        let config = &mut self.0[Self::CONFIG..];
+        config[..4].copy_from_slice(b"PCMP");
+        config[4..6].copy_from_slice(&80u16.to_le_bytes());
+        config[6] = 4;
+        config[8..16].copy_from_slice(b"HELLO   ");
+        config[16..28].copy_from_slice(b"SYNTHETIC   ");
+        config[34..36].copy_from_slice(&3u16.to_le_bytes());
+        // The processor entry is all zeros after its type, which is 0
+        config[64..72].copy_from_slice(&[2, 1, 0x11, 0, 0x00, 0x00, 0xb0, 0xfe]);
+        config[72..76].copy_from_slice(&[2, 2, 0x11, 1]);
+        config[76..80].copy_from_slice(&ioapic_base.to_le_bytes());
+
+        self.fix_checksums();
+    }
+
+    /// Makes both structures add up to zero.
+    fn fix_checksums(&mut self) {
+        fn checksum(bytes: &[u8]) -> u8 {
+            0u8.wrapping_sub(bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)))
+        }
+        self.0[10] = 0;
+        self.0[10] = checksum(&self.0[..Self::CONFIG]);
+        self.0[Self::CONFIG + 7] = 0;
+        self.0[Self::CONFIG + 7] = checksum(&self.0[Self::CONFIG..]);
+    }
+
+    fn probe(&self) -> crate::error::Result<usize> {
+        unsafe { super::mps::probe_ioapic_in(&[(self.0.as_ptr() as usize, self.0.len())]) }
+    }
+}
+
+#[test_case]
+fn mps_finds_ioapic_in_tables() {
+    use crate::error::{Error, IntError};
+
+    // Anywhere but the standard address, which is also the fallback
+    let mut tables = MpTables([0; 96]);
+    tables.fill(0xfed0_0000);
+    assert_eq!(tables.probe(), Ok(0xfed0_0000));
+
+    // Without the enabled one, the disabled IOAPIC isn't used
+    tables.0[MpTables::CONFIG + 75] = 0;
+    tables.fix_checksums();
+    assert_eq!(tables.probe(), Err(Error::Interrupt(IntError::MpsNoIoApic)));
+
+    // Nor is a table with a bad checksum
+    tables.fill(0xfed0_0000);
+    tables.0[MpTables::CONFIG + 40] ^= 1;
+    let config_addr = tables.0.as_ptr() as usize + MpTables::CONFIG;
+    assert_eq!(tables.probe(), Err(Error::Interrupt(IntError::MpsBadTable(config_addr))));
+
+    // The IOAPIC in use is the one the firmware's tables point at
+    assert_eq!(super::ioapic::ioapic_base(), unsafe { super::mps::probe_ioapic() });
+}
+
+#[test_case]
+fn mps_without_tables_uses_the_standard_ioapic() {
+    use super::mps::STANDARD_IOAPIC_BASE;
+
+    let tables = MpTables([0; 96]);
+    assert_eq!(tables.probe(), Ok(STANDARD_IOAPIC_BASE));
+}
+
+#[test_case]
+fn taken_vectors_are_refused() {
+    use super::irq::{allocate_vector, free_vector, reserve_vector};
+    use crate::error::{Error, IntError};
+
+    // Exceptions, the timer and the spurious vector
+    for vector in [14, (IRQ_OFFSET + super::IRQ_TIMER) as u8, LAPIC_SPURIOUS_VECTOR as u8] {
+        assert_eq!(reserve_vector(vector), Err(Error::Interrupt(IntError::VectorInUse(vector))));
+    }
+
+    let vector = allocate_vector().unwrap();
+    assert!(vector as usize >= IRQ_OFFSET);
+    assert_eq!(reserve_vector(vector), Err(IntError::VectorInUse(vector).into()));
+    free_vector(vector);
+    assert_eq!(reserve_vector(vector), Ok(()));
+    free_vector(vector);
+}
+
+#[test_case]
+fn vectors_run_out() {
+    use super::irq::{allocate_vector, free_vector};
+    use crate::error::IntError;
+
+    let mut taken = [0u8; 256];
+    let mut count = 0;
+    let error = loop {
+        match allocate_vector() {
+            Ok(vector) => {
+                taken[count] = vector;
+                count += 1;
+            }
+            Err(error) => break error,
+        }
+    };
+    assert_eq!(error, IntError::NoFreeVectors.into());
+    assert!(count > 0);
+
+    for &vector in &taken[..count] {
+        free_vector(vector);
+    }
+    free_vector(allocate_vector().unwrap());
+}
+
+#[test_case]
+fn irq_registration_errors() {
+    use super::irq::{is_registered, register_irq, set_irq_affinity, unregister_irq};
+    use crate::error::IntError;
+
+    fn handler(_irq: usize) {}
+
+    // QEMU's IOAPIC has 24 pins, and nothing uses the last one
+    let irq = 23;
+    assert_eq!(register_irq(24, handler), Err(IntError::GsiOutOfRange(24).into()));
+    assert_eq!(unregister_irq(irq), Err(IntError::NotRegistered(irq).into()));
+    assert_eq!(set_irq_affinity(irq, 0), Err(IntError::NotRegistered(irq).into()));
+    // The LAPIC timer has the vector of GSI 0
+    assert_eq!(
+        register_irq(0, handler),
+        Err(IntError::VectorInUse((IRQ_OFFSET + super::IRQ_TIMER) as u8).into())
+    );
+
+    register_irq(irq, handler).unwrap();
+    assert!(is_registered(irq));
+    assert_eq!(register_irq(irq, handler), Err(IntError::AlreadyRegistered(irq).into()));
+    assert_eq!(set_irq_affinity(irq, 200), Err(IntError::UnknownCpu(200).into()));
+    let apic_id = crate::cpu::get_cpu_id() as u32;
+    assert_eq!(set_irq_affinity(irq, apic_id), Ok(()));
+
+    unregister_irq(irq).unwrap();
+    assert!(!is_registered(irq));
+    assert_eq!(unregister_irq(irq), Err(IntError::NotRegistered(irq).into()));
+}
+
+#[test_case]
+fn ioapic_routing_errors() {
+    use crate::error::IntError;
+
+    assert_eq!(super::ioapic::gsi_count(), Ok(24));
+    assert_eq!(super::ioapic::route(24, 0), Err(IntError::GsiOutOfRange(24).into()));
+    assert_eq!(super::ioapic::route(23, 200), Err(IntError::UnknownCpu(200).into()));
+    assert_eq!(super::ioapic::mask(300), Err(IntError::GsiOutOfRange(300).into()));
+}
@@ -118,6 +118,36 @@ pub const XAPIC_ICR0: u32 = 0x300;
 /// Interrupt Command Register (ICR). Read/write. See Figure 10-28 for reserved bits
 pub const XAPIC_ICR1: u32 = 0x310;
 
+/// ICR delivery mode: the vector is delivered as is.
+///
+/// LOCAL MOD
+pub const ICR_DELIVERY_FIXED: u32 = 0b000 << 8;
+
+/// ICR delivery mode: INIT.
+///
+/// LOCAL MOD
+pub const ICR_DELIVERY_INIT: u32 = 0b101 << 8;
+
+/// ICR delivery mode: STARTUP, with the start page as the vector.
+///
+/// LOCAL MOD
+pub const ICR_DELIVERY_STARTUP: u32 = 0b110 << 8;
+
+/// ICR Delivery Status bit, set while the IPI is being sent.
+///
+/// LOCAL MOD
+pub const ICR_DELIVERY_PENDING: usize = 12;
+
+/// ICR Level bit. Everything but an INIT deassert sets it.
+///
+/// LOCAL MOD
+pub const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+/// ICR Trigger Mode bit: level instead of edge.
+///
+/// LOCAL MOD
+pub const ICR_TRIGGER_LEVEL: u32 = 1 << 15;
+
 /// LVT Timer register. Read/write. See Figure 10-8 for reserved bits.
 pub const XAPIC_LVT_TIMER: u32 = 0x320;
 
@@ -289,8 +319,8 @@ impl XAPIC {
             self.base.set_bit(11, true);
             wrmsr(IA32_APIC_BASE, self.base);
 
-            // Enable this XAPIC (set bit 8, spurious IRQ vector 15)
-            let svr: u32 = 1 << 8 | 15;
+            // Enable this XAPIC (set bit 8) and set the spurious IRQ vector
+            let svr: u32 = 1 << 8 | super::LAPIC_SPURIOUS_VECTOR as u32;
             self.write(ApicRegister::XAPIC_SVR, svr);
         }
     }
@@ -324,6 +354,77 @@ impl XAPIC {
     pub fn tsc_set_oneshot(&mut self, value: u32) {
         self.write(ApicRegister::XAPIC_TIMER_INIT_COUNT, value);
     }
+
+    /// Set the timer to periodic or one-shot mode.
+    ///
+    /// LOCAL MOD
+    pub fn timer_set_periodic(&mut self, periodic: bool) {
+        let mut lvt: u32 = self.read(ApicRegister::XAPIC_LVT_TIMER);
+        lvt.set_bit(17, periodic);
+        self.write(ApicRegister::XAPIC_LVT_TIMER, lvt);
+    }
+
+    /// Send a fixed interrupt with `vector` to the core with the given
+    /// physical APIC ID.
+    ///
+    /// LOCAL MOD
+    #[allow(dead_code)] // No other CPU is running yet
+    pub fn send_fixed_ipi(&mut self, dest_apic_id: u8, vector: u8) {
+        self.write_icr(dest_apic_id, ICR_LEVEL_ASSERT | ICR_DELIVERY_FIXED | vector as u32);
+    }
+
+    /// Send an INIT IPI to a core, the first step in starting an AP.
+    ///
+    /// LOCAL MOD
+    #[allow(dead_code)] // For booting the APs
+    pub fn send_init_ipi(&mut self, dest_apic_id: u8) {
+        self.write_icr(dest_apic_id, ICR_TRIGGER_LEVEL | ICR_LEVEL_ASSERT | ICR_DELIVERY_INIT);
+    }
+
+    /// Send a STARTUP IPI to a core, which starts it in real mode at
+    /// `page * 4096`.
+    ///
+    /// LOCAL MOD
+    #[allow(dead_code)] // For booting the APs
+    pub fn send_sipi(&mut self, dest_apic_id: u8, page: u8) {
+        self.write_icr(dest_apic_id, ICR_LEVEL_ASSERT | ICR_DELIVERY_STARTUP | page as u32);
+    }
+
+    /// Returns the highest vector set in the In-Service Register.
+    ///
+    /// LOCAL MOD
+    pub fn highest_in_service(&self) -> Option<u8> {
+        let isr = [
+            ApicRegister::XAPIC_ISR0,
+            ApicRegister::XAPIC_ISR1,
+            ApicRegister::XAPIC_ISR2,
+            ApicRegister::XAPIC_ISR3,
+            ApicRegister::XAPIC_ISR4,
+            ApicRegister::XAPIC_ISR5,
+            ApicRegister::XAPIC_ISR6,
+            ApicRegister::XAPIC_ISR7,
+        ];
+        isr.iter().enumerate().rev().find_map(|(i, &register)| {
+            let bits = self.read(register);
+            (bits != 0).then(|| (i * 32 + 31 - bits.leading_zeros() as usize) as u8)
+        })
+    }
+
+    /// Write the ICR with a physical destination and wait until the IPI
+    /// has been delivered.
+    ///
+    /// The high half must be written first, since writing the low half
+    /// sends the IPI.
+    ///
+    /// LOCAL MOD
+    fn write_icr(&mut self, dest_apic_id: u8, low: u32) {
+        self.write(ApicRegister::XAPIC_ICR1, (dest_apic_id as u32) << 24);
+        self.write(ApicRegister::XAPIC_ICR0, low);
+
+        while self.read(ApicRegister::XAPIC_ICR0).get_bit(ICR_DELIVERY_PENDING) {
+            core::hint::spin_loop();
+        }
+    }
 }
 
 impl ApicControl for XAPIC {
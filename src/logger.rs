@@ -0,0 +1,280 @@
+//! Kernel logger.
+//!
+//! Backs the `log` crate macros with the console. Each record is written
+//! as a single line:
+//!
+//! ```text
+//! [      1234] WARN interrupt::ioapic (ioapic.rs:87): GSI 9 has no override
+//! ```
+//!
+//! The timestamp is the number of timer ticks since boot. The target
+//! defaults to the module path with the crate name stripped; drivers can
+//! group related messages with `log::warn!(target: "ioapic", ...)`. The
+//! source location is only shown for warnings and errors, and only when
+//! `logsrc=on` is on the kernel command line.
+//!
+//! Messages that can repeat quickly, like device errors, should go through
+//! [`log_ratelimited!`](crate::log_ratelimited) so they can't flood the
+//! console.
+//!
+//! The last [`HISTORY_LEN`] lines are also kept in memory, so the panic
+//! handler can replay them with [`replay_to_serial`].
+
+#[cfg(test)]
+mod test;
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use log::{Level, Log, Metadata, Record};
+
+use crate::cmdline::CommandLine;
+use crate::collections::RingBuffer;
+use crate::sync::Mutex;
+
+/// Targets longer than this are truncated from the left.
+pub const TARGET_WIDTH: usize = 24;
+
+/// Messages let through per call site in each window.
+pub const RATELIMIT_BURST: u32 = 10;
+
+/// Length of a rate limiting window in timer ticks.
+pub const RATELIMIT_INTERVAL: u64 = 1000;
+
+/// Number of recent lines kept for `replay_to_serial`.
+pub const HISTORY_LEN: usize = 32;
+
+/// Longer lines are truncated in the history.
+pub const LOG_ENTRY_LEN: usize = 120;
+
+/// Prefix of module paths in this crate.
+const CRATE_PREFIX: &str = concat!(env!("CARGO_CRATE_NAME"), "::");
+
+/// Whether to show the source location of warnings and errors.
+static SHOW_SOURCE: AtomicBool = AtomicBool::new(false);
+
+static LOGGER: Logger = Logger;
+
+/// The most recent lines, oldest first.
+static HISTORY: Mutex<RingBuffer<LogEntry, HISTORY_LEN>> = Mutex::new(RingBuffer::new());
+
+/// The console logger.
+struct Logger;
+
+impl Log for Logger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let line = Line {
+            ticks: crate::time::ticks(),
+            record,
+            show_source: SHOW_SOURCE.load(Ordering::Relaxed),
+        };
+
+        let mut entry = LogEntry::new();
+        let _ = fmt::write(&mut entry, format_args!("{}", line));
+        // A fault handler may log while this CPU holds the history, so
+        // the line is left out of it rather than deadlocking
+        if let Some(mut history) = HISTORY.try_lock() {
+            history.push(entry);
+        }
+
+        crate::console::write_log(record.level(), format_args!("{}\n", line));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the logger.
+///
+/// This should be called only once, after `console::init` has applied the
+/// per-sink log levels.
+pub fn init(cmdline: &CommandLine) {
+    if let Some(value) = cmdline.get("logsrc") {
+        SHOW_SOURCE.store(matches!(value, "1" | "on"), Ordering::Relaxed);
+    }
+
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(crate::console::max_log_level());
+    }
+}
+
+/// Prints the recent log lines to COM1, oldest first.
+///
+/// The lines stay in the history.
+#[cfg(not(test))]
+pub fn replay_to_serial() {
+    for_each_recent(|line| {
+        crate::serial_println!("{}", line);
+    });
+}
+
+/// Calls `f` with each recent log line, oldest first.
+///
+/// This is for the panic path, where the history lock may be held by the
+/// CPU that panicked, so it's broken if it can't be taken.
+#[cfg(not(test))]
+pub fn for_each_recent(mut f: impl FnMut(&str)) {
+    let history = match HISTORY.try_lock() {
+        Some(history) => history,
+        None => unsafe {
+            HISTORY.force_unlock();
+            HISTORY.lock()
+        },
+    };
+
+    for entry in history.peek_all_iter() {
+        f(entry.as_str());
+    }
+}
+
+/// A log line kept in the history.
+///
+/// The level is part of the line, as the console shows it.
+#[derive(Clone, Copy)]
+pub struct LogEntry {
+    text: [u8; LOG_ENTRY_LEN],
+    len: usize,
+}
+
+impl LogEntry {
+    pub fn new() -> Self {
+        Self {
+            text: [0; LOG_ENTRY_LEN],
+            len: 0,
+        }
+    }
+
+    /// Returns the line, without the trailing newline.
+    pub fn as_str(&self) -> &str {
+        // Only whole characters are written
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+    }
+}
+
+/// Appends to the line, dropping whatever doesn't fit.
+impl fmt::Write for LogEntry {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut end = s.len().min(LOG_ENTRY_LEN - self.len);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+/// A formatted log line, without the trailing newline.
+pub struct Line<'a> {
+    pub ticks: u64,
+    pub record: &'a Record<'a>,
+    pub show_source: bool,
+}
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let record = self.record;
+        write!(f, "[{:>10}] {} {}", self.ticks, record.level(), short_target(record.target()))?;
+
+        if self.show_source && record.level() <= Level::Warn {
+            if let (Some(file), Some(line)) = (record.file(), record.line()) {
+                let file = file.rsplit('/').next().unwrap_or(file);
+                write!(f, " ({}:{})", file, line)?;
+            }
+        }
+
+        write!(f, ": {}", record.args())
+    }
+}
+
+/// Shortens a target for display.
+///
+/// The crate name is stripped, and anything longer than [`TARGET_WIDTH`]
+/// keeps only its last characters, since the innermost module is the most
+/// specific part.
+pub fn short_target(target: &str) -> &str {
+    let target = target.strip_prefix(CRATE_PREFIX).unwrap_or(target);
+    if target.len() <= TARGET_WIDTH {
+        return target;
+    }
+
+    let mut start = target.len() - TARGET_WIDTH;
+    while !target.is_char_boundary(start) {
+        start += 1;
+    }
+    &target[start..]
+}
+
+/// Logs a message, dropping it if the call site has logged too much.
+///
+/// Each call site may log [`RATELIMIT_BURST`] messages every
+/// [`RATELIMIT_INTERVAL`] ticks. Once a new window starts, a line with the
+/// number of dropped messages is logged first. The key is used as the
+/// target.
+///
+/// ```ignore
+/// log_ratelimited!(log::Level::Warn, "serial", "{} receive errors", errors);
+/// ```
+#[macro_export]
+macro_rules! log_ratelimited {
+    ($level:expr, $key:expr, $($arg:tt)+) => {{
+        static LIMIT: $crate::logger::RateLimit = $crate::logger::RateLimit::new();
+        if let Some(suppressed) = LIMIT.check(
+            $crate::time::ticks(),
+            $crate::logger::RATELIMIT_BURST,
+            $crate::logger::RATELIMIT_INTERVAL,
+        ) {
+            if suppressed > 0 {
+                ::log::log!(target: $key, $level, "suppressed {} similar messages", suppressed);
+            }
+            ::log::log!(target: $key, $level, $($arg)+);
+        }
+    }};
+}
+
+/// Rate limiting state of a call site.
+///
+/// This is lock-free, so it can be used from interrupt handlers. Under
+/// contention a window may let a message or two more through.
+pub struct RateLimit {
+    window_start: AtomicU64,
+    count: AtomicU32,
+    suppressed: AtomicU32,
+}
+
+impl RateLimit {
+    pub const fn new() -> Self {
+        Self {
+            window_start: AtomicU64::new(0),
+            count: AtomicU32::new(0),
+            suppressed: AtomicU32::new(0),
+        }
+    }
+
+    /// Records a message at tick `now`.
+    ///
+    /// Returns `None` if the message should be dropped, or the number of
+    /// messages dropped since the last one that was let through.
+    pub fn check(&self, now: u64, burst: u32, interval: u64) -> Option<u32> {
+        let start = self.window_start.load(Ordering::Relaxed);
+        if now.wrapping_sub(start) >= interval
+            && self
+                .window_start
+                .compare_exchange(start, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.count.store(1, Ordering::Relaxed);
+            return Some(self.suppressed.swap(0, Ordering::Relaxed));
+        }
+
+        if self.count.fetch_add(1, Ordering::Relaxed) < burst {
+            Some(0)
+        } else {
+            self.suppressed.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Log formatting tests.
+
+use core::fmt::Write;
+
+use log::{Level, Record};
+
+use super::{
+    short_target, Line, LogEntry, RateLimit, HISTORY, HISTORY_LEN, LOG_ENTRY_LEN, RATELIMIT_BURST,
+    RATELIMIT_INTERVAL, TARGET_WIDTH,
+};
+use crate::testing::Buffer;
+
+fn format(record: &Record, show_source: bool) -> Buffer {
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", Line { ticks: 1234, record, show_source }).unwrap();
+    buffer
+}
+
+#[test_case]
+fn format_without_source() {
+    let record = Record::builder()
+        .args(format_args!("GSI {} has no override", 9))
+        .level(Level::Warn)
+        .target("hello_os::interrupt::ioapic")
+        .file(Some("src/interrupt/ioapic.rs"))
+        .line(Some(87))
+        .build();
+
+    assert_eq!(
+        format(&record, false).as_str(),
+        "[      1234] WARN interrupt::ioapic: GSI 9 has no override"
+    );
+}
+
+#[test_case]
+fn format_with_source() {
+    let record = Record::builder()
+        .args(format_args!("GSI {} has no override", 9))
+        .level(Level::Warn)
+        .target("hello_os::interrupt::ioapic")
+        .file(Some("src/interrupt/ioapic.rs"))
+        .line(Some(87))
+        .build();
+
+    assert_eq!(
+        format(&record, true).as_str(),
+        "[      1234] WARN interrupt::ioapic (ioapic.rs:87): GSI 9 has no override"
+    );
+}
+
+#[test_case]
+fn no_source_below_warn() {
+    let record = Record::builder()
+        .args(format_args!("ready"))
+        .level(Level::Info)
+        .target("ioapic")
+        .file(Some("src/interrupt/ioapic.rs"))
+        .line(Some(42))
+        .build();
+
+    assert_eq!(format(&record, true).as_str(), "[      1234] INFO ioapic: ready");
+}
+
+#[test_case]
+fn long_target_is_truncated() {
+    let target = "hello_os::memory::page_allocator::reserved::ranges";
+    assert_eq!(short_target(target).len(), TARGET_WIDTH);
+    assert_eq!(short_target(target), "ocator::reserved::ranges");
+    assert_eq!(short_target("other_crate::foo"), "other_crate::foo");
+}
+
+#[test_case]
+fn rate_limit_budget() {
+    let limit = RateLimit::new();
+    let check = |now| limit.check(now, RATELIMIT_BURST, RATELIMIT_INTERVAL);
+
+    // Count lines the way `log_ratelimited!` emits them
+    let mut lines = 0;
+    for i in 0..1000 {
+        let now = i / 100;
+        if let Some(suppressed) = check(now) {
+            lines += if suppressed > 0 { 2 } else { 1 };
+        }
+    }
+    assert_eq!(lines, RATELIMIT_BURST);
+
+    // The next window reports what was dropped
+    assert_eq!(check(RATELIMIT_INTERVAL), Some(1000 - RATELIMIT_BURST));
+    assert_eq!(check(RATELIMIT_INTERVAL), Some(0));
+}
+
+#[test_case]
+fn log_entry_truncates_whole_characters() {
+    let mut entry = LogEntry::new();
+    for _ in 0..LOG_ENTRY_LEN - 1 {
+        entry.write_char('x').unwrap();
+    }
+    // Two bytes, so it doesn't fit
+    entry.write_char('é').unwrap();
+    assert_eq!(entry.as_str().len(), LOG_ENTRY_LEN - 1);
+
+    entry.write_str("yz").unwrap();
+    assert_eq!(entry.as_str().len(), LOG_ENTRY_LEN);
+    assert!(entry.as_str().ends_with("xy"));
+}
+
+#[test_case]
+fn history_keeps_recent_lines() {
+    log::info!("history test {}", 42);
+
+    let history = HISTORY.lock();
+    let last = history.peek_all_iter().last().expect("empty log history");
+    assert!(last.as_str().ends_with("INFO logger::test: history test 42"));
+    assert!(history.len() <= HISTORY_LEN);
+}
+
+#[test_case]
+fn history_is_skipped_while_held() {
+    let history = HISTORY.lock();
+    log::info!("history held");
+    drop(history);
+
+    let history = HISTORY.lock();
+    let last = history.peek_all_iter().last().expect("empty log history");
+    assert!(!last.as_str().ends_with("history held"));
+}
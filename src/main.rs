@@ -1,101 +1,185 @@
-#![cfg_attr(not(test), no_std, no_main)]
-#![allow(static_mut_refs)]
+#![no_std]
+#![no_main]
 #![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::testing::runner)]
+#![reexport_test_harness_main = "test_main"]
 
+mod acpi;
+mod boot;
+mod cmdline;
+mod collections;
+mod config;
+mod console;
 mod cpu;
 mod error;
+mod debug;
+mod drivers;
+#[cfg(not(target_arch = "x86_64"))]
+mod dt;
+#[cfg(feature = "faultinject")]
+mod faultinject;
 mod gdt;
 mod interrupt;
+mod logger;
+mod platform;
+mod port_io;
 mod serial;
+mod shell;
+#[cfg(test)]
+mod should_panic;
+mod sync;
+mod time;
+mod user;
+mod version;
 mod memory;
+mod qemu;
+mod selftest;
+#[cfg(test)]
+mod testing;
 
 use core::panic::PanicInfo;
 
-#[macro_use]
-extern crate lazy_static;
-
 extern crate alloc;
 
-// Add println! macro that redirects to serial
+/// Prints to the console.
 #[macro_export]
-macro_rules! println {
-    () => ($crate::serial::_print(format_args!("\n")));
-    ($($arg:tt)*) => ({
-        $crate::serial::_print(format_args!($($arg)*));
-        $crate::serial::_print(format_args!("\n"));
-    });
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
 }
 
-// Reference to the multiboot info pointer saved in boot.asm
-extern "C" {
-    static _bootinfo: usize;
+/// Prints to the console, appending a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+/// Entry point of the bootstrap processor.
+///
+/// `boot.asm` passes the physical address of the multiboot2 information
+/// and the magic value the bootloader left in EAX.
 #[unsafe(no_mangle)]
-pub extern "C" fn rust_main() -> ! {
+pub extern "C" fn rust_main(boot_info: usize, magic: u32) -> ! {
     unsafe {
-        
-        // Check if we can read/write to see CPU state
-        let rflags: u64;
-        core::arch::asm!("pushfq; pop {}", out(reg) rflags);
-        
-        // Initialize GDT and TSS
-        gdt::init_cpu();
-        
-        // Initialize memory allocator BEFORE enabling interrupts
-        // This must come early since interrupt handlers might allocate
-        let boot_info_addr = _bootinfo;
-        memory::init(boot_info_addr);
-        
-        // Initialize interrupt controllers and IDT
-        interrupt::init();
-        
-        interrupt::init_cpu();
-                
-        // Test the allocator
-        memory::test::test_all();
-        
-        // Infinite loop - timer interrupts will fire and print dots
-        loop {
-            core::arch::asm!("hlt");
-        }
-    }
-}
+        // Everything from locks to the panic handler needs the Cpu
+        cpu::set_up_per_cpu_ptr();
+        time::init();
+
+        // The console stage checks the magic, once it can say what's wrong
+        let args = boot::stage::BootArgs { boot_info_addr: boot_info, magic };
+        let phases = boot::stage::run(&boot::init::STAGES, &args);
 
-/// Test the memory allocator
-fn test_allocator() {
-    use alloc::boxed::Box;
-    use alloc::vec::Vec;
+        #[cfg(test)]
+        should_panic::run();
+        #[cfg(test)]
+        test_main();
+
+        boot::phase::boot_complete();
+        println!("{}", phases);
+        println!("{}", boot::phase::times());
+        println!("=== Kernel Initialized Successfully ===");
+        println!("CPU features: {}", cpu::CpuFeatures::detect().compact());
+
+        let self_tests = if config::get().self_test {
+            selftest::run_all()
+        } else {
+            selftest::Summary::default()
+        };
+
+        if qemu::test_mode() {
+            if !self_tests.is_success() {
+                qemu::exit(qemu::ExitCode::Failed);
+            }
+            qemu::exit(qemu::ExitCode::Success);
+        }
         
-    // Test Box allocation
-    let boxed_value = Box::new(42u64);
-    
-    // Test Vec allocation
-    let mut vec = Vec::new();
-    vec.push(1);
-    vec.push(2);
-    vec.push(3);
-    
-    // Test larger allocation
-    let large_box = Box::new([0u8; 1024]);
-    
+        // Hand the main loop over to the debug shell
+        shell::init();
+        #[cfg(feature = "faultinject")]
+        faultinject::init();
+        shell::run();
+    }
 }
 
 /// This function is called on panic.
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("\n!!! KERNEL PANIC !!!");
-    println!("{}", info);
-    
-    loop {
-        unsafe {
-            core::arch::asm!("cli; hlt");
+    // Before anything else has a chance to change them
+    let mut frame = interrupt::InterruptStackFrame::default();
+    unsafe { debug::capture_registers(&mut frame) };
+
+    match debug::panic_guard::enter() {
+        debug::PanicPath::Full => {}
+        debug::PanicPath::Minimal => {
+            debug::panic_guard::report_double_panic();
+            if qemu::test_mode() {
+                qemu::exit(qemu::ExitCode::Panicked);
+            }
+            debug::panic_guard::halt();
         }
+        debug::PanicPath::Halt => debug::panic_guard::halt(),
     }
+
+    let state = debug::MachineState::capture();
+    interrupt::abandon_handlers();
+
+    console::_print_panic(format_args!("\n!!! KERNEL PANIC !!!\n"));
+    console::_print_panic(format_args!("{}\n", version::BuildInfo::CURRENT));
+    console::_print_panic(format_args!("{}\n", info));
+    console::_print_panic(format_args!("Registers:\n{}{}\n", frame, state));
+    console::_print_panic(format_args!("Backtrace:\n{}", debug::Backtrace::from_rbp(frame.rbp)));
+
+    if let Some(fault) = interrupt::last_fault() {
+        console::_print_panic(format_args!("Registers at the fault:\n{}", fault));
+        console::_print_panic(format_args!("Stack around RSP {:#x}:\n", fault.rsp));
+        debug::hexdump((fault.rsp as usize).wrapping_sub(32) as *const u8, 64);
+        console::_print_panic(format_args!("Code around RIP {:#x}:\n", fault.rip));
+        debug::hexdump((fault.rip as usize).wrapping_sub(16) as *const u8, 32);
+    }
+
+    // Many panics come from running out of memory, and the panic may have
+    // happened with an allocator lock held
+    let timeout = core::time::Duration::from_millis(10);
+    match memory::get_allocator().try_summary_for(timeout) {
+        Some(summary) => console::_print_panic(format_args!("Allocator:\n{}\n", summary)),
+        None => console::_print_panic(format_args!("allocator state unavailable (lock held)\n")),
+    }
+
+    serial_println!("Recent log messages:");
+    logger::replay_to_serial();
+    debug::oops::save(info, &frame);
+
+    if qemu::test_mode() {
+        qemu::exit(qemu::ExitCode::Panicked);
+    }
+    match config::get().panic_action {
+        config::PanicAction::Halt => debug::panic_guard::halt(),
+        config::PanicAction::Exit => qemu::exit(qemu::ExitCode::Panicked),
+        config::PanicAction::Reboot { seconds } => {
+            for left in (1..=seconds).rev() {
+                console::_print_panic(format_args!("Rebooting in {}...\n", left));
+                cpu::tsc::busy_wait(core::time::Duration::from_secs(1));
+            }
+            console::_print_panic(format_args!("Rebooting\n"));
+            cpu::reboot()
+        }
+    }
+}
+
+/// Reports the failing test and exits QEMU.
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    testing::panic(info)
 }
 
 /// Allocation error handler
+///
+/// The panic handler prints the allocator summary, or says it's
+/// unavailable if the allocator failed while one of its locks is held.
 #[alloc_error_handler]
 fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
-    panic!("Allocation error: {:?}", layout);
+    panic!("Allocation error: {:?}", layout)
 }
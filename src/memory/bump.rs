@@ -0,0 +1,82 @@
+//! Bump allocator for the early boot phase
+//!
+//! Before the page allocator is up, memory is handed out linearly from
+//! the end of the kernel image. This is where the page metadata array
+//! lives. Memory from the bump allocator is never freed.
+
+use core::alloc::Layout;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::PhysAddr;
+
+/// A lock-free bump allocator
+pub struct BumpAllocator {
+    /// Start of the region, zero until `init`
+    start: AtomicUsize,
+    /// Next free address
+    next: AtomicUsize,
+    /// End of the region, zero when no more allocations are allowed
+    end: AtomicUsize,
+}
+
+impl BumpAllocator {
+    pub const fn new() -> Self {
+        Self {
+            start: AtomicUsize::new(0),
+            next: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    /// Set the region `[start, end)` to allocate from
+    ///
+    /// # Safety
+    /// The region must be unused, identity-mapped memory.
+    pub unsafe fn init(&self, start: PhysAddr, end: PhysAddr) {
+        self.start.store(start.0, Ordering::Relaxed);
+        self.next.store(start.0, Ordering::Relaxed);
+        self.end.store(end.0, Ordering::Release);
+    }
+
+    /// Allocate memory, or return null if the region is exhausted
+    pub fn alloc(&self, layout: Layout) -> *mut u8 {
+        let end = self.end.load(Ordering::Acquire);
+        let mut next = self.next.load(Ordering::Relaxed);
+
+        loop {
+            let Some(addr) = next.checked_next_multiple_of(layout.align()) else {
+                return null_mut();
+            };
+            let new_next = match addr.checked_add(layout.size()) {
+                Some(new_next) if new_next <= end => new_next,
+                _ => return null_mut(),
+            };
+
+            match self.next.compare_exchange_weak(next, new_next, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => return addr as *mut u8,
+                Err(current) => next = current,
+            }
+        }
+    }
+
+    /// Stop handing out memory
+    ///
+    /// Returns the end of the memory in use, which the page allocator must
+    /// not hand out.
+    pub fn finish(&self) -> PhysAddr {
+        self.end.store(0, Ordering::Release);
+        PhysAddr(self.next.load(Ordering::Relaxed))
+    }
+
+    /// Returns the end of the memory handed out so far
+    pub fn used_end(&self) -> PhysAddr {
+        PhysAddr(self.next.load(Ordering::Relaxed))
+    }
+
+    /// Returns whether `ptr` was handed out by this allocator
+    pub fn contains(&self, ptr: *mut u8) -> bool {
+        let addr = ptr as usize;
+        addr >= self.start.load(Ordering::Relaxed) && addr < self.next.load(Ordering::Relaxed)
+    }
+}
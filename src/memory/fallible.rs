@@ -0,0 +1,115 @@
+//! Allocation that reports failure instead of panicking
+//!
+//! `Box::new` and friends call the allocation error handler when memory
+//! runs out, which panics. Code that can get by with less, or without the
+//! memory at all, uses these instead and gets an [`AllocError`] back.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::Ordering;
+
+use super::page_allocator::{PageSize, PageStats};
+use super::{PhysAddr, ALLOCATOR, ALLOCATOR_MODE, MODE_EARLY, PAGE_ALLOCATOR};
+
+/// An allocation that failed
+#[derive(Debug, Clone, Copy)]
+pub struct AllocError {
+    /// What was asked for
+    pub layout: Layout,
+    /// The free memory when it failed, if the page allocator is in use
+    pub free: Option<PageStats>,
+}
+
+impl AllocError {
+    fn new(layout: Layout) -> Self {
+        let in_use = ALLOCATOR_MODE.load(Ordering::Acquire) != MODE_EARLY;
+        let free = in_use.then(|| PAGE_ALLOCATOR.stats());
+        Self { layout, free }
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "failed to allocate {} bytes aligned to {}",
+            self.layout.size(),
+            self.layout.align()
+        )?;
+        if let Some(free) = self.free {
+            write!(
+                f,
+                ", {} KB free in {} 4KB and {} 2MB pages",
+                free.free_bytes() / 1024,
+                free.free_4kb,
+                free.free_2mb
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Allocate memory for `layout` from the global allocator
+///
+/// Zero-sized layouts get a dangling pointer, like `Box` uses. Free the
+/// memory with `dealloc` and the same layout.
+pub fn try_alloc(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    if layout.size() == 0 {
+        return Ok(unsafe { NonNull::new_unchecked(layout.align() as *mut u8) });
+    }
+    NonNull::new(unsafe { ALLOCATOR.alloc(layout) }).ok_or_else(|| AllocError::new(layout))
+}
+
+/// Allocate `n` physically contiguous pages of the given size
+///
+/// The page allocator hands out single pages, so up to 512 4KB pages come
+/// from one 2MB page, and more than one 2MB page can't be allocated. Free
+/// the memory with `free_page` and the size of the page that was used.
+pub fn try_alloc_pages(n: usize, size: PageSize) -> Result<PhysAddr, AllocError> {
+    let layout = n
+        .checked_mul(size.bytes())
+        .and_then(|bytes| Layout::from_size_align(bytes, size.bytes()).ok())
+        .unwrap_or(Layout::new::<()>());
+    if n == 0 || ALLOCATOR_MODE.load(Ordering::Acquire) == MODE_EARLY {
+        return Err(AllocError::new(layout));
+    }
+
+    let page_size = match (n, size) {
+        (1, size) => size,
+        (_, PageSize::Size4KB) if layout.size() <= PageSize::Size2MB.bytes() => PageSize::Size2MB,
+        _ => return Err(AllocError::new(layout)),
+    };
+    PAGE_ALLOCATOR
+        .allocate_page(page_size)
+        .map(PhysAddr)
+        .ok_or_else(|| AllocError::new(layout))
+}
+
+/// Like `Box::new`, but fails instead of panicking
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let ptr = try_alloc(Layout::new::<T>())?.cast::<T>();
+    unsafe {
+        ptr.as_ptr().write(value);
+        Ok(Box::from_raw(ptr.as_ptr()))
+    }
+}
+
+/// Like `Vec::with_capacity`, but fails instead of panicking
+pub fn try_with_capacity<T>(capacity: usize) -> Result<Vec<T>, AllocError> {
+    let mut vec = Vec::new();
+    if vec.try_reserve_exact(capacity).is_err() {
+        let layout = Layout::array::<T>(capacity).unwrap_or(Layout::new::<()>());
+        return Err(AllocError::new(layout));
+    }
+    Ok(vec)
+}
+
+/// Like `vec![value; len]`, but fails instead of panicking
+pub fn try_vec<T: Clone>(value: T, len: usize) -> Result<Vec<T>, AllocError> {
+    let mut vec = try_with_capacity(len)?;
+    vec.resize(len, value);
+    Ok(vec)
+}
@@ -0,0 +1,116 @@
+//! Physical frame allocator interface
+//!
+//! The global allocator gets its memory through [`FrameAllocator`], so the
+//! backend can be swapped out, e.g. for [`TestFrameAllocator`] in tests.
+
+use super::page_allocator::{PageAllocator, PageSize};
+use super::PhysAddr;
+
+/// A source of physical page frames
+///
+/// # Safety
+/// Frames must be naturally aligned to their size, identity-mapped, and
+/// not handed out again until they are deallocated.
+pub unsafe trait FrameAllocator: Send + Sync {
+    /// Allocate a frame of the given size
+    fn allocate_frame(&self, size: PageSize) -> Option<PhysAddr>;
+
+    /// Return a frame from `allocate_frame` with the same size
+    fn deallocate_frame(&self, addr: PhysAddr, size: PageSize);
+
+    /// Check that `addr` is a frame of `size` that is allocated
+    fn verify_allocation(&self, addr: PhysAddr, size: PageSize) -> bool;
+}
+
+unsafe impl FrameAllocator for PageAllocator {
+    fn allocate_frame(&self, size: PageSize) -> Option<PhysAddr> {
+        self.allocate_page(size).map(PhysAddr)
+    }
+
+    fn deallocate_frame(&self, addr: PhysAddr, size: PageSize) {
+        self.free_page(addr.0, size);
+    }
+
+    fn verify_allocation(&self, addr: PhysAddr, size: PageSize) -> bool {
+        PageAllocator::verify_allocation(self, addr, size)
+    }
+}
+
+// The heap-debug red zones don't fit the tests that use it
+#[cfg(all(test, not(feature = "heap-debug")))]
+pub use test_allocator::TestFrameAllocator;
+
+#[cfg(all(test, not(feature = "heap-debug")))]
+mod test_allocator {
+    use alloc::vec::Vec;
+    use core::cell::UnsafeCell;
+
+    use super::{FrameAllocator, PageSize, PhysAddr};
+    use crate::sync::Mutex;
+
+    /// Size of the backing store
+    const STORE_SIZE: usize = 8 * 1024 * 1024;
+
+    #[repr(C, align(0x200000))]
+    struct Store(UnsafeCell<[u8; STORE_SIZE]>);
+
+    unsafe impl Sync for Store {}
+
+    static STORE: Store = Store(UnsafeCell::new([0; STORE_SIZE]));
+
+    /// A frame allocator backed by a static buffer that records every frame
+    ///
+    /// Freeing a frame that isn't allocated panics.
+    pub struct TestFrameAllocator {
+        /// Allocated `(addr, size)` frames
+        frames: Mutex<Vec<(usize, PageSize)>>,
+    }
+
+    impl TestFrameAllocator {
+        /// Creates an allocator
+        ///
+        /// All instances share the same backing store, so only one should
+        /// exist at a time.
+        pub const fn new() -> Self {
+            Self {
+                frames: Mutex::new(Vec::new()),
+            }
+        }
+
+        /// Returns the frames that are currently allocated
+        pub fn allocated(&self) -> Vec<(usize, PageSize)> {
+            self.frames.lock().clone()
+        }
+    }
+
+    unsafe impl FrameAllocator for TestFrameAllocator {
+        fn allocate_frame(&self, size: PageSize) -> Option<PhysAddr> {
+            let mut frames = self.frames.lock();
+            let start = STORE.0.get() as usize;
+
+            let addr = (start..start + STORE_SIZE)
+                .step_by(size.bytes())
+                .find(|&addr| {
+                    frames.iter().all(|&(other, other_size)| {
+                        addr + size.bytes() <= other || other + other_size.bytes() <= addr
+                    })
+                })?;
+
+            frames.push((addr, size));
+            Some(PhysAddr(addr))
+        }
+
+        fn deallocate_frame(&self, addr: PhysAddr, size: PageSize) {
+            let mut frames = self.frames.lock();
+            let index = frames
+                .iter()
+                .position(|&frame| frame == (addr.0, size))
+                .expect("Freed a frame that isn't allocated");
+            frames.swap_remove(index);
+        }
+
+        fn verify_allocation(&self, addr: PhysAddr, size: PageSize) -> bool {
+            self.frames.lock().contains(&(addr.0, size))
+        }
+    }
+}
@@ -0,0 +1,307 @@
+//! Red zones around heap allocations.
+//!
+//! With the `heap-debug` feature, every allocation from the global
+//! allocator is put in its frame with a header and a 16-byte canary on
+//! each side of the data:
+//!
+//! ```text
+//! | Header | padding | front canary | data | back canary | rest of the frame |
+//! ^ frame start                     ^ returned pointer
+//! ```
+//!
+//! The canaries are derived from the block's address and a value picked
+//! at boot, so stale data or a copy of another block doesn't pass for
+//! one. They're checked when the block is freed and by [`check_all`],
+//! which walks every live block. A mismatch panics with the block, the
+//! bytes that changed and the return addresses of the allocation.
+//!
+//! Without the feature none of this is compiled in.
+
+use core::alloc::Layout;
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::{self, null_mut};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::page_allocator::PageSize;
+use crate::cpu::tsc::rdtsc;
+use crate::debug::ksyms::Symbolized;
+use crate::debug::Backtrace;
+use crate::sync::Mutex;
+
+/// Size of each canary.
+pub const CANARY_LEN: usize = 16;
+
+/// Number of return addresses kept per block.
+pub const ORIGIN_FRAMES: usize = 4;
+
+/// Picked on first use, never zero after that.
+static SECRET: AtomicU64 = AtomicU64::new(0);
+
+/// Numbers the blocks in the order they were allocated.
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// The live blocks.
+static BLOCKS: Mutex<BlockList> = Mutex::new(BlockList { head: null_mut() });
+
+/// Kept at the start of the frame of each block.
+#[repr(C)]
+struct Header {
+    next: *mut Header,
+    prev: *mut Header,
+    /// The size that was asked for
+    size: usize,
+    /// Where the data starts, from the start of the frame
+    offset: usize,
+    /// From [`NEXT_SEQ`]
+    seq: u64,
+    /// Return addresses from the allocation, innermost first
+    origin: [u64; ORIGIN_FRAMES],
+}
+
+/// A doubly-linked list of the headers of live blocks.
+struct BlockList {
+    head: *mut Header,
+}
+
+unsafe impl Send for BlockList {}
+
+/// Where an allocation goes in its frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockLayout {
+    /// The frame to allocate
+    pub frame: PageSize,
+    /// Where the data starts, from the start of the frame
+    pub offset: usize,
+}
+
+impl BlockLayout {
+    /// Returns where `layout` goes, or `None` if it doesn't fit in a frame.
+    pub fn new(layout: Layout) -> Option<Self> {
+        let align = layout.align().max(CANARY_LEN);
+        let offset = (size_of::<Header>() + CANARY_LEN).next_multiple_of(align);
+        let needed = offset.checked_add(layout.size())?.checked_add(CANARY_LEN)?;
+
+        let frame = [PageSize::Size4KB, PageSize::Size2MB]
+            .into_iter()
+            .find(|frame| needed <= frame.bytes())?;
+        Some(Self { frame, offset })
+    }
+}
+
+/// A canary that didn't match.
+#[derive(Debug, Clone, Copy)]
+pub struct Corruption {
+    /// The start of the data
+    pub addr: usize,
+    pub size: usize,
+    /// Whether it's the canary after the data, i.e. an overflow
+    pub back: bool,
+    pub expected: [u8; CANARY_LEN],
+    pub found: [u8; CANARY_LEN],
+    pub origin: [u64; ORIGIN_FRAMES],
+}
+
+impl fmt::Display for Corruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, start) = match self.back {
+            true => ("overflow", self.addr + self.size),
+            false => ("underflow", self.addr - CANARY_LEN),
+        };
+        write!(f, "heap {} of the {}-byte block at {:#x}:", kind, self.size, self.addr)?;
+
+        for (i, (expected, found)) in self.expected.iter().zip(self.found).enumerate() {
+            if *expected != found {
+                write!(f, "\n  {:#x}: {:#04x}, expected {:#04x}", start + i, found, expected)?;
+            }
+        }
+
+        write!(f, "\nallocated from:")?;
+        for &addr in self.origin.iter().filter(|&&addr| addr != 0) {
+            write!(f, "\n  {:#018x} {}", addr, Symbolized::return_address(addr))?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the boot-time value the canaries are derived from.
+fn secret() -> u64 {
+    let secret = SECRET.load(Ordering::Relaxed);
+    if secret != 0 {
+        return secret;
+    }
+
+    // splitmix64 of the TSC, which differs from boot to boot
+    let mut z = rdtsc().wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    let candidate = (z ^ (z >> 31)) | 1;
+
+    match SECRET.compare_exchange(0, candidate, Ordering::Relaxed, Ordering::Relaxed) {
+        Ok(_) => candidate,
+        Err(existing) => existing,
+    }
+}
+
+/// Returns the canary for the block at `addr`.
+///
+/// The two sides differ, so one can't be copied over the other.
+fn canary(addr: usize, back: bool) -> [u8; CANARY_LEN] {
+    let seed = (addr as u64 ^ secret()).rotate_left(if back { 17 } else { 0 });
+    let mut bytes = [0; CANARY_LEN];
+    bytes[..8].copy_from_slice(&seed.to_le_bytes());
+    bytes[8..].copy_from_slice(&(!seed).rotate_left(29).to_le_bytes());
+    bytes
+}
+
+/// Returns the canaries' addresses for the block at `addr`.
+fn canary_addrs(addr: usize, size: usize) -> [(usize, bool); 2] {
+    [(addr - CANARY_LEN, false), (addr + size, true)]
+}
+
+/// Sets up a block in a freshly allocated frame and returns its data.
+///
+/// # Safety
+/// `frame` must be an unused frame of `block.frame`'s size.
+pub unsafe fn track(frame: *mut u8, layout: Layout, block: BlockLayout) -> *mut u8 {
+    let addr = frame as usize + block.offset;
+
+    // Skip the allocator's own frame
+    let backtrace = Backtrace::capture_up_to(ORIGIN_FRAMES + 1);
+    let mut origin = [0; ORIGIN_FRAMES];
+    for (slot, &frame) in origin.iter_mut().zip(backtrace.frames().iter().skip(1)) {
+        *slot = frame;
+    }
+
+    let header = frame as *mut Header;
+    unsafe {
+        for (canary_addr, back) in canary_addrs(addr, layout.size()) {
+            ptr::write(canary_addr as *mut [u8; CANARY_LEN], canary(addr, back));
+        }
+
+        let mut blocks = BLOCKS.lock();
+        header.write(Header {
+            next: blocks.head,
+            prev: null_mut(),
+            size: layout.size(),
+            offset: block.offset,
+            seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+            origin,
+        });
+        if let Some(next) = blocks.head.as_mut() {
+            next.prev = header;
+        }
+        blocks.head = header;
+    }
+    addr as *mut u8
+}
+
+/// Checks a block that's being freed and forgets it.
+///
+/// Returns the start of its frame. Panics if a canary changed.
+///
+/// # Safety
+/// `ptr` must come from [`track`] with the same layout.
+pub unsafe fn untrack(ptr: *mut u8, layout: Layout, block: BlockLayout) -> *mut u8 {
+    let frame = ptr as usize - block.offset;
+    let header = frame as *mut Header;
+
+    if let Err(corruption) = unsafe { check_block(&*header, ptr as usize) } {
+        panic!("{}", corruption);
+    }
+    crate::kassert_eq!(unsafe { (*header).size }, layout.size(), "freeing the block at {:p}", ptr);
+
+    unsafe {
+        let mut blocks = BLOCKS.lock();
+        let Header { next, prev, .. } = header.read();
+        match prev.as_mut() {
+            Some(prev) => prev.next = next,
+            None => blocks.head = next,
+        }
+        if let Some(next) = next.as_mut() {
+            next.prev = prev;
+        }
+    }
+    frame as *mut u8
+}
+
+/// Compares the canaries of the block at `addr` with what they should be.
+unsafe fn check_block(header: &Header, addr: usize) -> Result<(), Corruption> {
+    for (canary_addr, back) in canary_addrs(addr, header.size) {
+        let expected = canary(addr, back);
+        let found = unsafe { ptr::read_volatile(canary_addr as *const [u8; CANARY_LEN]) };
+        if found != expected {
+            return Err(Corruption {
+                addr,
+                size: header.size,
+                back,
+                expected,
+                found,
+                origin: header.origin,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Checks the canaries of one block from the global allocator.
+///
+/// # Safety
+/// `ptr` must be a live allocation from the global allocator, made after
+/// it switched to the page allocator.
+#[allow(dead_code)] // Called by hand while chasing a corruption
+pub unsafe fn check(ptr: *const u8) -> Result<(), Corruption> {
+    let blocks = BLOCKS.lock();
+    let mut header = blocks.head;
+    while let Some(h) = unsafe { header.as_ref() } {
+        let addr = header as usize + h.offset;
+        if addr == ptr as usize {
+            return unsafe { check_block(h, addr) };
+        }
+        header = h.next;
+    }
+    panic!("{:p} is not a live heap block", ptr);
+}
+
+/// Checks every live block, returning how many there are.
+pub fn try_check_all() -> Result<usize, Corruption> {
+    let blocks = BLOCKS.lock();
+    let mut header = blocks.head;
+    let mut count = 0;
+    while let Some(h) = unsafe { header.as_ref() } {
+        unsafe { check_block(h, header as usize + h.offset)? };
+        count += 1;
+        header = h.next;
+    }
+    Ok(count)
+}
+
+/// Checks every live block, and panics with the first corrupted one.
+#[allow(dead_code)] // Called by hand while chasing a corruption
+pub fn check_all() -> usize {
+    match try_check_all() {
+        Ok(count) => count,
+        Err(corruption) => panic!("{}", corruption),
+    }
+}
+
+/// Returns the number the next block will get.
+pub fn next_seq() -> u64 {
+    NEXT_SEQ.load(Ordering::Relaxed)
+}
+
+/// Calls `f` with the origin of every live block numbered `seq` or later.
+///
+/// `f` runs with the block list locked, so it mustn't allocate.
+pub fn for_each_since(seq: u64, mut f: impl FnMut(&[u64; ORIGIN_FRAMES])) {
+    let blocks = BLOCKS.lock();
+    let mut header = blocks.head;
+    while let Some(h) = unsafe { header.as_ref() } {
+        // Blocks are added at the head, so the rest are older
+        if h.seq < seq {
+            break;
+        }
+        f(&h.origin);
+        header = h.next;
+    }
+}
@@ -0,0 +1,150 @@
+//! Checking that memory is given back.
+//!
+//! [`snapshot`] reads how many pages of each size are allocated, which the
+//! page allocator keeps count of anyway, and [`compare`] reports how that
+//! changed between two snapshots. The test runner takes one before and
+//! after each test, and fails a test that leaves pages allocated:
+//!
+//! ```text
+//! +1 4KB and +0 2MB pages still allocated
+//!   1 heap blocks allocated from:
+//!     0xffffffff8012a3c4 hello_os::memory::SimpleAllocator::alloc+0x54
+//!     ...
+//! ```
+//!
+//! With the `heap-debug` feature heap blocks remember where they were
+//! allocated, so the report also lists the call sites of the blocks that
+//! are still live, most blocks first. Without it only the counts are
+//! known.
+
+use core::fmt;
+
+use super::page_allocator::PageSize;
+use super::PAGE_ALLOCATOR;
+#[cfg(feature = "heap-debug")]
+use super::heap::{self, ORIGIN_FRAMES};
+#[cfg(feature = "heap-debug")]
+use crate::debug::ksyms::Symbolized;
+
+/// Call sites kept in a report.
+#[cfg(feature = "heap-debug")]
+pub const MAX_SITES: usize = 8;
+
+/// Tests that are expected to leave memory allocated.
+///
+/// None do yet. A test that sets up something meant to last until the
+/// next reboot goes here rather than freeing it.
+#[cfg(test)]
+pub const ALLOWED_LEAKS: &[&str] = &[];
+
+/// The memory in use at some point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub allocated_4kb: usize,
+    pub allocated_2mb: usize,
+    /// The first heap block allocated after the snapshot
+    #[cfg(feature = "heap-debug")]
+    heap_seq: u64,
+}
+
+/// Heap blocks that were allocated in the same place.
+#[cfg(feature = "heap-debug")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeakSite {
+    /// Return addresses from the allocation, innermost first
+    pub origin: [u64; ORIGIN_FRAMES],
+    pub blocks: usize,
+}
+
+/// How the memory in use changed between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeakReport {
+    /// Pages allocated and not freed, negative if more were freed
+    pub delta_4kb: isize,
+    pub delta_2mb: isize,
+    /// Where the heap blocks still live were allocated, most first
+    #[cfg(feature = "heap-debug")]
+    pub sites: [Option<LeakSite>; MAX_SITES],
+    /// Blocks from sites that didn't fit in `sites`
+    #[cfg(feature = "heap-debug")]
+    pub other_blocks: usize,
+}
+
+impl LeakReport {
+    /// Returns whether the page counts are back where they were.
+    pub fn is_balanced(&self) -> bool {
+        self.delta_4kb == 0 && self.delta_2mb == 0
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:+} 4KB and {:+} 2MB pages still allocated",
+            self.delta_4kb, self.delta_2mb
+        )?;
+
+        #[cfg(feature = "heap-debug")]
+        {
+            for site in self.sites.iter().flatten() {
+                write!(f, "\n  {} heap blocks allocated from:", site.blocks)?;
+                for &addr in site.origin.iter().filter(|&&addr| addr != 0) {
+                    write!(f, "\n    {:#018x} {}", addr, Symbolized::return_address(addr))?;
+                }
+            }
+            if self.other_blocks > 0 {
+                write!(f, "\n  {} heap blocks allocated elsewhere", self.other_blocks)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the memory in use now.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        allocated_4kb: PAGE_ALLOCATOR.allocated_pages(PageSize::Size4KB),
+        allocated_2mb: PAGE_ALLOCATOR.allocated_pages(PageSize::Size2MB),
+        #[cfg(feature = "heap-debug")]
+        heap_seq: heap::next_seq(),
+    }
+}
+
+/// Reports what changed from `before` to `after`.
+///
+/// With `heap-debug`, the call sites are those of the blocks allocated
+/// after `before` that are still live now, so `after` should be recent.
+pub fn compare(before: &Snapshot, after: &Snapshot) -> LeakReport {
+    #[cfg(feature = "heap-debug")]
+    let (sites, other_blocks) = heap_sites(before.heap_seq);
+
+    LeakReport {
+        delta_4kb: after.allocated_4kb as isize - before.allocated_4kb as isize,
+        delta_2mb: after.allocated_2mb as isize - before.allocated_2mb as isize,
+        #[cfg(feature = "heap-debug")]
+        sites,
+        #[cfg(feature = "heap-debug")]
+        other_blocks,
+    }
+}
+
+/// Groups the live heap blocks from `seq` on by call site.
+///
+/// Counted in place, since allocating would need the heap lock.
+#[cfg(feature = "heap-debug")]
+fn heap_sites(seq: u64) -> ([Option<LeakSite>; MAX_SITES], usize) {
+    let mut sites = [None; MAX_SITES];
+    let mut other_blocks = 0;
+    heap::for_each_since(seq, |origin| {
+        if let Some(site) = sites.iter_mut().flatten().find(|site: &&mut LeakSite| site.origin == *origin) {
+            site.blocks += 1;
+        } else if let Some(slot) = sites.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(LeakSite { origin: *origin, blocks: 1 });
+        } else {
+            other_blocks += 1;
+        }
+    });
+    sites.sort_unstable_by_key(|site| core::cmp::Reverse(site.map_or(0, |site| site.blocks)));
+    (sites, other_blocks)
+}
@@ -0,0 +1,75 @@
+//! Allocator for pages below 1MB
+//!
+//! Application processors start in real mode, so their trampoline code and
+//! the data it uses must be below 1MB. The page allocator never hands out
+//! memory below the kernel, so low pages are tracked separately here.
+
+use super::multiboot2::{MemoryMapTag, MEMORY_AREA_TYPE_AVAILABLE};
+use super::page_allocator::PageSize;
+use super::paging::NULL_GUARD_SIZE;
+use super::PhysAddr;
+use crate::sync::Mutex;
+
+/// Memory below this is reachable from real mode
+pub const LOW_MEMORY_END: usize = 0x100000;
+
+const LOW_PAGES: usize = LOW_MEMORY_END / 4096;
+
+/// Free low pages, one bit per 4KB page
+pub struct LowMemory {
+    free: Mutex<[u64; LOW_PAGES / 64]>,
+}
+
+impl LowMemory {
+    pub const fn new() -> Self {
+        Self {
+            free: Mutex::new([0; LOW_PAGES / 64]),
+        }
+    }
+
+    /// Add the available pages below 1MB from the memory map
+    ///
+    /// The first pages hold the real-mode IVT and BIOS data and are
+    /// unmapped later to catch null pointers, and `exclude` is still in
+    /// use, so they are skipped.
+    pub fn init(&self, mmap: &MemoryMapTag, exclude: (PhysAddr, usize)) {
+        let mut free = self.free.lock();
+        let exclude_start = exclude.0 .0;
+        let exclude_end = exclude_start + exclude.1;
+
+        for area in mmap.memory_areas() {
+            if area.typ != MEMORY_AREA_TYPE_AVAILABLE {
+                continue;
+            }
+
+            // Only whole pages inside the area
+            let start = PageSize::Size4KB.align_up(PhysAddr(area.base_addr as usize));
+            let end = PageSize::Size4KB.align_down(PhysAddr((area.base_addr + area.length) as usize));
+            let mut addr = start.0.max(NULL_GUARD_SIZE);
+            while addr < end.0 && addr < LOW_MEMORY_END {
+                let overlaps = addr < exclude_end && exclude_start < addr + PageSize::Size4KB.bytes();
+                if !overlaps {
+                    let page = addr / PageSize::Size4KB.bytes();
+                    free[page / 64] |= 1 << (page % 64);
+                }
+                addr += PageSize::Size4KB.bytes();
+            }
+        }
+    }
+
+    /// Allocate a 4KB page below 1MB
+    pub fn alloc(&self) -> Option<PhysAddr> {
+        let mut free = self.free.lock();
+        let (index, word) = free.iter_mut().enumerate().find(|(_, word)| **word != 0)?;
+        let bit = word.trailing_zeros() as usize;
+        *word &= !(1 << bit);
+        Some(PhysAddr((index * 64 + bit) * PageSize::Size4KB.bytes()))
+    }
+
+    /// Return a page from `alloc`
+    pub fn free(&self, addr: PhysAddr) {
+        assert!(addr.0 < LOW_MEMORY_END && addr.is_page_aligned(PageSize::Size4KB));
+        let page = addr.0 / PageSize::Size4KB.bytes();
+        self.free.lock()[page / 64] |= 1 << (page % 64);
+    }
+}
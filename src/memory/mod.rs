@@ -1,33 +1,264 @@
 //! Memory allocator with 4KB and 2MB page support
 
+pub mod bump;
+pub mod fallible;
+pub mod frame_allocator;
+#[cfg(feature = "heap-debug")]
+pub mod heap;
+pub mod leak_check;
+pub mod low;
 pub mod multiboot2;
 pub mod page_allocator;
-pub mod mutex;
-pub mod test;
+pub mod paging;
+pub mod probe;
+pub mod rwlock;
+mod selftest;
+#[cfg(test)]
+mod test;
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
 
+use crate::boot::stage::BootArgs;
+use crate::debug::TimedLog;
+use crate::error::{MemError, Result, ResultExt};
+use crate::platform::MemorySource;
+use bump::BumpAllocator;
+use frame_allocator::FrameAllocator;
+use low::LowMemory;
+use multiboot2::MEMORY_AREA_TYPE_AVAILABLE;
 use page_allocator::{PageAllocator, PageSize};
 
+/// A physical address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct PhysAddr(pub usize);
+
+impl PhysAddr {
+    /// Returns whether the address is aligned to a page of the given size.
+    pub fn is_page_aligned(&self, size: PageSize) -> bool {
+        self.0 % size.bytes() == 0
+    }
+
+    /// Returns the address `offset` bytes above this one.
+    pub fn add(&self, offset: usize) -> PhysAddr {
+        PhysAddr(self.0 + offset)
+    }
+
+    /// Returns the address `offset` bytes above this one, or `None` on overflow.
+    #[allow(dead_code)] // Only the test uses it so far
+    pub fn checked_add(&self, offset: usize) -> Option<PhysAddr> {
+        self.0.checked_add(offset).map(PhysAddr)
+    }
+}
+
 /// The global page allocator instance
 static PAGE_ALLOCATOR: PageAllocator = PageAllocator::new();
 
+/// Free pages below 1MB
+static LOW_MEMORY: LowMemory = LowMemory::new();
+
+/// The allocator used until the page allocator is up
+static EARLY_ALLOC: BumpAllocator = BumpAllocator::new();
+
+/// Allocations come from `EARLY_ALLOC`
+const MODE_EARLY: u8 = 0;
+/// Allocations come from `PAGE_ALLOCATOR`
+const MODE_PAGE: u8 = 1;
+
+/// Which allocator backs the global allocator
+static ALLOCATOR_MODE: AtomicU8 = AtomicU8::new(MODE_EARLY);
+
+/// The kernel runs on the bootloader's identity map, where VA == PA
+const MAPPING_IDENTITY: u8 = 0;
+/// The kernel runs in the upper half, `KERNEL_PHYS_OFFSET` above its
+/// physical address
+const MAPPING_HIGH_HALF: u8 = 1;
+
+/// How the kernel image is mapped
+static MAPPING_MODE: AtomicU8 = AtomicU8::new(MAPPING_IDENTITY);
+
+/// Virtual minus physical address of the kernel in `MAPPING_HIGH_HALF`
+static KERNEL_PHYS_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Address of the multiboot information, set by `init`
+static BOOT_INFO_ADDR: AtomicUsize = AtomicUsize::new(0);
+
 /// Initialize the memory subsystem
 /// 
 /// # Safety
 /// Must be called exactly once during kernel initialization
-pub unsafe fn init(multiboot_info_addr: usize) {
+pub unsafe fn init(source: MemorySource) -> Result<()> {
+    match source {
+        MemorySource::Multiboot2(addr) => init_multiboot2(addr),
+        MemorySource::DeviceTree(_) => Err(MemError::Unsupported("device tree memory maps").into()),
+    }
+    .context("initializing memory")
+}
+
+/// The `memory` boot stage: sets up the allocators, then lets panics be
+/// kept across reboots in memory they no longer cover.
+pub fn init_stage(args: &BootArgs) -> Result<()> {
+    unsafe { init(MemorySource::Multiboot2(args.boot_info_addr))? };
+    crate::debug::oops::arm();
+    Ok(())
+}
+
+/// The `modules` boot stage: logs what the boot modules contain.
+///
+/// Nothing uses the modules yet. An ELF file would be a symbol table and a
+/// gzip file an initrd, but the point for now is to report files that are
+/// neither instead of treating them as one.
+pub fn modules_stage(_args: &BootArgs) -> Result<()> {
+    use multiboot2::ModuleKind;
+
+    for module in crate::boot::stage::boot_info().into_iter().flat_map(|boot_info| boot_info.modules()) {
+        match module.kind() {
+            ModuleKind::Elf64 => {
+                log::info!("Module {:?}: ELF, {} bytes", module.cmdline(), module.size())
+            }
+            ModuleKind::Gzip => {
+                log::info!("Module {:?}: gzip, {} bytes", module.cmdline(), module.size())
+            }
+            ModuleKind::Unknown => {
+                log::warn!("Module {:?}: unknown format, ignoring it", module.cmdline())
+            }
+        }
+    }
+    Ok(())
+}
+
+unsafe fn init_multiboot2(multiboot_info_addr: usize) -> Result<()> {
+    let timer = TimedLog::new();
+
     // Parse multiboot information
     let boot_info = multiboot2::BootInfo::parse(multiboot_info_addr as *const u8)
-        .expect("Failed to parse multiboot info");
+        .context("parsing multiboot info")?;
+    timer.checkpoint("Parsed multiboot info");
     
     // Find the memory map tag
-    let mmap_tag = boot_info.memory_map_tag()
-        .expect("No memory map found in multiboot info");
+    let mmap_tag = boot_info.memory_map_tag().context("reading the memory map")?;
+    mmap_tag.validate().context("validating the memory map")?;
+    // Only boot information we can use replaces the one from the bootloader
+    BOOT_INFO_ADDR.store(multiboot_info_addr, Ordering::Relaxed);
+    log::info!(
+        "Memory map: {} entries, {} MB available",
+        mmap_tag.count_entries(),
+        mmap_tag.count_available_bytes() / (1024 * 1024)
+    );
+
+    // Early allocations go right after the kernel, in the same memory area
+    let start = PageSize::Size4KB.align_up(kernel_end());
+    let area = mmap_tag.memory_areas()
+        .find(|area| {
+            area.typ == MEMORY_AREA_TYPE_AVAILABLE
+                && area.base_addr <= start.0 as u64
+                && (start.0 as u64) < area.base_addr + area.length
+        })
+        .ok_or(MemError::KernelNotInAvailableMemory)?;
+    EARLY_ALLOC.init(start, PhysAddr((area.base_addr + area.length) as usize));
     
     // Initialize the page allocator
-    PAGE_ALLOCATOR.init(mmap_tag);
+    PAGE_ALLOCATOR
+        .init(mmap_tag, &EARLY_ALLOC, &timer)
+        .context("setting up the page allocator")?;
+    switch_to_page_allocator();
+
+    // The boot information may be in low memory, and we keep using it
+    LOW_MEMORY.init(mmap_tag, (PhysAddr(multiboot_info_addr), boot_info.total_size()));
+
+    Ok(())
+}
+
+/// Make the global allocator use the page allocator
+///
+/// Memory from the early allocator stays in use forever.
+pub fn switch_to_page_allocator() {
+    ALLOCATOR_MODE.store(MODE_PAGE, Ordering::Release);
+}
+
+/// Returns the end of the kernel image and the early boot memory after it
+///
+/// Only meaningful once `init` has run.
+pub fn early_memory_end() -> PhysAddr {
+    EARLY_ALLOC.used_end()
+}
+
+/// Unmap the bottom of the address space to catch null pointers
+///
+/// Returns how much was unmapped: [`paging::NULL_GUARD_SIZE`], or less
+/// if the boot information is in the way. This must be called before
+/// other CPUs start, and after the last read of the BIOS data area.
+pub fn guard_null_pages() -> Result<usize> {
+    let size = match BOOT_INFO_ADDR.load(Ordering::Relaxed) {
+        0 => paging::NULL_GUARD_SIZE,
+        addr => PageSize::Size4KB
+            .align_down(PhysAddr(addr))
+            .0
+            .clamp(PageSize::Size4KB.bytes(), paging::NULL_GUARD_SIZE),
+    };
+    unsafe { paging::unmap_null_guard(paging::current_pml4(), size)? };
+    Ok(size)
+}
+
+/// Allocate a 4KB page below 1MB, for code and data used in real mode
+#[allow(dead_code)] // For the AP trampoline
+pub fn alloc_low_page() -> Option<PhysAddr> {
+    LOW_MEMORY.alloc()
+}
+
+/// Free a page from `alloc_low_page`
+#[allow(dead_code)] // For the AP trampoline
+pub fn free_low_page(addr: PhysAddr) {
+    LOW_MEMORY.free(addr);
+}
+
+/// The `null guard` boot stage.
+pub fn null_guard_stage(_args: &BootArgs) -> Result<()> {
+    guard_null_pages().map(|_| ())
+}
+
+/// Translate a kernel virtual address before the page tables can be walked
+///
+/// On the identity map this is `virt` itself. Once the kernel runs in the
+/// upper half, it's `virt` minus the offset of the kernel image, so it's
+/// only valid for addresses inside the image. Returns `None` for an
+/// address below the offset, which can't be in the image.
+pub fn virt_to_phys_early(virt: usize) -> Option<usize> {
+    virt.checked_sub(kernel_phys_offset() as usize)
+}
+
+/// Translate a virtual address with the current page tables
+#[allow(dead_code)] // Nothing translates after boot yet
+pub fn virt_to_phys(virt: usize) -> Option<PhysAddr> {
+    paging::page_table_walk(virt as u64).map(|(phys, _)| phys)
+}
+
+/// Returns how far above its physical address the kernel is mapped
+///
+/// This is 0 on the identity map, and the slide of the kernel image once
+/// it's relocated to the upper half.
+pub fn kernel_phys_offset() -> u64 {
+    match MAPPING_MODE.load(Ordering::Acquire) {
+        MAPPING_HIGH_HALF => KERNEL_PHYS_OFFSET.load(Ordering::Relaxed),
+        _ => 0,
+    }
+}
+
+/// Returns the end of the kernel image
+fn kernel_end() -> PhysAddr {
+    extern "C" {
+        static __end: u8;
+    }
+    let end = unsafe { &__end as *const u8 as usize };
+    PhysAddr(virt_to_phys_early(end).expect("kernel image below its mapping offset"))
+}
+
+/// Get the multiboot information passed by the bootloader
+pub fn boot_info() -> Option<&'static multiboot2::BootInfo> {
+    let addr = BOOT_INFO_ADDR.load(Ordering::Relaxed);
+    unsafe { multiboot2::BootInfo::parse(addr as *const u8).ok() }
 }
 
 /// Get a reference to the global page allocator
@@ -37,56 +268,106 @@ pub fn get_allocator() -> &'static PageAllocator {
 
 /// Simple global allocator that wastes a full 4KB page per allocation
 /// This matches the assignment specification
-pub struct SimpleAllocator;
+///
+/// Until `switch_to_page_allocator` is called, memory comes from the
+/// early bump allocator instead.
+pub struct SimpleAllocator {
+    frames: &'static dyn FrameAllocator,
+}
 
-unsafe impl GlobalAlloc for SimpleAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // As per assignment: "waste an entire 4KB page on an object that is smaller than a page"
-        if layout.size() == 0 {
-            return null_mut();
+impl SimpleAllocator {
+    /// Create an allocator that gets its pages from `frames`
+    pub const fn new(frames: &'static dyn FrameAllocator) -> Self {
+        Self { frames }
+    }
+
+    fn allocate(&self, size: PageSize) -> *mut u8 {
+        match self.frames.allocate_frame(size) {
+            Some(addr) => addr.0 as *mut u8,
+            None => null_mut(),
         }
-        
+    }
+
+    /// Allocate the frame for `layout`
+    #[cfg(not(feature = "heap-debug"))]
+    fn alloc_block(&self, layout: Layout) -> *mut u8 {
         // For allocations up to 4KB, allocate a 4KB page
-        if layout.size() <= 4096 {
-            match PAGE_ALLOCATOR.allocate_page(PageSize::Size4KB) {
-                Some(addr) => addr as *mut u8,
-                None => null_mut(),
-            }
+        if layout.size() <= PageSize::Size4KB.bytes() {
+            self.allocate(PageSize::Size4KB)
         } 
         // For allocations larger than 4KB but up to 2MB
-        else if layout.size() <= 2 * 1024 * 1024 {
+        else if layout.size() <= PageSize::Size2MB.bytes() {
             // For simplicity, just allocate a 2MB page if we need multiple 4KB pages
             // This wastes memory but avoids complexity of tracking contiguous allocation
-            match PAGE_ALLOCATOR.allocate_page(PageSize::Size2MB) {
-                Some(addr) => addr as *mut u8,
-                None => null_mut(),
-            }
+            self.allocate(PageSize::Size2MB)
         }
-        // For 2MB+ allocations
+        // Larger allocations would need contiguous 2MB pages
         else {
-            match PAGE_ALLOCATOR.allocate_page(PageSize::Size2MB) {
-                Some(addr) => addr as *mut u8,
-                None => null_mut(),
-            }
+            null_mut()
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    /// Allocate a frame with red zones around `layout`, see `heap`
+    #[cfg(feature = "heap-debug")]
+    fn alloc_block(&self, layout: Layout) -> *mut u8 {
+        let Some(block) = heap::BlockLayout::new(layout) else {
+            return null_mut();
+        };
+        let frame = self.allocate(block.frame);
+        if frame.is_null() {
+            return null_mut();
+        }
+        unsafe { heap::track(frame, layout, block) }
+    }
+}
+
+unsafe impl GlobalAlloc for SimpleAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if ALLOCATOR_MODE.load(Ordering::Acquire) == MODE_EARLY {
+            return EARLY_ALLOC.alloc(layout);
+        }
+
+        // As per assignment: "waste an entire 4KB page on an object that is smaller than a page"
         if layout.size() == 0 {
+            return null_mut();
+        }
+
+        self.alloc_block(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Early boot memory is never freed
+        if layout.size() == 0 || EARLY_ALLOC.contains(ptr) {
             return;
         }
         
-        let addr = ptr as usize;
-        
-        // Match the allocation strategy
-        if layout.size() <= 4096 {
-            PAGE_ALLOCATOR.free_page(addr, PageSize::Size4KB);
-        } else {
-            // We allocated a 2MB page for anything > 4KB
-            PAGE_ALLOCATOR.free_page(addr, PageSize::Size2MB);
+        #[cfg(not(feature = "heap-debug"))]
+        let (addr, size) = {
+            // Match the allocation strategy: we allocated a 2MB page for
+            // anything > 4KB
+            let size = if layout.size() <= PageSize::Size4KB.bytes() {
+                PageSize::Size4KB
+            } else {
+                PageSize::Size2MB
+            };
+            (ptr as usize, size)
+        };
+
+        #[cfg(feature = "heap-debug")]
+        let (addr, size) = {
+            // `alloc` succeeded, so the layout fits
+            let block = heap::BlockLayout::new(layout).unwrap();
+            (unsafe { heap::untrack(ptr, layout, block) } as usize, block.frame)
+        };
+
+        #[cfg(debug_assertions)]
+        if !self.frames.verify_allocation(PhysAddr(addr), size) {
+            panic!("dealloc of unallocated page {:#x} ({:?})", addr, layout);
         }
+
+        self.frames.deallocate_frame(PhysAddr(addr), size);
     }
 }
 
 #[global_allocator]
-pub static ALLOCATOR: SimpleAllocator = SimpleAllocator;
+pub static ALLOCATOR: SimpleAllocator = SimpleAllocator::new(&PAGE_ALLOCATOR);
@@ -1,10 +1,28 @@
 //! Multiboot2 boot information parser
 
+use core::fmt;
 use core::mem;
 use core::slice;
 
+use crate::error::{MemError, MultibootError};
+
 const MULTIBOOT2_TAG_TYPE_END: u32 = 0;
+const MULTIBOOT2_TAG_TYPE_CMDLINE: u32 = 1;
+const MULTIBOOT2_TAG_TYPE_MODULE: u32 = 3;
 const MULTIBOOT2_TAG_TYPE_MMAP: u32 = 6;
+const MULTIBOOT2_TAG_TYPE_FRAMEBUFFER: u32 = 8;
+const MULTIBOOT2_TAG_TYPE_ELF_SECTIONS: u32 = 9;
+const MULTIBOOT2_TAG_TYPE_ACPI_OLD: u32 = 14;
+const MULTIBOOT2_TAG_TYPE_ACPI_NEW: u32 = 15;
+
+/// What a multiboot2 bootloader leaves in EAX
+pub const BOOTLOADER_MAGIC: u32 = 0x36d76289;
+
+/// Memory area type for usable RAM
+pub const MEMORY_AREA_TYPE_AVAILABLE: u32 = 1;
+
+/// The least amount of available memory we can boot with
+const MIN_AVAILABLE_MEMORY: u64 = 4 * 1024 * 1024;
 
 /// Boot information structure passed by GRUB
 #[repr(C)]
@@ -15,40 +33,118 @@ pub struct BootInfo {
 
 impl BootInfo {
     /// Parse the boot information structure
-    /// 
+    ///
+    /// Checks that the tags fit in `total_size` and end with an end tag,
+    /// so later lookups can't run off the end.
+    ///
     /// # Safety
-    /// The pointer must point to valid multiboot2 data
-    pub unsafe fn parse(ptr: *const u8) -> Option<&'static Self> {
+    /// The pointer must be null or point to `total_size` readable bytes
+    pub unsafe fn parse(ptr: *const u8) -> Result<&'static Self, MultibootError> {
         if ptr.is_null() {
-            return None;
+            return Err(MultibootError::NullPointer);
+        }
+        if ptr as usize % 8 != 0 {
+            return Err(MultibootError::BadAlignment(ptr as usize));
+        }
+
+        let boot_info = &*(ptr as *const BootInfo);
+        // Room for the header and the end tag at least
+        let min_size = mem::size_of::<BootInfo>() + mem::size_of::<TagHeader>();
+        if boot_info.total_size() < min_size {
+            return Err(MultibootError::BadTotalSize(boot_info.total_size));
+        }
+        boot_info.check_tags()?;
+        Ok(boot_info)
+    }
+
+    /// Check that every tag fits and that there's an end tag
+    fn check_tags(&self) -> Result<(), MultibootError> {
+        let start = self as *const BootInfo as usize;
+        let header_size = mem::size_of::<TagHeader>();
+        let mut offset = mem::size_of::<BootInfo>();
+
+        while offset + header_size <= self.total_size() {
+            let header = unsafe { &*((start + offset) as *const TagHeader) };
+            let size = header.size();
+            if size < header_size || offset + size > self.total_size() {
+                return Err(MultibootError::TagOverrun {
+                    offset,
+                    size: size as u32,
+                });
+            }
+            if header.typ == MULTIBOOT2_TAG_TYPE_END {
+                return Ok(());
+            }
+            // Tags are 8-byte aligned
+            offset = (offset + size + 7) & !7;
         }
-        Some(&*(ptr as *const BootInfo))
+        Err(MultibootError::MissingTag(MULTIBOOT2_TAG_TYPE_END))
+    }
+
+    /// Size of the boot information in bytes
+    pub fn total_size(&self) -> usize {
+        self.total_size as usize
+    }
+
+    /// Get the kernel command line
+    pub fn command_line(&self) -> Result<&str, MultibootError> {
+        self.find_tag::<CommandLineTag>(MULTIBOOT2_TAG_TYPE_CMDLINE)?
+            .command_line()
     }
 
     /// Get the memory map tag
-    pub fn memory_map_tag(&self) -> Option<&MemoryMapTag> {
+    pub fn memory_map_tag(&self) -> Result<&MemoryMapTag, MultibootError> {
         self.find_tag(MULTIBOOT2_TAG_TYPE_MMAP)
     }
 
-    /// Find a tag by type
-    fn find_tag<T>(&self, tag_type: u32) -> Option<&T> {
-        let self_ptr = self as *const BootInfo as usize;
-        let mut current = self_ptr + 8; // Skip total_size and reserved
+    /// Get the framebuffer tag
+    pub fn framebuffer_tag(&self) -> Result<&FramebufferTag, MultibootError> {
+        self.find_tag(MULTIBOOT2_TAG_TYPE_FRAMEBUFFER)
+    }
 
-        loop {
-            let tag = unsafe { &*(current as *const TagHeader) };
+    /// Get the bootloader's copy of the ACPI RSDP, preferring the ACPI 2.0
+    /// one
+    ///
+    /// The copy isn't checked.
+    pub fn rsdp(&self) -> Option<&[u8]> {
+        let find = |typ| self.tags().find(|tag| tag.typ == typ).map(|tag| tag.payload());
+        find(MULTIBOOT2_TAG_TYPE_ACPI_NEW).or_else(|| find(MULTIBOOT2_TAG_TYPE_ACPI_OLD))
+    }
 
-            if tag.typ == MULTIBOOT2_TAG_TYPE_END {
-                return None;
-            }
+    /// Get an iterator over the modules loaded by the bootloader
+    pub fn modules(&self) -> impl Iterator<Item = ModuleEntry<'_>> {
+        self.tags().filter_map(|tag| ModuleEntry::from_tag(&tag))
+    }
 
-            if tag.typ == tag_type {
-                return Some(unsafe { &*(current as *const T) });
-            }
+    /// Get an iterator over the raw tags, up to the end tag
+    ///
+    /// Stops early at a tag that doesn't fit in `total_size`, so a corrupt
+    /// tag stream can't send us off into the rest of memory.
+    pub fn tags(&self) -> TagIter<'_> {
+        let start = self as *const BootInfo as usize;
+        TagIter {
+            start,
+            current: start + mem::size_of::<BootInfo>(),
+            end: start + self.total_size(),
+            _boot_info: self,
+        }
+    }
 
-            // Move to next tag (8-byte aligned)
-            current = (current + tag.size as usize + 7) & !7;
+    /// Find a tag by type, and check it's big enough for a `T`
+    fn find_tag<T>(&self, tag_type: u32) -> Result<&T, MultibootError> {
+        let tag = self
+            .tags()
+            .find(|tag| tag.typ == tag_type)
+            .ok_or(MultibootError::MissingTag(tag_type))?;
+        if (tag.size as usize) < mem::size_of::<T>() {
+            return Err(MultibootError::TagOverrun {
+                offset: tag.offset,
+                size: tag.size,
+            });
         }
+
+        let addr = self as *const BootInfo as usize + tag.offset;
+        Ok(unsafe { &*(addr as *const T) })
     }
 }
 
@@ -59,6 +155,330 @@ struct TagHeader {
     size: u32,
 }
 
+impl TagHeader {
+    /// Size of the tag in bytes, including the header
+    fn size(&self) -> usize {
+        #[cfg(feature = "faultinject")]
+        if crate::faultinject::corrupt_tag() {
+            return 0;
+        }
+        self.size as usize
+    }
+}
+
+/// A tag in the boot information
+#[derive(Clone, Copy)]
+pub struct Tag<'a> {
+    pub typ: u32,
+    pub size: u32,
+    /// Offset from the start of the boot information
+    pub offset: usize,
+    /// Everything after the header
+    payload: &'a [u8],
+}
+
+impl<'a> Tag<'a> {
+    /// Get the bytes after the tag header
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// Get the name of the tag type, if it's one we know
+    pub fn name(&self) -> Option<&'static str> {
+        match self.typ {
+            MULTIBOOT2_TAG_TYPE_CMDLINE => Some("command line"),
+            2 => Some("boot loader name"),
+            MULTIBOOT2_TAG_TYPE_MODULE => Some("module"),
+            4 => Some("basic memory info"),
+            5 => Some("BIOS boot device"),
+            MULTIBOOT2_TAG_TYPE_MMAP => Some("memory map"),
+            MULTIBOOT2_TAG_TYPE_FRAMEBUFFER => Some("framebuffer"),
+            MULTIBOOT2_TAG_TYPE_ELF_SECTIONS => Some("ELF sections"),
+            10 => Some("APM table"),
+            MULTIBOOT2_TAG_TYPE_ACPI_OLD => Some("ACPI old RSDP"),
+            MULTIBOOT2_TAG_TYPE_ACPI_NEW => Some("ACPI new RSDP"),
+            21 => Some("image load base"),
+            _ => None,
+        }
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.payload.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64_at(&self, offset: usize) -> Option<u64> {
+        let bytes = self.payload.get(offset..offset + 8)?;
+        Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// Get a NUL-terminated string in the payload
+    fn str_at(&self, offset: usize) -> Option<&'a str> {
+        let bytes = self.payload.get(offset..)?;
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        core::str::from_utf8(&bytes[..len]).ok()
+    }
+}
+
+/// Prints the fields of the tag types we know, or nothing
+impl fmt::Display for Tag<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.typ {
+            MULTIBOOT2_TAG_TYPE_CMDLINE => {
+                write!(f, "{:?}", self.str_at(0).unwrap_or("<invalid>"))
+            }
+            MULTIBOOT2_TAG_TYPE_MODULE => {
+                let (Some(start), Some(end)) = (self.u32_at(0), self.u32_at(4)) else {
+                    return write!(f, "<truncated>");
+                };
+                write!(f, "{:#x}-{:#x} {:?}", start, end, self.str_at(8).unwrap_or("<invalid>"))
+            }
+            MULTIBOOT2_TAG_TYPE_MMAP => {
+                let (Some(entry_size), Some(version)) = (self.u32_at(0), self.u32_at(4)) else {
+                    return write!(f, "<truncated>");
+                };
+                let entries = (self.payload.len() - 8).checked_div(entry_size as usize).unwrap_or(0);
+                write!(f, "{} entries of {} bytes, version {}", entries, entry_size, version)
+            }
+            MULTIBOOT2_TAG_TYPE_FRAMEBUFFER => {
+                let (Some(addr), Some(pitch), Some(width), Some(height)) =
+                    (self.u64_at(0), self.u32_at(8), self.u32_at(12), self.u32_at(16))
+                else {
+                    return write!(f, "<truncated>");
+                };
+                let bpp = self.payload.get(20).copied().unwrap_or(0);
+                let fb_type = self.payload.get(21).copied().unwrap_or(0);
+                write!(
+                    f,
+                    "{:#x}, {}x{}, {} bpp, pitch {}, type {}",
+                    addr, width, height, bpp, pitch, fb_type
+                )
+            }
+            MULTIBOOT2_TAG_TYPE_ELF_SECTIONS => {
+                let (Some(num), Some(entsize), Some(shndx)) =
+                    (self.u32_at(0), self.u32_at(4), self.u32_at(8))
+                else {
+                    return write!(f, "<truncated>");
+                };
+                write!(f, "{} sections of {} bytes, names in section {}", num, entsize, shndx)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Iterator over the tags in the boot information
+pub struct TagIter<'a> {
+    start: usize,
+    current: usize,
+    end: usize,
+    _boot_info: &'a BootInfo,
+}
+
+impl<'a> Iterator for TagIter<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header_size = mem::size_of::<TagHeader>();
+        if self.current + header_size > self.end {
+            return None;
+        }
+
+        let header = unsafe { &*(self.current as *const TagHeader) };
+        let size = header.size();
+        if header.typ == MULTIBOOT2_TAG_TYPE_END
+            || size < header_size
+            || self.current + size > self.end
+        {
+            return None;
+        }
+
+        let payload = unsafe {
+            slice::from_raw_parts((self.current + header_size) as *const u8, size - header_size)
+        };
+        let tag = Tag {
+            typ: header.typ,
+            size: size as u32,
+            offset: self.current - self.start,
+            payload,
+        };
+
+        // Tags are 8-byte aligned
+        self.current = (self.current + size + 7) & !7;
+        Some(tag)
+    }
+}
+
+/// Command line tag
+#[repr(C)]
+struct CommandLineTag {
+    typ: u32,
+    size: u32,
+}
+
+impl CommandLineTag {
+    /// Get the command line as a string, without the trailing NUL
+    fn command_line(&self) -> Result<&str, MultibootError> {
+        let self_ptr = self as *const CommandLineTag;
+        let len = (self.size as usize).saturating_sub(mem::size_of::<CommandLineTag>());
+        let bytes = unsafe { slice::from_raw_parts(self_ptr.add(1) as *const u8, len) };
+        let bytes = match bytes.iter().position(|&b| b == 0) {
+            Some(nul) => &bytes[..nul],
+            None => bytes,
+        };
+        core::str::from_utf8(bytes).map_err(|_| MultibootError::StringNotUtf8)
+    }
+}
+
+/// Framebuffer info tag
+#[repr(C)]
+pub struct FramebufferTag {
+    typ: u32,
+    size: u32,
+    pub addr: u64,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    fb_type: u8,
+    _reserved: u16,
+}
+
+/// Type of a framebuffer
+#[derive(Debug, Clone, Copy)]
+pub enum FramebufferType {
+    /// Indexed colors with a palette
+    Indexed,
+
+    /// Direct RGB colors
+    Rgb(RgbFields),
+
+    /// EGA text mode (width and height are in characters)
+    EgaText,
+
+    /// Unknown type
+    Unknown(u8),
+}
+
+/// Positions and sizes of the color fields in an RGB framebuffer pixel
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RgbFields {
+    pub red_position: u8,
+    pub red_size: u8,
+    pub green_position: u8,
+    pub green_size: u8,
+    pub blue_position: u8,
+    pub blue_size: u8,
+}
+
+impl FramebufferTag {
+    /// Get the framebuffer type along with the color info
+    ///
+    /// An RGB tag too short to hold the color info is `Unknown(1)`.
+    pub fn framebuffer_type(&self) -> FramebufferType {
+        match self.fb_type {
+            0 => FramebufferType::Indexed,
+            1 if self.size as usize >= mem::size_of::<FramebufferTag>() + mem::size_of::<RgbFields>() => {
+                let self_ptr = self as *const FramebufferTag;
+                let fields = unsafe { *(self_ptr.add(1) as *const RgbFields) };
+                FramebufferType::Rgb(fields)
+            }
+            2 => FramebufferType::EgaText,
+            other => FramebufferType::Unknown(other),
+        }
+    }
+}
+
+/// ELF file magic
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+/// `EI_CLASS` value of 64-bit ELF files
+const ELF_CLASS_64: u8 = 2;
+
+/// gzip file magic
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// A file loaded by the bootloader along with the kernel
+///
+/// Only the parser makes these, since [`ModuleEntry::bytes`] trusts the
+/// addresses.
+#[derive(Debug, Clone, Copy)]
+pub struct ModuleEntry<'a> {
+    /// Physical address of the first byte
+    start: u32,
+    /// Physical address after the last byte
+    end: u32,
+    /// The command line the module was loaded with
+    cmdline: &'a str,
+}
+
+/// What a module contains, going by its first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    /// A 64-bit ELF file
+    Elf64,
+
+    /// A gzip-compressed file
+    Gzip,
+
+    /// Anything else, including 32-bit ELF files
+    Unknown,
+}
+
+impl<'a> ModuleEntry<'a> {
+    fn from_tag(tag: &Tag<'a>) -> Option<Self> {
+        if tag.typ != MULTIBOOT2_TAG_TYPE_MODULE {
+            return None;
+        }
+        Some(Self {
+            start: tag.u32_at(0)?,
+            end: tag.u32_at(4)?,
+            cmdline: tag.str_at(8).unwrap_or(""),
+        })
+    }
+
+    /// The command line the module was loaded with
+    pub fn cmdline(&self) -> &'a str {
+        self.cmdline
+    }
+
+    /// Size of the module in bytes
+    pub fn size(&self) -> usize {
+        self.end.saturating_sub(self.start) as usize
+    }
+
+    /// Get the contents of the module
+    ///
+    /// Modules are in identity-mapped memory, but nothing stops the
+    /// allocators from handing it out, so this is only valid until
+    /// `memory::init`.
+    pub fn bytes(&self) -> &'a [u8] {
+        unsafe { slice::from_raw_parts(self.start as usize as *const u8, self.size()) }
+    }
+
+    /// Returns whether the module starts with the ELF magic
+    pub fn is_elf(&self) -> bool {
+        self.bytes().starts_with(&ELF_MAGIC)
+    }
+
+    /// Returns whether the module starts with the gzip magic
+    pub fn is_gzip(&self) -> bool {
+        self.bytes().starts_with(&GZIP_MAGIC)
+    }
+
+    /// Guess what the module contains
+    pub fn kind(&self) -> ModuleKind {
+        if self.is_elf() && self.bytes().get(4) == Some(&ELF_CLASS_64) {
+            ModuleKind::Elf64
+        } else if self.is_gzip() {
+            ModuleKind::Gzip
+        } else {
+            ModuleKind::Unknown
+        }
+    }
+}
+
 /// Memory map tag
 #[repr(C)]
 pub struct MemoryMapTag {
@@ -79,6 +499,37 @@ pub struct MemoryArea {
 }
 
 impl MemoryMapTag {
+    /// Sanity-check the memory map before trusting it
+    pub fn validate(&self) -> Result<(), MemError> {
+        let header_size = mem::size_of::<MemoryMapTag>() as u32;
+        if (self.entry_size as usize) < mem::size_of::<MemoryArea>() {
+            return Err(MemError::MemoryMapEntryTooSmall(self.entry_size));
+        }
+        if self.size < header_size || (self.size - header_size) % self.entry_size != 0 {
+            return Err(MemError::MemoryMapBadSize);
+        }
+
+        let mut available = 0u64;
+        let mut has_available = false;
+        for area in self.memory_areas() {
+            if area.base_addr.checked_add(area.length).is_none() {
+                return Err(MemError::MemoryMapOverflow(area.base_addr));
+            }
+            if area.typ == MEMORY_AREA_TYPE_AVAILABLE {
+                has_available = true;
+                available = available.saturating_add(area.length);
+            }
+        }
+
+        if !has_available {
+            return Err(MemError::NoAvailableMemory);
+        }
+        if available < MIN_AVAILABLE_MEMORY {
+            return Err(MemError::InsufficientMemory(available));
+        }
+        Ok(())
+    }
+
     /// Get an iterator over memory areas
     pub fn memory_areas(&self) -> MemoryAreaIter {
         let self_ptr = self as *const MemoryMapTag;
@@ -92,9 +543,22 @@ impl MemoryMapTag {
             entry_size,
         }
     }
+
+    /// Number of entries in the memory map
+    pub fn count_entries(&self) -> usize {
+        self.memory_areas().count()
+    }
+
+    /// Total size of the available memory areas in bytes
+    pub fn count_available_bytes(&self) -> u64 {
+        self.memory_areas()
+            .filter(|area| area.typ == MEMORY_AREA_TYPE_AVAILABLE)
+            .fold(0u64, |total, area| total.saturating_add(area.length))
+    }
 }
 
 /// Iterator over memory areas
+#[derive(Clone)]
 pub struct MemoryAreaIter {
     current: usize,
     end: usize,
@@ -105,7 +569,8 @@ impl Iterator for MemoryAreaIter {
     type Item = MemoryArea;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current >= self.end {
+        // A zero entry size would return the same entry forever
+        if self.entry_size == 0 || self.current + self.entry_size > self.end {
             return None;
         }
 
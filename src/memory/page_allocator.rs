@@ -1,12 +1,41 @@
 //! Physical page allocator with 4KB and 2MB page support
 
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use super::bump::BumpAllocator;
 use super::multiboot2::MemoryMapTag;
-use super::mutex::Mutex;
+use super::PhysAddr;
+#[cfg(feature = "alloc-events")]
+use crate::collections::RingBuffer;
+use crate::debug::TimedLog;
+use crate::error::{MemError, Result};
+#[cfg(feature = "lock-stats")]
+use crate::sync::LockStats;
+use crate::sync::{MappedMutexGuard, Mutex, MutexGuard};
+
+/// The lock used inside the allocator, selected by the `ticket-lock` feature
+#[cfg(not(feature = "ticket-lock"))]
+type AllocatorLock = crate::sync::RawSpinLock;
+#[cfg(feature = "ticket-lock")]
+type AllocatorLock = crate::sync::RawTicketLock;
 
 const PAGE_SIZE_4KB: usize = 4096;
 const PAGE_SIZE_2MB: usize = 2 * 1024 * 1024;
 const PAGES_PER_2MB: usize = 512;
 
+/// Maximum number of reserved physical ranges
+const MAX_RESERVED_RANGES: usize = 16;
+
+/// Number of allocations and frees kept with the `alloc-events` feature
+#[cfg(feature = "alloc-events")]
+pub const ALLOC_EVENTS: usize = 8;
+
+/// Maximum number of free list entries checked for a summary, so it takes
+/// bounded time even if a list has a cycle
+const INTEGRITY_CHECK_PAGES: usize = 1 << 16;
+
 /// Page size enum
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PageSize {
@@ -14,13 +43,49 @@ pub enum PageSize {
     Size2MB,
 }
 
+impl PageSize {
+    /// Returns the size of the page in bytes.
+    pub const fn bytes(&self) -> usize {
+        match self {
+            PageSize::Size4KB => PAGE_SIZE_4KB,
+            PageSize::Size2MB => PAGE_SIZE_2MB,
+        }
+    }
+
+    /// Returns the number of the page of this size that contains `addr`.
+    pub fn page_frame_number(&self, addr: PhysAddr) -> usize {
+        addr.0 / self.bytes()
+    }
+
+    /// Rounds `addr` down to a page boundary.
+    pub fn align_down(&self, addr: PhysAddr) -> PhysAddr {
+        PhysAddr(addr.0 & !(self.bytes() - 1))
+    }
+
+    /// Rounds `addr` up to a page boundary.
+    pub fn align_up(&self, addr: PhysAddr) -> PhysAddr {
+        self.align_down(addr.add(self.bytes() - 1))
+    }
+}
+
+/// Returns the address of a 4KB page frame.
+fn pfn_to_addr(pfn: usize) -> PhysAddr {
+    PhysAddr(pfn * PageSize::Size4KB.bytes())
+}
+
+/// Returns the frame number of the first 4KB page in the superpage containing `pfn`.
+fn superpage_head(pfn: usize) -> usize {
+    PageSize::Size4KB.page_frame_number(PageSize::Size2MB.align_down(pfn_to_addr(pfn)))
+}
+
 /// Page state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum PageState {
     Unavailable,
     Free4KB,
     Free2MB,
-    Allocated,
+    /// Allocated, as a page of this size
+    Allocated(PageSize),
 }
 
 /// Metadata for a single page
@@ -43,46 +108,183 @@ impl PageMetadata {
     }
 }
 
-struct PageArrayWrapper {
-    ptr: *mut PageMetadata,
+/// Physical ranges that must never be handed out (e.g., device MMIO)
+struct ReservedRanges {
+    /// Page-aligned `[start, end)` ranges
+    ranges: [(usize, usize); MAX_RESERVED_RANGES],
     len: usize,
 }
 
-unsafe impl Send for PageArrayWrapper {}
-unsafe impl Sync for PageArrayWrapper {}
-
-impl PageArrayWrapper {
+impl ReservedRanges {
     const fn new() -> Self {
         Self {
-            ptr: core::ptr::null_mut(),
+            ranges: [(0, 0); MAX_RESERVED_RANGES],
             len: 0,
         }
     }
 
-    fn as_slice(&self) -> &mut [PageMetadata] {
-        unsafe { core::slice::from_raw_parts_mut(self.ptr, self.len) }
+    /// Check if `[start, end)` overlaps any reserved range
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.ranges[..self.len]
+            .iter()
+            .any(|&(r_start, r_end)| start < r_end && r_start < end)
+    }
+}
+
+/// A snapshot of the allocator state
+#[derive(Debug, Clone, Copy)]
+pub struct PageStats {
+    /// Number of 4KB pages tracked
+    pub total_pages: usize,
+    /// Number of free 4KB pages
+    pub free_4kb: usize,
+    /// Number of free 2MB pages
+    pub free_2mb: usize,
+}
+
+impl PageStats {
+    /// Total free memory in bytes
+    pub fn free_bytes(&self) -> usize {
+        self.free_4kb * PAGE_SIZE_4KB + self.free_2mb * PAGE_SIZE_2MB
+    }
+}
+
+/// What a summary found when walking the free lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrity {
+    Ok,
+    /// A page on a free list is out of bounds, in the wrong state, or
+    /// doesn't link back to the page before it
+    Corrupt { pfn: usize, problem: &'static str },
+    /// The lists are longer than [`INTEGRITY_CHECK_PAGES`]
+    Unfinished,
+}
+
+/// A page allocation or free
+#[cfg(feature = "alloc-events")]
+#[derive(Debug, Clone, Copy)]
+pub struct AllocEvent {
+    pub tick: u64,
+    pub freed: bool,
+    pub size: PageSize,
+    pub addr: usize,
+}
+
+/// The state of the allocator, for the panic and OOM reports
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorSummary {
+    pub stats: PageStats,
+    /// Number of 4KB pages handed out and not freed
+    pub allocated_4kb: usize,
+    /// Number of 2MB pages handed out and not freed
+    pub allocated_2mb: usize,
+    /// Most memory that was allocated at once
+    pub peak_bytes: usize,
+    pub integrity: Integrity,
+    /// The last allocations and frees, oldest first
+    #[cfg(feature = "alloc-events")]
+    pub events: [Option<AllocEvent>; ALLOC_EVENTS],
+}
+
+impl fmt::Display for AllocatorSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Pages free: {} 4KB, {} 2MB ({} KB); allocated: {} 4KB, {} 2MB; peak {} KB",
+            self.stats.free_4kb,
+            self.stats.free_2mb,
+            self.stats.free_bytes() / 1024,
+            self.allocated_4kb,
+            self.allocated_2mb,
+            self.peak_bytes / 1024
+        )?;
+        match self.integrity {
+            Integrity::Ok => write!(f, "Free lists ok")?,
+            Integrity::Corrupt { pfn, problem } => write!(f, "Free lists corrupt at page {:#x}: {}", pfn, problem)?,
+            Integrity::Unfinished => write!(f, "Free lists too long to check")?,
+        }
+
+        #[cfg(feature = "alloc-events")]
+        for event in self.events.iter().flatten() {
+            let what = if event.freed { "free " } else { "alloc" };
+            let size = match event.size {
+                PageSize::Size4KB => "4KB",
+                PageSize::Size2MB => "2MB",
+            };
+            write!(f, "\n  tick {:>8} {} {} {:#x}", event.tick, what, size, event.addr)?;
+        }
+        Ok(())
     }
 }
 
 /// The physical page allocator
+///
+/// The free lists are locked before the page array, and the 4KB list
+/// before the 2MB list, so `page_array` is always the last lock taken.
 pub struct PageAllocator {
-    page_array: Mutex<PageArrayWrapper>,
-    free_4kb_list: Mutex<Option<usize>>,
-    free_2mb_list: Mutex<Option<usize>>,
-    kernel_end: Mutex<usize>,
+    page_array: Mutex<&'static mut [PageMetadata], AllocatorLock>,
+    free_4kb_list: Mutex<Option<usize>, AllocatorLock>,
+    free_2mb_list: Mutex<Option<usize>, AllocatorLock>,
+    kernel_end: Mutex<usize, AllocatorLock>,
+    reserved: Mutex<ReservedRanges, AllocatorLock>,
+    allocated_4kb: AtomicUsize,
+    allocated_2mb: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    #[cfg(feature = "alloc-events")]
+    events: Mutex<RingBuffer<AllocEvent, ALLOC_EVENTS>, AllocatorLock>,
 }
 
 impl PageAllocator {
     pub const fn new() -> Self {
         Self {
-            page_array: Mutex::new(PageArrayWrapper::new()),
+            page_array: Mutex::new(&mut []),
             free_4kb_list: Mutex::new(None),
             free_2mb_list: Mutex::new(None),
             kernel_end: Mutex::new(0),
+            reserved: Mutex::new(ReservedRanges::new()),
+            allocated_4kb: AtomicUsize::new(0),
+            allocated_2mb: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+            #[cfg(feature = "alloc-events")]
+            events: Mutex::new(RingBuffer::new()),
         }
     }
 
-    pub unsafe fn init(&self, mmap: &MemoryMapTag) {
+    /// Reserve a physical range so that it's never handed out
+    ///
+    /// This must be called before `init`.
+    pub fn reserve_range(&self, addr: PhysAddr, length: usize) -> Result<()> {
+        if self.is_initialized() {
+            return Err(MemError::AlreadyInitialized.into());
+        }
+
+        let mut reserved = self.reserved.lock();
+        if reserved.len == MAX_RESERVED_RANGES {
+            return Err(MemError::TooManyReservedRanges.into());
+        }
+
+        let start = PageSize::Size4KB.align_down(addr);
+        let end = PageSize::Size4KB.align_up(addr.add(length));
+        let len = reserved.len;
+        reserved.ranges[len] = (start.0, end.0);
+        reserved.len += 1;
+        Ok(())
+    }
+
+    /// Lock the page array
+    fn pages(&self) -> MappedMutexGuard<'_, [PageMetadata], AllocatorLock> {
+        MutexGuard::map(self.page_array.lock(), |pages| &mut **pages)
+    }
+
+    /// Returns whether `init` has completed
+    pub fn is_initialized(&self) -> bool {
+        !self.page_array.lock().is_empty()
+    }
+
+    /// Set up the page array and free lists
+    ///
+    /// The page array is allocated from `early`, which is then closed.
+    pub unsafe fn init(&self, mmap: &MemoryMapTag, early: &BumpAllocator, timer: &TimedLog) -> Result<()> {
         use crate::println;
         
         // Find the actual maximum usable address (only consider type 1 = available)
@@ -98,22 +300,27 @@ impl PageAllocator {
         }
         
         // Round up to nearest 2MB to make allocation simpler
-        let max_addr = (actual_max + PAGE_SIZE_2MB - 1) & !(PAGE_SIZE_2MB - 1);
-        let total_pages = max_addr / PAGE_SIZE_4KB;
+        let max_addr = PageSize::Size2MB.align_up(PhysAddr(actual_max)).0;
+        let total_pages = PageSize::Size4KB.page_frame_number(PhysAddr(max_addr));
+        if max_addr == 0 {
+            return Err(MemError::NoAvailableMemory.into());
+        }
+        if total_pages == 0 {
+            return Err(MemError::NoAvailableMemory.into());
+        }
         
+        timer.checkpoint("Scanned memory map");
         println!("Total pages to track: {}", total_pages);
         
-        // Get kernel end
-        extern "C" { static __end: u8; }
-        let kernel_end = (&__end as *const u8 as usize + PAGE_SIZE_4KB - 1) & !(PAGE_SIZE_4KB - 1);
-        
-        println!("Kernel end: {:#x}", kernel_end);
-        
-        // Allocate page_array after kernel
-        let metadata_size = total_pages * core::mem::size_of::<PageMetadata>();
-        println!("Metadata size: {} bytes ({} KB)", metadata_size, metadata_size / 1024);
-        
-        let page_array_ptr = kernel_end as *mut PageMetadata;
+        // The page array lives in early boot memory
+        let page_array_layout = core::alloc::Layout::array::<PageMetadata>(total_pages)
+            .map_err(|_| MemError::PageArrayTooLarge)?;
+        println!("Metadata size: {} bytes ({} KB)", page_array_layout.size(), page_array_layout.size() / 1024);
+
+        let page_array_ptr = early.alloc(page_array_layout) as *mut PageMetadata;
+        if page_array_ptr.is_null() {
+            return Err(MemError::NoEarlyMemory.into());
+        }
         let page_array_slice = core::slice::from_raw_parts_mut(page_array_ptr, total_pages);
         
         // Initialize all as unavailable
@@ -121,16 +328,14 @@ impl PageAllocator {
             page_array_slice[i] = PageMetadata::new();
         }
         
-        {
-            let mut wrapper = self.page_array.lock();
-            wrapper.ptr = page_array_ptr;
-            wrapper.len = total_pages;
-        }
+        *self.page_array.lock() = page_array_slice;
+        timer.checkpoint("Allocated page array");
         
-        let final_kernel_end = (kernel_end + metadata_size + PAGE_SIZE_4KB - 1) & !(PAGE_SIZE_4KB - 1);
+        // Everything below the end of early boot memory stays in use
+        let final_kernel_end = PageSize::Size4KB.align_up(early.finish()).0;
         *self.kernel_end.lock() = final_kernel_end;
         
-        println!("Final kernel end (after metadata): {:#x}", final_kernel_end);
+        println!("Final kernel end (after early allocations): {:#x}", final_kernel_end);
         
         // Mark available regions from memory map
         for entry in mmap.memory_areas() {
@@ -138,42 +343,37 @@ impl PageAllocator {
                 self.mark_available(entry.base_addr as usize, entry.length as usize);
             }
         }
+        timer.checkpoint("Marked available regions");
         
         // Build free lists
         self.build_lists();
+        timer.checkpoint("Built free lists");
         
         // Count free pages
-        let mut free_4kb = 0;
-        let mut free_2mb = 0;
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
-        for pfn in 0..pages.len() {
-            match pages[pfn].state {
-                PageState::Free4KB => free_4kb += 1,
-                PageState::Free2MB => free_2mb += 1,
-                _ => {}
-            }
-        }
-        drop(page_guard);
-        
-        println!("Free 4KB pages: {}", free_4kb);
-        println!("Free 2MB pages: {}", free_2mb);
-        println!("Total free memory: {} MB", (free_4kb * 4 + free_2mb * 2048) / 1024);
+        let stats = self.stats();
+        timer.checkpoint("Counted free pages");
+        println!("Free 4KB pages: {}", stats.free_4kb);
+        println!("Free 2MB pages: {}", stats.free_2mb);
+        println!("Total free memory: {} MB", stats.free_bytes() / (1024 * 1024));
+
+        Ok(())
     }
 
     fn mark_available(&self, base: usize, length: usize) {
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
-        let start_pfn = base / PAGE_SIZE_4KB;
-        let end_pfn = (base + length) / PAGE_SIZE_4KB;
-        let kernel_pfn = *self.kernel_end.lock() / PAGE_SIZE_4KB;
+        let start_pfn = PageSize::Size4KB.page_frame_number(PhysAddr(base));
+        let end_pfn = PageSize::Size4KB.page_frame_number(PhysAddr(base + length));
+        let kernel_pfn = PageSize::Size4KB.page_frame_number(PhysAddr(*self.kernel_end.lock()));
+        let reserved = self.reserved.lock();
+        let mut pages = self.pages();
         
         let mut pfn = start_pfn.max(kernel_pfn);
         while pfn < end_pfn && pfn < pages.len() {
-            let addr = pfn * PAGE_SIZE_4KB;
+            let addr = pfn_to_addr(pfn);
             
             // Try to make 2MB page
-            if addr % PAGE_SIZE_2MB == 0 && pfn + PAGES_PER_2MB <= end_pfn && pfn + PAGES_PER_2MB <= pages.len() {
+            if addr.is_page_aligned(PageSize::Size2MB) && pfn + PAGES_PER_2MB <= end_pfn && pfn + PAGES_PER_2MB <= pages.len()
+                && !reserved.overlaps(addr.0, addr.0 + PAGE_SIZE_2MB)
+            {
                 pages[pfn].state = PageState::Free2MB;
                 pages[pfn].counter = PAGES_PER_2MB as u16;
                 for i in 1..PAGES_PER_2MB {
@@ -181,15 +381,18 @@ impl PageAllocator {
                 }
                 pfn += PAGES_PER_2MB;
             } else {
-                pages[pfn].state = PageState::Free4KB;
+                if !reserved.overlaps(addr.0, addr.0 + PAGE_SIZE_4KB) {
+                    pages[pfn].state = PageState::Free4KB;
+                }
                 pfn += 1;
             }
         }
     }
 
     fn build_lists(&self) {
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
+        let mut list_4kb = self.free_4kb_list.lock();
+        let mut list_2mb = self.free_2mb_list.lock();
+        let mut pages = self.pages();
         let mut head_4kb = None;
         let mut head_2mb = None;
         
@@ -215,23 +418,139 @@ impl PageAllocator {
             }
         }
         
-        *self.free_4kb_list.lock() = head_4kb;
-        *self.free_2mb_list.lock() = head_2mb;
+        *list_4kb = head_4kb;
+        *list_2mb = head_2mb;
+    }
+
+    /// Contention statistics of the internal locks
+    #[cfg(feature = "lock-stats")]
+    pub fn lock_stats(&self) -> [(&'static str, LockStats); 5] {
+        [
+            ("page_array", self.page_array.stats()),
+            ("free_4kb_list", self.free_4kb_list.stats()),
+            ("free_2mb_list", self.free_2mb_list.stats()),
+            ("kernel_end", self.kernel_end.stats()),
+            ("reserved", self.reserved.stats()),
+        ]
+    }
+
+    /// Count the tracked and free pages
+    pub fn stats(&self) -> PageStats {
+        count_pages(&self.pages())
+    }
+
+    /// Count the allocated pages of `size`, without taking any lock
+    pub fn allocated_pages(&self, size: PageSize) -> usize {
+        self.allocated(size).load(Ordering::Relaxed)
+    }
+
+    /// Summarize the allocator for a panic report
+    ///
+    /// Each lock is waited for at most `timeout`, so this returns `None`
+    /// rather than hanging when the panic happened with one of them held.
+    pub fn try_summary_for(&self, timeout: Duration) -> Option<AllocatorSummary> {
+        let head_4kb = self.free_4kb_list.try_lock_for(timeout)?;
+        let head_2mb = self.free_2mb_list.try_lock_for(timeout)?;
+        let pages = self.page_array.try_lock_for(timeout)?;
+
+        let mut budget = INTEGRITY_CHECK_PAGES;
+        let integrity = match check_free_list(&pages, *head_4kb, PageState::Free4KB, &mut budget) {
+            Integrity::Ok => check_free_list(&pages, *head_2mb, PageState::Free2MB, &mut budget),
+            problem => problem,
+        };
+
+        #[cfg(feature = "alloc-events")]
+        let events = {
+            let mut events = [None; ALLOC_EVENTS];
+            let ring = self.events.try_lock_for(timeout)?;
+            for (slot, event) in events.iter_mut().zip(ring.peek_all_iter()) {
+                *slot = Some(*event);
+            }
+            events
+        };
+
+        Some(AllocatorSummary {
+            stats: count_pages(&pages),
+            allocated_4kb: self.allocated_4kb.load(Ordering::Relaxed),
+            allocated_2mb: self.allocated_2mb.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            integrity,
+            #[cfg(feature = "alloc-events")]
+            events,
+        })
+    }
+
+    /// Run `f` with the page array locked
+    #[cfg(test)]
+    pub fn with_pages_locked<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _pages = self.page_array.lock();
+        f()
     }
 
     pub fn allocate_page(&self, size: PageSize) -> Option<usize> {
-        match size {
+        #[cfg(feature = "faultinject")]
+        if crate::faultinject::fail_allocation(size.bytes()) {
+            return None;
+        }
+
+        let addr = match size {
             PageSize::Size4KB => self.alloc_4kb(),
             PageSize::Size2MB => self.alloc_2mb(),
+        }?;
+
+        self.allocated(size).fetch_add(1, Ordering::Relaxed);
+        let in_use = self.allocated_4kb.load(Ordering::Relaxed) * PAGE_SIZE_4KB
+            + self.allocated_2mb.load(Ordering::Relaxed) * PAGE_SIZE_2MB;
+        self.peak_bytes.fetch_max(in_use, Ordering::Relaxed);
+        self.record(false, size, addr);
+        Some(addr)
+    }
+
+    /// Returns whether the 4KB pages follow each other in physical memory
+    ///
+    /// DMA needs its buffers physically contiguous, so callers check what
+    /// they got before handing it to a device.
+    #[allow(dead_code)] // No DMA driver yet
+    pub fn is_physically_contiguous(pages: &[PhysAddr]) -> bool {
+        pages
+            .windows(2)
+            .all(|pair| pair[0].0.checked_add(PAGE_SIZE_4KB) == Some(pair[1].0))
+    }
+
+    /// Returns whether `addr` is the start of an allocated page of `size`
+    ///
+    /// Catches frees of addresses that were never handed out, like a
+    /// pointer into the middle of an allocation, and frees with the wrong
+    /// size, like a 4KB page that happens to be 2MB aligned.
+    pub fn verify_allocation(&self, addr: PhysAddr, size: PageSize) -> bool {
+        is_allocated(&self.pages(), addr, size)
+    }
+
+    /// The count of allocated pages of `size`
+    fn allocated(&self, size: PageSize) -> &AtomicUsize {
+        match size {
+            PageSize::Size4KB => &self.allocated_4kb,
+            PageSize::Size2MB => &self.allocated_2mb,
         }
     }
 
+    #[cfg(feature = "alloc-events")]
+    fn record(&self, freed: bool, size: PageSize, addr: usize) {
+        let tick = crate::time::ticks();
+        self.events.lock().push(AllocEvent { tick, freed, size, addr });
+    }
+
+    #[cfg(not(feature = "alloc-events"))]
+    fn record(&self, _freed: bool, _size: PageSize, _addr: usize) {}
+
     fn alloc_4kb(&self) -> Option<usize> {
+        crate::kdebug_assert!(self.is_initialized(), "PageAllocator::init was not called");
+
         let mut head = self.free_4kb_list.lock();
         
         if let Some(pfn) = *head {
-            let page_guard = self.page_array.lock();
-            let pages = page_guard.as_slice();
+            let mut pages = self.pages();
+            crate::kdebug_assert_eq!(pages[pfn].state, PageState::Free4KB, "pfn {:#x} on the 4KB free list", pfn);
             
             // Remove from list
             *head = pages[pfn].next;
@@ -239,19 +558,19 @@ impl PageAllocator {
                 pages[next].prev = None;
             }
             
-            pages[pfn].state = PageState::Allocated;
+            pages[pfn].state = PageState::Allocated(PageSize::Size4KB);
             pages[pfn].next = None;
             pages[pfn].prev = None;
             
             drop(head);
             
             // Update superpage counter
-            let sp_head = (pfn / PAGES_PER_2MB) * PAGES_PER_2MB;
+            let sp_head = superpage_head(pfn);
             if sp_head < pages.len() {
                 pages[sp_head].counter = pages[sp_head].counter.saturating_sub(1);
             }
             
-            return Some(pfn * PAGE_SIZE_4KB);
+            return Some(pfn_to_addr(pfn).0);
         }
         
         // No 4KB pages, try splitting 2MB page
@@ -271,8 +590,8 @@ impl PageAllocator {
         let mut head = self.free_2mb_list.lock();
         let pfn = (*head)?;
         
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
+        let mut pages = self.pages();
+        crate::kdebug_assert_eq!(pages[pfn].state, PageState::Free2MB, "pfn {:#x} on the 2MB free list", pfn);
         
         // Remove from list
         *head = pages[pfn].next;
@@ -280,19 +599,19 @@ impl PageAllocator {
             pages[next].prev = None;
         }
         
-        pages[pfn].state = PageState::Allocated;
+        pages[pfn].state = PageState::Allocated(PageSize::Size2MB);
         pages[pfn].next = None;
         pages[pfn].prev = None;
         
-        Some(pfn * PAGE_SIZE_4KB)
+        Some(pfn_to_addr(pfn).0)
     }
 
     fn split_2mb(&self) -> Option<()> {
+        let mut head_4kb = self.free_4kb_list.lock();
         let mut head = self.free_2mb_list.lock();
         let pfn = (*head)?;
         
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
+        let mut pages = self.pages();
         
         // Remove from 2MB list
         *head = pages[pfn].next;
@@ -303,8 +622,6 @@ impl PageAllocator {
         drop(head);
         
         // Convert to 4KB pages and add to 4KB list
-        let mut head_4kb = self.free_4kb_list.lock();
-        
         // Set up the first page with counter tracking
         pages[pfn].counter = PAGES_PER_2MB as u16;
         
@@ -324,32 +641,35 @@ impl PageAllocator {
     }
 
     pub fn free_page(&self, addr: usize, size: PageSize) {
-        let pfn = addr / PAGE_SIZE_4KB;
-        match size {
-            PageSize::Size4KB => self.free_4kb(pfn),
-            PageSize::Size2MB => self.free_2mb(pfn),
+        let freed = match size {
+            PageSize::Size4KB => self.free_4kb(PhysAddr(addr)),
+            PageSize::Size2MB => self.free_2mb(PhysAddr(addr)),
+        };
+
+        if freed {
+            // The count of a page's allocation may not have caught up yet
+            let _ = self.allocated(size).fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+            self.record(true, size, addr);
         }
     }
 
-    fn free_4kb(&self, pfn: usize) {
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
-        
-        // Bounds check
-        if pfn >= pages.len() {
-            return;
-        }
+    /// Returns whether the page was freed
+    fn free_4kb(&self, addr: PhysAddr) -> bool {
+        let mut head = self.free_4kb_list.lock();
+        let mut pages = self.pages();
         
-        // Check if already free
-        if pages[pfn].state == PageState::Free4KB {
-            return; // Already freed, prevent double-free
+        // Catches double frees, interior pointers and 2MB pages
+        if !is_allocated(&pages, addr, PageSize::Size4KB) {
+            crate::log_ratelimited!(log::Level::Warn, "page_allocator", "Free of unallocated 4KB page {:#x}", addr.0);
+            return false;
         }
+        let pfn = PageSize::Size4KB.page_frame_number(addr);
         
         // Mark as free first
         pages[pfn].state = PageState::Free4KB;
         
         // Update superpage counter (only on superpage head)
-        let sp_head = (pfn / PAGES_PER_2MB) * PAGES_PER_2MB;
+        let sp_head = superpage_head(pfn);
         let can_merge = if sp_head < pages.len() {
             // Only track counter on the superpage head page
             // Increment the counter for this free
@@ -360,7 +680,6 @@ impl PageAllocator {
         };
         
         // Add to 4KB list
-        let mut head = self.free_4kb_list.lock();
         pages[pfn].next = *head;
         pages[pfn].prev = None;
         
@@ -371,43 +690,45 @@ impl PageAllocator {
         }
         *head = Some(pfn);
         drop(head);
-        drop(page_guard);
+        drop(pages);
         
         // Try to merge
         if can_merge {
             self.try_merge(pfn);
         }
+        true
     }
 
-    fn free_2mb(&self, pfn: usize) {
-        // Make sure pfn is 2MB aligned
-        let aligned_pfn = (pfn / PAGES_PER_2MB) * PAGES_PER_2MB;
-        
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
+    /// Returns whether the page was freed
+    fn free_2mb(&self, addr: PhysAddr) -> bool {
+        let mut head = self.free_2mb_list.lock();
+        let mut pages = self.pages();
         
-        // Check if already in a valid state
-        if pages[aligned_pfn].state == PageState::Free2MB {
-            return; // Already freed
+        // Catches double frees, unaligned addresses and 4KB pages
+        if !is_allocated(&pages, addr, PageSize::Size2MB) {
+            crate::log_ratelimited!(log::Level::Warn, "page_allocator", "Free of unallocated 2MB page {:#x}", addr.0);
+            return false;
         }
+        let pfn = PageSize::Size4KB.page_frame_number(addr);
         
-        pages[aligned_pfn].state = PageState::Free2MB;
-        pages[aligned_pfn].counter = PAGES_PER_2MB as u16;
+        pages[pfn].state = PageState::Free2MB;
+        pages[pfn].counter = PAGES_PER_2MB as u16;
         
-        let mut head = self.free_2mb_list.lock();
-        pages[aligned_pfn].next = *head;
-        pages[aligned_pfn].prev = None;
+        pages[pfn].next = *head;
+        pages[pfn].prev = None;
         
         if let Some(old) = *head {
-            pages[old].prev = Some(aligned_pfn);
+            pages[old].prev = Some(pfn);
         }
-        *head = Some(aligned_pfn);
+        *head = Some(pfn);
+        true
     }
 
     fn try_merge(&self, pfn: usize) {
-        let sp_head = (pfn / PAGES_PER_2MB) * PAGES_PER_2MB;
-        let page_guard = self.page_array.lock();
-        let pages = page_guard.as_slice();
+        let sp_head = superpage_head(pfn);
+        let mut head_4kb = self.free_4kb_list.lock();
+        let mut head_2mb = self.free_2mb_list.lock();
+        let mut pages = self.pages();
         
         // Check all pages are free
         for i in 0..PAGES_PER_2MB {
@@ -425,7 +746,7 @@ impl PageAllocator {
             if let Some(prev_p) = prev {
                 pages[prev_p].next = next;
             } else {
-                *self.free_4kb_list.lock() = next;
+                *head_4kb = next;
             }
             
             if let Some(next_p) = next {
@@ -440,13 +761,64 @@ impl PageAllocator {
         pages[sp_head].state = PageState::Free2MB;
         pages[sp_head].counter = PAGES_PER_2MB as u16;
         
-        let mut head = self.free_2mb_list.lock();
-        pages[sp_head].next = *head;
+        pages[sp_head].next = *head_2mb;
         pages[sp_head].prev = None;
         
-        if let Some(old) = *head {
+        if let Some(old) = *head_2mb {
             pages[old].prev = Some(sp_head);
         }
-        *head = Some(sp_head);
+        *head_2mb = Some(sp_head);
+    }
+}
+
+/// Returns whether `addr` is the start of an allocated page of `size`
+fn is_allocated(pages: &[PageMetadata], addr: PhysAddr, size: PageSize) -> bool {
+    if !addr.is_page_aligned(size) {
+        return false;
+    }
+    let pfn = PageSize::Size4KB.page_frame_number(addr);
+    pages.get(pfn).is_some_and(|page| page.state == PageState::Allocated(size))
+}
+
+/// Count the free pages in the page array
+fn count_pages(pages: &[PageMetadata]) -> PageStats {
+    let mut stats = PageStats {
+        total_pages: pages.len(),
+        free_4kb: 0,
+        free_2mb: 0,
+    };
+
+    for page in pages.iter() {
+        match page.state {
+            PageState::Free4KB => stats.free_4kb += 1,
+            PageState::Free2MB => stats.free_2mb += 1,
+            _ => {}
+        }
+    }
+    stats
+}
+
+/// Walk a free list, checking at most `budget` pages
+fn check_free_list(pages: &[PageMetadata], head: Option<usize>, state: PageState, budget: &mut usize) -> Integrity {
+    let mut prev = None;
+    let mut next = head;
+    while let Some(pfn) = next {
+        if *budget == 0 {
+            return Integrity::Unfinished;
+        }
+        *budget -= 1;
+
+        let Some(page) = pages.get(pfn) else {
+            return Integrity::Corrupt { pfn, problem: "out of bounds" };
+        };
+        if page.state != state {
+            return Integrity::Corrupt { pfn, problem: "not free" };
+        }
+        if page.prev != prev {
+            return Integrity::Corrupt { pfn, problem: "bad back link" };
+        }
+        prev = Some(pfn);
+        next = page.next;
     }
+    Integrity::Ok
 }
@@ -0,0 +1,278 @@
+//! Page table inspection and mapping
+//!
+//! The boot code identity-maps the first 4GB, so the page tables can be
+//! read at their physical addresses. New mappings only use 4KB pages,
+//! with tables from the page allocator.
+//!
+//! The first [`NULL_GUARD_SIZE`] bytes are unmapped once the kernel no
+//! longer needs them, so a null pointer dereference faults instead of
+//! reading the real-mode IVT.
+
+use core::arch::asm;
+use core::fmt;
+
+use super::page_allocator::PageSize;
+use super::{PhysAddr, PAGE_ALLOCATOR};
+use crate::error::{MemError, Result};
+
+const PRESENT: u64 = 1 << 0;
+const WRITABLE: u64 = 1 << 1;
+const USER: u64 = 1 << 2;
+const HUGE_PAGE: u64 = 1 << 7;
+const NO_EXECUTE: u64 = 1 << 63;
+
+/// Physical address bits of a table entry
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Unmapped at the bottom of the address space to catch null pointers
+pub const NULL_GUARD_SIZE: usize = 64 * 1024;
+
+/// The effective permissions of a mapping
+///
+/// A page is only writable or user-accessible if every level allows it,
+/// and it's not executable if any level forbids it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFlags {
+    pub writable: bool,
+    pub user: bool,
+    pub nx: bool,
+    /// Mapped by a 2MB or 1GB page
+    pub large_page: bool,
+}
+
+impl fmt::Display for PageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}{}",
+            if self.writable { "rw" } else { "ro" },
+            if self.user { "user" } else { "kernel" },
+            if self.nx { "nx" } else { "x" },
+            if self.large_page { " large" } else { "" },
+        )
+    }
+}
+
+/// The result of a page table walk, for printing
+pub struct Translation {
+    pub virt: u64,
+    pub mapping: Option<(PhysAddr, PageFlags)>,
+}
+
+impl fmt::Display for Translation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mapping {
+            Some((phys, flags)) => write!(f, "VA {:#018x} → PA {:#018x} ({})", self.virt, phys.0, flags),
+            None => write!(f, "VA {:#018x} is not mapped", self.virt),
+        }
+    }
+}
+
+/// Returns the PML4 of the current page tables
+pub fn current_pml4() -> PhysAddr {
+    let cr3: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+    }
+    PhysAddr((cr3 & ADDRESS_MASK) as usize)
+}
+
+/// Translate a virtual address with the current page tables
+pub fn page_table_walk(virt: u64) -> Option<(PhysAddr, PageFlags)> {
+    unsafe { walk(current_pml4(), virt) }
+}
+
+/// Translate a virtual address for printing
+pub fn translate(virt: u64) -> Translation {
+    Translation {
+        virt,
+        mapping: page_table_walk(virt),
+    }
+}
+
+/// Translate a virtual address with the page tables rooted at `pml4`
+///
+/// # Safety
+/// `pml4` and every table it points to must be identity-mapped.
+pub unsafe fn walk(pml4: PhysAddr, virt: u64) -> Option<(PhysAddr, PageFlags)> {
+    // Bits 63:48 must copy bit 47
+    if ((virt as i64) << 16 >> 16) as u64 != virt {
+        return None;
+    }
+
+    let mut table = pml4.0 as u64;
+    let mut flags = PageFlags {
+        writable: true,
+        user: true,
+        nx: false,
+        large_page: false,
+    };
+
+    // PML4, PDPT, PD and PT, each indexed by 9 bits
+    for level in (0..4).rev() {
+        let shift = 12 + 9 * level;
+        let index = (virt >> shift) & 0x1FF;
+        let entry = unsafe { core::ptr::read_volatile((table as *const u64).add(index as usize)) };
+
+        if entry & PRESENT == 0 {
+            return None;
+        }
+        flags.writable &= entry & WRITABLE != 0;
+        flags.user &= entry & USER != 0;
+        flags.nx |= entry & NO_EXECUTE != 0;
+
+        // 1GB pages in the PDPT, 2MB pages in the PD
+        let is_page = level == 0 || (matches!(level, 1 | 2) && entry & HUGE_PAGE != 0);
+        if is_page {
+            flags.large_page = level != 0;
+            let offset_mask = (1u64 << shift) - 1;
+            let phys = (entry & ADDRESS_MASK & !offset_mask) | (virt & offset_mask);
+            return Some((PhysAddr(phys as usize), flags));
+        }
+
+        table = entry & ADDRESS_MASK;
+    }
+
+    unreachable!()
+}
+
+/// Index of the entry for `virt` in the table at `level`, where the PT is 0
+fn table_index(virt: u64, level: u32) -> usize {
+    ((virt >> (12 + 9 * level)) & 0x1FF) as usize
+}
+
+/// Map a 4KB page in the page tables rooted at `pml4`
+///
+/// Missing tables are allocated, and the tables on the way are made
+/// writable and user-accessible if the page is. `flags.nx` and
+/// `flags.large_page` are ignored. The TLB isn't flushed, so `virt` must
+/// not have been mapped before.
+///
+/// # Safety
+/// `pml4` and every table it points to must be identity-mapped, and the
+/// mapping must not pull memory out from under anyone.
+pub unsafe fn map_4kb(pml4: PhysAddr, virt: u64, phys: PhysAddr, flags: PageFlags) -> Result<()> {
+    let mut access = PRESENT;
+    if flags.writable {
+        access |= WRITABLE;
+    }
+    if flags.user {
+        access |= USER;
+    }
+
+    let mut table = pml4.0 as *mut u64;
+    for level in (1..4).rev() {
+        let entry = unsafe { &mut *table.add(table_index(virt, level)) };
+        if *entry & PRESENT == 0 {
+            let new_table = PAGE_ALLOCATOR
+                .allocate_page(PageSize::Size4KB)
+                .ok_or(MemError::OutOfMemory)?;
+            unsafe { core::ptr::write_bytes(new_table as *mut u8, 0, PageSize::Size4KB.bytes()) };
+            *entry = new_table as u64;
+        } else if *entry & HUGE_PAGE != 0 {
+            return Err(MemError::LargePageInTheWay(virt).into());
+        }
+        *entry |= access;
+        table = (*entry & ADDRESS_MASK) as *mut u64;
+    }
+
+    unsafe { *table.add(table_index(virt, 0)) = phys.0 as u64 | access };
+    Ok(())
+}
+
+/// Split the large page mapped by `entry` at `level` into the next level
+///
+/// The new table maps the same memory with the same permissions. Does
+/// nothing if the entry already points to a table.
+///
+/// # Safety
+/// The entry must be in identity-mapped page tables, and the TLB must be
+/// flushed before relying on the change.
+unsafe fn split_large_page(entry: &mut u64, level: u32) -> Result<()> {
+    if *entry & HUGE_PAGE == 0 {
+        return Ok(());
+    }
+
+    let table = PAGE_ALLOCATOR
+        .allocate_page(PageSize::Size4KB)
+        .ok_or(MemError::OutOfMemory)? as *mut u64;
+    let flags = *entry & !ADDRESS_MASK & !HUGE_PAGE;
+    let base = *entry & ADDRESS_MASK;
+    let step = 1u64 << (12 + 9 * (level - 1));
+    // Only PT entries have no page size bit
+    let huge = if level > 1 { HUGE_PAGE } else { 0 };
+    for i in 0..512 {
+        unsafe { *table.add(i) = (base + i as u64 * step) | flags | huge };
+    }
+
+    *entry = table as u64 | flags;
+    Ok(())
+}
+
+/// Unmap the first `size` bytes of the identity map to catch null pointers
+///
+/// The boot code maps them with a 1GB page, so that's split down to 4KB
+/// pages, with tables from the page allocator. The rest stays mapped as
+/// it was. Other CPUs must not be running yet, since only this one's TLB
+/// is flushed.
+///
+/// # Safety
+/// Nothing may use the memory any more, and `size` must be page-aligned
+/// and at most 2MB.
+pub unsafe fn unmap_null_guard(pml4: PhysAddr, size: usize) -> Result<()> {
+    let mut table = pml4.0 as *mut u64;
+    for level in (1..4).rev() {
+        let entry = unsafe { &mut *table.add(0) };
+        if *entry & PRESENT == 0 {
+            // Nothing to unmap
+            return Ok(());
+        }
+        unsafe { split_large_page(entry, level)? };
+        table = (*entry & ADDRESS_MASK) as *mut u64;
+    }
+
+    for i in 0..size / PageSize::Size4KB.bytes() {
+        unsafe { *table.add(i) = 0 };
+    }
+    unsafe {
+        asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _, options(nostack, preserves_flags));
+    }
+    Ok(())
+}
+
+/// Unmap everything under entry `index` of `pml4`
+///
+/// The tables and the 4KB pages they map are given back to the page
+/// allocator, and the TLB is flushed.
+///
+/// # Safety
+/// Everything under the entry must have been mapped with `map_4kb`, with
+/// pages from the page allocator that nobody uses any more.
+pub unsafe fn unmap_pml4_entry(pml4: PhysAddr, index: usize) {
+    /// Frees a table at `level`, where the PT is 0, and what it maps
+    unsafe fn free_table(table: u64, level: u32) {
+        for i in 0..512 {
+            let entry = unsafe { *(table as *const u64).add(i) };
+            if entry & PRESENT == 0 {
+                continue;
+            }
+            if level > 0 {
+                unsafe { free_table(entry & ADDRESS_MASK, level - 1) };
+            } else {
+                PAGE_ALLOCATOR.free_page((entry & ADDRESS_MASK) as usize, PageSize::Size4KB);
+            }
+        }
+        PAGE_ALLOCATOR.free_page(table as usize, PageSize::Size4KB);
+    }
+
+    let entry = unsafe { &mut *(pml4.0 as *mut u64).add(index) };
+    if *entry & PRESENT != 0 {
+        let table = *entry & ADDRESS_MASK;
+        *entry = 0;
+        unsafe {
+            // Reloading CR3 flushes every non-global mapping
+            asm!("mov {0}, cr3", "mov cr3, {0}", out(reg) _, options(nostack, preserves_flags));
+            free_table(table, 2);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Fault-safe memory access.
+//!
+//! Debugging helpers sometimes need to look at addresses that may not be
+//! mapped. Instead of taking a page fault, we walk the active page tables
+//! first and only touch the address if it's present.
+//!
+//! The page tables themselves are read through the identity map set up in
+//! `boot.asm`.
+
+use core::ptr;
+
+use x86::controlregs::cr3;
+
+/// The page is present.
+const PTE_PRESENT: u64 = 1 << 0;
+
+/// The entry maps a 2MB or 1GB page instead of pointing to a table.
+const PTE_HUGE: u64 = 1 << 7;
+
+/// The physical address bits of an entry.
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Number of entries in a page table.
+const PT_ENTRIES: usize = 512;
+
+/// Checks whether `addr` is mapped in the current address space.
+pub fn is_mapped(addr: usize) -> bool {
+    // Non-canonical addresses raise #GP
+    let top = (addr as i64) >> 47;
+    if top != 0 && top != -1 {
+        return false;
+    }
+
+    let mut table = unsafe { cr3() } & PTE_ADDR_MASK;
+    for level in (1..=4).rev() {
+        let index = (addr >> (12 + 9 * (level - 1))) % PT_ENTRIES;
+        let entry = unsafe { ptr::read_volatile((table as *const u64).add(index)) };
+
+        if entry & PTE_PRESENT == 0 {
+            return false;
+        }
+
+        // PDPT and PD entries may map 1GB and 2MB pages directly
+        if level == 1 || (level <= 3 && entry & PTE_HUGE != 0) {
+            return true;
+        }
+
+        table = entry & PTE_ADDR_MASK;
+    }
+
+    unreachable!()
+}
+
+/// Reads a byte, or returns `None` if the address is not mapped.
+pub fn read_u8(addr: usize) -> Option<u8> {
+    if !is_mapped(addr) {
+        return None;
+    }
+
+    Some(unsafe { ptr::read_volatile(addr as *const u8) })
+}
@@ -0,0 +1,230 @@
+//! Interrupt-safe reader-writer lock
+//!
+//! Like [`Mutex`](crate::sync::Mutex), this disables interrupts while a
+//! guard is held. Many readers can hold the lock at once. Writers take
+//! priority: once a writer is waiting, new readers wait for it so that
+//! they can't starve it.
+//!
+//! With the `lockdep` feature, readers and writers are both checked like
+//! a mutex.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::{self, Class};
+use crate::cpu::tsc;
+use crate::sync::raw_lock::Backoff;
+use crate::sync::IrqGuard;
+
+/// A writer holds the lock
+const WRITER: usize = 1 << (usize::BITS - 1);
+/// A writer is waiting for the readers to leave
+const WRITER_WAITING: usize = 1 << (usize::BITS - 2);
+/// The remaining bits count the readers
+const READERS: usize = !(WRITER | WRITER_WAITING);
+
+/// No CPU holds the write lock
+const NO_CPU: usize = usize::MAX;
+
+/// A reader-writer lock that disables interrupts while held
+pub struct RwLock<T> {
+    state: AtomicUsize,
+    /// CPU holding the write lock, to catch recursive locking
+    writer_cpu: AtomicUsize,
+    #[cfg(feature = "lockdep")]
+    class: Class,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for RwLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new lock
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            writer_cpu: AtomicUsize::new(NO_CPU),
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires shared access, blocking while a writer holds or wants the lock
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        #[cfg(feature = "lockdep")]
+        lockdep::check(self.class);
+
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Tries to acquire shared access without blocking
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let irq = IrqGuard::new();
+
+        let state = self.state.load(Ordering::Relaxed);
+        let acquired = state & (WRITER | WRITER_WAITING) == 0
+            && state & READERS != READERS
+            && self
+                .state
+                .compare_exchange(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok();
+
+        if acquired {
+            #[cfg(feature = "lockdep")]
+            lockdep::acquired(self.class);
+            Some(RwLockReadGuard { lock: self, _irq: irq })
+        } else {
+            None
+        }
+    }
+
+    /// Tries to acquire shared access until `timeout` has passed
+    ///
+    /// Like [`Mutex::try_lock_for`](crate::sync::Mutex::try_lock_for),
+    /// the timeout is best-effort.
+    #[allow(dead_code)] // No reader has a deadline yet
+    pub fn try_read_for(&self, timeout: Duration) -> Option<RwLockReadGuard<'_, T>> {
+        let deadline = tsc::deadline(timeout);
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(guard) = self.try_read() {
+                return Some(guard);
+            }
+            if tsc::rdtsc() >= deadline {
+                return None;
+            }
+            backoff.spin();
+        }
+    }
+
+    /// Acquires exclusive access, blocking until all readers have left
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        crate::kdebug_assert!(
+            self.state.load(Ordering::Relaxed) & WRITER == 0
+                || self.writer_cpu.load(Ordering::Relaxed) != current_cpu(),
+            "RwLock is already write-locked by this CPU",
+        );
+        #[cfg(feature = "lockdep")]
+        lockdep::check(self.class);
+
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+
+            // Keep new readers out until we get in
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Tries to acquire exclusive access without blocking
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let irq = IrqGuard::new();
+
+        let state = self.state.load(Ordering::Relaxed);
+        let acquired = state & (WRITER | READERS) == 0
+            && self
+                .state
+                .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok();
+
+        if acquired {
+            self.writer_cpu.store(current_cpu(), Ordering::Relaxed);
+            #[cfg(feature = "lockdep")]
+            lockdep::acquired(self.class);
+            Some(RwLockWriteGuard { lock: self, _irq: irq })
+        } else {
+            None
+        }
+    }
+
+    /// Tries to acquire exclusive access until `timeout` has passed
+    ///
+    /// Unlike [`write`](Self::write), this doesn't hold off new readers
+    /// while it waits.
+    #[allow(dead_code)] // No writer has a deadline yet
+    pub fn try_write_for(&self, timeout: Duration) -> Option<RwLockWriteGuard<'_, T>> {
+        let deadline = tsc::deadline(timeout);
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(guard) = self.try_write() {
+                return Some(guard);
+            }
+            if tsc::rdtsc() >= deadline {
+                return None;
+            }
+            backoff.spin();
+        }
+    }
+}
+
+/// Returns the ID of this CPU for the recursion check
+fn current_cpu() -> usize {
+    crate::cpu::get_cpu_id() as usize
+}
+
+/// RAII guard for shared access
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _irq: IrqGuard,
+}
+
+impl<'a, T> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::released(self.lock.class);
+        self.lock.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+/// RAII guard for exclusive access
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+    _irq: IrqGuard,
+}
+
+impl<'a, T> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::released(self.lock.class);
+        self.lock.writer_cpu.store(NO_CPU, Ordering::Relaxed);
+        // Waiting writers set their bit again while they spin
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
+
+impl<'a, T> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
@@ -0,0 +1,75 @@
+//! Allocator self-tests.
+
+use alloc::alloc::{alloc, alloc_zeroed, dealloc};
+use core::alloc::Layout;
+
+use super::fallible::{try_alloc, try_alloc_pages, try_box, try_vec};
+use super::page_allocator::PageSize;
+use super::get_allocator;
+use crate::ensure;
+use crate::error::Result;
+
+crate::selftest!(alloc_zeroed_memory, zeroed);
+crate::selftest!(alloc_aligned_memory, aligned);
+crate::selftest!(alloc_contiguous_pages, contiguous);
+crate::selftest!(alloc_fallible, fallible);
+
+/// Zeroed memory is zero even where a freed block left data behind.
+fn zeroed() -> Result<()> {
+    let layout = Layout::from_size_align(4096, 8).unwrap();
+    unsafe {
+        let dirty = alloc(layout);
+        ensure!(!dirty.is_null(), "allocation failed");
+        dirty.write_bytes(0xa5, layout.size());
+        dealloc(dirty, layout);
+
+        let zeroed = alloc_zeroed(layout);
+        ensure!(!zeroed.is_null(), "zeroed allocation failed");
+        let all_zero = core::slice::from_raw_parts(zeroed, layout.size()).iter().all(|&b| b == 0);
+        dealloc(zeroed, layout);
+        ensure!(all_zero, "zeroed memory has nonzero bytes");
+    }
+    Ok(())
+}
+
+/// Every power-of-two alignment up to a 4KB page is honored.
+fn aligned() -> Result<()> {
+    for shift in 3..=12 {
+        let layout = Layout::from_size_align(24, 1 << shift).unwrap();
+        let ptr = try_alloc(layout)?;
+        let aligned = ptr.as_ptr() as usize % layout.align() == 0;
+        unsafe { dealloc(ptr.as_ptr(), layout) };
+        ensure!(aligned, "allocation is misaligned");
+    }
+    Ok(())
+}
+
+/// Several 4KB pages at once come from one 2MB page.
+fn contiguous() -> Result<()> {
+    let allocator = get_allocator();
+    let before = allocator.allocated_pages(PageSize::Size2MB);
+    let pages = try_alloc_pages(8, PageSize::Size4KB)?;
+    let ok = pages.is_page_aligned(PageSize::Size2MB)
+        && allocator.allocated_pages(PageSize::Size2MB) == before + 1
+        && allocator.verify_allocation(pages, PageSize::Size2MB);
+    allocator.free_page(pages.0, PageSize::Size2MB);
+    ensure!(ok, "the pages aren't one 2MB page");
+
+    ensure!(
+        try_alloc_pages(1024, PageSize::Size4KB).is_err() && try_alloc_pages(2, PageSize::Size2MB).is_err(),
+        "more than a 2MB page was handed out"
+    );
+    Ok(())
+}
+
+/// The fallible allocation functions fail instead of panicking.
+fn fallible() -> Result<()> {
+    let boxed = try_box([7u64; 16])?;
+    ensure!(boxed.iter().all(|&x| x == 7), "box has the wrong contents");
+
+    let vec = try_vec(1u8, 1000)?;
+    ensure!(vec.len() == 1000 && vec.iter().all(|&x| x == 1), "vector has the wrong contents");
+
+    ensure!(try_vec(0u8, usize::MAX / 2).is_err(), "a huge vector was allocated");
+    Ok(())
+}
@@ -1 +1,869 @@
+//! Allocator tests.
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::Write;
+
+use super::bump::BumpAllocator;
+#[cfg(not(feature = "heap-debug"))]
+use super::frame_allocator::{FrameAllocator, TestFrameAllocator};
+use super::get_allocator;
+use super::page_allocator::{PageAllocator, PageSize};
+use super::paging::{self, PageFlags};
+use super::rwlock::RwLock;
+use super::PhysAddr;
+#[cfg(not(feature = "heap-debug"))]
+use super::SimpleAllocator;
+use crate::error::{MemError, MultibootError};
+use crate::sync::irq::are_interrupts_enabled;
+
+#[test_case]
+fn box_allocation() {
+    let boxed_value = Box::new(42u64);
+    assert_eq!(*boxed_value, 42);
+}
+
+#[test_case]
+fn vec_allocation() {
+    let mut vec = Vec::new();
+    vec.push(1);
+    vec.push(2);
+    vec.push(3);
+    assert_eq!(vec.iter().sum::<i32>(), 6);
+}
+
+#[test_case]
+fn large_allocation() {
+    let large_box = Box::new([0xaau8; 1024]);
+    assert!(large_box.iter().all(|&byte| byte == 0xaa));
+}
+
+#[test_case]
+fn many_boxes() {
+    // More than fit in a single 2MB page, so this forces splits
+    let boxes: Vec<Box<usize>> = (0..1000).map(Box::new).collect();
+    for (i, value) in boxes.iter().enumerate() {
+        assert_eq!(**value, i);
+    }
+}
+
+#[test_case]
+fn free_pages_are_reused() {
+    let allocator = get_allocator();
+    let before = allocator.stats();
+
+    for _ in 0..1000 {
+        let page = allocator
+            .allocate_page(PageSize::Size4KB)
+            .expect("Out of memory");
+        allocator.free_page(page, PageSize::Size4KB);
+    }
+
+    let after = allocator.stats();
+    assert_eq!(before.free_bytes(), after.free_bytes());
+}
+
+#[test_case]
+fn page_alignment() {
+    let allocator = get_allocator();
+    for size in [PageSize::Size4KB, PageSize::Size2MB] {
+        let page = allocator.allocate_page(size).expect("Out of memory");
+        assert_eq!(page % size.bytes(), 0);
+        allocator.free_page(page, size);
+    }
+}
+
+#[test_case]
+fn phys_addr_checked_add() {
+    assert_eq!(PhysAddr(0x1000).checked_add(0x234), Some(PhysAddr(0x1234)));
+    assert_eq!(PhysAddr(usize::MAX - 1).checked_add(1), Some(PhysAddr(usize::MAX)));
+    assert_eq!(PhysAddr(usize::MAX).checked_add(1), None);
+}
+
+#[test_case]
+fn physically_contiguous_pages() {
+    let pages = |addrs: &[usize]| addrs.iter().map(|&addr| PhysAddr(addr)).collect::<Vec<_>>();
+
+    assert!(PageAllocator::is_physically_contiguous(&[]));
+    assert!(PageAllocator::is_physically_contiguous(&pages(&[0x5000])));
+    assert!(PageAllocator::is_physically_contiguous(&pages(&[0x5000, 0x6000, 0x7000])));
+    assert!(!PageAllocator::is_physically_contiguous(&pages(&[0x5000, 0x7000])));
+    assert!(!PageAllocator::is_physically_contiguous(&pages(&[0x6000, 0x5000])));
+    assert!(!PageAllocator::is_physically_contiguous(&pages(&[usize::MAX - 0xfff, 0])));
+}
+
+#[test_case]
+fn verify_allocation() {
+    let allocator = get_allocator();
+    let page = allocator.allocate_page(PageSize::Size4KB).expect("Out of memory");
+
+    assert!(allocator.verify_allocation(PhysAddr(page), PageSize::Size4KB));
+    assert!(!allocator.verify_allocation(PhysAddr(page + 8), PageSize::Size4KB));
+    assert!(!allocator.verify_allocation(PhysAddr(page), PageSize::Size2MB));
+
+    allocator.free_page(page, PageSize::Size4KB);
+    assert!(!allocator.verify_allocation(PhysAddr(page), PageSize::Size4KB));
+
+    // Aligned for both sizes, so only the recorded size tells them apart
+    let page = allocator.allocate_page(PageSize::Size2MB).expect("Out of memory");
+    assert!(allocator.verify_allocation(PhysAddr(page), PageSize::Size2MB));
+    assert!(!allocator.verify_allocation(PhysAddr(page), PageSize::Size4KB));
+    allocator.free_page(page, PageSize::Size2MB);
+}
+
+/// Interior pointers, the wrong size, pages past the end and double frees
+#[test_case]
+fn bad_frees_are_ignored() {
+    let allocator = get_allocator();
+    let page = allocator.allocate_page(PageSize::Size4KB).expect("Out of memory");
+    let before = allocator.stats();
+
+    allocator.free_page(page + 8, PageSize::Size4KB);
+    allocator.free_page(page, PageSize::Size2MB);
+    allocator.free_page(usize::MAX - 0xfff, PageSize::Size4KB);
+    allocator.free_page(PageSize::Size2MB.align_down(PhysAddr(usize::MAX)).0, PageSize::Size2MB);
+    assert_eq!(allocator.stats().free_bytes(), before.free_bytes());
+    assert!(allocator.verify_allocation(PhysAddr(page), PageSize::Size4KB));
+
+    allocator.free_page(page, PageSize::Size4KB);
+    let after = allocator.stats();
+    allocator.free_page(page, PageSize::Size4KB);
+    assert_eq!(allocator.stats().free_bytes(), after.free_bytes());
+}
+
+/// Takes every lock order of the page allocator, so lockdep panics if
+/// two of them disagree
+#[cfg(feature = "lockdep")]
+#[test_case]
+fn lockdep_page_allocator() {
+    use core::time::Duration;
+
+    let allocator = get_allocator();
+    let mut pages = Vec::with_capacity(4096);
+    let free_2mb = allocator.stats().free_2mb;
+
+    // The 4KB pages run out and a 2MB page is split, then merged again
+    while pages.len() < pages.capacity() && allocator.stats().free_2mb == free_2mb {
+        pages.push(allocator.allocate_page(PageSize::Size4KB).expect("Out of memory"));
+    }
+    for page in pages {
+        allocator.free_page(page, PageSize::Size4KB);
+    }
+
+    let page = allocator.allocate_page(PageSize::Size2MB).expect("Out of memory");
+    assert!(allocator.verify_allocation(PhysAddr(page), PageSize::Size2MB));
+    allocator.free_page(page, PageSize::Size2MB);
+
+    assert!(allocator.try_summary_for(Duration::from_millis(1)).is_some());
+}
+
+#[test_case]
+fn bump_allocator() {
+    let mut region = [0u8; 256];
+    let start = region.as_mut_ptr() as usize;
+    let bump = BumpAllocator::new();
+    unsafe {
+        bump.init(PhysAddr(start), PhysAddr(start + region.len()));
+    }
+
+    let a = bump.alloc(Layout::from_size_align(3, 1).unwrap());
+    let b = bump.alloc(Layout::from_size_align(8, 8).unwrap());
+    assert_eq!(a as usize, start);
+    assert_eq!(b as usize % 8, 0);
+    assert!(bump.contains(a) && bump.contains(b));
+
+    // Exhausted
+    assert!(bump.alloc(Layout::from_size_align(256, 1).unwrap()).is_null());
+
+    // Closed
+    let end = bump.finish();
+    assert_eq!(end.0, b as usize + 8);
+    assert!(bump.alloc(Layout::from_size_align(1, 1).unwrap()).is_null());
+}
+
+#[test_case]
+fn rwlock_exclusion() {
+    let lock = RwLock::new(0);
+
+    // Readers share
+    let a = lock.read();
+    let b = lock.try_read().expect("second reader was refused");
+    assert!(lock.try_write().is_none());
+    drop((a, b));
+
+    // Writers don't
+    let mut writer = lock.try_write().expect("writer was refused");
+    *writer += 1;
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    drop(writer);
+
+    assert_eq!(*lock.read(), 1);
+}
+
+#[test_case]
+fn rwlock_restores_interrupts() {
+    let lock = RwLock::new(());
+    let enabled = are_interrupts_enabled();
+    {
+        let _guard = lock.read();
+        assert!(!are_interrupts_enabled());
+    }
+    assert_eq!(are_interrupts_enabled(), enabled);
+    {
+        let _guard = lock.write();
+        assert!(!are_interrupts_enabled());
+    }
+    assert_eq!(are_interrupts_enabled(), enabled);
+}
+
+/// Red zones move the data away from the start of the frame
+#[cfg(not(feature = "heap-debug"))]
+#[test_case]
+fn simple_allocator_page_sizes() {
+    static FRAMES: TestFrameAllocator = TestFrameAllocator::new();
+    let allocator = SimpleAllocator::new(&FRAMES);
+
+    let small = Layout::from_size_align(16, 8).unwrap();
+    let large = Layout::from_size_align(8192, 8).unwrap();
+    unsafe {
+        let a = allocator.alloc(small);
+        let b = allocator.alloc(large);
+        assert!(!a.is_null() && !b.is_null());
+        assert_eq!(a as usize % PageSize::Size4KB.bytes(), 0);
+        assert_eq!(b as usize % PageSize::Size2MB.bytes(), 0);
+
+        let frames = FRAMES.allocated();
+        assert!(frames.contains(&(a as usize, PageSize::Size4KB)));
+        assert!(frames.contains(&(b as usize, PageSize::Size2MB)));
+        assert!(FRAMES.verify_allocation(PhysAddr(a as usize), PageSize::Size4KB));
+        assert!(!FRAMES.verify_allocation(PhysAddr(a as usize + 16), PageSize::Size4KB));
+
+        allocator.dealloc(a, small);
+        allocator.dealloc(b, large);
+    }
+    assert!(FRAMES.allocated().is_empty());
+}
+
+/// Red zones make a 2MB allocation too big for a frame
+#[cfg(not(feature = "heap-debug"))]
+#[test_case]
+fn simple_allocator_out_of_frames() {
+    static FRAMES: TestFrameAllocator = TestFrameAllocator::new();
+    let allocator = SimpleAllocator::new(&FRAMES);
+
+    // The backing store holds four 2MB frames
+    let layout = Layout::from_size_align(PageSize::Size2MB.bytes(), 8).unwrap();
+    unsafe {
+        let frames: Vec<*mut u8> = (0..4).map(|_| allocator.alloc(layout)).collect();
+        assert!(frames.iter().all(|frame| !frame.is_null()));
+        assert!(allocator.alloc(layout).is_null());
+
+        for frame in frames {
+            allocator.dealloc(frame, layout);
+        }
+    }
+    assert!(FRAMES.allocated().is_empty());
+}
+
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+#[test_case]
+fn page_table_walk_levels() {
+    static mut TABLES: [Table; 4] = [const { Table([0; 512]) }; 4];
+
+    unsafe {
+        let tables = &mut *core::ptr::addr_of_mut!(TABLES);
+        let addr = |table: &Table| table as *const Table as u64;
+        let (pdpt, pd, pt) = (addr(&tables[1]), addr(&tables[2]), addr(&tables[3]));
+
+        // 0x0 is mapped by a 4KB page, 0x200000 by a read-only 2MB page,
+        // and 1GB upwards by an NX 1GB page
+        tables[0].0[0] = pdpt | 0b111;
+        tables[1].0[0] = pd | 0b111;
+        tables[1].0[1] = 0x8000_0000 | (1 << 63) | 0b1000_0011;
+        tables[2].0[0] = pt | 0b111;
+        tables[2].0[1] = 0x40_0000 | 0b1000_0101;
+        tables[3].0[0] = 0x1234_5000 | 0b11;
+
+        let pml4 = PhysAddr(addr(&tables[0]) as usize);
+        assert_eq!(
+            paging::walk(pml4, 0x123),
+            Some((PhysAddr(0x1234_5123), PageFlags { writable: true, user: false, nx: false, large_page: false }))
+        );
+        assert_eq!(
+            paging::walk(pml4, 0x20_0042),
+            Some((PhysAddr(0x40_0042), PageFlags { writable: false, user: true, nx: false, large_page: true }))
+        );
+        assert_eq!(
+            paging::walk(pml4, 0x4000_1000),
+            Some((PhysAddr(0x8000_1000), PageFlags { writable: true, user: false, nx: true, large_page: true }))
+        );
+        assert_eq!(paging::walk(pml4, 0x1000), None);
+        assert_eq!(paging::walk(pml4, 0x8000_0000), None);
+        assert_eq!(paging::walk(pml4, 0x0000_8000_0000_0000), None);
+    }
+}
+
+#[test_case]
+fn page_table_walk_identity_map() {
+    static VALUE: u64 = 0;
+    let virt = &VALUE as *const u64 as u64;
+    let (phys, flags) = paging::page_table_walk(virt).expect("Kernel is not mapped");
+    assert_eq!(phys.0 as u64, virt);
+    assert!(flags.writable);
+    // The null guard split the first 2MB into 4KB pages
+    assert_eq!(flags.large_page, virt >= PageSize::Size2MB.bytes() as u64);
+}
+
+#[test_case]
+fn null_guard_is_unmapped() {
+    assert_eq!(paging::page_table_walk(0), None);
+    assert_eq!(paging::page_table_walk(0x40e), None);
+    assert!(!super::probe::is_mapped(8));
+
+    // Everything around it is mapped as before
+    let guard_end = paging::NULL_GUARD_SIZE as u64;
+    let (phys, flags) = paging::page_table_walk(guard_end).expect("Guard is too large");
+    assert_eq!(phys.0 as u64, guard_end);
+    assert!(flags.writable && !flags.large_page);
+    let (phys, flags) = paging::page_table_walk(0x20_0000).expect("Second 2MB is not mapped");
+    assert_eq!(phys.0, 0x20_0000);
+    assert!(flags.writable && flags.large_page);
+}
+
+/// Reads a field through a null pointer. The page fault must be reported
+/// as "NULL pointer dereference (offset 0x8)". That fails the run, so the
+/// test is only built with the `null-deref-test` feature.
+#[cfg(feature = "null-deref-test")]
+#[test_case]
+fn null_deref_is_reported() {
+    struct Node {
+        _next: Option<Box<Node>>,
+        value: u64,
+    }
+
+    let node = core::hint::black_box(core::ptr::null::<Node>());
+    let value = unsafe { core::ptr::read_volatile(&raw const (*node).value) };
+    panic!("Read {} through a null pointer", value);
+}
+
+#[test_case]
+fn boot_info_tags_stay_in_bounds() {
+    let boot_info = super::boot_info().expect("No boot information");
+    let start = boot_info as *const _ as usize;
+
+    let mut found_mmap = false;
+    for tag in boot_info.tags() {
+        let payload = tag.payload();
+        assert_eq!(payload.len() + 8, tag.size as usize);
+        assert!(payload.as_ptr() as usize + payload.len() <= start + boot_info.total_size());
+        found_mmap |= tag.name() == Some("memory map");
+    }
+    assert!(found_mmap);
+}
+
+#[test_case]
+fn memory_map_counts_do_not_consume() {
+    let mmap = super::boot_info()
+        .and_then(|boot_info| boot_info.memory_map_tag().ok())
+        .expect("No memory map");
+
+    let areas = mmap.memory_areas();
+    let copy = areas.clone();
+    assert_eq!(areas.count(), mmap.count_entries());
+    assert_eq!(copy.count(), mmap.count_entries());
+
+    let available: u64 = mmap
+        .memory_areas()
+        .filter(|area| area.typ == super::multiboot2::MEMORY_AREA_TYPE_AVAILABLE)
+        .map(|area| area.length)
+        .sum();
+    assert_eq!(mmap.count_available_bytes(), available);
+    assert!(mmap.count_entries() > 0);
+}
+
+#[test_case]
+fn module_kind_from_magic() {
+    use super::multiboot2::ModuleKind;
+
+    /// Passes `bytes` as a module through the parser
+    fn kind(bytes: &[u8]) -> (ModuleKind, bool, bool) {
+        let start = bytes.as_ptr() as u32;
+        let mut raw = RawBootInfo::new();
+        // A module tag with the command line "test", then the end tag
+        raw.set(0, 40).set(8, 3).set(12, 21).set(16, start).set(20, start + bytes.len() as u32);
+        raw.bytes_mut()[24..29].copy_from_slice(b"test\0");
+        raw.set(32, 0).set(36, 8);
+
+        let module = raw.parse().unwrap().modules().next().unwrap();
+        assert_eq!((module.cmdline(), module.size()), ("test", bytes.len()));
+        (module.kind(), module.is_elf(), module.is_gzip())
+    }
+
+    static ELF64: [u8; 8] = [0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+    static ELF32: [u8; 8] = [0x7f, b'E', b'L', b'F', 1, 1, 1, 0];
+    static GZIP: [u8; 4] = [0x1f, 0x8b, 8, 0];
+    static TEXT: [u8; 4] = *b"ELF\n";
+
+    assert_eq!(kind(&ELF64), (ModuleKind::Elf64, true, false));
+    assert_eq!(kind(&ELF32), (ModuleKind::Unknown, true, false));
+    assert_eq!(kind(&GZIP), (ModuleKind::Gzip, false, true));
+    assert_eq!(kind(&TEXT), (ModuleKind::Unknown, false, false));
+    // Too short for any magic
+    assert_eq!(kind(&ELF64[..2]), (ModuleKind::Unknown, false, false));
+}
+
+#[test_case]
+fn try_alloc_pages_until_exhausted() {
+    use super::fallible::try_alloc_pages;
+
+    // Room for every 2MB page, allocated before the pages run out
+    let mut pages: Vec<PhysAddr> = Vec::with_capacity(4096);
+
+    let error = loop {
+        match try_alloc_pages(1, PageSize::Size2MB) {
+            Ok(addr) => pages.push(addr),
+            Err(error) => break error,
+        }
+        assert!(pages.len() < pages.capacity(), "more 2MB pages than expected");
+    };
+    assert!(!pages.is_empty());
+    assert_eq!(error.layout.size(), PageSize::Size2MB.bytes());
+    assert_eq!(error.free.expect("no free memory snapshot").free_2mb, 0);
+
+    for &addr in &pages {
+        get_allocator().free_page(addr.0, PageSize::Size2MB);
+    }
+    let addr = try_alloc_pages(1, PageSize::Size2MB).expect("freed pages are not reused");
+    get_allocator().free_page(addr.0, PageSize::Size2MB);
+}
+
+#[test_case]
+fn try_alloc_rejects_what_cannot_fit() {
+    use super::fallible::{try_alloc, try_alloc_pages, try_box, try_vec};
+
+    // Allocations bigger than a 2MB page used to get one anyway
+    let layout = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+    let error = try_alloc(layout).unwrap_err();
+    assert_eq!(error.layout, layout);
+    assert!(try_vec(0u8, 3 * 1024 * 1024).is_err());
+    assert!(try_alloc_pages(2, PageSize::Size2MB).is_err());
+    assert!(try_alloc_pages(0, PageSize::Size4KB).is_err());
+
+    let boxed = try_box([7u8; 64]).unwrap();
+    assert_eq!(boxed[63], 7);
+    let vec = try_vec(1u32, 100).unwrap();
+    assert_eq!(vec.iter().sum::<u32>(), 100);
+
+    // Several 4KB pages share a 2MB page
+    let addr = try_alloc_pages(3, PageSize::Size4KB).unwrap();
+    assert!(addr.is_page_aligned(PageSize::Size2MB));
+    get_allocator().free_page(addr.0, PageSize::Size2MB);
+}
+
+#[test_case]
+fn early_translation_matches_page_tables() {
+    // We still run on the bootloader's identity map
+    assert_eq!(super::kernel_phys_offset(), 0);
+
+    let cpu = crate::cpu::get_current() as *const _ as usize;
+    assert_eq!(super::virt_to_phys_early(cpu), Some(cpu));
+    assert_eq!(super::virt_to_phys_early(0), Some(0));
+    assert_eq!(super::virt_to_phys(cpu), Some(PhysAddr(cpu)));
+}
+
+/// Boot information built word by word, for feeding the parser bad data
+#[repr(C, align(8))]
+struct RawBootInfo([u32; 32]);
+
+impl RawBootInfo {
+    /// Starts with the header and no tags, with `total_size` set later
+    fn new() -> Self {
+        Self([0; 32])
+    }
+
+    fn set(&mut self, offset: usize, value: u32) -> &mut Self {
+        self.0[offset / 4] = value;
+        self
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.0.as_mut_ptr() as *mut u8, 128) }
+    }
+
+    fn parse(&self) -> Result<&'static super::multiboot2::BootInfo, MultibootError> {
+        unsafe { super::multiboot2::BootInfo::parse(self.0.as_ptr() as *const u8) }
+    }
+}
+
+#[test_case]
+fn corrupt_boot_info_is_rejected() {
+    use super::multiboot2::BootInfo;
+
+    assert_eq!(
+        unsafe { BootInfo::parse(core::ptr::null()) }.err(),
+        Some(MultibootError::NullPointer)
+    );
+
+    let mut raw = RawBootInfo::new();
+    raw.set(0, 16).set(8, 0).set(12, 8);
+    assert!(raw.parse().is_ok());
+    let misaligned = unsafe { raw.0.as_ptr().cast::<u8>().add(4) };
+    assert_eq!(
+        unsafe { BootInfo::parse(misaligned) }.err(),
+        Some(MultibootError::BadAlignment(misaligned as usize))
+    );
+
+    // No room for the end tag
+    raw.set(0, 12);
+    assert_eq!(raw.parse().err(), Some(MultibootError::BadTotalSize(12)));
+
+    // A command line tag that claims more than there is
+    raw.set(0, 32).set(8, 1).set(12, 100);
+    assert_eq!(
+        raw.parse().err(),
+        Some(MultibootError::TagOverrun { offset: 8, size: 100 })
+    );
+
+    // A tag too small for its own header, which used to loop forever
+    raw.set(12, 0);
+    assert_eq!(
+        raw.parse().err(),
+        Some(MultibootError::TagOverrun { offset: 8, size: 0 })
+    );
+
+    // The tags stop at total_size without an end tag
+    raw.set(12, 16).set(24, 1).set(28, 8);
+    assert_eq!(raw.parse().err(), Some(MultibootError::MissingTag(0)));
+}
+
+#[test_case]
+fn boot_info_tag_errors() {
+    let mut raw = RawBootInfo::new();
+    // A command line tag with a bad UTF-8 byte, then the end tag
+    raw.set(0, 40).set(8, 1).set(12, 12).set(24, 0).set(28, 8);
+    raw.bytes_mut()[16..20].copy_from_slice(&[b'a', 0xff, b'b', 0]);
+
+    let boot_info = raw.parse().unwrap();
+    assert_eq!(boot_info.command_line(), Err(MultibootError::StringNotUtf8));
+    assert_eq!(boot_info.memory_map_tag().err(), Some(MultibootError::MissingTag(6)));
+    assert_eq!(boot_info.framebuffer_tag().err(), Some(MultibootError::MissingTag(8)));
+
+    raw.bytes_mut()[17] = b'b';
+    assert_eq!(raw.parse().unwrap().command_line(), Ok("abb"));
+}
+
+#[test_case]
+fn short_rgb_framebuffer_tag() {
+    use super::multiboot2::FramebufferType;
+
+    let mut raw = RawBootInfo::new();
+    // An RGB framebuffer tag that ends before its color fields
+    raw.set(0, 48).set(8, 8).set(12, 32).set(40, 0).set(44, 8);
+    raw.bytes_mut()[37] = 1;
+    let tag = raw.parse().unwrap().framebuffer_tag().unwrap();
+    assert!(matches!(tag.framebuffer_type(), FramebufferType::Unknown(1)));
+
+    raw.set(0, 56).set(12, 38).set(48, 0).set(52, 8);
+    raw.bytes_mut()[40..46].copy_from_slice(&[16, 8, 8, 8, 0, 8]);
+    let tag = raw.parse().unwrap().framebuffer_tag().unwrap();
+    let FramebufferType::Rgb(fields) = tag.framebuffer_type() else {
+        panic!("not an RGB framebuffer");
+    };
+    assert_eq!((fields.red_position, fields.green_position, fields.blue_position), (16, 8, 0));
+}
+
+#[test_case]
+fn memory_map_with_zero_entry_size() {
+    let mut raw = RawBootInfo::new();
+    // A memory map tag with one entry's worth of bytes but entry_size 0
+    raw.set(0, 56).set(8, 6).set(12, 40).set(16, 0).set(20, 0);
+    raw.set(24, 0).set(32, 0x1000_0000).set(40, 1);
+    raw.set(48, 0).set(52, 8);
+
+    let mmap = raw.parse().unwrap().memory_map_tag().unwrap();
+    assert_eq!(mmap.count_entries(), 0);
+    assert_eq!(mmap.validate(), Err(MemError::MemoryMapEntryTooSmall(0)));
+}
+
+#[test_case]
+fn memory_init_error_has_context() {
+    use crate::platform::MemorySource;
+
+    let mut raw = RawBootInfo::new();
+    raw.set(0, 32).set(8, 1).set(12, 100);
+    let addr = raw.0.as_ptr() as usize;
+
+    let error = unsafe { super::init(MemorySource::Multiboot2(addr)) }.unwrap_err();
+    let mut buffer = crate::testing::Buffer::new();
+    write!(buffer, "{}", error).unwrap();
+    assert_eq!(
+        buffer.as_str(),
+        "initializing memory: parsing multiboot info: \
+         multiboot: tag at offset 0x8 has a bad size of 100 bytes"
+    );
+    assert_eq!(error.code(), 0x503);
+}
+
+#[test_case]
+fn allocator_summary_counts_pages() {
+    use super::page_allocator::Integrity;
+    use core::time::Duration;
+
+    let timeout = Duration::from_millis(10);
+    let before = get_allocator().try_summary_for(timeout).unwrap();
+    assert_eq!(before.integrity, Integrity::Ok);
+
+    let addr = get_allocator().allocate_page(PageSize::Size4KB).unwrap();
+    let during = get_allocator().try_summary_for(timeout).unwrap();
+    assert_eq!(during.allocated_4kb, before.allocated_4kb + 1);
+    assert!(during.peak_bytes >= (during.allocated_4kb + during.allocated_2mb * 512) * 4096);
+
+    get_allocator().free_page(addr, PageSize::Size4KB);
+    let after = get_allocator().try_summary_for(timeout).unwrap();
+    assert_eq!(after.allocated_4kb, before.allocated_4kb);
+    assert_eq!(after.integrity, Integrity::Ok);
+
+    let mut buffer = crate::testing::Buffer::new();
+    write!(buffer, "{}", after).unwrap();
+    assert!(buffer.as_str().starts_with("Pages free: "));
+    assert!(buffer.as_str().contains("\nFree lists ok"));
+}
+
+/// The panic handler must not hang if it panicked with the lock held.
+#[test_case]
+fn allocator_summary_gives_up_when_locked() {
+    use core::time::Duration;
+
+    let allocator = get_allocator();
+    let summary = allocator.with_pages_locked(|| allocator.try_summary_for(Duration::from_millis(1)));
+    assert!(summary.is_none());
+    assert!(allocator.try_summary_for(Duration::from_millis(1)).is_some());
+}
+
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn heap_overflow_by_one_byte() {
+    use super::heap;
+
+    let block = Box::new([0u8; 24]);
+    let ptr = block.as_ptr() as *mut u8;
+    unsafe {
+        assert!(heap::check(ptr).is_ok());
+
+        let saved = ptr.add(24).read();
+        ptr.add(24).write(!saved);
+        let corruption = heap::check(ptr).unwrap_err();
+        assert!(corruption.back);
+        assert_eq!((corruption.addr, corruption.size), (ptr as usize, 24));
+        assert_eq!(corruption.found[0], !corruption.expected[0]);
+        assert_eq!(corruption.found[1..], corruption.expected[1..]);
+        assert!(heap::try_check_all().is_err());
+
+        let mut buffer = crate::testing::Buffer::new();
+        write!(buffer, "{}", corruption).unwrap();
+        assert!(buffer.as_str().starts_with("heap overflow of the 24-byte block at"));
+
+        ptr.add(24).write(saved);
+        assert!(heap::check(ptr).is_ok());
+    }
+}
+
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn heap_underflow() {
+    use super::heap;
+
+    let block = Box::new(0u64);
+    let ptr = &*block as *const u64 as *mut u8;
+    unsafe {
+        let saved = ptr.sub(1).read();
+        ptr.sub(1).write(!saved);
+        let corruption = heap::check(ptr).unwrap_err();
+        assert!(!corruption.back);
+        assert_eq!(corruption.found[heap::CANARY_LEN - 1], !saved);
+
+        ptr.sub(1).write(saved);
+        assert!(heap::check(ptr).is_ok());
+    }
+}
+
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn heap_canaries_differ_by_block() {
+    use super::heap;
+
+    let a = Box::new(1u32);
+    let b = Box::new(2u32);
+    let (a, b) = (&*a as *const u32 as *mut u8, &*b as *const u32 as *mut u8);
+    unsafe {
+        // Another block's canary doesn't pass
+        let saved = a.add(4).cast::<[u8; 16]>().read();
+        a.add(4).copy_from(b.add(4), heap::CANARY_LEN);
+        assert!(heap::check(a).is_err());
+        a.add(4).cast::<[u8; 16]>().write(saved);
+    }
+}
+
+/// Lots of allocations of all sizes and alignments, to show the red
+/// zones don't report corruption that isn't there.
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn heap_stress_has_no_false_positives() {
+    use super::heap;
+
+    let before = heap::check_all();
+    let mut blocks: Vec<Box<[u8]>> = Vec::new();
+    for i in 0..200usize {
+        let len = (i * 37) % 5000 + 1;
+        let mut block = alloc::vec![i as u8; len].into_boxed_slice();
+        block[len - 1] = 0xff;
+        blocks.push(block);
+        if i % 3 == 0 {
+            blocks.swap_remove(i % blocks.len());
+        }
+    }
+    assert_eq!(heap::check_all(), before + blocks.len() + 1);
+
+    for align in [1, 8, 64, 4096] {
+        let layout = Layout::from_size_align(100, align).unwrap();
+        unsafe {
+            let ptr = super::ALLOCATOR.alloc(layout);
+            assert_eq!(ptr as usize % align, 0);
+            ptr.write_bytes(0xaa, 100);
+            assert!(heap::check(ptr).is_ok());
+            super::ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+
+    drop(blocks);
+    assert_eq!(heap::check_all(), before);
+}
+
+#[cfg(feature = "faultinject")]
+#[test_case]
+fn faultinject_fails_nth_allocation() {
+    use crate::faultinject;
+
+    let injected = faultinject::injected();
+    faultinject::fail_nth_allocation(2);
+    let first = get_allocator().allocate_page(PageSize::Size4KB);
+    let second = get_allocator().allocate_page(PageSize::Size4KB);
+    let third = get_allocator().allocate_page(PageSize::Size4KB);
+    faultinject::reset();
+
+    assert!(first.is_some());
+    assert_eq!(second, None);
+    assert!(third.is_some());
+    assert_eq!(faultinject::injected(), injected + 1);
+    get_allocator().free_page(first.unwrap(), PageSize::Size4KB);
+    get_allocator().free_page(third.unwrap(), PageSize::Size4KB);
+}
+
+#[cfg(feature = "faultinject")]
+#[test_case]
+fn faultinject_fallible_allocation_degrades() {
+    use super::fallible::{try_alloc_pages, try_box, try_vec};
+    use crate::faultinject;
+
+    // Only 4KB pages run out
+    faultinject::fail_allocations_matching(PageSize::Size4KB.bytes()..PageSize::Size4KB.bytes() + 1);
+    let boxed = try_box(42u64);
+    let vec = try_vec(0u8, 100);
+    let small = try_alloc_pages(1, PageSize::Size4KB);
+    let large = try_alloc_pages(1, PageSize::Size2MB);
+    faultinject::reset();
+
+    let error = boxed.unwrap_err();
+    assert_eq!(error.layout, Layout::new::<u64>());
+    assert!(error.free.is_some());
+    assert_eq!(vec.unwrap_err().layout.size(), 100);
+    assert!(small.is_err());
+    get_allocator().free_page(large.unwrap().0, PageSize::Size2MB);
+
+    assert_eq!(*try_box(42u64).unwrap(), 42);
+}
+
+#[cfg(feature = "faultinject")]
+#[test_case]
+fn faultinject_corrupt_tag_is_reported() {
+    use crate::error::ResultExt;
+    use crate::faultinject;
+
+    let mut raw = RawBootInfo::new();
+    // A command line tag, then the end tag
+    raw.set(0, 32).set(8, 1).set(12, 12).set(24, 0).set(28, 8);
+    raw.bytes_mut()[16..20].copy_from_slice(b"abc\0");
+
+    // Parsed the way memory::init does, without touching the live allocator
+    faultinject::corrupt_next_multiboot_tag();
+    let error = raw.parse().context("parsing multiboot info").err().unwrap();
+    let mut buffer = crate::testing::Buffer::new();
+    write!(buffer, "{}", error).unwrap();
+    assert_eq!(
+        buffer.as_str(),
+        "parsing multiboot info: multiboot: tag at offset 0x8 has a bad size of 0 bytes"
+    );
+
+    // Only the next tag is corrupted
+    let boot_info = raw.parse().unwrap();
+    faultinject::corrupt_next_multiboot_tag();
+    assert_eq!(boot_info.command_line(), Err(MultibootError::MissingTag(1)));
+    assert_eq!(boot_info.command_line(), Ok("abc"));
+}
+
+#[test_case]
+fn leak_check_counts_unfreed_pages() {
+    use super::leak_check::{compare, snapshot};
+
+    let before = snapshot();
+    let small = get_allocator().allocate_page(PageSize::Size4KB).unwrap();
+    let large = get_allocator().allocate_page(PageSize::Size2MB).unwrap();
+    let report = compare(&before, &snapshot());
+    assert_eq!((report.delta_4kb, report.delta_2mb), (1, 1));
+    assert!(!report.is_balanced());
+
+    let mut buffer = crate::testing::Buffer::new();
+    write!(buffer, "{}", report).unwrap();
+    assert!(buffer.as_str().starts_with("+1 4KB and +1 2MB pages still allocated"));
+
+    get_allocator().free_page(small, PageSize::Size4KB);
+    get_allocator().free_page(large, PageSize::Size2MB);
+    assert!(compare(&before, &snapshot()).is_balanced());
+}
+
+#[cfg(feature = "heap-debug")]
+#[test_case]
+fn leak_check_groups_heap_blocks_by_call_site() {
+    use super::leak_check::{compare, snapshot};
+
+    let before = snapshot();
+    let mut blocks = Vec::with_capacity(3);
+    let once = Box::new(0u8);
+    for i in 0..3u64 {
+        blocks.push(Box::new(i));
+    }
+    let report = compare(&before, &snapshot());
+
+    // The Vec's buffer, `once` and the three from the loop
+    assert_eq!(report.delta_4kb, 5);
+    let sites: Vec<usize> = report.sites.iter().flatten().map(|site| site.blocks).collect();
+    assert_eq!(sites.iter().sum::<usize>() + report.other_blocks, 5);
+    assert_eq!(sites[0], 3);
+
+    drop(once);
+    drop(blocks);
+    assert!(compare(&before, &snapshot()).is_balanced());
+}
+
+/// Leaves a heap block allocated, so the test runner fails it with the
+/// leak report. That fails the run, so the test is only built with the
+/// `leak-test` feature, by `make test-leak-check`.
+#[cfg(feature = "leak-test")]
+#[test_case]
+fn leaky_test_is_caught() {
+    core::mem::forget(Box::new(42u64));
+}
@@ -0,0 +1,11 @@
+//! Where the boot information comes from.
+
+/// The structure that describes physical memory
+#[derive(Debug, Clone, Copy)]
+pub enum MemorySource {
+    /// Multiboot2 boot information at this address, on x86-64
+    Multiboot2(usize),
+    /// A flattened device tree at this address
+    #[allow(dead_code)] // No bootloader passes a device tree yet
+    DeviceTree(usize),
+}
@@ -0,0 +1,43 @@
+//! I/O port access that tests can stand in for.
+//!
+//! Drivers that poll a device through a few ports do it through a
+//! [`PortIo`], so their tests can fake the device, feed it errors and
+//! check what was written to it. [`Pio`] is the real thing.
+
+/// Byte-wide I/O ports.
+///
+/// The methods take `&self` like the `in` and `out` instructions, which
+/// change nothing the compiler knows about. Fakes keep their state in
+/// cells.
+pub trait PortIo {
+    /// Reads a byte from `port`.
+    fn read(&self, port: u16) -> u8;
+
+    /// Writes a byte to `port`.
+    fn write(&self, port: u16, value: u8);
+}
+
+/// The machine's I/O ports.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Pio;
+
+impl PortIo for Pio {
+    fn read(&self, port: u16) -> u8 {
+        unsafe { x86::io::inb(port) }
+    }
+
+    fn write(&self, port: u16, value: u8) {
+        unsafe { x86::io::outb(port, value) }
+    }
+}
+
+/// Lets a test keep its fake and look at it while a driver uses it.
+impl<P: PortIo> PortIo for &P {
+    fn read(&self, port: u16) -> u8 {
+        (**self).read(port)
+    }
+
+    fn write(&self, port: u16, value: u8) {
+        (**self).write(port, value)
+    }
+}
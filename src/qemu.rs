@@ -0,0 +1,115 @@
+//! QEMU `isa-debug-exit` support.
+//!
+//! This lets automated tests terminate QEMU with a status code instead of
+//! relying on timeouts. Run QEMU with:
+//!
+//! ```text
+//! qemu-system-x86_64 ... -device isa-debug-exit,iobase=0xf4,iosize=0x04
+//! ```
+//!
+//! QEMU exits with `(value << 1) | 1`, so [`ExitCode::Success`] becomes
+//! host status 33, [`ExitCode::Failed`] 35 and [`ExitCode::Panicked`] 37.
+//! None can be confused with QEMU's own exit statuses: 0 when the machine
+//! resets under `-no-reboot`, as after a triple fault, and 1 on errors.
+//! `test-runner.sh` turns them into 0, 1 and 2.
+//!
+//! A device at another port or width can be given on the command line as
+//! `qemu.exit=<port>[:<bytes>]`, e.g. `qemu.exit=0x501:2`.
+
+#[cfg(test)]
+mod test;
+
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+
+use x86::io::{outb, outl, outw};
+
+use crate::cmdline::CommandLine;
+
+/// The default I/O port of the device.
+pub const DEFAULT_EXIT_PORT: u16 = 0xf4;
+
+/// The default width of the port in bytes.
+pub const DEFAULT_EXIT_IOSIZE: u8 = 4;
+
+static EXIT_PORT: AtomicU16 = AtomicU16::new(DEFAULT_EXIT_PORT);
+static EXIT_IOSIZE: AtomicU8 = AtomicU8::new(DEFAULT_EXIT_IOSIZE);
+
+/// Whether the kernel was booted with the `test` command line flag.
+static TEST_MODE: AtomicBool = AtomicBool::new(false);
+
+/// A status to exit QEMU with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ExitCode {
+    /// Host exit status 33.
+    Success = 0x10,
+
+    /// A test failed. Host exit status 35.
+    Failed = 0x11,
+
+    /// The kernel panicked outside a test. Host exit status 37.
+    Panicked = 0x12,
+}
+
+/// Reads the `test` flag and the `qemu.exit=` option.
+pub fn init(cmdline: &CommandLine) {
+    set_test_mode(cmdline.has("test"));
+    if let Some(value) = cmdline.get("qemu.exit") {
+        match parse_exit_device(value) {
+            Some((port, iosize)) => set_exit_device(port, iosize),
+            None => log::warn!("qemu: Invalid exit device {:?}", value),
+        }
+    }
+}
+
+/// Parses `<port>[:<bytes>]`, where the width is 1, 2 or 4 bytes and 4
+/// if not given.
+fn parse_exit_device(value: &str) -> Option<(u16, u8)> {
+    let (port, iosize) = match value.split_once(':') {
+        Some((port, iosize)) => (port, iosize.parse().ok()?),
+        None => (value, DEFAULT_EXIT_IOSIZE),
+    };
+    if !matches!(iosize, 1 | 2 | 4) {
+        return None;
+    }
+    let port = crate::shell::parse_number(port).ok()?.try_into().ok()?;
+    Some((port, iosize))
+}
+
+/// Sets the port and width of the device if it's not at the default.
+pub fn set_exit_device(port: u16, iosize: u8) {
+    EXIT_PORT.store(port, Ordering::Relaxed);
+    EXIT_IOSIZE.store(iosize, Ordering::Relaxed);
+}
+
+/// Sets whether we're running under the test harness.
+pub fn set_test_mode(enabled: bool) {
+    TEST_MODE.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether we're running under the test harness.
+pub fn test_mode() -> bool {
+    TEST_MODE.load(Ordering::Relaxed)
+}
+
+/// Exits QEMU with a status code.
+///
+/// If the device isn't present the write does nothing, and we power off
+/// on success and reboot otherwise.
+pub fn exit(code: ExitCode) -> ! {
+    let port = EXIT_PORT.load(Ordering::Relaxed);
+    let value = code as u32;
+
+    unsafe {
+        match EXIT_IOSIZE.load(Ordering::Relaxed) {
+            1 => outb(port, value as u8),
+            2 => outw(port, value as u16),
+            _ => outl(port, value),
+        }
+    }
+
+    if code == ExitCode::Success {
+        crate::acpi::poweroff();
+    }
+    crate::cpu::reboot()
+}
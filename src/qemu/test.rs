@@ -0,0 +1,18 @@
+//! Exit device option tests.
+
+use super::{parse_exit_device, DEFAULT_EXIT_IOSIZE, DEFAULT_EXIT_PORT};
+
+#[test_case]
+fn exit_device_option() {
+    assert_eq!(parse_exit_device("0xf4"), Some((DEFAULT_EXIT_PORT, DEFAULT_EXIT_IOSIZE)));
+    assert_eq!(parse_exit_device("0x501:2"), Some((0x501, 2)));
+    assert_eq!(parse_exit_device("1281:1"), Some((0x501, 1)));
+}
+
+#[test_case]
+fn invalid_exit_device_option() {
+    assert_eq!(parse_exit_device(""), None);
+    assert_eq!(parse_exit_device("0x10000"), None);
+    assert_eq!(parse_exit_device("0xf4:3"), None);
+    assert_eq!(parse_exit_device("0xf4:"), None);
+}
@@ -0,0 +1,105 @@
+//! Self-tests of a booted kernel.
+//!
+//! Unlike the `#[test_case]` tests, these run in a normal kernel, with
+//! the real hardware or QEMU configuration, and only with `selftest` on
+//! the command line. Nothing runs them otherwise, so they can take a
+//! while.
+//!
+//! Any module can add one with [`selftest!`](crate::selftest!), which
+//! puts it in the `.selftests` section. [`run_all`] runs them all once
+//! initialization is done and prints a line per test and a summary. In
+//! `test` mode, QEMU then exits with the result.
+
+#[cfg(test)]
+mod test;
+
+use core::fmt;
+
+use crate::error::Result;
+use crate::println;
+
+/// A self-test.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SelfTest {
+    pub name: &'static str,
+    pub run: fn() -> Result<()>,
+}
+
+/// Adds a self-test.
+///
+/// The test is a `fn() -> Result<()>` and fails if it returns an error.
+/// The name has to be unique in the kernel, as it names a static.
+#[macro_export]
+macro_rules! selftest {
+    ($name:ident, $run:path) => {
+        #[used]
+        #[allow(non_upper_case_globals)]
+        #[unsafe(link_section = ".selftests")]
+        static $name: $crate::selftest::SelfTest = $crate::selftest::SelfTest {
+            name: stringify!($name),
+            run: $run,
+        };
+    };
+}
+
+// Set by the linker around the `.selftests` section. Only their
+// addresses mean anything.
+extern "C" {
+    static __selftests_start: u8;
+    static __selftests_end: u8;
+}
+
+/// Returns the self-tests, in link order.
+pub fn all() -> &'static [SelfTest] {
+    unsafe {
+        let start = (&raw const __selftests_start).cast::<SelfTest>();
+        let end = (&raw const __selftests_end).cast::<SelfTest>();
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// How a run of self-tests went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    /// Returns whether every test passed.
+    pub fn is_success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Self-tests: {} passed, {} failed", self.passed, self.failed)
+    }
+}
+
+/// Runs `tests` in order, printing a line for each.
+pub fn run(tests: &[SelfTest]) -> Summary {
+    let mut summary = Summary::default();
+    for test in tests {
+        match (test.run)() {
+            Ok(()) => {
+                println!("[selftest] {} ... ok", test.name);
+                summary.passed += 1;
+            }
+            Err(e) => {
+                println!("[selftest] {} ... FAILED: {}", test.name, e);
+                summary.failed += 1;
+            }
+        }
+    }
+    summary
+}
+
+/// Runs every self-test and prints the summary.
+pub fn run_all() -> Summary {
+    let summary = run(all());
+    println!("{}", summary);
+    summary
+}
@@ -0,0 +1,41 @@
+//! Self-test registry tests.
+
+use super::{SelfTest, Summary};
+use crate::error::Error;
+
+#[test_case]
+fn registered_tests_are_found() {
+    let names = || super::all().iter().map(|test| test.name);
+    let expected = [
+        "alloc_zeroed_memory",
+        "alloc_aligned_memory",
+        "alloc_contiguous_pages",
+        "alloc_fallible",
+        "user_mode",
+        "breakpoint_returns",
+    ];
+    for name in expected {
+        assert!(names().any(|n| n == name), "{}", name);
+    }
+}
+
+#[test_case]
+fn allocator_self_tests_pass() {
+    let allocator_tests = super::all().iter().filter(|test| test.name.starts_with("alloc_"));
+    for test in allocator_tests {
+        assert_eq!((test.run)(), Ok(()), "{}", test.name);
+    }
+}
+
+#[test_case]
+fn failures_are_counted() {
+    let tests = [
+        SelfTest { name: "passes", run: || Ok(()) },
+        SelfTest { name: "fails", run: || Err(Error::Other("broken")) },
+        SelfTest { name: "passes too", run: || Ok(()) },
+    ];
+    let summary = super::run(&tests);
+    assert_eq!(summary, Summary { passed: 2, failed: 1 });
+    assert!(!summary.is_success());
+    assert!(super::run(&[]).is_success());
+}
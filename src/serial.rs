@@ -1,62 +1,412 @@
-use x86::io::{outb, inb};
-use spin::Mutex;
-use lazy_static::lazy_static;
+#[cfg(test)]
+mod test;
+
+use alloc::vec::Vec;
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::Result;
+use crate::memory::fallible::try_with_capacity;
+use crate::port_io::{Pio, PortIo};
+use crate::sync::{Lazy, Mutex, TicketMutex};
+
 const COM1: u16 = 0x3F8; // First serial port
 
+// Line status register bits
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_OVERRUN: u8 = 1 << 1;
+const LSR_PARITY: u8 = 1 << 2;
+const LSR_FRAMING: u8 = 1 << 3;
+const LSR_BREAK: u8 = 1 << 4;
+const LSR_ERRORS: u8 = LSR_OVERRUN | LSR_PARITY | LSR_FRAMING | LSR_BREAK;
+const LSR_THR_EMPTY: u8 = 1 << 5;
 
-lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(COM1) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
-}
+/// IIR bits 7:6 read 0b11 when the FIFOs are enabled.
+const IIR_FIFO_ENABLED: u8 = 0xC0;
+
+/// Transmit FIFO depth of a 16550A.
+const FIFO_DEPTH: usize = 16;
+
+/// Size of the output buffer of `BufferedSerialPort`.
+const BUFFER_SIZE: usize = 64;
 
-pub struct SerialPort {
+/// Times `write_raw` polls for THR empty before writing anyway.
+const RAW_WRITE_SPINS: usize = 100_000;
+
+/// Whether text output expands `\n` to `\r\n`.
+static CRLF: AtomicBool = AtomicBool::new(true);
+
+/// Called when a break condition is received, outside of the port lock.
+static BREAK_HANDLER: Mutex<Option<fn()>> = Mutex::new(None);
+
+/// COM1, initialized on first use.
+///
+/// The lock keeps interrupts off, so an interrupt handler that prints
+/// can't deadlock on it. Every CPU prints, so it's a ticket lock to serve
+/// them in turn rather than let one keep the console to itself.
+pub static SERIAL1: Lazy<TicketMutex<BufferedSerialPort>> = Lazy::new(|| {
+    let mut serial_port = unsafe { SerialPort::new(COM1) };
+    serial_port.init();
+    TicketMutex::new(BufferedSerialPort::new(serial_port))
+});
+
+pub struct SerialPort<P: PortIo = Pio> {
+    io: P,
     base: u16,
+    /// Bytes we can write per THR-empty condition (1 on 8250-class UARTs)
+    fifo_size: usize,
+    stats: SerialStats,
+    break_pending: bool,
+}
+
+/// Receive errors reported by the line status register.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerialStats {
+    pub overrun: u64,
+    pub parity: u64,
+    pub framing: u64,
+    pub breaks: u64,
+}
+
+impl SerialStats {
+    /// Returns the total number of errors.
+    pub fn total(&self) -> u64 {
+        self.overrun + self.parity + self.framing + self.breaks
+    }
 }
 
 impl SerialPort {
     pub unsafe fn new(base: u16) -> SerialPort {
-        SerialPort { base }
+        SerialPort::with_io(Pio, base)
+    }
+}
+
+impl<P: PortIo> SerialPort<P> {
+    /// Creates a port at `base` that goes through `io`.
+    pub fn with_io(io: P, base: u16) -> Self {
+        SerialPort {
+            io,
+            base,
+            fifo_size: 1,
+            stats: SerialStats::default(),
+            break_pending: false,
+        }
     }
 
     pub fn init(&mut self) {
-        unsafe {
-            // Disable interrupts
-            outb(self.base + 1, 0x00);
-            // Enable DLAB (set baud rate divisor)
-            outb(self.base + 3, 0x80);
-            // Set divisor to 3 (lo byte) 38400 baud
-            outb(self.base + 0, 0x03);
-            // (hi byte)
-            outb(self.base + 1, 0x00);
-            // 8 bits, no parity, one stop bit
-            outb(self.base + 3, 0x03);
-            // Enable FIFO, clear them, with 14-byte threshold
-            outb(self.base + 2, 0xC7);
-            // IRQs enabled, RTS/DSR set
-            outb(self.base + 4, 0x0B);
+        let io = &self.io;
+        // Disable interrupts
+        io.write(self.base + 1, 0x00);
+        // Enable DLAB (set baud rate divisor)
+        io.write(self.base + 3, 0x80);
+        // Set the divisor for the configured speed
+        let [divisor_lo, divisor_hi] = crate::config::get().serial_divisor().to_le_bytes();
+        io.write(self.base, divisor_lo);
+        io.write(self.base + 1, divisor_hi);
+        // 8 bits, no parity, one stop bit
+        io.write(self.base + 3, 0x03);
+        // Enable FIFO, clear them, with 14-byte threshold
+        io.write(self.base + 2, 0xC7);
+        // IRQs enabled, RTS/DSR set
+        io.write(self.base + 4, 0x0B);
+
+        // Older UARTs ignore the FIFO enable and have a single-byte THR
+        self.fifo_size = if io.read(self.base + 2) & IIR_FIFO_ENABLED == IIR_FIFO_ENABLED {
+            FIFO_DEPTH
+        } else {
+            1
+        };
+    }
+
+    fn wait_for_thr_empty(&self) {
+        while (self.io.read(self.base + 5) & LSR_THR_EMPTY) == 0 {}
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        self.wait_for_thr_empty();
+        self.io.write(self.base, byte);
+    }
+
+    /// Writes bytes, filling the transmit FIFO each time it drains.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(self.fifo_size) {
+            // With FIFOs enabled, THR empty means the whole FIFO is empty
+            self.wait_for_thr_empty();
+            for &byte in chunk {
+                self.io.write(self.base, byte);
+            }
         }
     }
 
+    /// Writes text, expanding newlines to CRLF unless disabled.
+    ///
+    /// Use `write_bytes` for data that must go out unchanged.
+    pub fn write_str(&mut self, s: &str) {
+        if !CRLF.load(Ordering::Relaxed) {
+            self.write_bytes(s.as_bytes());
+            return;
+        }
+
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.write_bytes(first.as_bytes());
+        }
+        for line in lines {
+            self.write_bytes(b"\r\n");
+            self.write_bytes(line.as_bytes());
+        }
+    }
+
+    /// Reads a byte if one has been received.
+    ///
+    /// Bytes received with a line error are counted and discarded.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        let lsr = self.io.read(self.base + 5);
+        if lsr & LSR_DATA_READY == 0 {
+            return None;
+        }
+
+        let byte = self.io.read(self.base);
+        if lsr & LSR_ERRORS == 0 {
+            return Some(byte);
+        }
+
+        // An overrun means an earlier byte was lost, this one is fine
+        if lsr & LSR_OVERRUN != 0 {
+            self.stats.overrun += 1;
+        }
+        if lsr & LSR_PARITY != 0 {
+            self.stats.parity += 1;
+        }
+        if lsr & LSR_FRAMING != 0 {
+            self.stats.framing += 1;
+        }
+        if lsr & LSR_BREAK != 0 {
+            self.stats.breaks += 1;
+            self.break_pending = true;
+        }
+
+        if lsr & (LSR_PARITY | LSR_FRAMING | LSR_BREAK) != 0 {
+            return None;
+        }
+        Some(byte)
+    }
+
+    /// Returns the receive error counts.
+    pub fn stats(&self) -> SerialStats {
+        self.stats
+    }
+}
+
+/// A serial port that batches output.
+///
+/// Formatting writes a few bytes at a time, and waiting for the UART on
+/// each of them is slow. Bytes are collected here and written a FIFO at a
+/// time when the buffer fills up, at the end of a line, or on `flush`.
+///
+/// The buffer is on the heap, so the port writes each byte straight out
+/// until it's given one with `set_buffer`.
+pub struct BufferedSerialPort<P: PortIo = Pio> {
+    inner: SerialPort<P>,
+    buf: Vec<u8>,
+}
+
+impl<P: PortIo> BufferedSerialPort<P> {
+    pub const fn new(inner: SerialPort<P>) -> Self {
+        Self { inner, buf: Vec::new() }
+    }
+
+    /// Buffers output in `buf` from now on, up to its capacity.
+    pub fn set_buffer(&mut self, mut buf: Vec<u8>) {
+        self.flush();
+        buf.clear();
+        self.buf = buf;
+    }
+
+    /// Buffers a byte, flushing at the end of a line.
     pub fn write_byte(&mut self, byte: u8) {
-        unsafe {
-            // Wait for transmit buffer to be empty
-            while (inb(self.base + 5) & 0x20) == 0 {}
-            outb(self.base, byte);
+        if self.buf.capacity() == 0 {
+            self.inner.write_byte(byte);
+            return;
+        }
+
+        // Flushing when full keeps the buffer from growing here
+        self.buf.push(byte);
+        if self.buf.len() == self.buf.capacity() || byte == b'\n' {
+            self.flush();
         }
     }
 
+    /// Buffers text, expanding newlines to CRLF unless disabled.
     pub fn write_str(&mut self, s: &str) {
+        let crlf = CRLF.load(Ordering::Relaxed);
         for byte in s.bytes() {
+            if byte == b'\n' && crlf {
+                self.write_byte(b'\r');
+            }
             self.write_byte(byte);
         }
     }
+
+    /// Writes out the buffered bytes.
+    pub fn flush(&mut self) {
+        if !self.buf.is_empty() {
+            self.inner.write_bytes(&self.buf);
+            self.buf.clear();
+        }
+    }
+
+    /// Returns the number of bytes waiting to be written.
+    #[cfg(test)]
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Flushes, then returns the port for unbuffered access.
+    pub fn unbuffered(&mut self) -> &mut SerialPort<P> {
+        self.flush();
+        &mut self.inner
+    }
+
+    /// Reads a byte if one has been received.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        self.inner.try_read_byte()
+    }
+
+    /// Returns whether a break was received since the last call.
+    fn take_break(&mut self) -> bool {
+        core::mem::take(&mut self.inner.break_pending)
+    }
+
+    /// Returns the receive error counts.
+    pub fn stats(&self) -> SerialStats {
+        self.inner.stats()
+    }
+}
+
+impl<P: PortIo> fmt::Write for BufferedSerialPort<P> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_str(s);
+        Ok(())
+    }
+}
+
+/// Sets whether text output expands `\n` to `\r\n`.
+pub fn set_crlf(enabled: bool) {
+    CRLF.store(enabled, Ordering::Relaxed);
+}
+
+/// Gives COM1 its output buffer, once there's a heap.
+///
+/// Until then, and if this fails, COM1 writes a byte at a time. The
+/// buffer is allocated before taking the port, so the allocator can log.
+pub fn init_buffer() -> Result<()> {
+    let buf = try_with_capacity(BUFFER_SIZE)?;
+    SERIAL1.lock().set_buffer(buf);
+    Ok(())
+}
+
+/// Returns the receive error counts of COM1.
+pub fn stats() -> SerialStats {
+    SERIAL1.lock().stats()
+}
+
+/// Sets the action to run when a break is received on COM1, returning
+/// the previous one.
+pub fn set_break_handler(handler: Option<fn()>) -> Option<fn()> {
+    core::mem::replace(&mut *BREAK_HANDLER.lock(), handler)
 }
 
-impl core::fmt::Write for SerialPort {
+/// Reads a byte from COM1 if one has been received.
+pub fn try_read_byte() -> Option<u8> {
+    read_from(&SERIAL1)
+}
+
+/// Reads a byte from `port` if one has been received, logging receive
+/// errors and running the break handler on a break.
+fn read_from<P: PortIo>(port: &TicketMutex<BufferedSerialPort<P>>) -> Option<u8> {
+    let (byte, break_received, errors, stats) = {
+        let mut port = port.lock();
+        let before = port.stats();
+        let byte = port.try_read_byte();
+        let errors = port.stats().total() - before.total();
+        (byte, port.take_break(), errors, port.stats())
+    };
+
+    if errors > 0 {
+        crate::log_ratelimited!(log::Level::Warn, "serial", "Receive error, stats: {:?}", stats);
+    }
+
+    if break_received {
+        // Don't hold the lock, with interrupts off, while the handler runs
+        let handler = *BREAK_HANDLER.lock();
+        if let Some(handler) = handler {
+            handler();
+        }
+    }
+
+    byte
+}
+
+/// Reads a line from the serial port or the PS/2 keyboard into `buf`,
+/// echoing it to the console.
+///
+/// Backspace is handled, and input beyond the size of the buffer is
+/// dropped. A break received on the serial line discards what was typed
+/// and returns an empty line. The receive interrupt isn't enabled, so
+/// between polls the CPU halts until the next tick. Keys typed meanwhile
+/// wait in the keyboard's event queue. This must be called with interrupts
+/// enabled.
+pub fn read_line(buf: &mut [u8]) -> &str {
+    let mut len = 0;
+
+    loop {
+        let mut byte = 0;
+        let mut cancelled = false;
+        let keyboard = || crate::drivers::ps2_keyboard::try_read_char().map(|c| c as u8);
+        crate::time::wait_until(|| match try_read_byte().or_else(keyboard) {
+            Some(received) => {
+                byte = received;
+                true
+            }
+            None => {
+                cancelled = crate::shell::take_break();
+                cancelled
+            }
+        });
+
+        if cancelled {
+            crate::println!("^Break");
+            len = 0;
+            break;
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                crate::println!();
+                break;
+            }
+            // Backspace or DEL
+            0x08 | 0x7f => {
+                if len > 0 {
+                    len -= 1;
+                    crate::print!("\x08 \x08");
+                }
+            }
+            0x20..=0x7e if len < buf.len() => {
+                buf[len] = byte;
+                len += 1;
+                crate::print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+
+    // Only printable ASCII is stored
+    core::str::from_utf8(&buf[..len]).unwrap()
+}
+
+impl<P: PortIo> core::fmt::Write for SerialPort<P> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         self.write_str(s);
         Ok(())
@@ -66,7 +416,32 @@ impl core::fmt::Write for SerialPort {
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
     use core::fmt::Write;
-    SERIAL1.lock().write_fmt(args).unwrap();
+    let mut port = SERIAL1.lock();
+    // Only a `Display` impl can fail here, and printing must not panic
+    let _ = port.write_fmt(args);
+    port.flush();
+}
+
+/// Writes raw bytes to COM1, without newline translation.
+#[doc(hidden)]
+pub fn _print_bytes(data: &[u8]) {
+    SERIAL1.lock().unbuffered().write_bytes(data);
+}
+
+/// Writes bytes to COM1 without taking the port lock.
+///
+/// For the panic handler once nothing else can be trusted. Waiting for the
+/// UART gives up after a while, so a missing or stuck one can't hang us.
+pub fn write_raw(bytes: &[u8]) {
+    for &byte in bytes {
+        for _ in 0..RAW_WRITE_SPINS {
+            if Pio.read(COM1 + 5) & LSR_THR_EMPTY != 0 {
+                break;
+            }
+            core::hint::spin_loop();
+        }
+        Pio.write(COM1, byte);
+    }
 }
 
 /// Prints to the host through the serial interface.
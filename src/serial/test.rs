@@ -0,0 +1,304 @@
+//! Serial port tests, against a fake UART.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::*;
+use crate::cpu::tsc::rdtsc;
+
+const BASE: u16 = 0x3F8;
+
+/// A UART that receives bytes queued by the test, with the line status
+/// to report for each, and keeps what is written to it.
+#[derive(Default)]
+struct FakeUart {
+    rx: RefCell<VecDeque<(u8, u8)>>,
+    tx: RefCell<Vec<u8>>,
+    /// Line status reads, each a wait for the UART on real hardware
+    lsr_reads: Cell<usize>,
+}
+
+impl FakeUart {
+    /// Queues `byte`, received with the line status bits in `lsr`.
+    fn receive(&self, lsr: u8, byte: u8) {
+        self.rx.borrow_mut().push_back((lsr, byte));
+    }
+}
+
+impl PortIo for FakeUart {
+    fn read(&self, port: u16) -> u8 {
+        match port - BASE {
+            0 => self.rx.borrow_mut().pop_front().map_or(0, |(_, byte)| byte),
+            5 => {
+                self.lsr_reads.set(self.lsr_reads.get() + 1);
+                match self.rx.borrow().front() {
+                    Some(&(lsr, _)) => lsr | LSR_DATA_READY | LSR_THR_EMPTY,
+                    None => LSR_THR_EMPTY,
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&self, port: u16, value: u8) {
+        if port == BASE {
+            self.tx.borrow_mut().push(value);
+        }
+    }
+}
+
+/// A buffered port on a fake UART, with a buffer like COM1's.
+fn buffered_port() -> BufferedSerialPort<FakeUart> {
+    let mut port = BufferedSerialPort::new(SerialPort::with_io(FakeUart::default(), BASE));
+    port.set_buffer(Vec::with_capacity(BUFFER_SIZE));
+    port
+}
+
+fn fake_port() -> TicketMutex<BufferedSerialPort<FakeUart>> {
+    TicketMutex::new(buffered_port())
+}
+
+#[test_case]
+fn receive_errors_are_counted() {
+    let port = fake_port();
+    {
+        let mut port = port.lock();
+        let uart = &port.unbuffered().io;
+        uart.receive(0, b'a');
+        uart.receive(LSR_OVERRUN, b'b');
+        uart.receive(LSR_PARITY, b'c');
+        uart.receive(LSR_FRAMING, b'd');
+        uart.receive(LSR_FRAMING | LSR_OVERRUN, b'e');
+    }
+
+    // Only an overrun keeps the byte, it was an earlier one that got lost
+    assert_eq!(read_from(&port), Some(b'a'));
+    assert_eq!(read_from(&port), Some(b'b'));
+    assert_eq!(read_from(&port), None);
+    assert_eq!(read_from(&port), None);
+    assert_eq!(read_from(&port), None);
+
+    let stats = port.lock().stats();
+    assert_eq!((stats.overrun, stats.parity, stats.framing, stats.breaks), (2, 1, 2, 0));
+    assert_eq!(stats.total(), 5);
+
+    // Nothing left, and no error to count
+    assert_eq!(read_from(&port), None);
+    assert_eq!(port.lock().stats().total(), 5);
+}
+
+#[test_case]
+fn break_runs_the_handler() {
+    static BREAKS: AtomicUsize = AtomicUsize::new(0);
+    fn count() {
+        BREAKS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let port = fake_port();
+    {
+        let mut port = port.lock();
+        let uart = &port.unbuffered().io;
+        // A break reads as a NUL with a framing error too
+        uart.receive(LSR_BREAK | LSR_FRAMING, 0);
+        uart.receive(0, b'a');
+    }
+
+    let previous = set_break_handler(Some(count));
+    let break_byte = read_from(&port);
+    let next_byte = read_from(&port);
+    set_break_handler(previous);
+
+    assert_eq!(break_byte, None);
+    assert_eq!(next_byte, Some(b'a'));
+    assert_eq!(BREAKS.load(Ordering::Relaxed), 1);
+
+    let mut port = port.lock();
+    let stats = port.stats();
+    assert_eq!((stats.breaks, stats.framing), (1, 1));
+    // The handler took the break
+    assert!(!port.take_break());
+}
+
+/// Runs `f` with CRLF expansion set to `enabled`, then restores it.
+fn with_crlf(enabled: bool, f: impl FnOnce()) {
+    let previous = CRLF.load(Ordering::Relaxed);
+    set_crlf(enabled);
+    f();
+    set_crlf(previous);
+}
+
+#[test_case]
+fn newlines_become_crlf() {
+    with_crlf(true, || {
+        let mut port = SerialPort::with_io(FakeUart::default(), BASE);
+        port.write_str("a\nb\n\n");
+        assert_eq!(*port.io.tx.borrow(), b"a\r\nb\r\n\r\n");
+
+        let mut port = buffered_port();
+        port.write_str("a\nb\n\n");
+        assert_eq!(*port.unbuffered().io.tx.borrow(), b"a\r\nb\r\n\r\n");
+    });
+}
+
+#[test_case]
+fn crlf_off_writes_newlines_unchanged() {
+    with_crlf(false, || {
+        let mut port = SerialPort::with_io(FakeUart::default(), BASE);
+        port.write_str("a\nb\n\n");
+        assert_eq!(*port.io.tx.borrow(), b"a\nb\n\n");
+
+        let mut port = buffered_port();
+        port.write_str("a\nb\n\n");
+        assert_eq!(*port.unbuffered().io.tx.borrow(), b"a\nb\n\n");
+    });
+}
+
+#[test_case]
+fn raw_bytes_are_not_translated() {
+    with_crlf(true, || {
+        let mut port = SerialPort::with_io(FakeUart::default(), BASE);
+        port.write_bytes(b"a\nb");
+        assert_eq!(*port.io.tx.borrow(), b"a\nb");
+    });
+}
+
+#[test_case]
+fn serial_buffer_flushes_on_newline() {
+    let mut port = buffered_port();
+
+    port.write_str("ab");
+    assert_eq!(port.buffered(), 2);
+    assert!(port.inner.io.tx.borrow().is_empty());
+
+    port.write_str("c\nd");
+    assert_eq!(*port.inner.io.tx.borrow(), b"abc\r\n");
+    assert_eq!(port.buffered(), 1);
+
+    port.flush();
+    assert_eq!(*port.inner.io.tx.borrow(), b"abc\r\nd");
+    assert_eq!(port.buffered(), 0);
+}
+
+#[test_case]
+fn unbuffered_until_given_a_buffer() {
+    let mut port = BufferedSerialPort::new(SerialPort::with_io(FakeUart::default(), BASE));
+    port.write_str("ab");
+    assert_eq!(port.buffered(), 0);
+    assert_eq!(*port.inner.io.tx.borrow(), b"ab");
+
+    port.set_buffer(Vec::with_capacity(BUFFER_SIZE));
+    port.write_str("c");
+    assert_eq!(port.buffered(), 1);
+    assert_eq!(*port.inner.io.tx.borrow(), b"ab");
+}
+
+#[test_case]
+fn serial_buffer_flushes_when_full() {
+    let mut port = buffered_port();
+
+    for _ in 0..BUFFER_SIZE - 1 {
+        port.write_byte(b'x');
+    }
+    assert!(port.inner.io.tx.borrow().is_empty());
+
+    port.write_byte(b'x');
+    assert_eq!(port.inner.io.tx.borrow().len(), BUFFER_SIZE);
+    assert_eq!(port.buffered(), 0);
+}
+
+/// Compares printing a 200-character line made of many small writes with
+/// and without buffering.
+///
+/// What buffering saves is waiting for the UART, once per write without
+/// it and once per FIFO with it. That is counted on a fake UART with a
+/// FIFO; the time it takes on COM1 is only logged.
+#[test_case]
+fn serial_buffering_benchmark() {
+    // Formats as 66 two-character elements with separators
+    let values = [0u8; 66];
+    let line_len = 200;
+
+    let mut unbuffered = SerialPort::with_io(FakeUart::default(), BASE);
+    unbuffered.fifo_size = FIFO_DEPTH;
+    writeln!(unbuffered, "{:?}", values).unwrap();
+    assert_eq!(unbuffered.io.tx.borrow().len(), line_len);
+
+    let mut buffered = buffered_port();
+    buffered.inner.fifo_size = FIFO_DEPTH;
+    writeln!(buffered, "{:?}", values).unwrap();
+    assert_eq!(*buffered.inner.io.tx.borrow(), *unbuffered.io.tx.borrow());
+
+    // One wait per FIFO of each buffer flush, a full one or the last line
+    let full_flushes = line_len / BUFFER_SIZE;
+    let max_waits = full_flushes * BUFFER_SIZE.div_ceil(FIFO_DEPTH) + (line_len % BUFFER_SIZE).div_ceil(FIFO_DEPTH);
+    let unbuffered_waits = unbuffered.io.lsr_reads.get();
+    let buffered_waits = buffered.inner.io.lsr_reads.get();
+    assert!(buffered_waits <= max_waits, "{} UART waits buffered", buffered_waits);
+    assert!(unbuffered_waits > values.len(), "{} UART waits unbuffered", unbuffered_waits);
+
+    let mut port = SERIAL1.lock();
+    let start = rdtsc();
+    writeln!(port.unbuffered(), "{:?}", values).unwrap();
+    let unbuffered_cycles = rdtsc() - start;
+
+    let start = rdtsc();
+    writeln!(*port, "{:?}", values).unwrap();
+    port.flush();
+    let buffered_cycles = rdtsc() - start;
+
+    drop(port);
+    log::info!(
+        "200-character line: {} UART waits and {} cycles unbuffered, {} waits and {} cycles buffered",
+        unbuffered_waits,
+        unbuffered_cycles,
+        buffered_waits,
+        buffered_cycles
+    );
+}
+
+/// Compares writing 1 KB of log text a byte at a time with writing it in
+/// FIFO-sized batches.
+#[test_case]
+fn write_bytes_benchmark() {
+    let data = [b'x'; 1024];
+
+    let mut port = SerialPort::with_io(FakeUart::default(), BASE);
+    port.fifo_size = FIFO_DEPTH;
+    for &byte in &data {
+        port.write_byte(byte);
+    }
+    let byte_waits = port.io.lsr_reads.get();
+
+    let mut port = SerialPort::with_io(FakeUart::default(), BASE);
+    port.fifo_size = FIFO_DEPTH;
+    port.write_bytes(&data);
+    let batch_waits = port.io.lsr_reads.get();
+    assert_eq!(port.io.tx.borrow().len(), data.len());
+
+    assert_eq!(byte_waits, data.len());
+    assert_eq!(batch_waits, data.len() / FIFO_DEPTH);
+
+    let mut port = SERIAL1.lock();
+    let port = port.unbuffered();
+    let start = rdtsc();
+    for &byte in &data {
+        port.write_byte(byte);
+    }
+    let byte_cycles = rdtsc() - start;
+
+    let start = rdtsc();
+    port.write_bytes(&data);
+    let batch_cycles = rdtsc() - start;
+    port.write_bytes(b"\r\n");
+
+    log::info!(
+        "1 KB: {} UART waits and {} cycles a byte at a time, {} waits and {} cycles batched",
+        byte_waits,
+        byte_cycles,
+        batch_waits,
+        batch_cycles
+    );
+}
@@ -0,0 +1,441 @@
+//! A tiny debug shell on the serial port.
+//!
+//! Commands are looked up in a fixed-size table. Other modules can add
+//! their own with [`register`]. The shell runs from the main loop and
+//! halts while waiting for input, so interrupts keep being serviced.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{AcpiError, Error, MemError, Result};
+use crate::memory::leak_check::{self, Snapshot};
+use crate::debug::panic_guard::PanicsWhenPrinted;
+use crate::memory::rwlock::RwLock;
+use crate::sync::Mutex;
+use crate::{acpi, interrupt, memory, print, println, time};
+
+/// Maximum number of commands.
+const MAX_COMMANDS: usize = 32;
+
+/// Maximum length of an input line.
+const MAX_LINE: usize = 128;
+
+/// Maximum number of words in a line, including the command.
+const MAX_ARGS: usize = 8;
+
+/// The prompt.
+const PROMPT: &str = "> ";
+
+/// A command handler. It receives the arguments after the command name.
+pub type CommandFn = fn(&[&str]) -> Result<()>;
+
+/// A registered command.
+#[derive(Clone, Copy)]
+struct Command {
+    name: &'static str,
+    help: &'static str,
+    handler: CommandFn,
+}
+
+/// The command table. It's looked up on every line and only written at
+/// registration.
+static COMMANDS: RwLock<[Option<Command>; MAX_COMMANDS]> = RwLock::new([None; MAX_COMMANDS]);
+
+/// What `leaks` compares against, set when the shell starts.
+static LEAK_BASELINE: Mutex<Option<Snapshot>> = Mutex::new(None);
+
+/// Registers a command.
+pub fn register(name: &'static str, help: &'static str, handler: CommandFn) -> Result<()> {
+    let mut commands = COMMANDS.write();
+    let slot = commands
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(Error::TooMany("shell commands"))?;
+
+    *slot = Some(Command {
+        name,
+        help,
+        handler,
+    });
+    Ok(())
+}
+
+/// Set by the serial break handler, until whatever is reading input
+/// takes it.
+static BREAK: AtomicBool = AtomicBool::new(false);
+
+/// Registers the built-in commands, and makes a break on the serial line
+/// drop back to the prompt.
+pub fn init() {
+    let builtins: [(&'static str, &'static str, CommandFn); 22] = [
+        ("help", "List the available commands", help),
+        ("version", "Show which build is running", version),
+        ("config", "Show the boot parameters and where they came from", config),
+        ("mem", "Show page allocator statistics", mem),
+        ("leaks", "leaks [mark]: Show pages allocated since the shell started or the last mark", leaks),
+        ("mmap", "Dump the multiboot memory map", mmap),
+        ("mbi", "Dump the raw multiboot2 tags", mbi),
+        ("lsacpi", "List the ACPI tables", lsacpi),
+        ("ticks", "Show the number of timer ticks since boot", ticks),
+        ("uptime", "Show the time since boot", uptime),
+        ("boottime", "Show how long each boot phase took", boottime),
+        ("irqstats", "Show interrupt counts per IRQ, busiest first", irqstats),
+        ("irqaffinity", "irqaffinity <irq> <apic id>: Route an IRQ to another CPU", irqaffinity),
+        ("idt", "idt [vector]: Show the interrupt handlers", idt),
+        ("serial", "Show serial receive error counts", serial),
+        ("consoles", "Show the console sinks and their write errors", consoles),
+        ("dump", "dump <addr> <len>: Hexdump memory", dump),
+        ("walk", "walk [addr]: Translate a virtual address", walk),
+        ("cpuid", "Show CPU feature flags", cpuid),
+        ("panic", "panic [double]: Trigger a kernel panic, or one in the panic handler", panic),
+        ("reboot", "Reset the machine", reboot),
+        ("poweroff", "Turn the machine off", poweroff),
+    ];
+
+    for (name, help, handler) in builtins {
+        register(name, help, handler).expect("Failed to register shell command");
+    }
+    *LEAK_BASELINE.lock() = Some(leak_check::snapshot());
+    crate::serial::set_break_handler(Some(on_break));
+}
+
+/// Runs from the serial driver when a break is received.
+fn on_break() {
+    BREAK.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether a break was received since the last call.
+///
+/// Loops that wait for input check this to give up and go back to the
+/// prompt.
+pub fn take_break() -> bool {
+    BREAK.swap(false, Ordering::Relaxed)
+}
+
+/// Runs the shell forever.
+pub fn run() -> ! {
+    let mut buf = [0u8; MAX_LINE];
+
+    loop {
+        print!("{}", PROMPT);
+        let line = crate::serial::read_line(&mut buf);
+        execute(line);
+    }
+}
+
+/// Runs a single command line.
+pub fn execute(line: &str) {
+    let mut args = [""; MAX_ARGS];
+    let mut argc = 0;
+    for word in line.split_whitespace() {
+        if argc == MAX_ARGS {
+            println!("Too many arguments");
+            return;
+        }
+        args[argc] = word;
+        argc += 1;
+    }
+
+    let Some((&name, args)) = args[..argc].split_first() else {
+        return;
+    };
+
+    // Don't hold the lock while running the command
+    let command = COMMANDS
+        .read()
+        .iter()
+        .flatten()
+        .find(|command| command.name == name)
+        .copied();
+
+    match command {
+        Some(command) => {
+            if let Err(e) = (command.handler)(args) {
+                println!("{}: {}", name, e);
+            }
+        }
+        None => println!("Unknown command {:?}, try \"help\"", name),
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number.
+pub fn parse_number(s: &str) -> Result<usize> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| Error::InvalidNumber)
+}
+
+/// Returns the argument at `index`.
+fn arg<'a>(args: &[&'a str], index: usize, name: &'static str) -> Result<&'a str> {
+    args.get(index).copied().ok_or(Error::MissingArgument(name))
+}
+
+fn help(_args: &[&str]) -> Result<()> {
+    let commands = *COMMANDS.read();
+    for command in commands.iter().flatten() {
+        println!("  {:10} {}", command.name, command.help);
+    }
+    Ok(())
+}
+
+fn mem(_args: &[&str]) -> Result<()> {
+    let stats = memory::get_allocator().stats();
+    println!("Tracked pages: {}", stats.total_pages);
+    println!("Free 4KB pages: {}", stats.free_4kb);
+    println!("Free 2MB pages: {}", stats.free_2mb);
+    println!("Total free memory: {} KB", stats.free_bytes() / 1024);
+
+    #[cfg(feature = "lock-stats")]
+    for (name, lock) in memory::get_allocator().lock_stats() {
+        println!(
+            "Lock {}: {} acquisitions, {} contended, {} spins",
+            name, lock.acquisitions, lock.contended, lock.spins
+        );
+    }
+    Ok(())
+}
+
+fn mmap(_args: &[&str]) -> Result<()> {
+    let mmap = memory::boot_info()
+        .and_then(|boot_info| boot_info.memory_map_tag().ok())
+        .ok_or(MemError::NoMemoryMap)?;
+
+    for area in mmap.memory_areas() {
+        println!(
+            "  {:#018x}-{:#018x} type {}",
+            area.base_addr,
+            area.base_addr + area.length,
+            area.typ
+        );
+    }
+    Ok(())
+}
+
+/// Dumps the boot information tag by tag, for when the kernel can't find
+/// something in it.
+fn mbi(_args: &[&str]) -> Result<()> {
+    /// Tags to print at most, in case the stream is corrupt
+    const MAX_TAGS: usize = 64;
+    /// Payload bytes to dump per tag
+    const MAX_DUMP: usize = 32;
+
+    let boot_info = memory::boot_info().ok_or(MemError::BadBootInfo)?;
+    println!(
+        "Boot information at {:#x}, {} bytes",
+        boot_info as *const _ as usize,
+        boot_info.total_size()
+    );
+
+    for (i, tag) in boot_info.tags().enumerate() {
+        if i == MAX_TAGS {
+            println!("Stopping after {} tags", MAX_TAGS);
+            break;
+        }
+
+        println!(
+            "Tag {} ({}), {} bytes: {}",
+            tag.typ,
+            tag.name().unwrap_or("unknown"),
+            tag.size,
+            tag
+        );
+        let payload = tag.payload();
+        crate::hexdump!(&payload[..payload.len().min(MAX_DUMP)]);
+    }
+    Ok(())
+}
+
+fn lsacpi(_args: &[&str]) -> Result<()> {
+    use crate::acpi::tables::{Hpet, Madt, Mcfg};
+
+    let tables = acpi::tables().ok_or(AcpiError::NoRsdp)?;
+    let root = tables.root;
+    println!("{} at {:#x}, revision {}, OEM {}", root.name(), root.address(), root.revision, root.oem_id());
+    for table in tables.iter() {
+        let length = table.length;
+        println!(
+            "  {} at {:#10x} {:6} bytes  revision {}  OEM {:6} {}",
+            table.name(),
+            table.address(),
+            length,
+            table.revision,
+            table.oem_id(),
+            table.oem_table_id()
+        );
+    }
+    if tables.skipped > 0 {
+        println!("{} tables skipped, see the log", tables.skipped);
+    }
+
+    if let Some(madt) = acpi::find::<Madt>() {
+        let address = madt.local_apic_address;
+        println!("Local APIC at {:#x}", address);
+    }
+    if let Some(hpet) = acpi::find::<Hpet>() {
+        let address = hpet.base_address.address;
+        println!("HPET at {:#x}", address);
+    }
+    for entry in acpi::find::<Mcfg>().into_iter().flat_map(Mcfg::entries) {
+        let (base, segment) = (entry.base_address, entry.segment);
+        println!("PCIe segment {} buses {}-{} at {:#x}", segment, entry.start_bus, entry.end_bus, base);
+    }
+    Ok(())
+}
+
+fn leaks(args: &[&str]) -> Result<()> {
+    let now = leak_check::snapshot();
+    let mut baseline = LEAK_BASELINE.lock();
+    match args.first() {
+        Some(&"mark") => *baseline = Some(now),
+        Some(_) => return Err(Error::Other("usage: leaks [mark]")),
+        None => {
+            let report = leak_check::compare(baseline.get_or_insert(now), &now);
+            match report.is_balanced() {
+                true => println!("Page counts are back where they were"),
+                false => println!("{}", report),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn version(_args: &[&str]) -> Result<()> {
+    let build = crate::version::BuildInfo::CURRENT;
+    println!("{}", build);
+    if build.is_dirty() {
+        println!("Built with uncommitted changes");
+    }
+    Ok(())
+}
+
+fn config(_args: &[&str]) -> Result<()> {
+    let config = crate::config::get();
+    for (option, value, source) in config.options() {
+        println!("  {:10} {:8} {}", option, source, value);
+    }
+    for warning in config.warnings() {
+        println!("  {}, ignored", warning);
+    }
+    Ok(())
+}
+
+fn ticks(_args: &[&str]) -> Result<()> {
+    println!("{} ticks", time::ticks());
+    Ok(())
+}
+
+fn uptime(_args: &[&str]) -> Result<()> {
+    let ms = time::uptime_ms();
+    println!("up {}.{:03} s, {} ticks", ms / 1000, ms % 1000, time::ticks());
+    Ok(())
+}
+
+fn boottime(_args: &[&str]) -> Result<()> {
+    println!("{}", crate::boot::phase::times());
+    Ok(())
+}
+
+fn irqstats(_args: &[&str]) -> Result<()> {
+    let rows = (0..interrupt::NUM_IRQS)
+        .map(|irq| (irq, interrupt::irq_count(irq)))
+        .filter(|&(irq, count)| count != 0 || interrupt::irq::is_registered(irq));
+
+    // Sorting needs a table, and without memory for it they're shown in
+    // IRQ order
+    match memory::fallible::try_with_capacity(interrupt::NUM_IRQS) {
+        Ok(mut table) => {
+            table.extend(rows);
+            table.sort_unstable_by_key(|&(irq, count)| (core::cmp::Reverse(count), irq));
+            for (irq, count) in table {
+                println!("  IRQ {:3}: {}", irq, count);
+            }
+        }
+        Err(e) => {
+            println!("  Not sorted: {}", e);
+            for (irq, count) in rows {
+                println!("  IRQ {:3}: {}", irq, count);
+            }
+        }
+    }
+    println!("  Spurious: {}", interrupt::spurious_irq_count());
+    Ok(())
+}
+
+fn irqaffinity(args: &[&str]) -> Result<()> {
+    let irq = parse_number(arg(args, 0, "irq")?)?;
+    let apic_id = parse_number(arg(args, 1, "apic id")?)?;
+    interrupt::irq::set_irq_affinity(irq, apic_id as u32)?;
+    println!("IRQ {} goes to APIC ID {}", irq, apic_id);
+    Ok(())
+}
+
+fn idt(args: &[&str]) -> Result<()> {
+    let Some(vector) = args.first() else {
+        return interrupt::dump_idt();
+    };
+
+    let vector = parse_number(vector)?;
+    match interrupt::handler_addr(vector)? {
+        Some(addr) => println!("{:#04x}: {}", vector, crate::debug::ksyms::Symbolized::new(addr)),
+        None => println!("{:#04x}: no handler", vector),
+    }
+    Ok(())
+}
+
+fn serial(_args: &[&str]) -> Result<()> {
+    let stats = crate::serial::stats();
+    println!("Overrun errors: {}", stats.overrun);
+    println!("Parity errors: {}", stats.parity);
+    println!("Framing errors: {}", stats.framing);
+    println!("Breaks: {}", stats.breaks);
+    Ok(())
+}
+
+fn consoles(_args: &[&str]) -> Result<()> {
+    for sink in crate::console::sinks() {
+        let state = if sink.enabled { "on" } else { "off" };
+        println!("  {:10} {:3} {:5} {} errors", sink.name, state, sink.max_level, sink.errors);
+    }
+    Ok(())
+}
+
+fn dump(args: &[&str]) -> Result<()> {
+    let addr = parse_number(arg(args, 0, "addr")?)?;
+    let len = parse_number(arg(args, 1, "len")?)?;
+    println!("{:#x}:", addr);
+    crate::debug::hexdump(addr as *const u8, len);
+    Ok(())
+}
+
+fn walk(args: &[&str]) -> Result<()> {
+    // The VGA text buffer by default
+    let virt = match args.first() {
+        Some(addr) => parse_number(addr)?,
+        None => 0xB8000,
+    };
+    println!("{}", memory::paging::translate(virt as u64));
+    Ok(())
+}
+
+fn cpuid(_args: &[&str]) -> Result<()> {
+    println!("CPU {} of {} online", crate::cpu::get_cpu_id(), crate::boot::online_cpu_count());
+    crate::cpu::CpuFeatures::detect().print_summary();
+    Ok(())
+}
+
+fn panic(args: &[&str]) -> Result<()> {
+    match args.first() {
+        Some(&"double") => panic!("Double panic requested from the shell: {}", PanicsWhenPrinted),
+        Some(_) => Err(Error::Other("usage: panic [double]")),
+        None => panic!("Panic requested from the shell"),
+    }
+}
+
+fn reboot(_args: &[&str]) -> Result<()> {
+    crate::cpu::reboot();
+}
+
+fn poweroff(_args: &[&str]) -> Result<()> {
+    crate::acpi::poweroff();
+}
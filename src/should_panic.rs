@@ -0,0 +1,80 @@
+//! Panics on purpose, for the `should_panic*` test binaries.
+//!
+//! Those binaries are the test kernel built under another name (see
+//! `Cargo.toml`), so they boot like it and go through its panic handler.
+//! [`run`] picks what to do from the crate name before any test runs, and
+//! does nothing in the `hello-os` test kernel.
+//!
+//! Each scenario prints what the panic report has to contain first:
+//!
+//! ```text
+//! [should-panic] expect=on purpose
+//! ```
+//!
+//! `test-runner.sh` passes the binary only if the kernel exits with the
+//! panic status and the text shows up after that line.
+
+use crate::debug::panic_guard::PanicsWhenPrinted;
+use crate::sync::Mutex;
+use crate::{interrupt, println};
+
+/// The scenarios, by the name of the binary that runs them.
+const SCENARIOS: &[(&str, fn())] = &[
+    ("should_panic", panic_in_boot),
+    ("should_panic_double", double_panic),
+    ("should_panic_abba", abba_deadlock),
+    #[cfg(feature = "faultinject")]
+    ("should_panic_oom", out_of_memory),
+];
+
+/// Runs the scenario if this is a `should_panic*` binary.
+pub fn run() {
+    let name = env!("CARGO_CRATE_NAME");
+    if let Some((_, scenario)) = SCENARIOS.iter().find(|(scenario, _)| *scenario == name) {
+        scenario();
+    }
+}
+
+/// Tells the test runner what the report has to contain.
+fn expect(text: &str) {
+    println!("[should-panic] expect={}", text);
+}
+
+/// A plain panic outside any test, which exits with `ExitCode::Panicked`.
+fn panic_in_boot() {
+    expect("on purpose");
+    panic!("on purpose");
+}
+
+/// A panic while printing the panic message, which has to be reported
+/// without formatting instead of recursing.
+fn double_panic() {
+    expect("DOUBLE PANIC");
+    panic!("{}", PanicsWhenPrinted);
+}
+
+/// Takes lock A then B here, and B then A in a breakpoint handler that
+/// comes in between, as two code paths would. With one CPU the handler
+/// spins on A, which debug builds report instead of hanging.
+fn abba_deadlock() {
+    static A: Mutex<()> = Mutex::new(());
+    static B: Mutex<()> = Mutex::new(());
+
+    expect("possible deadlock");
+    let _a = A.lock();
+    interrupt::on_next_breakpoint(|| {
+        let _b = B.lock();
+        let _a = A.lock();
+    });
+    unsafe { core::arch::asm!("int3") };
+    let _b = B.lock();
+}
+
+/// Runs out of memory in `Box::new`, which has to end in the allocation
+/// error handler rather than somewhere unexpected.
+#[cfg(feature = "faultinject")]
+fn out_of_memory() {
+    expect("Allocation error: Layout { size: 8");
+    crate::faultinject::fail_allocations_matching(0..usize::MAX);
+    core::hint::black_box(alloc::boxed::Box::new(0u64));
+}
@@ -0,0 +1,48 @@
+//! Synchronization primitives.
+//!
+//! - [`Mutex`]: A spin lock that keeps interrupts disabled while held.
+//! - [`Once`]: A value that is initialized once, at runtime.
+//! - [`Lazy`]: A [`Once`] that initializes itself on first use.
+//! - [`TicketMutex`]: A fair [`Mutex`].
+//! - [`ReentrantMutex`]: A mutex the CPU holding it can take again.
+//! - [`SeqLock`]: A value that readers copy without locking.
+//! - [`WaitQueue`]: Halts the CPU until a condition holds.
+//! - [`Barrier`]: Makes CPUs wait for each other.
+//! - [`IrqGuard`]: Keeps interrupts disabled, with or without a lock.
+//! - [`RawLock`]: The lock inside a mutex, to choose between fairness and
+//!   speed.
+//!
+//! In debug builds, [`deadlock`] turns a mutex that is never released into
+//! a panic. With the `lockdep` feature, [`lockdep`] catches locks taken in
+//! inconsistent orders before they deadlock.
+
+pub mod barrier;
+#[cfg(debug_assertions)]
+pub mod deadlock;
+pub mod irq;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
+mod mutex;
+mod once;
+pub mod raw_lock;
+mod reentrant;
+mod seqlock;
+mod wait_queue;
+#[cfg(test)]
+mod test;
+
+pub use barrier::Barrier;
+pub use irq::IrqGuard;
+#[cfg(feature = "lock-stats")]
+pub use mutex::LockStats;
+pub use mutex::{MappedMutexGuard, Mutex, MutexGuard};
+pub use once::{Lazy, Once};
+#[allow(unused_imports)] // Public API, not all of it named outside sync yet
+pub use raw_lock::{RawLock, RawSpinLock};
+pub use raw_lock::RawTicketLock;
+pub use reentrant::{ReentrantMutex, ReentrantMutexGuard};
+pub use seqlock::SeqLock;
+pub use wait_queue::WaitQueue;
+
+/// A mutex that is handed to waiters in the order they arrived.
+pub type TicketMutex<T> = Mutex<T, RawTicketLock>;
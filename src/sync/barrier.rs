@@ -0,0 +1,160 @@
+//! Rendezvous points for CPUs.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use crate::cpu::tsc;
+use crate::sync::raw_lock::{RawLock, RawSpinLock};
+
+/// Makes a number of CPUs wait until all of them have arrived
+///
+/// It's spin-based, for bring-up code that runs with interrupts off, and
+/// can be reused: once everyone has arrived, the next `wait` starts a new
+/// phase. CPUs are identified by their IDs, which must be below 64.
+///
+/// A [`wait_timeout`](Self::wait_timeout) that times out breaks the
+/// barrier. The CPUs waiting at it are let go, and every later wait
+/// returns at once, all with the same [`BarrierTimeout`], so a CPU that
+/// turns up late doesn't wait for a phase that can't finish.
+pub struct Barrier {
+    n: usize,
+    lock: RawSpinLock,
+    /// The phase, bumped when everyone has arrived
+    generation: AtomicUsize,
+    /// Set once a wait has timed out, after `missing`
+    broken: AtomicBool,
+    /// The CPUs that hadn't arrived when the barrier broke
+    missing: AtomicU64,
+    state: UnsafeCell<Arrivals>,
+}
+
+/// CPUs that have arrived in the current phase
+struct Arrivals {
+    count: usize,
+    /// Bit `n` is set if CPU `n` has arrived
+    cpus: u64,
+}
+
+unsafe impl Sync for Barrier {}
+unsafe impl Send for Barrier {}
+
+/// Returned by [`Barrier::wait`]
+#[derive(Debug, Clone, Copy)]
+pub struct BarrierWaitResult {
+    leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// Returns whether this CPU was the last to arrive
+    ///
+    /// Exactly one CPU in each phase is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.leader
+    }
+}
+
+/// Returned when not everyone arrived in time, and the barrier broke
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierTimeout {
+    /// Bit `n` is set if CPU `n` didn't arrive, assuming the CPUs that
+    /// should are numbered from zero
+    pub missing: u64,
+}
+
+impl Barrier {
+    /// Creates a barrier for `n` CPUs
+    pub const fn new(n: usize) -> Self {
+        Self {
+            n,
+            lock: RawSpinLock::INIT,
+            generation: AtomicUsize::new(0),
+            broken: AtomicBool::new(false),
+            missing: AtomicU64::new(0),
+            state: UnsafeCell::new(Arrivals { count: 0, cpus: 0 }),
+        }
+    }
+
+    /// Waits until all CPUs have arrived
+    ///
+    /// Fails if the barrier is broken, or breaks while waiting.
+    pub fn wait(&self) -> Result<BarrierWaitResult, BarrierTimeout> {
+        self.wait_until(u64::MAX)
+    }
+
+    /// Waits until all CPUs have arrived or `timeout` has passed
+    ///
+    /// On timeout, the barrier breaks.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<BarrierWaitResult, BarrierTimeout> {
+        self.wait_until(tsc::deadline(timeout))
+    }
+
+    /// Returns the timeout that broke the barrier, if it's broken.
+    pub fn broken(&self) -> Option<BarrierTimeout> {
+        self.broken.load(Ordering::Acquire).then(|| BarrierTimeout {
+            missing: self.missing.load(Ordering::Relaxed),
+        })
+    }
+
+    fn wait_until(&self, deadline: u64) -> Result<BarrierWaitResult, BarrierTimeout> {
+        let bit = 1u64 << crate::cpu::get_cpu_id();
+
+        self.lock.lock();
+        if let Some(timeout) = self.broken() {
+            unsafe {
+                self.lock.unlock();
+            }
+            return Err(timeout);
+        }
+        let generation = self.generation.load(Ordering::Relaxed);
+        let state = unsafe { &mut *self.state.get() };
+        state.count += 1;
+        state.cpus |= bit;
+
+        if state.count == self.n {
+            state.count = 0;
+            state.cpus = 0;
+            self.generation.fetch_add(1, Ordering::Release);
+            unsafe {
+                self.lock.unlock();
+            }
+            return Ok(BarrierWaitResult { leader: true });
+        }
+        unsafe {
+            self.lock.unlock();
+        }
+
+        while self.generation.load(Ordering::Acquire) == generation {
+            if let Some(timeout) = self.broken() {
+                return Err(timeout);
+            }
+            if tsc::rdtsc() >= deadline {
+                return self.break_phase(generation);
+            }
+            core::hint::spin_loop();
+        }
+        Ok(BarrierWaitResult { leader: false })
+    }
+
+    /// Breaks the barrier after a timeout, unless the phase just finished
+    /// or someone else broke it first.
+    fn break_phase(&self, generation: usize) -> Result<BarrierWaitResult, BarrierTimeout> {
+        self.lock.lock();
+        let result = if self.generation.load(Ordering::Acquire) != generation {
+            Ok(BarrierWaitResult { leader: false })
+        } else if let Some(timeout) = self.broken() {
+            Err(timeout)
+        } else {
+            let state = unsafe { &*self.state.get() };
+            let expected = if self.n >= 64 { u64::MAX } else { (1 << self.n) - 1 };
+            let missing = expected & !state.cpus;
+            self.missing.store(missing, Ordering::Relaxed);
+            self.broken.store(true, Ordering::Release);
+            Err(BarrierTimeout { missing })
+        };
+        unsafe {
+            self.lock.unlock();
+        }
+        result
+    }
+}
@@ -0,0 +1,83 @@
+//! Deadlock detection for debug builds.
+//!
+//! A [`Mutex`](super::Mutex) remembers which CPU took it and where. A CPU
+//! that waits for it for about a second panics with both locations instead
+//! of hanging:
+//!
+//! ```text
+//! possible deadlock: lock held by CPU 0 acquired at src/memory/page_allocator.rs:301:38,
+//! waited 1000 ms from src/memory/page_allocator.rs:412:29
+//! ```
+//!
+//! The timeout is measured with the TSC, so it is only as accurate as
+//! [`tsc::khz`].
+
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use crate::cpu::tsc::{self, rdtsc};
+
+/// How long to wait for a lock before reporting a deadlock.
+const TIMEOUT_MS: u64 = 1000;
+
+/// The last holder of a lock.
+pub struct Owner {
+    cpu: AtomicUsize,
+    location: AtomicPtr<Location<'static>>,
+}
+
+impl Owner {
+    pub const fn new() -> Self {
+        Self {
+            cpu: AtomicUsize::new(0),
+            location: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Records that the current CPU took the lock at `location`.
+    #[inline(always)]
+    pub fn set(&self, location: &'static Location<'static>) {
+        self.cpu.store(crate::cpu::get_cpu_id() as usize, Ordering::Relaxed);
+        self.location.store(location as *const _ as *mut _, Ordering::Relaxed);
+    }
+}
+
+/// Times a CPU waiting for a lock.
+pub struct Waiter {
+    location: &'static Location<'static>,
+    /// When the wait started, zero until the first check.
+    start: u64,
+}
+
+impl Waiter {
+    pub fn new(location: &'static Location<'static>) -> Self {
+        Self { location, start: 0 }
+    }
+
+    /// Panics if the lock has been held by `owner` for too long.
+    pub fn check(&mut self, owner: &Owner) {
+        let now = rdtsc();
+        if self.start == 0 {
+            self.start = now;
+            return;
+        }
+
+        let waited_ms = (now - self.start) / tsc::khz();
+        if waited_ms < TIMEOUT_MS {
+            return;
+        }
+
+        let cpu = owner.cpu.load(Ordering::Relaxed);
+        match unsafe { owner.location.load(Ordering::Relaxed).as_ref() } {
+            Some(acquired) => panic!(
+                "possible deadlock: lock held by CPU {} acquired at {}, waited {} ms from {}",
+                cpu, acquired, waited_ms, self.location
+            ),
+            None => panic!(
+                "possible deadlock: lock held by an unknown owner, waited {} ms from {}",
+                waited_ms, self.location
+            ),
+        }
+    }
+}
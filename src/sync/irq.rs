@@ -0,0 +1,105 @@
+//! Interrupt-disabled critical sections.
+//!
+//! An [`IrqGuard`] keeps interrupts off on the current CPU while it lives.
+//! Guards nest: each CPU counts how many it has, and interrupts are only
+//! turned back on, if they were on to begin with, when the last one is
+//! dropped. The order guards are dropped in doesn't matter.
+
+use core::arch::asm;
+use core::marker::PhantomData;
+
+use crate::cpu;
+
+/// Keeps interrupts disabled on the current CPU until dropped
+#[must_use]
+pub struct IrqGuard {
+    /// Whether this guard disabled interrupts
+    outermost: bool,
+    /// Guards belong to a CPU
+    _not_send: PhantomData<*mut ()>,
+}
+
+impl IrqGuard {
+    /// Disables interrupts
+    pub fn new() -> Self {
+        let enabled = are_interrupts_enabled();
+        disable_interrupts();
+
+        let cpu = cpu::get_current();
+        let outermost = cpu.irq_depth == 0;
+        if outermost {
+            cpu.irqs_were_enabled = enabled;
+        }
+        cpu.irq_depth += 1;
+
+        Self {
+            outermost,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns whether interrupts were enabled when this guard disabled
+    /// them, as opposed to being disabled already by another guard.
+    #[allow(dead_code)] // Callers ask restores_interrupts so far
+    pub fn is_outermost(&self) -> bool {
+        self.outermost
+    }
+
+    /// Returns whether interrupts are enabled again when this guard is
+    /// dropped.
+    pub fn restores_interrupts(&self) -> bool {
+        self.outermost && cpu::get_current().irqs_were_enabled
+    }
+}
+
+impl Drop for IrqGuard {
+    fn drop(&mut self) {
+        let cpu = cpu::get_current();
+        crate::kdebug_assert!(cpu.irq_depth > 0, "IrqGuard dropped more often than created");
+        cpu.irq_depth -= 1;
+
+        if cpu.irq_depth == 0 && cpu.irqs_were_enabled {
+            enable_interrupts();
+        }
+    }
+}
+
+/// Returns the number of live guards on the current CPU
+#[cfg(test)]
+pub fn depth() -> usize {
+    cpu::get_current().irq_depth
+}
+
+/// Check if interrupts are enabled
+pub fn are_interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq; pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    (rflags & (1 << 9)) != 0
+}
+
+/// Disable interrupts
+fn disable_interrupts() {
+    unsafe {
+        asm!("cli", options(nomem, nostack));
+    }
+}
+
+/// Enables interrupts, halts until one arrives and disables them again
+///
+/// `sti` holds interrupts off until after the next instruction, so one
+/// that arrives before `hlt` still wakes the CPU. Handlers run while we
+/// halt, so memory may have changed when this returns.
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("sti; hlt; cli", options(nostack));
+    }
+}
+
+/// Enable interrupts
+fn enable_interrupts() {
+    unsafe {
+        asm!("sti", options(nomem, nostack));
+    }
+}
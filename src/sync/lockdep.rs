@@ -0,0 +1,304 @@
+//! Lock-order validation.
+//!
+//! With the `lockdep` feature, every [`Mutex`](super::Mutex) and
+//! [`RwLock`](crate::memory::rwlock::RwLock) belongs to a class: the
+//! place where it was created. Each CPU keeps a stack of the classes it
+//! holds, and taking a lock records an edge from every held class to the
+//! new one. An edge that closes a cycle means two code paths take the same
+//! locks in opposite orders, so we panic with both chains as soon as the
+//! second order is seen, whether or not the deadlock actually happens:
+//!
+//! ```text
+//! lock order inversion
+//!   this CPU: src/memory/page_allocator.rs:180:25 -> src/console.rs:170:38
+//!   seen before: src/console.rs:170:38 -> src/memory/page_allocator.rs:180:25
+//! ```
+//!
+//! Locks that are taken with `try_lock` can't block, so they don't add
+//! edges, but they count as held.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::panic::Location;
+
+use super::raw_lock::{RawLock, RawSpinLock};
+use super::IrqGuard;
+
+/// A lock class, identified by where the lock was created.
+pub type Class = &'static Location<'static>;
+
+/// Maximum number of classes. Later classes aren't checked.
+const MAX_CLASSES: usize = 64;
+
+/// Maximum number of locks a CPU can hold at once. Deeper locks aren't
+/// checked.
+const MAX_HELD: usize = 16;
+
+/// Fills unused entries.
+const UNUSED: Class = Location::caller();
+
+/// The lock order graph.
+static GRAPH: GraphLock = GraphLock {
+    raw: RawSpinLock::INIT,
+    graph: UnsafeCell::new(Graph::new()),
+};
+
+/// Protects the graph.
+///
+/// This can't be a [`Mutex`](super::Mutex), which would check itself.
+struct GraphLock {
+    raw: RawSpinLock,
+    graph: UnsafeCell<Graph>,
+}
+
+unsafe impl Sync for GraphLock {}
+
+impl GraphLock {
+    /// Runs `f` with the graph locked and interrupts disabled.
+    fn with<R>(&self, f: impl FnOnce(&mut Graph) -> R) -> R {
+        let _irq = IrqGuard::new();
+        self.raw.lock();
+        let result = f(unsafe { &mut *self.graph.get() });
+        unsafe {
+            self.raw.unlock();
+        }
+        result
+    }
+}
+
+/// The classes seen so far and the order they were taken in.
+pub struct Graph {
+    classes: [Class; MAX_CLASSES],
+    /// Number of classes seen.
+    len: usize,
+    /// Bit `b` of `edges[a]` is set if class `b` was taken while `a` was held.
+    edges: [u64; MAX_CLASSES],
+}
+
+impl Graph {
+    pub const fn new() -> Self {
+        Self {
+            classes: [UNUSED; MAX_CLASSES],
+            len: 0,
+            edges: [0; MAX_CLASSES],
+        }
+    }
+
+    /// Returns the index of a class, registering it if it's new.
+    fn index(&mut self, class: Class) -> Option<usize> {
+        let known = &self.classes[..self.len];
+        if let Some(i) = known.iter().position(|known| core::ptr::eq(*known, class)) {
+            return Some(i);
+        }
+
+        if self.len == MAX_CLASSES {
+            return None;
+        }
+        self.classes[self.len] = class;
+        self.len += 1;
+        Some(self.len - 1)
+    }
+
+    /// Records that `acquiring` is being taken while `held` are held.
+    ///
+    /// Fails if that's the opposite of an order seen before.
+    pub fn acquire(&mut self, held: &[Class], acquiring: Class) -> Result<(), Inversion> {
+        let Some(to) = self.index(acquiring) else {
+            return Ok(());
+        };
+
+        for (depth, &class) in held.iter().enumerate() {
+            let Some(from) = self.index(class) else {
+                continue;
+            };
+            if self.edges[from] & (1 << to) != 0 {
+                continue;
+            }
+
+            if let Some(path) = self.path(to, from) {
+                let mut current = Chain::new();
+                for &class in &held[depth..] {
+                    current.push(class);
+                }
+                current.push(acquiring);
+                return Err(Inversion { current, previous: path });
+            }
+
+            self.edges[from] |= 1 << to;
+        }
+
+        Ok(())
+    }
+
+    /// Finds a chain of recorded edges from `from` to `to`.
+    fn path(&self, from: usize, to: usize) -> Option<Chain> {
+        const NONE: u8 = u8::MAX;
+
+        // Breadth-first, remembering where we came from
+        let mut parent = [NONE; MAX_CLASSES];
+        let mut visited: u64 = 1 << from;
+        let mut queue = [0u8; MAX_CLASSES];
+        let (mut head, mut tail) = (0, 1);
+        queue[0] = from as u8;
+
+        while head < tail && visited & (1 << to) == 0 {
+            let node = queue[head] as usize;
+            head += 1;
+
+            let mut next = self.edges[node] & !visited;
+            while next != 0 {
+                let i = next.trailing_zeros() as usize;
+                next &= next - 1;
+                visited |= 1 << i;
+                parent[i] = node as u8;
+                queue[tail] = i as u8;
+                tail += 1;
+            }
+        }
+
+        if visited & (1 << to) == 0 {
+            return None;
+        }
+
+        // Walk back from `to`, then reverse
+        let mut reversed = Chain::new();
+        let mut node = to;
+        loop {
+            reversed.push(self.classes[node]);
+            if node == from {
+                break;
+            }
+            node = parent[node] as usize;
+        }
+
+        let mut chain = Chain::new();
+        for &class in reversed.as_slice().iter().rev() {
+            chain.push(class);
+        }
+        Some(chain)
+    }
+}
+
+/// A sequence of classes, each taken while holding the one before.
+pub struct Chain {
+    classes: [Class; MAX_CLASSES],
+    len: usize,
+}
+
+impl Chain {
+    const fn new() -> Self {
+        Self {
+            classes: [UNUSED; MAX_CLASSES],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, class: Class) {
+        if self.len < MAX_CLASSES {
+            self.classes[self.len] = class;
+            self.len += 1;
+        }
+    }
+
+    /// Returns the classes in order.
+    pub fn as_slice(&self) -> &[Class] {
+        &self.classes[..self.len]
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, class) in self.as_slice().iter().enumerate() {
+            if i != 0 {
+                write!(f, " -> ")?;
+            }
+            write!(f, "{}", class)?;
+        }
+        Ok(())
+    }
+}
+
+/// Two code paths that take locks in opposite orders.
+pub struct Inversion {
+    /// The order this CPU is taking them in.
+    pub current: Chain,
+    /// The order seen before.
+    pub previous: Chain,
+}
+
+impl fmt::Display for Inversion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "lock order inversion")?;
+        writeln!(f, "  this CPU: {}", self.current)?;
+        write!(f, "  seen before: {}", self.previous)
+    }
+}
+
+/// The classes a CPU holds, innermost last.
+pub struct HeldLocks {
+    classes: [Class; MAX_HELD],
+    len: usize,
+    /// Locks taken while the stack was full.
+    overflow: usize,
+}
+
+impl HeldLocks {
+    pub const fn new() -> Self {
+        Self {
+            classes: [UNUSED; MAX_HELD],
+            len: 0,
+            overflow: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[Class] {
+        &self.classes[..self.len]
+    }
+
+    fn push(&mut self, class: Class) {
+        if self.len < MAX_HELD {
+            self.classes[self.len] = class;
+            self.len += 1;
+        } else {
+            self.overflow += 1;
+        }
+    }
+
+    /// Removes the innermost entry of `class`. Locks can be released in
+    /// any order.
+    fn remove(&mut self, class: Class) {
+        if self.overflow > 0 {
+            self.overflow -= 1;
+            return;
+        }
+
+        if let Some(i) = self.as_slice().iter().rposition(|held| core::ptr::eq(*held, class)) {
+            self.classes.copy_within(i + 1..self.len, i);
+            self.len -= 1;
+        }
+    }
+}
+
+fn held_locks() -> &'static mut HeldLocks {
+    &mut crate::cpu::get_current().held_locks
+}
+
+/// Checks that `class` may be taken while holding the current locks.
+///
+/// Call this before blocking on the lock.
+pub fn check(class: Class) {
+    let result = GRAPH.with(|graph| graph.acquire(held_locks().as_slice(), class));
+    if let Err(inversion) = result {
+        panic!("{}", inversion);
+    }
+}
+
+/// Records that the current CPU holds `class`.
+pub fn acquired(class: Class) {
+    held_locks().push(class);
+}
+
+/// Records that the current CPU released `class`.
+pub fn released(class: Class) {
+    held_locks().remove(class);
+}
@@ -0,0 +1,321 @@
+//! Interrupt-safe Mutex implementation
+//! 
+//! This mutex disables interrupts while holding the lock to prevent deadlocks
+//! with interrupt handlers that might try to acquire the same lock. The
+//! guard holds an [`IrqGuard`] for that.
+//!
+//! With the `lock-stats` feature, each mutex counts how often it was
+//! acquired and how much waiting that took. In debug builds it remembers
+//! its owner, so waiting for it for too long panics with a deadlock report
+//! (see [`crate::sync::deadlock`]). With the `lockdep` feature, the order
+//! locks are taken in is checked (see [`crate::sync::lockdep`]).
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::time::Duration;
+#[cfg(any(debug_assertions, feature = "lockdep"))]
+use core::panic::Location;
+#[cfg(feature = "lock-stats")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(debug_assertions)]
+use crate::sync::deadlock::{Owner, Waiter};
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::{self, Class};
+use crate::cpu::tsc;
+use crate::sync::raw_lock::{Backoff, RawLock, RawSpinLock};
+use crate::sync::IrqGuard;
+
+/// A mutual exclusion primitive that disables interrupts while held
+///
+/// The lock itself is a [`RawLock`], a test-and-set spin lock by default.
+pub struct Mutex<T, R: RawLock = RawSpinLock> {
+    raw: R,
+    #[cfg(feature = "lock-stats")]
+    stats: LockCounters,
+    #[cfg(debug_assertions)]
+    owner: Owner,
+    #[cfg(feature = "lockdep")]
+    class: Class,
+    data: UnsafeCell<T>,
+}
+
+/// Contention statistics of a mutex
+#[cfg(feature = "lock-stats")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LockStats {
+    /// Number of times the mutex was acquired
+    pub acquisitions: usize,
+    /// Acquisitions that had to wait
+    pub contended: usize,
+    /// Total number of waiting iterations
+    pub spins: usize,
+}
+
+#[cfg(feature = "lock-stats")]
+struct LockCounters {
+    acquisitions: AtomicUsize,
+    contended: AtomicUsize,
+    spins: AtomicUsize,
+}
+
+#[cfg(feature = "lock-stats")]
+impl LockCounters {
+    const fn new() -> Self {
+        Self {
+            acquisitions: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            spins: AtomicUsize::new(0),
+        }
+    }
+
+    fn record(&self, spins: usize) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if spins != 0 {
+            self.contended.fetch_add(1, Ordering::Relaxed);
+            self.spins.fetch_add(spins, Ordering::Relaxed);
+        }
+    }
+}
+
+unsafe impl<T: Send, R: RawLock + Sync> Sync for Mutex<T, R> {}
+unsafe impl<T: Send, R: RawLock + Send> Send for Mutex<T, R> {}
+
+impl<T, R: RawLock> Mutex<T, R> {
+    /// Creates a new mutex
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: R::INIT,
+            #[cfg(feature = "lock-stats")]
+            stats: LockCounters::new(),
+            #[cfg(debug_assertions)]
+            owner: Owner::new(),
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the mutex, blocking until it becomes available
+    /// Disables interrupts before acquiring the lock
+    #[track_caller]
+    pub fn lock(&self) -> MutexGuard<'_, T, R> {
+        let irq = IrqGuard::new();
+
+        #[cfg(feature = "lockdep")]
+        lockdep::check(self.class);
+
+        #[cfg(debug_assertions)]
+        let _spins = {
+            let mut waiter = Waiter::new(Location::caller());
+            let spins = self.raw.lock_with(|| waiter.check(&self.owner));
+            self.owner.set(Location::caller());
+            spins
+        };
+        #[cfg(not(debug_assertions))]
+        let _spins = self.raw.lock();
+        #[cfg(feature = "lock-stats")]
+        self.stats.record(_spins);
+        #[cfg(feature = "lockdep")]
+        lockdep::acquired(self.class);
+
+        MutexGuard { mutex: self, _irq: irq }
+    }
+
+    /// Tries to acquire the mutex without blocking
+    #[track_caller]
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T, R>> {
+        let irq = IrqGuard::new();
+
+        if self.raw.try_lock() {
+            #[cfg(debug_assertions)]
+            self.owner.set(Location::caller());
+            #[cfg(feature = "lock-stats")]
+            self.stats.record(0);
+            #[cfg(feature = "lockdep")]
+            lockdep::acquired(self.class);
+            Some(MutexGuard { mutex: self, _irq: irq })
+        } else {
+            None
+        }
+    }
+
+    /// Tries to acquire the mutex until `timeout` has passed
+    ///
+    /// Interrupts are enabled again between attempts if they were before.
+    /// The timeout is measured with the TSC, so it's only as accurate as
+    /// [`tsc::khz`].
+    #[track_caller]
+    pub fn try_lock_for(&self, timeout: Duration) -> Option<MutexGuard<'_, T, R>> {
+        let deadline = tsc::deadline(timeout);
+        let mut backoff = Backoff::new();
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return Some(guard);
+            }
+            if tsc::rdtsc() >= deadline {
+                return None;
+            }
+            backoff.spin();
+        }
+    }
+
+    /// Returns a mutable reference to the data
+    ///
+    /// No locking is needed, since the mutex is borrowed mutably.
+    #[allow(dead_code)] // No mutex is owned outright yet
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the mutex and returns the data
+    #[allow(dead_code)] // Nothing takes a mutex apart yet
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+
+    /// Releases the mutex, whoever holds it
+    ///
+    /// For the panic path, where the holder may never release it.
+    ///
+    /// # Safety
+    /// The guard of the previous owner must never be dropped.
+    #[cfg(not(test))]
+    pub unsafe fn force_unlock(&self) {
+        unsafe {
+            self.raw.unlock();
+        }
+    }
+
+    /// Returns whether other CPUs are waiting for the mutex
+    ///
+    /// Only some lock types can tell, the others always return false.
+    #[allow(dead_code)] // Nothing backs off on contention yet
+    pub fn is_contended(&self) -> bool {
+        self.raw.is_contended()
+    }
+
+    /// Returns the contention statistics
+    #[cfg(feature = "lock-stats")]
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.stats.acquisitions.load(Ordering::Relaxed),
+            contended: self.stats.contended.load(Ordering::Relaxed),
+            spins: self.stats.spins.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// RAII guard for the mutex
+pub struct MutexGuard<'a, T, R: RawLock = RawSpinLock> {
+    mutex: &'a Mutex<T, R>,
+    /// Dropped after the lock is released
+    _irq: IrqGuard,
+}
+
+impl<'a, T, R: RawLock> Drop for MutexGuard<'a, T, R> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::released(self.mutex.class);
+
+        // Release the lock, interrupts are restored when `_irq` is dropped
+        unsafe {
+            self.mutex.raw.unlock();
+        }
+    }
+}
+
+impl<'a, T, R: RawLock> Deref for MutexGuard<'a, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T, R: RawLock> DerefMut for MutexGuard<'a, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T, R: RawLock> MutexGuard<'a, T, R> {
+    /// Narrows the guard to a part of the data
+    ///
+    /// The lock is still released, and interrupts restored, when the new
+    /// guard is dropped. This is an associated function so it can't be
+    /// confused with a method of `T`.
+    pub fn map<U: ?Sized>(this: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedMutexGuard<'a, U, R> {
+        let data = f(unsafe { &mut *this.mutex.data.get() }) as *mut U;
+        Self::into_mapped(this, data)
+    }
+
+    /// Like [`map`](Self::map), but gives the guard back if `f` returns
+    /// `None`
+    #[allow(dead_code)] // Every caller so far maps infallibly
+    pub fn try_map<U: ?Sized>(
+        this: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedMutexGuard<'a, U, R>, Self> {
+        match f(unsafe { &mut *this.mutex.data.get() }) {
+            Some(data) => {
+                let data = data as *mut U;
+                Ok(Self::into_mapped(this, data))
+            }
+            None => Err(this),
+        }
+    }
+
+    fn into_mapped<U: ?Sized>(this: Self, data: *mut U) -> MappedMutexGuard<'a, U, R> {
+        // The mapped guard takes over unlocking
+        let this = ManuallyDrop::new(this);
+        MappedMutexGuard {
+            raw: &this.mutex.raw,
+            #[cfg(feature = "lockdep")]
+            class: this.mutex.class,
+            data,
+            _irq: unsafe { ptr::read(&this._irq) },
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// A [`MutexGuard`] narrowed to a part of the data with [`MutexGuard::map`]
+pub struct MappedMutexGuard<'a, U: ?Sized, R: RawLock = RawSpinLock> {
+    raw: &'a R,
+    #[cfg(feature = "lockdep")]
+    class: Class,
+    data: *mut U,
+    /// Dropped after the lock is released
+    _irq: IrqGuard,
+    _marker: PhantomData<&'a mut U>,
+}
+
+impl<'a, U: ?Sized, R: RawLock> Drop for MappedMutexGuard<'a, U, R> {
+    fn drop(&mut self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::released(self.class);
+
+        unsafe {
+            self.raw.unlock();
+        }
+    }
+}
+
+impl<'a, U: ?Sized, R: RawLock> Deref for MappedMutexGuard<'a, U, R> {
+    type Target = U;
+
+    fn deref(&self) -> &U {
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, U: ?Sized, R: RawLock> DerefMut for MappedMutexGuard<'a, U, R> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.data }
+    }
+}
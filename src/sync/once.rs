@@ -0,0 +1,170 @@
+//! One-time initialization.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// Not initialized yet.
+const UNINIT: u8 = 0;
+
+/// An initializer is running.
+const INITIALIZING: u8 = 1;
+
+/// The value is ready.
+const READY: u8 = 2;
+
+/// An initializer panicked.
+const POISONED: u8 = 3;
+
+/// No CPU is running the initializer.
+const NO_CPU: usize = usize::MAX;
+
+/// A value that is initialized once.
+///
+/// Racing initializers on other CPUs wait for the first one to finish.
+/// An initializer that needs its own value would wait forever, so that
+/// panics instead.
+pub struct Once<T> {
+    state: AtomicU8,
+    /// The CPU running the initializer.
+    initializer: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send + Sync> Sync for Once<T> {}
+unsafe impl<T: Send> Send for Once<T> {}
+
+impl<T> Once<T> {
+    /// Creates an uninitialized value.
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(UNINIT),
+            initializer: AtomicUsize::new(NO_CPU),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Initializes the value with `f` unless it has been already.
+    ///
+    /// Returns the value either way.
+    pub fn call_once(&self, f: impl FnOnce() -> T) -> &T {
+        if self
+            .state
+            .compare_exchange(UNINIT, INITIALIZING, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+        {
+            self.initializer.store(current_cpu(), Ordering::Relaxed);
+
+            // Mark the value poisoned if `f` unwinds
+            let poison = Poison(&self.state);
+            let value = f();
+            core::mem::forget(poison);
+
+            unsafe {
+                (*self.value.get()).write(value);
+            }
+            self.initializer.store(NO_CPU, Ordering::Relaxed);
+            self.state.store(READY, Ordering::Release);
+        }
+
+        self.wait()
+    }
+
+    /// Same as [`call_once`](Self::call_once).
+    #[allow(dead_code)] // Everything calls call_once so far
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        self.call_once(f)
+    }
+
+    /// Returns the value if it's initialized.
+    pub fn get(&self) -> Option<&T> {
+        match self.state.load(Ordering::Acquire) {
+            READY => Some(unsafe { self.get_unchecked() }),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value is initialized.
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == READY
+    }
+
+    /// Waits for a running initializer.
+    fn wait(&self) -> &T {
+        loop {
+            match self.state.load(Ordering::Acquire) {
+                READY => return unsafe { self.get_unchecked() },
+                POISONED => panic!("Once initializer panicked"),
+                INITIALIZING => {
+                    if self.initializer.load(Ordering::Relaxed) == current_cpu() {
+                        panic!("Once accessed from its own initializer");
+                    }
+                    core::hint::spin_loop();
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    unsafe fn get_unchecked(&self) -> &T {
+        unsafe { (*self.value.get()).assume_init_ref() }
+    }
+}
+
+impl<T> Drop for Once<T> {
+    fn drop(&mut self) {
+        if *self.state.get_mut() == READY {
+            unsafe {
+                self.value.get_mut().assume_init_drop();
+            }
+        }
+    }
+}
+
+/// Poisons a `Once` when dropped.
+///
+/// Kernel panics never unwind, so only the host tests get to see this.
+struct Poison<'a>(&'a AtomicU8);
+
+impl Drop for Poison<'_> {
+    fn drop(&mut self) {
+        self.0.store(POISONED, Ordering::Release);
+    }
+}
+
+/// A value that is initialized on first use.
+pub struct Lazy<T, F = fn() -> T> {
+    once: Once<T>,
+    init: F,
+}
+
+unsafe impl<T: Send + Sync, F: Sync> Sync for Lazy<T, F> {}
+
+impl<T, F: Fn() -> T> Lazy<T, F> {
+    /// Creates a value that is initialized with `init` on first use.
+    pub const fn new(init: F) -> Self {
+        Self {
+            once: Once::new(),
+            init,
+        }
+    }
+
+    /// Initializes the value if it isn't already and returns it.
+    pub fn force(this: &Self) -> &T {
+        this.once.call_once(&this.init)
+    }
+}
+
+impl<T, F: Fn() -> T> Deref for Lazy<T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        Lazy::force(self)
+    }
+}
+
+/// Returns the ID of this CPU for the reentrancy check.
+fn current_cpu() -> usize {
+    crate::cpu::get_cpu_id() as usize
+}
@@ -0,0 +1,172 @@
+//! Raw lock implementations.
+//!
+//! These only provide mutual exclusion. [`Mutex`](super::Mutex)
+//! wraps one to protect data and manage the interrupt flag.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A raw mutual exclusion lock.
+///
+/// # Safety
+/// `lock` and a successful `try_lock` must not return while another caller
+/// holds the lock.
+pub unsafe trait RawLock {
+    /// An unlocked lock.
+    const INIT: Self;
+
+    /// Acquires the lock, spinning until it's available.
+    ///
+    /// Returns how many times it had to wait, which is zero if the lock
+    /// was free.
+    fn lock(&self) -> usize {
+        self.lock_with(|| {})
+    }
+
+    /// Like `lock`, but calls `on_wait` every time it has to wait.
+    fn lock_with(&self, on_wait: impl FnMut()) -> usize;
+
+    /// Acquires the lock if it's available.
+    fn try_lock(&self) -> bool;
+
+    /// Releases the lock.
+    ///
+    /// # Safety
+    /// The caller must hold the lock.
+    unsafe fn unlock(&self);
+
+    /// Returns whether other callers are waiting for the lock.
+    ///
+    /// This is only a hint for diagnostics.
+    fn is_contended(&self) -> bool {
+        false
+    }
+}
+
+/// Most pause instructions between two looks at a contended lock.
+const MAX_BACKOFF: usize = 64;
+
+/// Capped exponential backoff for spin loops.
+pub struct Backoff {
+    pauses: usize,
+}
+
+impl Backoff {
+    pub const fn new() -> Self {
+        Self { pauses: 1 }
+    }
+
+    /// Pauses, for twice as long as last time up to a limit.
+    pub fn spin(&mut self) {
+        for _ in 0..self.pauses {
+            core::hint::spin_loop();
+        }
+        self.pauses = (self.pauses * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// A test-and-test-and-set spin lock.
+///
+/// This is cheap, but waiters acquire it in no particular order. Waiters
+/// only read the lock until it looks free, and back off exponentially, so
+/// the cache line isn't bounced between CPUs.
+pub struct RawSpinLock {
+    locked: AtomicBool,
+}
+
+unsafe impl RawLock for RawSpinLock {
+    const INIT: Self = Self {
+        locked: AtomicBool::new(false),
+    };
+
+    fn lock_with(&self, mut on_wait: impl FnMut()) -> usize {
+        let mut spins = 0;
+        let mut backoff = Backoff::new();
+
+        loop {
+            if self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return spins;
+            }
+
+            while self.locked.load(Ordering::Relaxed) {
+                backoff.spin();
+                spins += 1;
+                on_wait();
+            }
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A ticket lock.
+///
+/// Waiters are served in the order they arrived, so none can starve.
+pub struct RawTicketLock {
+    /// The next ticket to hand out.
+    next: AtomicUsize,
+    /// The ticket being served.
+    owner: AtomicUsize,
+}
+
+impl RawTicketLock {
+    /// Takes a ticket.
+    pub(super) fn take_ticket(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Waits until `ticket` is served, calling `on_wait` while waiting.
+    ///
+    /// Returns how many times it had to wait.
+    pub(super) fn wait(&self, ticket: usize, mut on_wait: impl FnMut()) -> usize {
+        let mut spins = 0;
+        while self.owner.load(Ordering::Acquire) != ticket {
+            core::hint::spin_loop();
+            spins += 1;
+            on_wait();
+        }
+        spins
+    }
+}
+
+unsafe impl RawLock for RawTicketLock {
+    const INIT: Self = Self {
+        next: AtomicUsize::new(0),
+        owner: AtomicUsize::new(0),
+    };
+
+    fn lock_with(&self, on_wait: impl FnMut()) -> usize {
+        let ticket = self.take_ticket();
+        self.wait(ticket, on_wait)
+    }
+
+    fn try_lock(&self) -> bool {
+        // Only take a ticket if it would be served right away
+        let owner = self.owner.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(owner, owner.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        let owner = self.owner.load(Ordering::Relaxed);
+        self.owner.store(owner.wrapping_add(1), Ordering::Release);
+    }
+
+    fn is_contended(&self) -> bool {
+        let next = self.next.load(Ordering::Relaxed);
+        let owner = self.owner.load(Ordering::Relaxed);
+        next.wrapping_sub(owner) > 1
+    }
+}
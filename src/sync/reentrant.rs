@@ -0,0 +1,173 @@
+//! A mutex that the CPU holding it can lock again.
+//!
+//! Code that holds a [`ReentrantMutex`] can call back into code that takes
+//! it, like a console sink that logs a warning while it's being written
+//! to. Only the outermost guard releases the lock. Since several guards
+//! can be alive at once, they only give out `&T`; use `Cell` and friends
+//! for anything that changes.
+//!
+//! The owner is the CPU. Once there are threads, it should be the thread.
+
+use core::cell::UnsafeCell;
+use core::ops::Deref;
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep::{self, Class};
+use crate::sync::raw_lock::{RawLock, RawSpinLock};
+use crate::sync::IrqGuard;
+
+/// Owner of a mutex that isn't locked.
+const NO_OWNER: usize = usize::MAX;
+
+/// A mutex that can be locked again by the CPU holding it
+///
+/// Interrupts are disabled while it's held, like with [`Mutex`](super::Mutex).
+pub struct ReentrantMutex<T> {
+    raw: RawSpinLock,
+    /// ID of the CPU holding the lock
+    owner: AtomicUsize,
+    /// Number of live guards, only touched by the owner
+    depth: UnsafeCell<usize>,
+    #[cfg(feature = "lockdep")]
+    class: Class,
+    data: T,
+}
+
+// Only one CPU can get at the data at a time
+unsafe impl<T: Send> Sync for ReentrantMutex<T> {}
+unsafe impl<T: Send> Send for ReentrantMutex<T> {}
+
+impl<T> ReentrantMutex<T> {
+    /// Creates a new mutex
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(value: T) -> Self {
+        Self {
+            raw: RawSpinLock::INIT,
+            owner: AtomicUsize::new(NO_OWNER),
+            depth: UnsafeCell::new(0),
+            #[cfg(feature = "lockdep")]
+            class: Location::caller(),
+            data: value,
+        }
+    }
+
+    /// Acquires the mutex, blocking until it becomes available unless the
+    /// current CPU already holds it
+    pub fn lock(&self) -> ReentrantMutexGuard<'_, T> {
+        let irq = IrqGuard::new();
+
+        if !self.relock() {
+            // Taking it again doesn't add to the lock order
+            #[cfg(feature = "lockdep")]
+            lockdep::check(self.class);
+
+            self.raw.lock();
+            self.acquired();
+        }
+
+        ReentrantMutexGuard { mutex: self, _irq: irq }
+    }
+
+    /// Tries to acquire the mutex without blocking
+    pub fn try_lock(&self) -> Option<ReentrantMutexGuard<'_, T>> {
+        let irq = IrqGuard::new();
+
+        if !self.relock() {
+            if !self.raw.try_lock() {
+                return None;
+            }
+            self.acquired();
+        }
+
+        Some(ReentrantMutexGuard { mutex: self, _irq: irq })
+    }
+
+    /// Returns the depth of the current CPU's hold on the mutex, which is
+    /// zero if it doesn't hold it
+    #[cfg(test)]
+    pub fn depth(&self) -> usize {
+        if self.is_owner() {
+            unsafe { *self.depth.get() }
+        } else {
+            0
+        }
+    }
+
+    /// Releases the mutex, whoever holds it
+    ///
+    /// # Safety
+    /// The guards of the previous owner must never be dropped.
+    pub unsafe fn force_unlock(&self) {
+        if self.owner.swap(NO_OWNER, Ordering::Relaxed) != NO_OWNER {
+            unsafe {
+                *self.depth.get() = 0;
+                self.raw.unlock();
+            }
+        }
+    }
+
+    fn is_owner(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == crate::cpu::get_cpu_id() as usize
+    }
+
+    /// Takes the mutex again if the current CPU holds it.
+    ///
+    /// Interrupts must be disabled, so nothing else on this CPU can
+    /// change the depth while we do.
+    fn relock(&self) -> bool {
+        if !self.is_owner() {
+            return false;
+        }
+        unsafe {
+            *self.depth.get() += 1;
+        }
+        true
+    }
+
+    /// Makes the current CPU the owner, after taking the raw lock.
+    fn acquired(&self) {
+        self.owner.store(crate::cpu::get_cpu_id() as usize, Ordering::Relaxed);
+        unsafe {
+            *self.depth.get() = 1;
+        }
+        #[cfg(feature = "lockdep")]
+        lockdep::acquired(self.class);
+    }
+}
+
+/// RAII guard for the reentrant mutex
+pub struct ReentrantMutexGuard<'a, T> {
+    mutex: &'a ReentrantMutex<T>,
+    /// Dropped after the lock is released
+    _irq: IrqGuard,
+}
+
+impl<T> Drop for ReentrantMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let depth = unsafe { &mut *self.mutex.depth.get() };
+        crate::kdebug_assert!(*depth > 0, "ReentrantMutexGuard dropped more often than created");
+        *depth -= 1;
+        if *depth > 0 {
+            return;
+        }
+
+        #[cfg(feature = "lockdep")]
+        lockdep::released(self.mutex.class);
+
+        self.mutex.owner.store(NO_OWNER, Ordering::Relaxed);
+        unsafe {
+            self.mutex.raw.unlock();
+        }
+    }
+}
+
+impl<T> Deref for ReentrantMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.mutex.data
+    }
+}
@@ -0,0 +1,69 @@
+//! Sequence locks.
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+/// A value that is read often and written rarely, by a single writer
+///
+/// Readers never block the writer or each other. The writer makes the
+/// sequence number odd while it updates the value, and readers retry
+/// until they see the same even number before and after their copy.
+/// That makes it a good fit for data updated from an interrupt handler,
+/// since reading it doesn't disable interrupts.
+///
+/// Writers must not race each other; use a lock around `write` if there
+/// can be more than one.
+pub struct SeqLock<T: Copy> {
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    /// Creates a new sequence lock
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a consistent copy of the value
+    pub fn read(&self) -> T {
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 != 0 {
+                core::hint::spin_loop();
+                continue;
+            }
+
+            // May be torn, in which case the sequence will have changed
+            let value = unsafe { ptr::read_volatile(self.data.get()) };
+
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+
+    /// Updates the value
+    pub fn write(&self, f: impl FnOnce(&mut T)) {
+        let seq = self.seq.load(Ordering::Relaxed);
+        crate::kdebug_assert!(seq % 2 == 0, "SeqLock written from two places at once");
+
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        let mut value = unsafe { ptr::read_volatile(self.data.get()) };
+        f(&mut value);
+        unsafe {
+            ptr::write_volatile(self.data.get(), value);
+        }
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
@@ -0,0 +1,423 @@
+//! Synchronization primitive tests.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+use super::{
+    Barrier, IrqGuard, Lazy, Mutex, MutexGuard, Once, RawLock, RawSpinLock, RawTicketLock,
+    ReentrantMutex, SeqLock, TicketMutex, WaitQueue,
+};
+use crate::cpu::tsc;
+use crate::memory::rwlock::RwLock;
+
+#[test_case]
+fn once_runs_once() {
+    let once = Once::new();
+    let mut calls = 0;
+
+    assert!(once.get().is_none());
+    assert_eq!(*once.call_once(|| {
+        calls += 1;
+        42
+    }), 42);
+    assert_eq!(*once.call_once(|| 0), 42);
+    assert_eq!(*once.get_or_init(|| 0), 42);
+    assert_eq!(once.get(), Some(&42));
+    assert_eq!(calls, 1);
+}
+
+#[test_case]
+fn lazy_initializes_on_first_use() {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    static VALUE: Lazy<usize> = Lazy::new(|| {
+        CALLS.fetch_add(1, Ordering::Relaxed);
+        7
+    });
+
+    assert_eq!(CALLS.load(Ordering::Relaxed), 0);
+    assert_eq!(*VALUE, 7);
+    assert_eq!(*VALUE, 7);
+    assert_eq!(CALLS.load(Ordering::Relaxed), 1);
+}
+
+#[test_case]
+fn ticket_lock_is_fifo() {
+    let lock = RawTicketLock::INIT;
+
+    // Three CPUs line up
+    let first = lock.take_ticket();
+    let second = lock.take_ticket();
+    let third = lock.take_ticket();
+    assert!(lock.is_contended());
+
+    lock.wait(first, || {});
+    assert!(!lock.try_lock());
+    unsafe { lock.unlock() };
+
+    lock.wait(second, || {});
+    unsafe { lock.unlock() };
+
+    lock.wait(third, || {});
+    assert!(!lock.is_contended());
+    unsafe { lock.unlock() };
+
+    assert!(lock.try_lock());
+    unsafe { lock.unlock() };
+}
+
+#[test_case]
+fn ticket_mutex() {
+    let mutex = TicketMutex::new(0);
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert!(mutex.try_lock().is_none());
+        assert!(!mutex.is_contended());
+    }
+    assert_eq!(*mutex.lock(), 1);
+}
+
+/// Uncontended lock and unlock cost. With a single CPU this only shows
+/// that the ticket lock costs about the same as the spin lock, which has
+/// to hold within a factor of two plus some noise.
+#[test_case]
+fn lock_overhead() {
+    const ITERATIONS: u64 = 10_000;
+
+    fn cycles_per_lock<R: RawLock>(mutex: &Mutex<u64, R>) -> u64 {
+        let start = unsafe { core::arch::x86_64::_rdtsc() };
+        for _ in 0..ITERATIONS {
+            *mutex.lock() += 1;
+        }
+        let end = unsafe { core::arch::x86_64::_rdtsc() };
+        (end - start) / ITERATIONS
+    }
+
+    let spin: Mutex<u64, RawSpinLock> = Mutex::new(0);
+    let ticket: Mutex<u64, RawTicketLock> = Mutex::new(0);
+    let spin_cycles = cycles_per_lock(&spin);
+    let ticket_cycles = cycles_per_lock(&ticket);
+    crate::print!("(spin {} cycles, ticket {} cycles) ", spin_cycles, ticket_cycles);
+
+    assert_eq!(*spin.lock(), ITERATIONS);
+    assert_eq!(*ticket.lock(), ITERATIONS);
+    assert!(
+        ticket_cycles <= spin_cycles * 2 + 100,
+        "ticket lock {} cycles, spin lock {} cycles",
+        ticket_cycles,
+        spin_cycles
+    );
+}
+
+#[test_case]
+fn uncontended_lock_does_not_spin() {
+    let spin = RawSpinLock::INIT;
+    assert_eq!(spin.lock(), 0);
+    assert!(!spin.try_lock());
+    unsafe { spin.unlock() };
+
+    let ticket = RawTicketLock::INIT;
+    assert_eq!(ticket.lock(), 0);
+    unsafe { ticket.unlock() };
+}
+
+#[cfg(feature = "lock-stats")]
+#[test_case]
+fn lock_stats_count_acquisitions() {
+    let mutex: Mutex<u64> = Mutex::new(0);
+    *mutex.lock() += 1;
+    drop(mutex.try_lock());
+
+    let stats = mutex.stats();
+    assert_eq!(stats.acquisitions, 2);
+    assert_eq!(stats.contended, 0);
+    assert_eq!(stats.spins, 0);
+}
+
+#[cfg(feature = "lockdep")]
+#[test_case]
+fn lockdep_reports_first_inversion() {
+    use core::panic::Location;
+
+    use super::lockdep::{Class, Graph};
+
+    #[track_caller]
+    fn class() -> Class {
+        Location::caller()
+    }
+    let (a, b, c) = (class(), class(), class());
+    let mut graph = Graph::new();
+
+    assert!(graph.acquire(&[], a).is_ok());
+    assert!(graph.acquire(&[a], b).is_ok());
+    assert!(graph.acquire(&[a, b], c).is_ok());
+
+    // The same order again is fine
+    assert!(graph.acquire(&[a], b).is_ok());
+
+    // Caught on the first inverted acquisition, nothing has to deadlock
+    let inversion = graph.acquire(&[b], a).err().expect("Inversion not detected");
+    assert_eq!(inversion.current.as_slice(), &[b, a]);
+    assert_eq!(inversion.previous.as_slice(), &[a, b]);
+
+    // Through a chain of edges
+    let inversion = graph.acquire(&[c], a).err().expect("Inversion not detected");
+    assert_eq!(inversion.current.as_slice(), &[c, a]);
+    assert_eq!(inversion.previous.as_slice().first(), Some(&a));
+    assert_eq!(inversion.previous.as_slice().last(), Some(&c));
+}
+
+#[cfg(feature = "lockdep")]
+#[test_case]
+fn lockdep_allows_consistent_order() {
+    let outer: Mutex<()> = Mutex::new(());
+    let inner: Mutex<()> = Mutex::new(());
+
+    for _ in 0..2 {
+        let _outer = outer.lock();
+        let _inner = inner.lock();
+    }
+    let _inner = inner.lock();
+}
+
+#[test_case]
+fn irq_guards_nest() {
+    use super::irq::{are_interrupts_enabled, depth};
+
+    let enabled = are_interrupts_enabled();
+    {
+        let outer = IrqGuard::new();
+        assert!(outer.is_outermost());
+        {
+            let inner = IrqGuard::new();
+            assert!(!inner.is_outermost());
+            assert_eq!(depth(), 2);
+        }
+        assert!(!are_interrupts_enabled());
+        assert_eq!(depth(), 1);
+    }
+    assert_eq!(are_interrupts_enabled(), enabled);
+    assert_eq!(depth(), 0);
+}
+
+#[test_case]
+fn irq_guards_drop_in_any_order() {
+    use super::irq::are_interrupts_enabled;
+
+    let enabled = are_interrupts_enabled();
+    let outer = IrqGuard::new();
+    let inner = IrqGuard::new();
+
+    drop(outer);
+    assert!(!are_interrupts_enabled());
+    drop(inner);
+    assert_eq!(are_interrupts_enabled(), enabled);
+}
+
+#[test_case]
+fn mutex_inside_irq_guard() {
+    use super::irq::{are_interrupts_enabled, depth};
+
+    let enabled = are_interrupts_enabled();
+    let mutex: Mutex<u32> = Mutex::new(0);
+
+    let irq = IrqGuard::new();
+    {
+        let mut guard = mutex.lock();
+        *guard += 1;
+        assert_eq!(depth(), 2);
+    }
+    // Unlocking doesn't turn interrupts back on under the outer guard
+    assert!(!are_interrupts_enabled());
+    assert!(mutex.try_lock().is_some());
+    assert!(!are_interrupts_enabled());
+    drop(irq);
+
+    assert_eq!(are_interrupts_enabled(), enabled);
+    assert_eq!(*mutex.lock(), 1);
+}
+
+#[test_case]
+fn try_lock_for_succeeds_before_deadline() {
+    let mutex: Mutex<u32> = Mutex::new(3);
+    let guard = mutex.try_lock_for(Duration::from_millis(1));
+    assert_eq!(guard.as_deref(), Some(&3));
+}
+
+#[test_case]
+fn try_lock_for_times_out() {
+    use super::irq::are_interrupts_enabled;
+
+    let enabled = are_interrupts_enabled();
+    let mutex: Mutex<u32> = Mutex::new(0);
+    let lock: RwLock<u32> = RwLock::new(0);
+
+    let held = mutex.lock();
+    let start = tsc::rdtsc();
+    assert!(mutex.try_lock_for(Duration::from_millis(1)).is_none());
+    assert!(tsc::rdtsc() - start >= tsc::cycles(Duration::from_millis(1)));
+    drop(held);
+    assert_eq!(are_interrupts_enabled(), enabled);
+
+    let writer = lock.write();
+    assert!(lock.try_read_for(Duration::from_micros(100)).is_none());
+    assert!(lock.try_write_for(Duration::from_micros(100)).is_none());
+    drop(writer);
+    assert_eq!(are_interrupts_enabled(), enabled);
+
+    let reader = lock.read();
+    assert!(lock.try_read_for(Duration::from_micros(100)).is_some());
+    assert!(lock.try_write_for(Duration::from_micros(100)).is_none());
+    drop(reader);
+    assert_eq!(are_interrupts_enabled(), enabled);
+}
+
+#[test_case]
+fn reentrant_mutex_nests() {
+    let mutex = ReentrantMutex::new(Cell::new(1));
+    {
+        let outer = mutex.lock();
+        let inner = mutex.lock();
+        assert_eq!(mutex.depth(), 2);
+        inner.set(2);
+        assert!(mutex.try_lock().is_some());
+        drop(inner);
+        assert_eq!(outer.get(), 2);
+        assert_eq!(mutex.depth(), 1);
+    }
+    assert_eq!(mutex.depth(), 0);
+    assert!(mutex.try_lock().is_some());
+}
+
+#[test_case]
+fn seqlock_reads_whole_writes() {
+    let lock = SeqLock::new((0u64, 0u64));
+    for i in 1..=100 {
+        lock.write(|pair| *pair = (i, i * 2));
+        let (a, b) = lock.read();
+        assert_eq!((a, b), (i, i * 2));
+    }
+}
+
+/// Reads the clock while the timer interrupt updates it.
+#[test_case]
+fn clock_reads_are_consistent() {
+    let first = crate::time::clock();
+    let mut last = first;
+    let start = crate::time::ticks();
+
+    // Wait for a few ticks, so reads overlap writes
+    while crate::time::ticks() < start + 3 {
+        let clock = crate::time::clock();
+        assert!(clock.ticks >= last.ticks);
+        assert_eq!(clock.boot_tsc, first.boot_tsc);
+        assert_eq!(clock.tsc_hz, first.tsc_hz);
+        last = clock;
+    }
+
+    let before = crate::time::now_ns();
+    assert!(crate::time::now_ns() >= before);
+}
+
+#[test_case]
+fn wait_queue_without_waiters() {
+    let queue = WaitQueue::new();
+    assert!(!queue.wake_one());
+    assert_eq!(queue.wake_all(), 0);
+
+    // Doesn't wait if the condition already holds
+    queue.wait_until(|| true);
+}
+
+/// The timer is the producer: every tick wakes the waiter exactly once.
+#[test_case]
+fn wait_queue_wakes_on_every_tick() {
+    let start = crate::time::ticks();
+    let mut checks = 0;
+    crate::time::wait_until(|| {
+        checks += 1;
+        crate::time::ticks() >= start + 3
+    });
+
+    assert!(crate::time::ticks() >= start + 3);
+    // The first check, the one after enqueueing, and one per wakeup
+    assert!(checks >= 2);
+    assert!(checks <= 2 * (crate::time::ticks() - start) as usize + 2);
+
+    let before = crate::time::uptime_ms();
+    crate::time::sleep_ms(2);
+    assert!(crate::time::uptime_ms() >= before + 2);
+}
+
+#[test_case]
+fn barrier_is_reusable() {
+    let barrier = Barrier::new(1);
+    for _ in 0..3 {
+        assert!(barrier.wait().unwrap().is_leader());
+    }
+    assert!(barrier.broken().is_none());
+}
+
+#[test_case]
+fn barrier_timeout_reports_missing_cpus() {
+    let barrier = Barrier::new(3);
+    let me = 1 << crate::cpu::get_cpu_id();
+
+    let timeout = barrier.wait_timeout(Duration::from_micros(100)).unwrap_err();
+    assert_eq!(timeout.missing, 0b111 & !me);
+
+    // Broken now, so later waits don't wait
+    assert_eq!(barrier.broken(), Some(timeout));
+    assert_eq!(barrier.wait().unwrap_err(), timeout);
+}
+
+#[test_case]
+fn mapped_guard_unlocks_on_drop() {
+    use super::irq::are_interrupts_enabled;
+
+    let enabled = are_interrupts_enabled();
+    let mutex: Mutex<(u32, [u8; 4])> = Mutex::new((1, [0; 4]));
+
+    {
+        // The original guard is moved into `map`, so only the field is reachable
+        let mut bytes = MutexGuard::map(mutex.lock(), |(_, bytes)| &mut bytes[..]);
+        bytes[2] = 7;
+        assert!(mutex.try_lock().is_none());
+        assert!(!are_interrupts_enabled());
+    }
+
+    assert_eq!(are_interrupts_enabled(), enabled);
+    assert_eq!(mutex.lock().1, [0, 0, 7, 0]);
+}
+
+#[test_case]
+fn try_map_returns_guard() {
+    let mutex: Mutex<Option<u32>> = Mutex::new(Some(5));
+
+    let guard = MutexGuard::try_map(mutex.lock(), |value| value.as_mut())
+        .ok()
+        .expect("try_map failed");
+    assert_eq!(*guard, 5);
+    drop(guard);
+
+    *mutex.lock() = None;
+    let guard = match MutexGuard::try_map(mutex.lock(), |value| value.as_mut()) {
+        Ok(_) => panic!("try_map mapped None"),
+        Err(guard) => guard,
+    };
+    // Still locked until the returned guard is dropped
+    assert!(guard.is_none());
+    assert!(mutex.try_lock().is_none());
+    drop(guard);
+    assert!(mutex.try_lock().is_some());
+}
+
+#[test_case]
+fn mutex_get_mut_and_into_inner() {
+    let mut mutex: Mutex<u32> = Mutex::new(1);
+
+    *mutex.get_mut() += 1;
+    assert_eq!(*mutex.lock(), 2);
+    assert_eq!(mutex.into_inner(), 2);
+}
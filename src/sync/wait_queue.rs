@@ -0,0 +1,76 @@
+//! Waiting for a condition without spinning.
+//!
+//! There are no threads yet, so the waiters are CPUs. A waiting CPU halts
+//! until an interrupt arrives, checks whether it was woken, and halts
+//! again if not. Wakeups come from interrupt handlers, which only mark
+//! waiters as woken.
+//!
+//! The predicate is checked with interrupts disabled, and `sti; hlt`
+//! doesn't let an interrupt in between the two instructions, so a wakeup
+//! can't slip in after the check and before the CPU halts.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::sync::irq::{self, IrqGuard};
+
+/// CPUs waiting for a condition
+pub struct WaitQueue {
+    /// Bit `n` is set while CPU `n` waits
+    waiters: AtomicU64,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: AtomicU64::new(0),
+        }
+    }
+
+    /// Halts until `pred` returns true
+    ///
+    /// `pred` runs with interrupts disabled, once before waiting and again
+    /// after every wakeup. Interrupts must be enabled, since nothing could
+    /// wake us otherwise.
+    pub fn wait_until(&self, mut pred: impl FnMut() -> bool) {
+        let bit = 1 << crate::cpu::get_cpu_id();
+
+        loop {
+            let irq = IrqGuard::new();
+            if pred() {
+                return;
+            }
+
+            crate::kassert!(irq.restores_interrupts(), "Waiting with interrupts disabled");
+
+            // Enqueue, then check again, in case we were woken in between
+            self.waiters.fetch_or(bit, Ordering::AcqRel);
+            if pred() {
+                self.waiters.fetch_and(!bit, Ordering::AcqRel);
+                return;
+            }
+
+            while self.waiters.load(Ordering::Acquire) & bit != 0 {
+                irq::wait_for_interrupt();
+            }
+        }
+    }
+
+    /// Wakes the waiting CPU with the lowest ID
+    ///
+    /// Returns whether there was one.
+    #[allow(dead_code)] // Every waker wakes all so far
+    pub fn wake_one(&self) -> bool {
+        self.waiters
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |waiters| {
+                (waiters != 0).then(|| waiters & (waiters - 1))
+            })
+            .is_ok()
+    }
+
+    /// Wakes all waiting CPUs
+    ///
+    /// Returns how many there were.
+    pub fn wake_all(&self) -> usize {
+        self.waiters.swap(0, Ordering::AcqRel).count_ones() as usize
+    }
+}
@@ -0,0 +1,127 @@
+//! In-kernel test framework.
+//!
+//! `cargo test` builds the kernel with the `#[test_case]` functions
+//! collected by `custom_test_frameworks`. After initialization `rust_main`
+//! calls the generated `test_main`, which hands them to [`runner`]. The
+//! result is reported by exiting QEMU through [`crate::qemu`], so a single
+//! `cargo test` run produces a pass/fail status (see `test-runner.sh`).
+//!
+//! Each test also prints a line for the host to parse:
+//!
+//! ```text
+//! [test-result] name=hello_os::memory::test::box_allocation status=ok
+//! ```
+//!
+//! The status is `ok` or `failed`. A panic outside a test, say during
+//! boot, exits with [`ExitCode::Panicked`] instead of a failure.
+//!
+//! A test that leaves pages allocated fails too, with the report from
+//! [`leak_check`], unless it's in [`leak_check::ALLOWED_LEAKS`].
+
+use core::fmt;
+use core::panic::PanicInfo;
+
+use crate::memory::leak_check;
+use crate::qemu::{self, ExitCode};
+use crate::sync::Mutex;
+use crate::{print, println};
+
+/// The name of the test that is running, for the panic handler.
+static CURRENT_TEST: Mutex<Option<&'static str>> = Mutex::new(None);
+
+/// A fixed-size buffer to format into without the heap.
+pub struct Buffer {
+    bytes: [u8; 1024],
+    len: usize,
+}
+
+impl Buffer {
+    pub fn new() -> Self {
+        Self {
+            bytes: [0; 1024],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap()
+    }
+}
+
+impl fmt::Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// A test case.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        let name = core::any::type_name::<T>();
+        *CURRENT_TEST.lock() = Some(name);
+
+        print!("{}... ", name);
+        let before = leak_check::snapshot();
+        self();
+        let leaks = leak_check::compare(&before, &leak_check::snapshot());
+        if !leaks.is_balanced() && !leak_check::ALLOWED_LEAKS.contains(&name) {
+            panic!("test leaked memory: {}", leaks);
+        }
+        println!("[ok]");
+        println!("[test-result] name={} status=ok", name);
+
+        *CURRENT_TEST.lock() = None;
+    }
+}
+
+/// Runs all tests and exits QEMU.
+pub fn runner(tests: &[&dyn Testable]) {
+    println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+
+    println!("All tests passed");
+    qemu::exit(ExitCode::Success);
+}
+
+/// Reports a failed test and exits QEMU.
+pub fn panic(info: &PanicInfo) -> ! {
+    use crate::debug::{panic_guard, PanicPath};
+
+    match panic_guard::enter() {
+        PanicPath::Full => {}
+        PanicPath::Minimal => {
+            panic_guard::report_double_panic();
+            qemu::exit(ExitCode::Panicked);
+        }
+        PanicPath::Halt => panic_guard::halt(),
+    }
+
+    let current = CURRENT_TEST.try_lock().and_then(|test| *test);
+
+    crate::console::_print_panic(format_args!("[FAILED]\n"));
+    if let Some(name) = current {
+        crate::console::_print_panic(format_args!("Test {} failed\n", name));
+    }
+    crate::console::_print_panic(format_args!("{}\n", info));
+    crate::console::_print_panic(format_args!("Backtrace:\n{}", crate::debug::Backtrace::capture()));
+
+    match current {
+        Some(name) => {
+            crate::console::_print_panic(format_args!("[test-result] name={} status=failed\n", name));
+            qemu::exit(ExitCode::Failed);
+        }
+        None => qemu::exit(ExitCode::Panicked),
+    }
+}
@@ -0,0 +1,102 @@
+//! Timekeeping.
+//!
+//! Time is kept in a [`Clock`] that the timer interrupt updates on every
+//! tick and anything can read, like the logger for every line. It's in a
+//! [`SeqLock`] so reading it doesn't disable interrupts.
+//!
+//! Ticks count timer interrupts on the bootstrap processor. Wall-clock
+//! durations come from the TSC, so they're only as accurate as
+//! [`tsc::khz`].
+
+use crate::cpu::tsc;
+use crate::sync::{SeqLock, WaitQueue};
+
+/// A snapshot of the time state.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    /// Number of timer interrupts since boot
+    pub ticks: u64,
+    /// TSC value at boot
+    pub boot_tsc: u64,
+    /// TSC frequency in Hz, zero until `init`
+    pub tsc_hz: u64,
+}
+
+impl Clock {
+    const fn new() -> Self {
+        Self {
+            ticks: 0,
+            boot_tsc: 0,
+            tsc_hz: 0,
+        }
+    }
+
+    /// Returns the nanoseconds between boot and the TSC value `tsc`.
+    pub fn tsc_to_ns(&self, tsc: u64) -> u64 {
+        if self.tsc_hz == 0 {
+            return 0;
+        }
+        let cycles = tsc.saturating_sub(self.boot_tsc) as u128;
+        (cycles * 1_000_000_000 / self.tsc_hz as u128) as u64
+    }
+}
+
+static CLOCK: SeqLock<Clock> = SeqLock::new(Clock::new());
+
+/// Woken on every tick.
+static TICK_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Starts the clock.
+///
+/// This should be called once, as early as possible.
+pub fn init() {
+    let tsc_hz = tsc::khz() * 1000;
+    CLOCK.write(|clock| {
+        clock.boot_tsc = tsc::rdtsc();
+        clock.tsc_hz = tsc_hz;
+    });
+}
+
+/// Counts a timer interrupt.
+///
+/// Only the timer interrupt of the bootstrap processor may call this.
+pub fn tick() {
+    CLOCK.write(|clock| clock.ticks += 1);
+    TICK_QUEUE.wake_all();
+}
+
+/// Returns a snapshot of the clock.
+pub fn clock() -> Clock {
+    CLOCK.read()
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    CLOCK.read().ticks
+}
+
+/// Returns the nanoseconds since boot.
+pub fn now_ns() -> u64 {
+    // Read the TSC after the clock, so it's never before `boot_tsc`
+    let clock = CLOCK.read();
+    clock.tsc_to_ns(tsc::rdtsc())
+}
+
+/// Returns the milliseconds since boot.
+pub fn uptime_ms() -> u64 {
+    now_ns() / 1_000_000
+}
+
+/// Halts until `pred` returns true, checking it on every tick.
+///
+/// This is for conditions that don't have an interrupt of their own.
+pub fn wait_until(pred: impl FnMut() -> bool) {
+    TICK_QUEUE.wait_until(pred);
+}
+
+/// Halts for at least `ms` milliseconds.
+#[allow(dead_code)] // Nothing sleeps for a fixed time yet
+pub fn sleep_ms(ms: u64) {
+    let deadline = uptime_ms() + ms;
+    wait_until(|| uptime_ms() >= deadline);
+}
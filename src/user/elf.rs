@@ -0,0 +1,154 @@
+//! Loading ELF executables.
+//!
+//! Only what a static executable needs is supported: the file header and
+//! the `PT_LOAD` program headers. Sections and relocations are ignored.
+//!
+//! References:
+//! - System V ABI, Chapter 4 (Object Files) and Chapter 5 (Program Loading)
+
+use crate::error::{ElfError, Result};
+
+const MAGIC: [u8; 4] = *b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+
+/// Size of the file header.
+const HEADER_SIZE: usize = 64;
+
+/// Size of a program header.
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// A segment to load into memory.
+const PT_LOAD: u32 = 1;
+
+/// The segment is writable.
+const PF_W: u32 = 1 << 1;
+
+/// A program header, with the fields we use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+    pub mem_size: u64,
+}
+
+impl Segment {
+    pub fn is_writable(&self) -> bool {
+        self.flags & PF_W != 0
+    }
+}
+
+/// A parsed ELF executable.
+pub struct Elf<'a> {
+    bytes: &'a [u8],
+    pub entry: u64,
+    phoff: usize,
+    phnum: usize,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl<'a> Elf<'a> {
+    /// Checks the file header.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err(ElfError::NotElf.into());
+        }
+        let supported = bytes[4] == CLASS_64
+            && bytes[5] == DATA_LITTLE_ENDIAN
+            && read_u16(bytes, 16) == TYPE_EXECUTABLE
+            && read_u16(bytes, 18) == MACHINE_X86_64
+            && read_u16(bytes, 54) as usize == PROGRAM_HEADER_SIZE;
+        crate::ensure!(supported, ElfError::Unsupported);
+
+        let phoff = read_u64(bytes, 32) as usize;
+        let phnum = read_u16(bytes, 56) as usize;
+        let end = phnum
+            .checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|size| size.checked_add(phoff));
+        crate::ensure!(end.is_some_and(|end| end <= bytes.len()), ElfError::Truncated(phoff));
+
+        Ok(Self {
+            bytes,
+            entry: read_u64(bytes, 24),
+            phoff,
+            phnum,
+        })
+    }
+
+    /// Returns the segments to load.
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + '_ {
+        (0..self.phnum)
+            .map(|i| self.phoff + i * PROGRAM_HEADER_SIZE)
+            .filter(|&offset| read_u32(self.bytes, offset) == PT_LOAD)
+            .map(|offset| Segment {
+                flags: read_u32(self.bytes, offset + 4),
+                offset: read_u64(self.bytes, offset + 8),
+                vaddr: read_u64(self.bytes, offset + 16),
+                file_size: read_u64(self.bytes, offset + 32),
+                mem_size: read_u64(self.bytes, offset + 40),
+            })
+    }
+
+    /// Returns the bytes of a segment in the file.
+    fn data(&self, segment: &Segment) -> Result<&'a [u8]> {
+        let start = segment.offset as usize;
+        let end = start.checked_add(segment.file_size as usize);
+        match end {
+            Some(end) if end <= self.bytes.len() && segment.file_size <= segment.mem_size => {
+                Ok(&self.bytes[start..end])
+            }
+            _ => Err(ElfError::Truncated(start).into()),
+        }
+    }
+}
+
+/// Loads an executable into user memory, returning its entry point.
+///
+/// A page shared by two segments keeps the permissions of the first.
+/// Memory beyond the file size of a segment is left zeroed.
+pub fn load_elf(bytes: &[u8]) -> Result<u64> {
+    let elf = Elf::parse(bytes)?;
+
+    for segment in elf.segments() {
+        let data = elf.data(&segment)?;
+        crate::ensure!(
+            super::is_user_range(segment.vaddr, segment.mem_size),
+            ElfError::BadAddress(segment.vaddr)
+        );
+
+        let end = segment.vaddr + segment.mem_size;
+        let mut virt = segment.vaddr;
+        while virt < end {
+            let page_end = (virt | 0xfff) + 1;
+            let phys = super::map_page(virt, segment.is_writable())?;
+
+            // Through the identity map, so the user mapping can be read-only
+            let copied = (virt - segment.vaddr) as usize;
+            let len = (page_end.min(end) - virt) as usize;
+            let src = data.get(copied..).unwrap_or(&[]);
+            let src = &src[..len.min(src.len())];
+            let dst = phys.0 + (virt & 0xfff) as usize;
+            unsafe { core::ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len()) };
+
+            virt = page_end;
+        }
+    }
+
+    crate::ensure!(super::is_user_range(elf.entry, 1), ElfError::BadAddress(elf.entry));
+    Ok(elf.entry)
+}
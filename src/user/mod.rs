@@ -0,0 +1,173 @@
+//! Running programs in ring 3.
+//!
+//! User programs live in the second 512GB of the address space, under
+//! their own PML4 entry, so they can be unmapped all at once and never
+//! overlap the identity map of the kernel. Only one program runs at a
+//! time: [`enter_user_mode`] returns when it exits.
+//!
+//! There's no filesystem, so the only program is [`TEST_BINARY`], which
+//! [`self_test`] runs as a self-test.
+
+pub mod elf;
+pub mod syscall;
+#[cfg(test)]
+mod test;
+
+use core::arch::naked_asm;
+
+use crate::cpu::KERNEL_RSP_OFFSET;
+use crate::ensure;
+use crate::error::Result;
+use crate::gdt::GlobalDescriptorTable as GDT;
+use crate::memory::page_allocator::PageSize;
+use crate::memory::paging::{self, PageFlags};
+use crate::memory::{self, PhysAddr};
+
+/// The PML4 entry of user memory.
+const USER_PML4_INDEX: usize = 1;
+
+/// Start of user memory.
+pub const USER_BASE: u64 = (USER_PML4_INDEX as u64) << 39;
+
+/// End of user memory.
+pub const USER_END: u64 = USER_BASE + (1 << 39);
+
+/// Top of the user stack, a page below the end of user memory.
+pub const USER_STACK_TOP: u64 = USER_END - PAGE_SIZE;
+
+/// Size of the user stack.
+const USER_STACK_SIZE: u64 = 16 * 1024;
+
+const PAGE_SIZE: u64 = PageSize::Size4KB.bytes() as u64;
+
+/// RFLAGS of a new program: only interrupts enabled.
+const USER_RFLAGS: u64 = 1 << 9;
+
+/// `tests/user_hello/user_hello.asm`, assembled by the build script.
+pub static TEST_BINARY: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/user_hello.elf"));
+
+/// What [`TEST_BINARY`] writes.
+pub const TEST_MESSAGE: &str = "Hello from user mode\n";
+
+crate::selftest!(user_mode, self_test);
+
+/// Runs [`TEST_BINARY`] to check that user mode works.
+///
+/// This goes through the GDT, IDT, page tables, ELF loading and system
+/// calls. The program exits with what its write returned, and the system
+/// call count shows it got that far.
+pub fn self_test() -> Result<()> {
+    let calls = syscall::count();
+    let status = elf::load_elf(TEST_BINARY)
+        .and_then(|entry| Ok((entry, map_stack()?)))
+        .map(|(entry, stack)| unsafe { enter_user_mode(entry, stack) });
+    unsafe { unmap_all() };
+
+    ensure!(status? == TEST_MESSAGE.len() as u64, "the test program failed to write");
+    ensure!(syscall::count() == calls + 2, "the test program made the wrong system calls");
+    Ok(())
+}
+
+/// Returns whether `[start, start + len)` is in user memory.
+pub fn is_user_range(start: u64, len: u64) -> bool {
+    start >= USER_BASE && start.checked_add(len).is_some_and(|end| end <= USER_END)
+}
+
+/// Maps a zeroed page of user memory at `virt`.
+///
+/// Returns the physical address, where the kernel can fill the page in.
+/// If `virt` is already mapped, the page that's there is returned.
+pub fn map_page(virt: u64, writable: bool) -> Result<PhysAddr> {
+    let virt = virt & !(PAGE_SIZE - 1);
+    if let Some((phys, _)) = paging::page_table_walk(virt) {
+        return Ok(phys);
+    }
+
+    let phys = memory::fallible::try_alloc_pages(1, PageSize::Size4KB)?;
+    let flags = PageFlags {
+        writable,
+        user: true,
+        nx: false,
+        large_page: false,
+    };
+    unsafe {
+        core::ptr::write_bytes(phys.0 as *mut u8, 0, PAGE_SIZE as usize);
+        if let Err(e) = paging::map_4kb(paging::current_pml4(), virt, phys, flags) {
+            memory::get_allocator().free_page(phys.0, PageSize::Size4KB);
+            return Err(e);
+        }
+    }
+    Ok(phys)
+}
+
+/// Maps the user stack, returning the initial stack pointer.
+pub fn map_stack() -> Result<u64> {
+    let mut page = USER_STACK_TOP - USER_STACK_SIZE;
+    while page < USER_STACK_TOP {
+        map_page(page, true)?;
+        page += PAGE_SIZE;
+    }
+    Ok(USER_STACK_TOP)
+}
+
+/// Unmaps all user memory and frees it.
+///
+/// # Safety
+/// No program may be running, and nothing may use user memory any more.
+pub unsafe fn unmap_all() {
+    unsafe { paging::unmap_pml4_entry(paging::current_pml4(), USER_PML4_INDEX) };
+}
+
+/// Runs user code at `entry` with the stack pointer at `stack`.
+///
+/// Returns the status the program passed to the exit system call. The
+/// kernel's callee-saved registers and RFLAGS are kept on this stack, and
+/// [`syscall::exit_to_kernel`] picks them up again.
+///
+/// # Safety
+/// The code and stack must be mapped in user memory, and system calls
+/// must have been set up with [`syscall::init_cpu`].
+#[unsafe(naked)]
+pub unsafe extern "C" fn enter_user_mode(entry: u64, stack: u64) -> u64 {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "pushfq",
+        // Now 16-byte aligned, for the system calls that run below
+        "mov gs:[{kernel_rsp}], rsp",
+
+        // Interrupt return frame
+        "push {user_ss}",
+        "push rsi",
+        "push {user_rflags}",
+        "push {user_cs}",
+        "push rdi",
+
+        // Don't leak kernel values
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+
+        kernel_rsp = const KERNEL_RSP_OFFSET,
+        user_ss = const GDT::USER_SS,
+        user_cs = const GDT::USER_CS,
+        user_rflags = const USER_RFLAGS,
+    );
+}
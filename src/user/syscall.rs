@@ -0,0 +1,172 @@
+//! System calls.
+//!
+//! A program makes a system call with the `syscall` instruction, with the
+//! number in RAX and the arguments in RDI, RSI and RDX. The result comes
+//! back in RAX. Like on Linux, RCX and R11 are clobbered and every other
+//! register is kept.
+//!
+//! | Number | Call                  | Returns         |
+//! |--------|-----------------------|-----------------|
+//! | 0      | `write(buf, len)`     | bytes written   |
+//! | 1      | `exit(status)`        | doesn't         |
+//!
+//! `GS` isn't touched by user programs, so the entry code reaches the
+//! per-CPU data without `swapgs`.
+
+use core::arch::{asm, naked_asm};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86::controlregs::{cr4, Cr4};
+use x86::msr::{rdmsr, wrmsr, IA32_EFER, IA32_FMASK, IA32_LSTAR, IA32_STAR};
+
+use super::is_user_range;
+use crate::cpu::{KERNEL_RSP_OFFSET, USER_RSP_OFFSET};
+use crate::gdt::GlobalDescriptorTable as GDT;
+
+pub const SYS_WRITE: u64 = 0;
+pub const SYS_EXIT: u64 = 1;
+
+/// Returned for an unknown system call or bad arguments.
+pub const EINVAL: u64 = -22i64 as u64;
+
+/// `IA32_EFER` bit enabling `syscall` and `sysret`.
+const EFER_SCE: u64 = 1 << 0;
+
+/// RFLAGS bits cleared on entry: TF, IF, DF and AC.
+const SYSCALL_RFLAGS_MASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 18);
+
+/// Number of system calls made.
+static SYSCALL_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Sets up system calls on the current CPU.
+///
+/// `sysret` takes user SS from `IA32_STAR[63:48] + 8` and user CS from
+/// `+ 16`, which is why user data comes right before user code in the GDT.
+pub unsafe fn init_cpu() {
+    let star = ((GDT::KERNEL_SS as u64) << 48) | ((GDT::KERNEL_CS as u64) << 32);
+    unsafe {
+        wrmsr(IA32_STAR, star);
+        wrmsr(IA32_LSTAR, syscall_entry as *const () as u64);
+        wrmsr(IA32_FMASK, SYSCALL_RFLAGS_MASK);
+        wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_SCE);
+    }
+}
+
+/// Returns the number of system calls made.
+pub fn count() -> u64 {
+    SYSCALL_COUNT.load(Ordering::Relaxed)
+}
+
+/// Where `syscall` lands.
+///
+/// Switches to the kernel stack, saves the registers the call must keep,
+/// and calls [`syscall_dispatch`].
+#[unsafe(naked)]
+unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "mov gs:[{user_rsp}], rsp",
+        "mov rsp, gs:[{kernel_rsp}]",
+        "push qword ptr gs:[{user_rsp}]",
+        "push rcx", // User RIP
+        "push r11", // User RFLAGS
+        "push rdi",
+        "push rsi",
+        "push rdx",
+        "push r8",
+        "push r9",
+        "push r10",
+        "sub rsp, 8", // Align the stack for the call
+
+        // fn syscall_dispatch(number, arg0, arg1, arg2) -> u64
+        "mov rcx, rdx",
+        "mov rdx, rsi",
+        "mov rsi, rdi",
+        "mov rdi, rax",
+        "call {dispatch}",
+
+        "add rsp, 8",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdx",
+        "pop rsi",
+        "pop rdi",
+        "pop r11",
+        "pop rcx",
+        "pop rsp",
+        "sysretq",
+
+        user_rsp = const USER_RSP_OFFSET,
+        kernel_rsp = const KERNEL_RSP_OFFSET,
+        dispatch = sym syscall_dispatch,
+    );
+}
+
+/// Handles a system call.
+///
+/// This runs with interrupts disabled.
+extern "C" fn syscall_dispatch(number: u64, arg0: u64, arg1: u64, _arg2: u64) -> u64 {
+    SYSCALL_COUNT.fetch_add(1, Ordering::Relaxed);
+    match number {
+        SYS_WRITE => write(arg0, arg1),
+        SYS_EXIT => unsafe { exit_to_kernel(arg0) },
+        _ => EINVAL,
+    }
+}
+
+/// Writes a user buffer to the serial port.
+fn write(buf: u64, len: u64) -> u64 {
+    if !is_user_range(buf, len) {
+        return EINVAL;
+    }
+    // Only mapped pages are read
+    let mut page = buf & !0xfff;
+    while page < buf + len {
+        if crate::memory::paging::page_table_walk(page).is_none_or(|(_, flags)| !flags.user) {
+            return EINVAL;
+        }
+        page += 0x1000;
+    }
+
+    with_user_access(|| {
+        let bytes = unsafe { core::slice::from_raw_parts(buf as *const u8, len as usize) };
+        crate::serial::_print_bytes(bytes);
+    });
+    len
+}
+
+/// Runs `f` with access to user memory allowed, if SMAP would forbid it.
+fn with_user_access<R>(f: impl FnOnce() -> R) -> R {
+    let smap = unsafe { cr4() }.contains(Cr4::CR4_ENABLE_SMAP);
+    // Not `nomem`: the accesses in `f` mustn't be moved outside the window
+    if smap {
+        unsafe { asm!("stac", options(nostack)) };
+    }
+    let result = f();
+    if smap {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+    result
+}
+
+/// Returns from [`enter_user_mode`](super::enter_user_mode) with `status`.
+///
+/// # Safety
+/// Must be called on the stack set up by `enter_user_mode`.
+#[unsafe(naked)]
+unsafe extern "C" fn exit_to_kernel(status: u64) -> ! {
+    naked_asm!(
+        "mov rax, rdi",
+        "mov rsp, gs:[{kernel_rsp}]",
+        "popfq",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+
+        kernel_rsp = const KERNEL_RSP_OFFSET,
+    );
+}
@@ -0,0 +1,52 @@
+//! User mode tests.
+
+use alloc::vec::Vec;
+
+use super::elf::{load_elf, Elf};
+use super::{enter_user_mode, map_stack, syscall, unmap_all, TEST_BINARY, TEST_MESSAGE, USER_BASE};
+use crate::error::{ElfError, Error};
+
+#[test_case]
+fn elf_header_is_checked() {
+    assert!(matches!(Elf::parse(b"#!/bin/sh"), Err(Error::Elf(ElfError::NotElf))));
+
+    let mut bytes: Vec<u8> = TEST_BINARY.into();
+    bytes[18] = 0x28; // ARM
+    assert!(matches!(Elf::parse(&bytes), Err(Error::Elf(ElfError::Unsupported))));
+
+    let elf = Elf::parse(TEST_BINARY).unwrap();
+    let segments: Vec<_> = elf.segments().collect();
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].vaddr, USER_BASE);
+    assert!(!segments[0].is_writable());
+}
+
+#[test_case]
+fn elf_outside_user_memory_is_rejected() {
+    let mut bytes: Vec<u8> = TEST_BINARY.into();
+    // p_vaddr of the only program header, right after the file header
+    bytes[64 + 16..64 + 24].copy_from_slice(&0x10_0000u64.to_le_bytes());
+    assert_eq!(load_elf(&bytes), Err(ElfError::BadAddress(0x10_0000).into()));
+}
+
+#[test_case]
+fn user_program_runs() {
+    let before = syscall::count();
+
+    let entry = load_elf(TEST_BINARY).unwrap();
+    let stack = map_stack().unwrap();
+    let status = unsafe { enter_user_mode(entry, stack) };
+    unsafe { unmap_all() };
+
+    // The program exits with what the write returned
+    assert_eq!(status, TEST_MESSAGE.len() as u64);
+    assert_eq!(syscall::count(), before + 2);
+    assert!(crate::memory::paging::page_table_walk(entry).is_none());
+}
+
+#[test_case]
+fn self_test_passes_twice() {
+    // The second run finds the user mappings of the first gone
+    super::self_test().unwrap();
+    super::self_test().unwrap();
+}
@@ -0,0 +1,72 @@
+//! Which build is running.
+//!
+//! `build.rs` records the git commit, build time, cargo profile and rustc
+//! version. The boot banner and the panic report start with
+//! [`BuildInfo::CURRENT`], so every log says which build wrote it:
+//!
+//! ```text
+//! hello-os 0.1.0 (3f9c2a1b7e4d-dirty, debug, built 2026-10-16T09:12:44Z, rustc 1.86.0-nightly)
+//! ```
+//!
+//! The commit is `git describe` output, with `-dirty` if the tree had
+//! uncommitted changes, or "unknown" if the kernel wasn't built from a
+//! git checkout.
+
+#[cfg(test)]
+mod test;
+
+use core::fmt;
+
+pub const NAME: &str = env!("CARGO_PKG_NAME");
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT: &str = env!("HELLO_OS_GIT");
+pub const BUILD_TIME: &str = env!("HELLO_OS_BUILD_TIME");
+pub const PROFILE: &str = env!("HELLO_OS_PROFILE");
+pub const RUSTC: &str = env!("HELLO_OS_RUSTC");
+
+/// `GIT` when there's no commit to record.
+pub const UNKNOWN: &str = "unknown";
+
+/// Where a kernel came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub git: &'static str,
+    pub build_time: &'static str,
+    pub profile: &'static str,
+    pub rustc: &'static str,
+}
+
+impl BuildInfo {
+    /// This kernel.
+    pub const CURRENT: Self = Self {
+        name: NAME,
+        version: VERSION,
+        git: GIT,
+        build_time: BUILD_TIME,
+        profile: PROFILE,
+        rustc: RUSTC,
+    };
+
+    /// Returns whether the tree had uncommitted changes.
+    pub fn is_dirty(&self) -> bool {
+        self.git.ends_with("-dirty")
+    }
+}
+
+/// Writes the one-line summary.
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} (", self.name, self.version)?;
+        match self.git {
+            UNKNOWN => write!(f, "unknown commit")?,
+            git => write!(f, "{}", git)?,
+        }
+        write!(
+            f,
+            ", {}, built {}, {})",
+            self.profile, self.build_time, self.rustc
+        )
+    }
+}
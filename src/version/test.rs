@@ -0,0 +1,50 @@
+//! Build information tests.
+
+use core::fmt::Write;
+
+use super::{BuildInfo, UNKNOWN};
+use crate::testing::Buffer;
+
+const INFO: BuildInfo = BuildInfo {
+    name: "hello-os",
+    version: "0.1.0",
+    git: "3f9c2a1b7e4d-dirty",
+    build_time: "2026-10-16T09:12:44Z",
+    profile: "debug",
+    rustc: "rustc 1.86.0-nightly",
+};
+
+#[test_case]
+fn summary_has_every_component() {
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", INFO).unwrap();
+    assert_eq!(
+        buffer.as_str(),
+        "hello-os 0.1.0 (3f9c2a1b7e4d-dirty, debug, built 2026-10-16T09:12:44Z, rustc 1.86.0-nightly)"
+    );
+    assert!(INFO.is_dirty());
+}
+
+#[test_case]
+fn summary_without_git() {
+    let info = BuildInfo { git: UNKNOWN, ..INFO };
+    let mut buffer = Buffer::new();
+    write!(buffer, "{}", info).unwrap();
+    assert_eq!(
+        buffer.as_str(),
+        "hello-os 0.1.0 (unknown commit, debug, built 2026-10-16T09:12:44Z, rustc 1.86.0-nightly)"
+    );
+    assert!(!info.is_dirty());
+}
+
+#[test_case]
+fn current_build_is_recorded() {
+    let current = BuildInfo::CURRENT;
+    assert_eq!(current.name, "hello-os");
+    assert!(!current.git.is_empty());
+    assert!(current.profile == "debug" || current.profile == "release");
+    assert!(current.rustc.starts_with("rustc ") || current.rustc == UNKNOWN);
+    // YYYY-MM-DDTHH:MM:SSZ
+    assert_eq!(current.build_time.len(), 20);
+    assert!(current.build_time.ends_with('Z'));
+}
@@ -0,0 +1,125 @@
+#!/usr/bin/env bash
+# Boots a kernel built by cargo in QEMU.
+#
+# This is the cargo runner for our target, so `cargo test` boots the test
+# kernel and exits with its result. The kernel reports the result through
+# the isa-debug-exit device, and this script turns QEMU's status into:
+#
+#   0    the tests passed (QEMU status 33)
+#   1    a test failed (35)
+#   2    the kernel panicked outside a test (37)
+#   3    the machine reset or powered off without reporting, e.g. a
+#        triple fault (0)
+#   124  the kernel hung, and HELLO_OS_TIMEOUT seconds passed
+#
+# Test kernels print a `[test-result] name=... status=...` line per test,
+# which is summed up at the end. Test binaries named `should_panic*` are
+# expected to panic, so status 2 is a pass for them, as long as the text
+# from their `[should-panic] expect=...` line shows up after it.
+#
+# Extra kernel command line options can be given in HELLO_OS_CMDLINE.
+# QEMU exits when the machine resets, unless HELLO_OS_REBOOT=1.
+set -euo pipefail
+
+kernel="$1"
+shift
+
+workdir="$(mktemp -d)"
+trap 'rm -rf "${workdir}"' EXIT
+
+mkdir -p "${workdir}/iso/boot/grub"
+cp "${kernel}" "${workdir}/iso/boot/hello-os"
+# Integration test binaries have no symbol table section
+if readelf -SW "${kernel}" | grep -q ' \.ksyms '; then
+	"$(dirname "$0")/ksyms.sh" "${workdir}/iso/boot/hello-os"
+fi
+
+# Test binaries live in target/.../deps
+cmdline="${HELLO_OS_CMDLINE:-}"
+is_test=0
+if [[ "${kernel}" == */deps/* ]]; then
+	is_test=1
+	cmdline="test ${cmdline}"
+fi
+
+cat > "${workdir}/iso/boot/grub/grub.cfg" <<GRUBEOF
+set timeout=0
+set default=0
+
+menuentry "Hello OS" {
+    multiboot2 /boot/hello-os ${cmdline}
+    boot
+}
+GRUBEOF
+
+mkrescue=""
+for candidate in i686-elf-grub-mkrescue grub-mkrescue grub2-mkrescue; do
+	if command -v "${candidate}" >/dev/null 2>&1; then
+		mkrescue="${candidate}"
+		break
+	fi
+done
+if [[ -z "${mkrescue}" ]]; then
+	echo "ERROR: No grub-mkrescue found!" >&2
+	exit 1
+fi
+"${mkrescue}" -o "${workdir}/hello-os.iso" "${workdir}/iso" >/dev/null 2>&1
+
+no_reboot="-no-reboot"
+if [[ "${HELLO_OS_REBOOT:-}" == 1 ]]; then
+	no_reboot=""
+fi
+
+# Only test kernels get a time limit, the shell waits for input forever
+run=()
+if [[ "${is_test}" == 1 ]]; then
+	run=(timeout --foreground "${HELLO_OS_TIMEOUT:-300}")
+fi
+
+set +e
+"${run[@]}" qemu-system-x86_64 \
+	-cdrom "${workdir}/hello-os.iso" \
+	-nographic \
+	${no_reboot} \
+	-device isa-debug-exit,iobase=0xf4,iosize=0x04 \
+	"$@" | tee "${workdir}/output"
+status=${PIPESTATUS[0]}
+set -e
+
+case "${status}" in
+	33) result=0 ;;
+	35) result=1 ;;
+	37) result=2 ;;
+	0) result=3 ;;
+	*) result="${status}" ;;
+esac
+
+if [[ "${is_test}" == 1 ]]; then
+	output="$(tr -d '\r' <"${workdir}/output")"
+	passed="$(grep -c '^\[test-result\] .* status=ok$' <<<"${output}" || true)"
+	failed="$(grep '^\[test-result\] .* status=failed$' <<<"${output}" | sed 's/.* name=\([^ ]*\) .*/\1/' || true)"
+	case "${result}" in
+		0) outcome="all tests passed" ;;
+		1) outcome="test failed: ${failed}" ;;
+		2) outcome="kernel panicked outside a test" ;;
+		3) outcome="machine reset, probably a triple fault" ;;
+		124) outcome="timed out" ;;
+		*) outcome="QEMU exited with ${status}" ;;
+	esac
+	echo "test summary: ${passed} passed, $(grep -c . <<<"${failed}" || true) failed, ${outcome}"
+
+	if [[ "$(basename "${kernel}")" == should_panic* ]]; then
+		if [[ "${result}" != 2 ]]; then
+			echo "expected a panic outside a test" >&2
+			exit 1
+		fi
+		expect="$(sed -n 's/^\[should-panic\] expect=//p' <<<"${output}" | head -n 1)"
+		if [[ -n "${expect}" ]] && ! sed '1,/^\[should-panic\] expect=/d' <<<"${output}" | grep -qF -- "${expect}"; then
+			echo "expected the panic report to contain \"${expect}\"" >&2
+			exit 1
+		fi
+		exit 0
+	fi
+fi
+
+exit "${result}"
@@ -0,0 +1,278 @@
+//! Host tests of the synchronization primitives, with threads standing in
+//! for CPUs.
+//!
+//! The kernel has one CPU running tests, so what these primitives do with
+//! several waiters can only be tested here. Their modules are included as
+//! they are, and what they use from the rest of the kernel is replaced
+//! below. Run with `make test-host`.
+
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Stands in for the per-CPU data and the TSC.
+mod cpu {
+    use std::cell::Cell;
+
+    thread_local! {
+        static ID: Cell<i32> = const { Cell::new(0) };
+    }
+
+    pub fn get_cpu_id() -> i32 {
+        ID.with(Cell::get)
+    }
+
+    /// Makes this thread CPU `id`.
+    pub fn set_cpu_id(id: i32) {
+        ID.with(|cell| cell.set(id));
+    }
+
+    /// Nanoseconds instead of cycles.
+    pub mod tsc {
+        use std::sync::OnceLock;
+        use std::time::{Duration, Instant};
+
+        static START: OnceLock<Instant> = OnceLock::new();
+
+        pub fn rdtsc() -> u64 {
+            START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+        }
+
+        pub fn deadline(duration: Duration) -> u64 {
+            rdtsc().saturating_add(duration.as_nanos() as u64)
+        }
+    }
+}
+
+/// Threads can't disable interrupts, or halt until one arrives.
+mod irq {
+    pub struct IrqGuard;
+
+    impl IrqGuard {
+        pub fn new() -> Self {
+            Self
+        }
+
+        pub fn restores_interrupts(&self) -> bool {
+            true
+        }
+    }
+
+    pub fn wait_for_interrupt() {
+        std::thread::yield_now();
+    }
+}
+
+/// The kernel's assertion, minus the log.
+#[macro_export]
+macro_rules! kassert {
+    ($($arg:tt)*) => {
+        assert!($($arg)*)
+    };
+}
+
+#[path = "../../src/sync/barrier.rs"]
+mod barrier;
+#[path = "../../src/sync/once.rs"]
+mod once;
+#[path = "../../src/sync/raw_lock.rs"]
+mod raw_lock;
+#[path = "../../src/sync/wait_queue.rs"]
+mod wait_queue;
+
+/// Where the kernel has the modules above.
+mod sync {
+    pub(crate) use crate::irq;
+    pub(crate) use crate::raw_lock;
+}
+
+use barrier::Barrier;
+use once::Once;
+use wait_queue::WaitQueue;
+
+/// Runs `f` on `n` threads, as CPUs 0 to `n - 1`, and returns what each
+/// returned, in CPU order.
+fn on_cpus<T: Send + 'static>(n: usize, f: impl Fn(usize) -> T + Send + Sync + 'static) -> Vec<T> {
+    let f = Arc::new(f);
+    let threads: Vec<_> = (0..n)
+        .map(|id| {
+            let f = Arc::clone(&f);
+            thread::spawn(move || {
+                cpu::set_cpu_id(id as i32);
+                f(id)
+            })
+        })
+        .collect();
+    threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+}
+
+#[test]
+fn barrier_has_one_leader_per_phase() {
+    const CPUS: usize = 4;
+    const PHASES: usize = 100;
+    let barrier = Arc::new(Barrier::new(CPUS));
+    let arrived = Arc::new(AtomicUsize::new(0));
+
+    let leaders = on_cpus(CPUS, {
+        let (barrier, arrived) = (Arc::clone(&barrier), Arc::clone(&arrived));
+        move |_| {
+            let mut leaders = 0;
+            for phase in 0..PHASES {
+                arrived.fetch_add(1, Ordering::SeqCst);
+                if barrier.wait().unwrap().is_leader() {
+                    leaders += 1;
+                }
+                // Nobody gets past before everyone has arrived
+                assert!(arrived.load(Ordering::SeqCst) >= (phase + 1) * CPUS);
+            }
+            leaders
+        }
+    });
+    assert_eq!(leaders.iter().sum::<usize>(), PHASES);
+}
+
+#[test]
+fn barrier_timeout_releases_waiters() {
+    // CPU 2 never comes, and CPU 0 gives up on it
+    let barrier = Arc::new(Barrier::new(3));
+    let results = on_cpus(2, {
+        let barrier = Arc::clone(&barrier);
+        move |id| match id {
+            0 => {
+                thread::sleep(Duration::from_millis(50));
+                barrier.wait_timeout(Duration::from_millis(50))
+            }
+            _ => barrier.wait(),
+        }
+    });
+
+    let missing = 0b100;
+    assert_eq!(results[0].unwrap_err().missing, missing);
+    assert_eq!(results[1].unwrap_err().missing, missing);
+
+    // CPU 2 turns up late and doesn't wait either
+    let late = on_cpus(3, move |id| (id == 2).then(|| barrier.wait()));
+    assert_eq!(late[2].unwrap().unwrap_err().missing, missing);
+}
+
+#[test]
+fn barrier_timeout_after_everyone_arrived_succeeds() {
+    let barrier = Arc::new(Barrier::new(2));
+    let results = on_cpus(2, {
+        let barrier = Arc::clone(&barrier);
+        move |_| barrier.wait_timeout(Duration::from_secs(10))
+    });
+    assert!(results.iter().all(|result| result.is_ok()));
+    assert_eq!(results.iter().filter(|result| result.unwrap().is_leader()).count(), 1);
+    assert!(barrier.broken().is_none());
+}
+
+#[test]
+fn wait_queue_hands_out_every_wakeup_once() {
+    const CONSUMERS: usize = 3;
+    const ITEMS: usize = 2000;
+    let queue = Arc::new(WaitQueue::new());
+    let items = Arc::new(AtomicUsize::new(0));
+
+    // CPU 0 produces, and the others each wait for a share
+    let taken = on_cpus(CONSUMERS + 1, {
+        let (queue, items) = (Arc::clone(&queue), Arc::clone(&items));
+        move |id| {
+            if id == 0 {
+                // One at a time, so each wakeup races with a consumer
+                // on its way to sleep
+                for _ in 0..ITEMS * CONSUMERS {
+                    items.fetch_add(1, Ordering::SeqCst);
+                    queue.wake_one();
+                    while items.load(Ordering::SeqCst) != 0 {
+                        thread::yield_now();
+                    }
+                }
+                return 0;
+            }
+            for _ in 0..ITEMS {
+                queue.wait_until(|| {
+                    items
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                        .is_ok()
+                });
+            }
+            ITEMS
+        }
+    });
+
+    // Nobody was left waiting for an item that was never handed out
+    assert_eq!(taken.iter().sum::<usize>(), ITEMS * CONSUMERS);
+    assert_eq!(items.load(Ordering::SeqCst), 0);
+    assert_eq!(queue.wake_all(), 0);
+}
+
+#[test]
+fn wait_queue_wake_all_wakes_every_waiter() {
+    const CPUS: usize = 4;
+    let queue = Arc::new(WaitQueue::new());
+    let ready = Arc::new(AtomicBool::new(false));
+
+    let woken = on_cpus(CPUS, {
+        let (queue, ready) = (Arc::clone(&queue), Arc::clone(&ready));
+        move |id| {
+            if id == 0 {
+                thread::sleep(Duration::from_millis(50));
+                ready.store(true, Ordering::SeqCst);
+                queue.wake_all();
+            } else {
+                queue.wait_until(|| ready.load(Ordering::SeqCst));
+            }
+        }
+    });
+    assert_eq!(woken.len(), CPUS);
+    assert_eq!(queue.wake_all(), 0);
+}
+
+#[test]
+fn once_runs_one_of_racing_initializers() {
+    const CPUS: usize = 4;
+    let once = Arc::new(Once::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let values = on_cpus(CPUS, {
+        let (once, runs) = (Arc::clone(&once), Arc::clone(&runs));
+        move |id| {
+            *once.call_once(|| {
+                runs.fetch_add(1, Ordering::SeqCst);
+                // Long enough for the others to find it initializing
+                thread::sleep(Duration::from_millis(50));
+                id
+            })
+        }
+    });
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert!(values.iter().all(|&value| value == values[0]));
+}
+
+#[test]
+#[should_panic(expected = "Once accessed from its own initializer")]
+fn once_initializer_using_itself_panics() {
+    static ONCE: Once<u32> = Once::new();
+    ONCE.call_once(|| *ONCE.call_once(|| 1) + 1);
+}
+
+#[test]
+fn once_panicking_initializer_poisons_it() {
+    let once = Arc::new(Once::<u32>::new());
+    let first = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        once.call_once(|| panic!("initializer failed"));
+    }));
+    assert!(first.is_err());
+    assert_eq!(once.get(), None);
+
+    // Another CPU gets a panic instead of waiting forever
+    let second = on_cpus(2, move |id| {
+        (id == 1).then(|| std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *once.call_once(|| 2))))
+    });
+    let message = second[1].as_ref().unwrap().as_ref().unwrap_err();
+    assert_eq!(message.downcast_ref::<&str>(), Some(&"Once initializer panicked"));
+}
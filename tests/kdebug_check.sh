@@ -0,0 +1,48 @@
+#!/usr/bin/env bash
+# Checks that kdebug_assert! leaves nothing in a release kernel.
+#
+# Usage: tests/kdebug_check.sh
+#
+# Builds the kernel in debug and release, and looks for the text of a
+# kdebug_assert! and a kassert! in each. The kassert! has to be in both,
+# which shows the search works, and the kdebug_assert! only in debug.
+set -u
+
+root="$(cd "$(dirname "$0")/.." && pwd)"
+target="${root}/target/x86_64-unknown-none"
+kassert="GS base did not stick"
+kdebug="IrqGuard dropped more often than created"
+
+status=0
+for profile in debug release; do
+	flags=()
+	[[ "${profile}" == release ]] && flags=(--release)
+	if ! (cd "${root}" && cargo build --bin hello-os "${flags[@]}" >/dev/null 2>&1); then
+		echo "FAIL the ${profile} kernel doesn't build"
+		exit 1
+	fi
+
+	kernel="${target}/${profile}/hello-os"
+	if ! grep -qaF "${kassert}" "${kernel}"; then
+		echo "FAIL the kassert! is missing from the ${profile} kernel"
+		status=1
+	fi
+	if grep -qaF "${kdebug}" "${kernel}"; then
+		found=yes
+	else
+		found=no
+	fi
+	case "${profile}:${found}" in
+	debug:no)
+		echo "FAIL the kdebug_assert! is missing from the debug kernel"
+		status=1
+		;;
+	release:yes)
+		echo "FAIL the kdebug_assert! is still in the release kernel"
+		status=1
+		;;
+	esac
+done
+
+[[ "${status}" == 0 ]] && echo "ok   kdebug_assert! is compiled out of release kernels"
+exit "${status}"
@@ -0,0 +1,29 @@
+#!/usr/bin/env bash
+# Checks that the per-test leak check catches a leaky test.
+#
+# Usage: tests/leak_check.sh
+#
+# Builds the test kernel with the `leak-test` feature, which adds a test
+# that leaves a heap block allocated. The run has to fail on that test,
+# with the leak report, and on no other: the tests before it stay within
+# their own memory.
+set -u
+
+root="$(cd "$(dirname "$0")/.." && pwd)"
+leaky="hello_os::memory::test::leaky_test_is_caught"
+
+output="$(cd "${root}" && cargo test --features leak-test --bin hello-os 2>&1)"
+status=$?
+
+if [[ "${status}" == 0 ]]; then
+	echo "FAIL the leaky test passed"
+	exit 1
+elif [[ "${output}" != *"test leaked memory: +1 4KB and +0 2MB pages still allocated"* ]]; then
+	echo "FAIL no leak report in the output"
+	exit 1
+elif [[ "${output}" != *"test failed: ${leaky}"* ]]; then
+	echo "FAIL another test failed first:"
+	grep '^test summary:' <<<"${output}"
+	exit 1
+fi
+echo "ok   ${leaky} was caught"
@@ -0,0 +1,53 @@
+#!/usr/bin/env bash
+# Boots the kernel with each `panic=` option, panics it from the shell, and
+# checks what happens next.
+#
+# Usage: tests/panic_action.sh [kernel], after `make`.
+#
+# test-runner.sh runs QEMU with -no-reboot, so a reboot shows up as QEMU
+# exiting on its own after the countdown, which test-runner.sh reports as 3.
+set -u
+
+root="$(cd "$(dirname "$0")/.." && pwd)"
+kernel="${1:-${root}/build/hello-os}"
+failed=0
+
+# Boots with `panic=$1` and types `panic` once the shell is up. Sets
+# `output` and `status`. Extra environment variables can be given after.
+boot() {
+	output="$( (sleep 5; printf 'panic\r') |
+		env HELLO_OS_CMDLINE="panic=$1" "${@:2}" timeout 30 "${root}/test-runner.sh" "${kernel}" 2>&1)"
+	status=$?
+}
+
+# Checks the exit status and that the output has `$3`.
+check() {
+	local name=$1 expected_status=$2 expected_output=$3
+	if [[ "${status}" != "${expected_status}" ]]; then
+		echo "FAIL ${name}: exit status ${status}, expected ${expected_status}"
+		failed=1
+	elif [[ "${output}" != *"${expected_output}"* ]]; then
+		echo "FAIL ${name}: no \"${expected_output}\" in the output"
+		failed=1
+	else
+		echo "ok   ${name}"
+	fi
+}
+
+# test-runner.sh turns the panic status 37 into 2
+boot exit
+check "panic=exit" 2 "KERNEL PANIC"
+
+boot reboot:1
+check "panic=reboot:1" 3 "Rebooting in 1..."
+
+# RAM survives the reset, so the second boot finds the first one's panic
+# and then sits in the shell until timeout gives up
+boot reboot:1 HELLO_OS_REBOOT=1
+check "oops record after reboot" 124 "previous kernel oops found"
+
+# Still halted when timeout gives up
+boot halt
+check "panic=halt" 124 "KERNEL PANIC"
+
+exit "${failed}"
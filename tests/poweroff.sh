@@ -0,0 +1,31 @@
+#!/usr/bin/env bash
+# Boots the kernel on each QEMU machine type, types `poweroff` in the
+# shell, and checks that QEMU exits.
+#
+# Usage: tests/poweroff.sh [kernel], after `make`.
+#
+# QEMU exits with 0 when the machine powers off, which test-runner.sh
+# reports as 3. HELLO_OS_REBOOT=1 keeps QEMU running on a reset, so only
+# a power off ends it before timeout gives up.
+set -u
+
+root="$(cd "$(dirname "$0")/.." && pwd)"
+kernel="${1:-${root}/build/hello-os}"
+failed=0
+
+for machine in pc q35; do
+	output="$( (sleep 5; printf 'poweroff\r') |
+		HELLO_OS_REBOOT=1 timeout 30 "${root}/test-runner.sh" "${kernel}" -machine "${machine}" 2>&1)"
+	status=$?
+	if [[ "${status}" != 3 ]]; then
+		echo "FAIL ${machine}: exit status ${status}, expected 3"
+		failed=1
+	elif [[ "${output}" != *"Powering off through port"* ]]; then
+		echo "FAIL ${machine}: didn't power off through the FADT"
+		failed=1
+	else
+		echo "ok   ${machine}"
+	fi
+done
+
+exit "${failed}"
@@ -0,0 +1,56 @@
+; A tiny user program for the user mode test.
+;
+; Assembled as a flat binary with its own ELF header, so no linker is
+; needed. It writes a message with system call 0, then exits with what
+; the write returned using system call 1.
+
+bits 64
+org 0x8000000000            ; user::USER_BASE
+
+ehdr:
+    db 0x7f, "ELF"
+    db 2                    ; 64-bit
+    db 1                    ; little endian
+    db 1                    ; ELF version
+    db 0                    ; System V ABI
+    times 8 db 0
+    dw 2                    ; executable
+    dw 0x3e                 ; x86-64
+    dd 1                    ; ELF version
+    dq _start               ; entry point
+    dq phdr - $$            ; program header offset
+    dq 0                    ; section header offset
+    dd 0                    ; flags
+    dw ehdr_size
+    dw phdr_size
+    dw 1                    ; number of program headers
+    dw 0, 0, 0              ; no section headers
+ehdr_size equ $ - ehdr
+
+phdr:
+    dd 1                    ; PT_LOAD
+    dd 5                    ; readable and executable
+    dq 0                    ; offset in the file
+    dq $$                   ; virtual address
+    dq $$                   ; physical address
+    dq file_size            ; size in the file
+    dq file_size            ; size in memory
+    dq 0x1000               ; alignment
+phdr_size equ $ - phdr
+
+_start:
+    mov eax, 0              ; write
+    lea rdi, [rel message]
+    mov esi, message_len
+    syscall
+
+    mov edi, eax            ; exit with the number of bytes written
+    mov eax, 1
+    syscall
+    jmp $
+
+message:
+    db "Hello from user mode", 10
+message_len equ $ - message
+
+file_size equ $ - $$